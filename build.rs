@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // use the vendored `protoc`, so that none has to be installed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_prost_build::compile_protos("proto/storage.proto")?;
+    Ok(())
+}
@@ -0,0 +1,400 @@
+//! Operational endpoints, served on the separate `admin_bind` listener so that they are never
+//! exposed alongside the S3 API.
+//!
+//! - `GET /health`: Whether the process is alive.
+//! - `GET /ready`: Whether requests can be served, which is not the case while the store is
+//!   still being opened and repaired, or when the disk is full.
+//! - `GET /metrics`: Prometheus metrics.
+//! - `GET /admin/usage`: The storage used by each namespace.
+//! - `GET /admin/usecases`: The registered usecases along with their policies.
+//! - `GET /admin/scopes`: The named files of each usecase and scope, as counted against
+//!   their quotas.
+//! - `GET /admin/namespaces/{namespace}/files/{file_id}`: A file along with its chunks,
+//!   segments and reference counts.
+//! - `POST /admin/gc`: Releases chunks uploaded for assembling and reserved names which have
+//!   expired, and removes segments which are no longer referenced.
+//! - `POST /admin/lifecycle`: Applies the lifecycle rules of all buckets right away, rather
+//!   than waiting for the next `lifecycle_interval`.
+//! - `POST /admin/scrub`: Verifies the contents of all stored chunks.
+
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use serde::Serialize;
+
+use crate::new_datamodel::store::FileStore;
+use crate::new_datamodel::{
+    chunk, file, quota, report, segment, usecase, Chunking, HashAlgorithm, Namespace,
+};
+use crate::server::AppStateRef;
+
+/// The buckets of the `kycok_request_duration_seconds` histogram.
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Installs the global Prometheus recorder, or returns the already installed one.
+pub fn prometheus_handle() -> PrometheusHandle {
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+    HANDLE
+        .get_or_init(|| {
+            let recorder = PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full("kycok_request_duration_seconds".into()),
+                    DURATION_BUCKETS,
+                )
+                .unwrap()
+                .build_recorder();
+            let handle = recorder.handle();
+            // another recorder might have been installed already, in which case ours stays empty
+            let _ = metrics::set_global_recorder(recorder);
+            handle
+        })
+        .clone()
+}
+
+/// The admin endpoints are served before the store is opened, which can take a while when
+/// it has to be repaired.
+#[derive(Default)]
+pub(crate) struct AdminState {
+    /// Set once the store is opened.
+    pub(crate) app: OnceLock<AppStateRef>,
+}
+
+pub(crate) type AdminStateRef = Arc<AdminState>;
+
+pub(crate) fn router(state: AdminStateRef) -> Router {
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/ready", get(ready))
+        .route("/metrics", get(|| async { prometheus_handle().render() }))
+        .route("/admin/usage", get(usage))
+        .route("/admin/usecases", get(usecases))
+        .route("/admin/scopes", get(scopes))
+        .route(
+            "/admin/namespaces/{namespace}/files/{file_id}",
+            get(inspect_file),
+        )
+        .route("/admin/gc", post(collect_garbage))
+        .route("/admin/lifecycle", post(apply_lifecycle))
+        .route("/admin/scrub", post(scrub))
+        .with_state(state)
+}
+
+async fn ready(State(state): State<AdminStateRef>) -> Response {
+    let Some(app) = state.app.get() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "opening the store").into_response();
+    };
+    match app.filestore.available_space() {
+        // a new segment needs to fit, as well as the chunk that overflows it
+        Some(Ok(space))
+            if space < app.config.storage.segment_size + app.config.storage.chunk_size =>
+        {
+            (StatusCode::SERVICE_UNAVAILABLE, "disk full").into_response()
+        }
+        Some(Err(err)) => {
+            tracing::error!(
+                error = &err as &dyn std::error::Error,
+                "failed to check the disk"
+            );
+            (StatusCode::SERVICE_UNAVAILABLE, "failed to check the disk").into_response()
+        }
+        _ => "ready".into_response(),
+    }
+}
+
+/// Runs `f` with the store on a blocking thread, or responds with `503` while the store is
+/// still being opened.
+async fn with_filestore<T, F>(state: &AdminState, f: F) -> Result<T, Response>
+where
+    F: FnOnce(&FileStore) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let Some(app) = state.app.get().cloned() else {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "opening the store").into_response());
+    };
+    tokio::task::spawn_blocking(move || f(&app.filestore))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+#[derive(Serialize)]
+struct NamespaceUsage {
+    namespace: u64,
+    /// The bucket the namespace belongs to, unless it was deleted.
+    bucket: Option<String>,
+    /// The usecase whose buckets share the namespace, when deduplicating per usecase.
+    usecase: Option<String>,
+    files: u64,
+    file_bytes: u64,
+    chunks: u64,
+    chunk_bytes: u64,
+    stored_bytes: u64,
+}
+
+async fn usage(State(state): State<AdminStateRef>) -> Result<Json<Vec<NamespaceUsage>>, Response> {
+    let (usage, buckets, usecases) = with_filestore(&state, |filestore| {
+        (
+            filestore.usage(),
+            filestore.list_buckets(),
+            filestore.list_usecases(),
+        )
+    })
+    .await?;
+
+    let usage = usage
+        .into_iter()
+        .map(|usage| NamespaceUsage {
+            namespace: usage.namespace.0,
+            bucket: buckets
+                .iter()
+                .find(|bucket| bucket.namespace == usage.namespace)
+                .map(|bucket| bucket.name.clone()),
+            usecase: usecases
+                .iter()
+                .find(|usecase| usecase.namespace == usage.namespace)
+                .map(|usecase| usecase.name.clone()),
+            files: usage.files,
+            file_bytes: usage.file_bytes,
+            chunks: usage.chunks,
+            chunk_bytes: usage.chunk_bytes,
+            stored_bytes: usage.stored_bytes,
+        })
+        .collect();
+    Ok(Json(usage))
+}
+
+#[derive(Serialize)]
+struct Usecase {
+    name: String,
+    namespace: u64,
+    ttl: Option<u64>,
+    tti: Option<u64>,
+    chunking: Chunking,
+    chunk_size: u64,
+    /// The zstd level, or `null` if chunks are stored uncompressed.
+    compression_level: Option<i32>,
+    max_object_size: Option<u64>,
+    dedup: usecase::DedupScope,
+    hash_algorithm: HashAlgorithm,
+    hard_quota: quota::Quota,
+    soft_quota: quota::Quota,
+}
+
+async fn usecases(State(state): State<AdminStateRef>) -> Result<Json<Vec<Usecase>>, Response> {
+    let usecases = with_filestore(&state, |filestore| filestore.list_usecases()).await?;
+    let usecases = usecases
+        .into_iter()
+        .map(
+            |usecase::Usecase {
+                 name,
+                 namespace,
+                 policy,
+             }| Usecase {
+                name,
+                namespace: namespace.0,
+                ttl: policy.ttl,
+                tti: policy.tti,
+                chunking: policy.chunking,
+                chunk_size: policy.chunk_size,
+                compression_level: policy.compression_level,
+                max_object_size: policy.max_object_size,
+                dedup: policy.dedup,
+                hash_algorithm: policy.hash_algorithm,
+                hard_quota: policy.hard_quota,
+                soft_quota: policy.soft_quota,
+            },
+        )
+        .collect();
+    Ok(Json(usecases))
+}
+
+#[derive(Serialize)]
+struct ScopeUsage {
+    usecase: String,
+    scope: String,
+    objects: u64,
+    logical_bytes: u64,
+    physical_bytes: u64,
+}
+
+async fn scopes(State(state): State<AdminStateRef>) -> Result<Json<Vec<ScopeUsage>>, Response> {
+    let scope_usage = with_filestore(&state, |filestore| filestore.scope_usage()).await?;
+    let scope_usage = scope_usage
+        .into_iter()
+        .map(
+            |quota::ScopeUsage {
+                 usecase,
+                 scope,
+                 usage,
+             }| ScopeUsage {
+                usecase,
+                scope,
+                objects: usage.objects,
+                logical_bytes: usage.logical_bytes,
+                physical_bytes: usage.physical_bytes,
+            },
+        )
+        .collect();
+    Ok(Json(scope_usage))
+}
+
+#[derive(Serialize)]
+struct FileInfo {
+    file_id: String,
+    size: u64,
+    refcount: u32,
+    inline: bool,
+    chunks: Vec<ChunkInfo>,
+}
+
+#[derive(Serialize)]
+struct ChunkInfo {
+    chunk_id: String,
+    size: u32,
+    compression: &'static str,
+    compressed_size: u32,
+    refcount: u32,
+    segment_id: String,
+    offset_in_segment: u32,
+    segment_refcount: u32,
+}
+
+async fn inspect_file(
+    State(state): State<AdminStateRef>,
+    Path((namespace, file_id)): Path<(u64, String)>,
+) -> Result<Json<FileInfo>, Response> {
+    let Ok(file_id) = file_id.parse::<file::FileId>() else {
+        return Err((StatusCode::BAD_REQUEST, "invalid file id").into_response());
+    };
+    let info = with_filestore(&state, move |filestore| {
+        FileStore::with_namespace(filestore, Namespace(namespace)).inspect_file(file_id)
+    })
+    .await?;
+    let Some(info) = info else {
+        return Err((StatusCode::NOT_FOUND, "no such file").into_response());
+    };
+
+    let chunks = info
+        .chunks
+        .into_iter()
+        .map(
+            |report::ChunkInfo {
+                 chunk_id,
+                 chunk,
+                 refcount,
+                 segment_refcount,
+             }| ChunkInfo {
+                chunk_id: chunk_id.to_string(),
+                size: chunk.size,
+                compression: match chunk.compression {
+                    chunk::Compression::None => "none",
+                    chunk::Compression::Zstd => "zstd",
+                },
+                compressed_size: chunk.compressed_size,
+                refcount,
+                segment_id: segment_hex(chunk.segment_id),
+                offset_in_segment: chunk.offset_in_segment,
+                segment_refcount,
+            },
+        )
+        .collect();
+    Ok(Json(FileInfo {
+        file_id: info.file_id.to_string(),
+        size: info.file.size,
+        refcount: info.refcount,
+        inline: matches!(info.file.contents, file::FileContents::Inline(_)),
+        chunks,
+    }))
+}
+
+/// Formats the ID the way segment files are named on disk.
+fn segment_hex(segment_id: segment::SegmentId) -> String {
+    uuid::Uuid::from_bytes(segment_id.uuid).simple().to_string()
+}
+
+#[derive(Serialize)]
+struct GcReport {
+    chunk_refs_expired: u64,
+    reservations_expired: u64,
+    segments_removed: u64,
+    bytes_freed: u64,
+}
+
+async fn collect_garbage(State(state): State<AdminStateRef>) -> Result<Json<GcReport>, Response> {
+    let report = with_filestore(&state, |filestore| filestore.collect_garbage())
+        .await?
+        .map_err(|err| {
+            tracing::error!(
+                error = &err as &dyn std::error::Error,
+                "garbage collection failed"
+            );
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        })?;
+    Ok(Json(GcReport {
+        chunk_refs_expired: report.chunk_refs_expired,
+        reservations_expired: report.reservations_expired,
+        segments_removed: report.segments_removed,
+        bytes_freed: report.bytes_freed,
+    }))
+}
+
+#[derive(Serialize)]
+struct LifecycleReport {
+    objects_expired: u64,
+    versions_expired: u64,
+    versions_locked: u64,
+    uploads_aborted: u64,
+}
+
+/// The files released by the rules are freed right away, but their segments are only removed
+/// by the next garbage collection.
+async fn apply_lifecycle(
+    State(state): State<AdminStateRef>,
+) -> Result<Json<LifecycleReport>, Response> {
+    let defaults = state.app.get().map(|app| app.config.storage.clone());
+    let report = with_filestore(&state, move |filestore| {
+        filestore.apply_lifecycle(&defaults.unwrap_or_default(), SystemTime::now())
+    })
+    .await?;
+    Ok(Json(LifecycleReport {
+        objects_expired: report.objects_expired,
+        versions_expired: report.versions_expired,
+        versions_locked: report.versions_locked,
+        uploads_aborted: report.uploads_aborted,
+    }))
+}
+
+#[derive(Serialize)]
+struct ScrubReport {
+    chunks_checked: u64,
+    corrupt_chunks: Vec<CorruptChunk>,
+}
+
+#[derive(Serialize)]
+struct CorruptChunk {
+    namespace: u64,
+    chunk_id: String,
+}
+
+async fn scrub(State(state): State<AdminStateRef>) -> Result<Json<ScrubReport>, Response> {
+    let report = with_filestore(&state, |filestore| filestore.scrub()).await?;
+    let corrupt_chunks = report
+        .corrupt_chunks
+        .into_iter()
+        .map(|(namespace, chunk_id)| CorruptChunk {
+            namespace: namespace.0,
+            chunk_id: chunk_id.to_string(),
+        })
+        .collect();
+    Ok(Json(ScrubReport {
+        chunks_checked: report.chunks_checked,
+        corrupt_chunks,
+    }))
+}
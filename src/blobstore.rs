@@ -1,5 +1,6 @@
 use tokio::io::AsyncRead;
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 pub struct BlobId([u8; 16]);
 
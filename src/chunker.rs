@@ -2,7 +2,7 @@ use std::pin::pin;
 
 use fastcdc::v2020::AsyncStreamCDC;
 use futures_util::{Stream, StreamExt};
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::AsyncRead;
 
 pub enum ChunkingStrategy {
     None,
@@ -16,6 +16,15 @@ const MIN_CHUNK: u32 = 1 * ONE_MEG;
 const AVG_CHUNK: u32 = 2 * ONE_MEG;
 const MAX_CHUNK: u32 = 4 * ONE_MEG;
 
+impl ChunkingStrategy {
+    /// Content-defined chunks of at most `max_size`, which are split at the same boundaries as
+    /// files stored with [`Chunking::ContentDefined`](crate::new_datamodel::Chunking) and a
+    /// `chunk_size` of `max_size`.
+    pub fn content_defined(max_size: u32) -> Self {
+        Self::Cdc(max_size / 16, max_size / 4, max_size)
+    }
+}
+
 impl Default for ChunkingStrategy {
     fn default() -> Self {
         Self::Cdc(MIN_CHUNK, AVG_CHUNK, MAX_CHUNK)
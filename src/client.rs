@@ -0,0 +1,646 @@
+//! A client for the kycok server, implementing the API of `design/api.md`.
+//!
+//! Blobs are stored in one bucket per usecase and scope, which is created on first use:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), kycok::client::Error> {
+//! use kycok::client::{StorageScope, StorageServiceBuilder};
+//!
+//! // defined/configured statically:
+//! let attachments = StorageServiceBuilder::new("http://localhost:8080").for_usecase("attachments");
+//!
+//! // used dynamically:
+//! let storage = attachments.with_scope(StorageScope::for_organization(17));
+//! let storage_id = storage.put_blob("some raw attachment contents", None).await?;
+//! let contents = storage.get_blob(&storage_id).await?.bytes().await?;
+//! storage.delete_blob(&storage_id).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Large blobs can be uploaded with [`StorageClient::put_blob_chunked`] instead, which splits,
+//! hashes and compresses them locally, and only uploads the chunks the server does not have.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::pin::pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use axum::http::{StatusCode, Uri};
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt, TryStream, TryStreamExt};
+use reqwest::header::{CONTENT_ENCODING, IF_NONE_MATCH};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+use crate::chunker::{self, ChunkingStrategy};
+use crate::new_datamodel::{bucket, chunk, HashAlgorithm};
+use crate::sigv4;
+
+/// How many chunks are checked for being missing at once.
+const CHUNK_BATCH_SIZE: usize = 64;
+/// How many chunks are uploaded concurrently.
+const CHUNK_UPLOAD_CONCURRENCY: usize = 4;
+
+/// Configures the connection to the server, shared by all usecases created from it.
+#[derive(Debug, Clone)]
+pub struct StorageServiceBuilder {
+    client: reqwest::Client,
+    endpoint: String,
+    credentials: Option<(String, String)>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    max_chunk_size: u32,
+}
+
+impl StorageServiceBuilder {
+    /// Connects to the server at `endpoint`, like `http://localhost:8080`.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        let mut endpoint = endpoint.into();
+        while endpoint.ends_with('/') {
+            endpoint.pop();
+        }
+        Self {
+            client: reqwest::Client::new(),
+            endpoint,
+            credentials: None,
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(100),
+            max_chunk_size: 4 * 1024 * 1024,
+        }
+    }
+
+    /// Signs all requests with these credentials, which is required when the server has an
+    /// auth config.
+    pub fn credentials(
+        mut self,
+        access_key: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((access_key.into(), secret_key.into()));
+        self
+    }
+
+    /// How often requests are retried on connection errors and `5xx` responses.
+    ///
+    /// Uploads of streaming [`Body`]s are never retried, as the stream is consumed.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// The delay before the first retry, which doubles with every further retry.
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    /// The maximum size of the chunks [`StorageClient::put_blob_chunked`] splits blobs into,
+    /// between 1 KiB and 16 MiB. It must not exceed the `chunk_size` of the usecase.
+    ///
+    /// With the same size as the `chunk_size` of a usecase with content-defined chunking,
+    /// chunks are also deduplicated with blobs uploaded as a whole.
+    pub fn max_chunk_size(mut self, max_chunk_size: u32) -> Self {
+        self.max_chunk_size =
+            max_chunk_size.clamp(fastcdc::v2020::MAXIMUM_MIN, fastcdc::v2020::MAXIMUM_MAX);
+        self
+    }
+
+    /// Creates the service for the given usecase, which has to be configured on the server.
+    pub fn for_usecase(&self, usecase: impl Into<String>) -> StorageService {
+        let inner = Inner {
+            client: self.client.clone(),
+            endpoint: self.endpoint.clone(),
+            credentials: self.credentials.clone(),
+            max_retries: self.max_retries,
+            retry_backoff: self.retry_backoff,
+            max_chunk_size: self.max_chunk_size,
+            known_buckets: Default::default(),
+        };
+        StorageService {
+            inner: Arc::new(inner),
+            usecase: usecase.into(),
+        }
+    }
+}
+
+/// The scope blobs are isolated in, within a usecase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageScope(String);
+
+impl StorageScope {
+    pub fn for_organization(org_id: u64) -> Self {
+        Self(format!("org-{org_id}"))
+    }
+}
+
+impl fmt::Display for StorageScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The storage of a single usecase, see [`StorageServiceBuilder::for_usecase`].
+#[derive(Debug, Clone)]
+pub struct StorageService {
+    inner: Arc<Inner>,
+    usecase: String,
+}
+
+impl StorageService {
+    pub fn with_scope(&self, scope: StorageScope) -> StorageClient {
+        StorageClient {
+            inner: self.inner.clone(),
+            bucket: bucket::scoped_name(&self.usecase, &scope.0),
+            usecase: self.usecase.clone(),
+            scope,
+        }
+    }
+}
+
+/// The opaque ID of a blob, unique within its usecase and scope.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StorageId(String);
+
+impl fmt::Display for StorageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A [`StorageId`] may only consist of ASCII alphanumerics, `-`, `_`, `.`, `~` and `/`,
+/// and must not start with a `/` or contain `.` or `..` path segments.
+impl FromStr for StorageId {
+    type Err = InvalidStorageId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let is_valid = |c: u8| c.is_ascii_alphanumeric() || b"-_.~/".contains(&c);
+        let is_dot_segment = |segment| segment == "." || segment == "..";
+        if s.is_empty()
+            || s.len() > 1024
+            || s.starts_with('/')
+            || !s.bytes().all(is_valid)
+            || s.split('/').any(is_dot_segment)
+        {
+            return Err(InvalidStorageId);
+        }
+        Ok(Self(s.into()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidStorageId;
+
+impl fmt::Display for InvalidStorageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid storage id")
+    }
+}
+
+impl std::error::Error for InvalidStorageId {}
+
+/// The contents of a blob to upload, which can be streamed.
+#[derive(Debug)]
+pub struct Body(reqwest::Body);
+
+impl Body {
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: TryStream + Send + 'static,
+        S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+        Bytes: From<S::Ok>,
+    {
+        Self(reqwest::Body::wrap_stream(stream))
+    }
+
+    pub fn from_reader(reader: impl AsyncRead + Send + 'static) -> Self {
+        Self::from_stream(ReaderStream::new(reader))
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(contents: Bytes) -> Self {
+        Self(contents.into())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(contents: Vec<u8>) -> Self {
+        Self(contents.into())
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(contents: &'static [u8]) -> Self {
+        Self(contents.into())
+    }
+}
+
+impl From<String> for Body {
+    fn from(contents: String) -> Self {
+        Self(contents.into())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(contents: &'static str) -> Self {
+        Self(contents.into())
+    }
+}
+
+/// A downloaded blob, whose contents are streamed.
+#[derive(Debug)]
+pub struct Blob(reqwest::Response);
+
+impl Blob {
+    /// The size of the blob, if the server sent it upfront.
+    pub fn size(&self) -> Option<u64> {
+        self.0.content_length()
+    }
+
+    pub async fn bytes(self) -> Result<Bytes, Error> {
+        Ok(self.0.bytes().await?)
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes, Error>> {
+        self.0.bytes_stream().map_err(Error::from)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The blob does not exist.
+    NotFound,
+    /// The server rejected the request.
+    Server {
+        status: StatusCode,
+        code: String,
+        message: String,
+    },
+    /// The request could not be sent, or the response could not be read.
+    Http(reqwest::Error),
+    /// The contents of a chunked upload could not be read.
+    Read(chunker::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("The blob does not exist"),
+            Self::Server {
+                status,
+                code,
+                message,
+            } => write!(f, "The server responded with {status} {code}: {message}"),
+            Self::Http(err) => write!(f, "The request failed: {err}"),
+            Self::Read(err) => write!(f, "Reading the contents failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(err) => Some(err),
+            Self::Read(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Http(err)
+    }
+}
+
+/// Stores and retrieves blobs within a usecase and scope, see [`StorageService::with_scope`].
+#[derive(Debug, Clone)]
+pub struct StorageClient {
+    inner: Arc<Inner>,
+    usecase: String,
+    scope: StorageScope,
+    bucket: String,
+}
+
+impl StorageClient {
+    /// Uploads a blob, returning its ID, which is allocated by the server unless given
+    /// explicitly.
+    ///
+    /// Uploading to an existing ID replaces the blob. Uploads to an allocated ID only create
+    /// the blob, so that retrying them is idempotent.
+    pub async fn put_blob(
+        &self,
+        contents: impl Into<Body>,
+        id: Option<StorageId>,
+    ) -> Result<StorageId, Error> {
+        self.ensure_bucket().await?;
+
+        let (id, create_only) = self.id_or_allocate(id).await?;
+        let mut request = self.request(Method::PUT, Some(&id));
+        if create_only {
+            set_create_only(&mut request);
+        }
+        *request.body_mut() = Some(contents.into().0);
+        self.inner.execute(request).await?;
+        Ok(id)
+    }
+
+    /// Uploads a blob like [`Self::put_blob`], but splits it into content-defined chunks,
+    /// which are hashed with BLAKE3 and compressed with zstd locally.
+    ///
+    /// Only the chunks the server does not have yet are uploaded, and stored as they were
+    /// compressed, before the blob is assembled from all of them.
+    pub async fn put_blob_chunked(
+        &self,
+        contents: impl AsyncRead + Unpin,
+        id: Option<StorageId>,
+    ) -> Result<StorageId, Error> {
+        self.ensure_bucket().await?;
+
+        let strategy = ChunkingStrategy::content_defined(self.inner.max_chunk_size);
+        let mut chunks = pin!(chunker::chunk_stream(strategy, contents));
+        let mut chunk_ids = vec![];
+        let mut batch = vec![];
+        while let Some(chunk) = chunks.next().await {
+            batch.push(chunk.map_err(Error::Read)?);
+            if batch.len() == CHUNK_BATCH_SIZE {
+                chunk_ids.extend(
+                    self.upload_missing_chunks(std::mem::take(&mut batch))
+                        .await?,
+                );
+            }
+        }
+        chunk_ids.extend(self.upload_missing_chunks(batch).await?);
+
+        let (id, create_only) = self.id_or_allocate(id).await?;
+        let mut request = self.request(Method::POST, Some(&id));
+        request.url_mut().set_query(Some("assemble"));
+        if create_only {
+            set_create_only(&mut request);
+        }
+        let body = serde_json::to_vec(&ChunkList { chunks: chunk_ids }).unwrap();
+        *request.body_mut() = Some(body.into());
+        self.inner.execute(request).await?;
+        Ok(id)
+    }
+
+    /// The given ID, or a new one allocated by the server, along with whether it was allocated.
+    async fn id_or_allocate(&self, id: Option<StorageId>) -> Result<(StorageId, bool), Error> {
+        if let Some(id) = id {
+            return Ok((id, false));
+        }
+        let mut request = self.request(Method::POST, None);
+        request.url_mut().set_query(Some("allocate"));
+        let response = self.inner.execute(request).await?;
+        let Allocated { id } = response.json().await?;
+        Ok((StorageId(id), true))
+    }
+
+    /// Uploads the chunks which the server does not have yet, returning the IDs of all chunks.
+    async fn upload_missing_chunks(&self, chunks: Vec<Vec<u8>>) -> Result<Vec<String>, Error> {
+        if chunks.is_empty() {
+            return Ok(vec![]);
+        }
+        let chunk_ids: Vec<_> = chunks
+            .iter()
+            .map(|chunk| chunk::ChunkId::from_contents(HashAlgorithm::Blake3, chunk).to_string())
+            .collect();
+
+        let mut request = self.request(Method::POST, None);
+        request.url_mut().set_query(Some("missing-chunks"));
+        let body = serde_json::to_vec(&ChunkList {
+            chunks: chunk_ids.clone(),
+        })
+        .unwrap();
+        *request.body_mut() = Some(body.into());
+        let response = self.inner.execute(request).await?;
+        let MissingChunks { missing } = response.json().await?;
+
+        let uploads = chunk_ids
+            .iter()
+            .zip(chunks)
+            .filter_map(|(chunk_id, chunk)| {
+                missing.contains(chunk_id).then_some(async move {
+                    let compressed = zstd::bulk::compress(&chunk, zstd::DEFAULT_COMPRESSION_LEVEL)
+                        .map_err(|err| Error::Read(err.into()))?;
+                    let mut request = self.request(Method::POST, None);
+                    request
+                        .url_mut()
+                        .set_query(Some(&format!("chunk={chunk_id}")));
+                    let headers = request.headers_mut();
+                    headers.insert(CONTENT_ENCODING, "zstd".parse().unwrap());
+                    *request.body_mut() = Some(compressed.into());
+                    self.inner.execute(request).await.map(drop)
+                })
+            });
+        futures_util::stream::iter(uploads)
+            .buffer_unordered(CHUNK_UPLOAD_CONCURRENCY)
+            .try_collect::<()>()
+            .await?;
+        Ok(chunk_ids)
+    }
+
+    pub async fn get_blob(&self, id: &StorageId) -> Result<Blob, Error> {
+        let request = self.request(Method::GET, Some(id));
+        let response = self.inner.execute(request).await?;
+        Ok(Blob(response))
+    }
+
+    /// Deletes a blob, which succeeds if it does not exist.
+    pub async fn delete_blob(&self, id: &StorageId) -> Result<(), Error> {
+        let request = self.request(Method::DELETE, Some(id));
+        match self.inner.execute(request).await {
+            Ok(_) | Err(Error::NotFound) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn request(&self, method: Method, id: Option<&StorageId>) -> reqwest::Request {
+        let mut url = format!("{}/{}", self.inner.endpoint, self.bucket);
+        if let Some(id) = id {
+            url.push('/');
+            url.push_str(&id.0);
+        }
+        let url = reqwest::Url::parse(&url).expect("the endpoint should be a valid url");
+        reqwest::Request::new(method, url)
+    }
+
+    /// Creates the bucket of the usecase and scope, unless it was already created.
+    async fn ensure_bucket(&self) -> Result<(), Error> {
+        if self
+            .inner
+            .known_buckets
+            .lock()
+            .unwrap()
+            .contains(&self.bucket)
+        {
+            return Ok(());
+        }
+
+        let mut request = self.request(Method::PUT, None);
+        let headers = request.headers_mut();
+        headers.insert("x-kycok-usecase", self.usecase.parse().unwrap());
+        headers.insert("x-kycok-scope", self.scope.0.parse().unwrap());
+        match self.inner.execute(request).await {
+            Ok(_) => {}
+            Err(Error::Server { code, .. }) if code == "BucketAlreadyExists" => {}
+            Err(err) => return Err(err),
+        }
+
+        let mut known_buckets = self.inner.known_buckets.lock().unwrap();
+        known_buckets.insert(self.bucket.clone());
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    client: reqwest::Client,
+    endpoint: String,
+    /// The access and secret key.
+    credentials: Option<(String, String)>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    max_chunk_size: u32,
+    /// The buckets which are known to exist.
+    known_buckets: Mutex<HashSet<String>>,
+}
+
+/// The body of chunk negotiation and assemble requests.
+#[derive(Serialize)]
+struct ChunkList {
+    chunks: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct MissingChunks {
+    missing: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Allocated {
+    id: String,
+}
+
+/// Makes the upload only create the blob, and succeed when it is retried with the same
+/// contents.
+fn set_create_only(request: &mut reqwest::Request) {
+    let headers = request.headers_mut();
+    headers.insert(IF_NONE_MATCH, "*".parse().unwrap());
+    headers.insert("x-kycok-idempotent", "true".parse().unwrap());
+}
+
+/// The `<Error>` body of S3 error responses.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(rename = "Code")]
+    code: String,
+    #[serde(rename = "Message", default)]
+    message: String,
+}
+
+impl Inner {
+    /// Sends the request, retrying it unless its body is a stream, and turns error responses
+    /// into an [`Error`].
+    async fn execute(&self, mut request: reqwest::Request) -> Result<reqwest::Response, Error> {
+        let mut attempt = 0;
+        loop {
+            let retry = request.try_clone().filter(|_| attempt < self.max_retries);
+            self.sign(&mut request);
+
+            let result = self.client.execute(request).await;
+            let should_retry = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(err) => err.is_connect() || err.is_timeout(),
+            };
+            match retry {
+                Some(retry) if should_retry => {
+                    let backoff = self.retry_backoff * 2u32.saturating_pow(attempt);
+                    tracing::debug!(attempt, ?backoff, "retrying request");
+                    tokio::time::sleep(backoff).await;
+                    request = retry;
+                    attempt += 1;
+                }
+                _ => return check_response(result?).await,
+            }
+        }
+    }
+
+    fn sign(&self, request: &mut reqwest::Request) {
+        let Some((access_key, secret_key)) = &self.credentials else {
+            return;
+        };
+        let uri: Uri = request.url().as_str().parse().unwrap();
+        let method = request.method().clone();
+        sigv4::sign_request(
+            access_key,
+            secret_key,
+            &method,
+            &uri,
+            request.headers_mut(),
+            SystemTime::now(),
+        );
+    }
+}
+
+async fn check_response(response: reqwest::Response) -> Result<reqwest::Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await?;
+    let ErrorResponse { code, message } =
+        quick_xml::de::from_str(&body).unwrap_or_else(|_| ErrorResponse {
+            code: status.canonical_reason().unwrap_or_default().into(),
+            message: body,
+        });
+    if matches!(code.as_str(), "NoSuchKey" | "NoSuchBucket") {
+        return Err(Error::NotFound);
+    }
+    Err(Error::Server {
+        status,
+        code,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_datamodel::file;
+
+    #[test]
+    fn test_storage_id() {
+        let id: StorageId = "attachments/some-file_v1.txt".parse().unwrap();
+        assert_eq!(id.to_string(), "attachments/some-file_v1.txt");
+
+        for invalid in [
+            "",
+            "/absolute",
+            "with space",
+            "percent%20",
+            "query?",
+            "a/../b",
+        ] {
+            assert_eq!(
+                invalid.parse::<StorageId>(),
+                Err(InvalidStorageId),
+                "{invalid}"
+            );
+        }
+
+        // ids allocated by the server are valid
+        for format in [file::IdFormat::Uuid, file::IdFormat::Compact] {
+            let allocated = format.generate();
+            assert_eq!(
+                allocated.parse::<StorageId>(),
+                Ok(StorageId(allocated.clone()))
+            );
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! The configuration of the kycok server.
+//!
+//! It is read from a TOML file, and individual values can be overridden on the command line:
+//!
+//! ```toml
+//! bind = "127.0.0.1:8080"
+//! admin_bind = "127.0.0.1:9090"
+//! grpc_bind = "127.0.0.1:50051"
+//! sentry_bind = "127.0.0.1:8081"
+//! public_url = "https://kycok.example.com"
+//! backend = "fjall"
+//! data_dir = "/var/lib/kycok"
+//! auth_config = "/etc/kycok/auth.toml"
+//! log_format = "json"
+//! log_level = "info,kycok=debug"
+//! lifecycle_interval = 3600
+//!
+//! [storage]
+//! inline_size = 256
+//! chunk_size = 8388608
+//! segment_size = 1073741824
+//! compression_level = 3
+//!
+//! [usecases.attachments]
+//! ttl = 2592000
+//! tti = 604800
+//! chunking = "content-defined"
+//! chunk_size = 4194304
+//! compression_level = "none"
+//! max_object_size = 104857600
+//! dedup = "usecase"
+//! hash_algorithm = "blake3"
+//!
+//! [usecases.attachments.hard_quota]
+//! objects = 1000000
+//! logical_bytes = 107374182400
+//!
+//! [usecases.attachments.soft_quota]
+//! physical_bytes = 53687091200
+//! ```
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Deserializer};
+use tracing_subscriber::EnvFilter;
+
+use crate::new_datamodel::quota::Quota;
+use crate::new_datamodel::usecase::{DedupScope, Policy};
+use crate::new_datamodel::{deserialize_compression_level, Chunking, Config, HashAlgorithm};
+use crate::sentry;
+
+/// The usecase buckets belong to when none is given explicitly.
+pub const DEFAULT_USECASE: &str = "default";
+
+/// Where the server persists its data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Everything is kept in memory, and lost on shutdown.
+    Mem,
+    /// Everything is persisted to `data_dir`, or a temporary directory if that is not set.
+    #[default]
+    Fjall,
+}
+
+/// How log output is formatted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, multi-line text.
+    #[default]
+    Pretty,
+    /// One JSON object per line, including the fields of all active spans.
+    Json,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The address the server listens on.
+    pub bind: SocketAddr,
+    /// The address the admin endpoints, like `/metrics`, are served on. They are disabled if
+    /// this is not set.
+    pub admin_bind: Option<SocketAddr>,
+    /// The address the [`grpc`](crate::grpc) metadata service is served on. It is disabled
+    /// if this is not set.
+    pub grpc_bind: Option<SocketAddr>,
+    /// The address the [`sentry`](crate::sentry) chunk upload endpoints are served on. They
+    /// are disabled if this is not set, and need the `sentry` usecase to be configured.
+    pub sentry_bind: Option<SocketAddr>,
+    /// The URL the S3 API is reachable at, which signed URLs point to. Defaults to
+    /// `http://{bind}` for the gRPC service, and to the URL of the request for `presign`.
+    pub public_url: Option<String>,
+    pub backend: Backend,
+    pub data_dir: Option<PathBuf>,
+    /// The path to the [`AuthConfig`](crate::sigv4::AuthConfig). Requests are unauthenticated
+    /// if this is not set.
+    pub auth_config: Option<PathBuf>,
+    /// The default chunking and compression settings.
+    pub storage: Config,
+    /// The usecases buckets can be created with, in addition to [`DEFAULT_USECASE`]. They are
+    /// registered with the store on startup, updating the policies of existing usecases.
+    pub usecases: BTreeMap<String, Usecase>,
+    pub log_format: LogFormat,
+    /// The log filter, in the `RUST_LOG` syntax, which takes precedence if it is set.
+    pub log_level: String,
+    /// How often the lifecycle rules of all buckets are applied, in seconds, each time
+    /// followed by a garbage collection.
+    pub lifecycle_interval: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            admin_bind: None,
+            grpc_bind: None,
+            sentry_bind: None,
+            public_url: None,
+            backend: Backend::default(),
+            data_dir: None,
+            auth_config: None,
+            storage: Config::default(),
+            usecases: BTreeMap::new(),
+            log_format: LogFormat::default(),
+            log_level: "info".into(),
+            lifecycle_interval: 60 * 60,
+        }
+    }
+}
+
+/// The policy of a usecase, where unset values are taken from the `storage` config.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Usecase {
+    /// The time in seconds after which files expire.
+    pub ttl: Option<u64>,
+    /// The time in seconds after which files expire if they are not accessed.
+    pub tti: Option<u64>,
+    pub chunking: Option<Chunking>,
+    pub chunk_size: Option<u64>,
+    /// Overrides the zstd level of the `storage` config, `"none"` disables compression.
+    #[serde(deserialize_with = "deserialize_usecase_compression")]
+    pub compression_level: Option<Option<i32>>,
+    /// Larger uploads are rejected.
+    pub max_object_size: Option<u64>,
+    /// Whether buckets of the usecase share identical contents. This cannot be changed once
+    /// the usecase was registered.
+    pub dedup: DedupScope,
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// The limits of each scope, beyond which uploads are rejected.
+    pub hard_quota: Quota,
+    /// The limits of each scope, beyond which uploads are logged.
+    pub soft_quota: Quota,
+}
+
+fn deserialize_usecase_compression<'de, D>(deserializer: D) -> Result<Option<Option<i32>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_compression_level(deserializer).map(Some)
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file `{}`", path.display()))?;
+        Self::from_toml(&contents)
+            .with_context(|| format!("invalid config file `{}`", path.display()))
+    }
+
+    pub fn from_toml(contents: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Checks the config for values the server cannot run with.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Err(err) = EnvFilter::try_new(&self.log_level) {
+            bail!("invalid `log_level`: {err}");
+        }
+        match &self.public_url {
+            Some(public_url) => match reqwest::Url::parse(public_url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                _ => bail!("`public_url` must be an http(s) URL"),
+            },
+            None if self.grpc_bind.is_some() && self.bind.ip().is_unspecified() => {
+                bail!(
+                    "`public_url` is required for the gRPC service when binding to `{}`",
+                    self.bind.ip()
+                )
+            }
+            None => {}
+        }
+        if self.sentry_bind.is_some() && !self.usecases.contains_key(sentry::USECASE) {
+            bail!(
+                "`usecases.{}` is required for the Sentry chunk upload endpoints",
+                sentry::USECASE
+            );
+        }
+        if self.backend == Backend::Mem && self.data_dir.is_some() {
+            bail!("`data_dir` cannot be used with the `mem` backend");
+        }
+        if self.lifecycle_interval == 0 {
+            bail!("`lifecycle_interval` must be greater than 0");
+        }
+
+        let storage = &self.storage;
+        let policy = Policy::from_config(storage);
+        self.validate_chunking("storage", &policy)?;
+        validate_compression_level("storage.compression_level", storage.compression_level)?;
+
+        for (name, usecase) in &self.usecases {
+            if name.is_empty() {
+                bail!("usecase names must not be empty");
+            }
+            if usecase.ttl == Some(0) {
+                bail!("`usecases.{name}.ttl` must be greater than 0");
+            }
+            if usecase.tti == Some(0) {
+                bail!("`usecases.{name}.tti` must be greater than 0");
+            }
+            if usecase.max_object_size == Some(0) {
+                bail!("`usecases.{name}.max_object_size` must be greater than 0");
+            }
+
+            let policy = self.usecase_policy(name).unwrap();
+            self.validate_chunking(&format!("usecases.{name}"), &policy)?;
+            if let Some(level) = usecase.compression_level {
+                validate_compression_level(&format!("usecases.{name}.compression_level"), level)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The URL the S3 API is reachable at, without a trailing `/`.
+    pub fn public_url(&self) -> String {
+        match &self.public_url {
+            Some(public_url) => public_url.trim_end_matches('/').into(),
+            None => format!("http://{}", self.bind),
+        }
+    }
+
+    /// The policy of the given usecase, or `None` if the usecase is not configured.
+    pub fn usecase_policy(&self, usecase: &str) -> Option<Policy> {
+        let defaults = Policy::from_config(&self.storage);
+        let Some(usecase) = self.usecases.get(usecase) else {
+            return (usecase == DEFAULT_USECASE).then_some(defaults);
+        };
+        Some(Policy {
+            ttl: usecase.ttl,
+            tti: usecase.tti,
+            chunking: usecase.chunking.unwrap_or(defaults.chunking),
+            chunk_size: usecase.chunk_size.unwrap_or(defaults.chunk_size),
+            compression_level: usecase
+                .compression_level
+                .unwrap_or(defaults.compression_level),
+            max_object_size: usecase.max_object_size.or(defaults.max_object_size),
+            dedup: usecase.dedup,
+            hash_algorithm: usecase.hash_algorithm.unwrap_or(defaults.hash_algorithm),
+            hard_quota: usecase.hard_quota.clone(),
+            soft_quota: usecase.soft_quota.clone(),
+        })
+    }
+
+    /// The policies of all configured usecases, including [`DEFAULT_USECASE`].
+    pub fn usecase_policies(&self) -> BTreeMap<&str, Policy> {
+        std::iter::once(DEFAULT_USECASE)
+            .chain(self.usecases.keys().map(String::as_str))
+            .map(|name| (name, self.usecase_policy(name).unwrap()))
+            .collect()
+    }
+
+    fn validate_chunking(&self, key: &str, policy: &Policy) -> anyhow::Result<()> {
+        let chunk_sizes = policy.chunking.chunk_sizes();
+        if !chunk_sizes.contains(&policy.chunk_size) {
+            bail!(
+                "`{key}.chunk_size` must be between {} and {} with {:?} chunking",
+                chunk_sizes.start(),
+                chunk_sizes.end(),
+                policy.chunking,
+            );
+        }
+        if self.storage.segment_size + policy.chunk_size > u32::MAX as u64 {
+            bail!(
+                "`storage.segment_size` plus `{key}.chunk_size` must not exceed {}",
+                u32::MAX
+            );
+        }
+        Ok(())
+    }
+}
+
+fn validate_compression_level(key: &str, level: Option<i32>) -> anyhow::Result<()> {
+    let range = zstd::compression_level_range();
+    match level {
+        Some(level) if !range.contains(&level) => bail!(
+            "`{key}` must be between {} and {}, or \"none\"",
+            range.start(),
+            range.end()
+        ),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = ServerConfig::from_toml(
+            r#"
+            bind = "127.0.0.1:9000"
+            admin_bind = "127.0.0.1:9001"
+            backend = "mem"
+
+            [storage]
+            chunk_size = 1024
+            compression_level = "none"
+
+            [usecases.attachments]
+            ttl = 3600
+
+            [usecases.debug-files]
+            compression_level = 19
+            chunking = "content-defined"
+            max_object_size = 1048576
+            dedup = "usecase"
+            hash_algorithm = "sha1"
+
+            [usecases.debug-files.hard_quota]
+            objects = 100
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.bind, SocketAddr::from(([127, 0, 0, 1], 9000)));
+        assert_eq!(
+            config.admin_bind,
+            Some(SocketAddr::from(([127, 0, 0, 1], 9001)))
+        );
+        assert_eq!(config.backend, Backend::Mem);
+        assert_eq!(config.storage.chunk_size, 1024);
+        assert_eq!(config.storage.inline_size, Config::default().inline_size);
+        assert_eq!(config.storage.compression_level, None);
+
+        let policy = config.usecase_policy("default").unwrap();
+        assert_eq!(policy, Policy::from_config(&config.storage));
+        let policy = config.usecase_policy("attachments").unwrap();
+        assert_eq!((policy.ttl, policy.compression_level), (Some(3600), None));
+        assert_eq!(policy.chunk_size, 1024);
+        let policy = config.usecase_policy("debug-files").unwrap();
+        assert_eq!(
+            policy,
+            Policy {
+                ttl: None,
+                tti: None,
+                chunking: Chunking::ContentDefined,
+                chunk_size: 1024,
+                compression_level: Some(19),
+                max_object_size: Some(1048576),
+                dedup: DedupScope::Usecase,
+                hash_algorithm: HashAlgorithm::Sha1,
+                hard_quota: Quota {
+                    objects: Some(100),
+                    ..Default::default()
+                },
+                soft_quota: Quota::default(),
+            }
+        );
+        assert!(config.usecase_policy("unknown").is_none());
+
+        let policies = config.usecase_policies();
+        let names: Vec<_> = policies.keys().copied().collect();
+        assert_eq!(names, ["attachments", "debug-files", "default"]);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let err = ServerConfig::from_toml("chunk_size = 1024").unwrap_err();
+        assert!(err.to_string().contains("unknown field"), "{err}");
+
+        let err = ServerConfig::from_toml("[storage]\ncompression_level = \"fast\"").unwrap_err();
+        assert!(
+            err.to_string().contains("invalid compression level"),
+            "{err}"
+        );
+
+        let invalid = [
+            "backend = \"mem\"\ndata_dir = \"/tmp\"",
+            "[storage]\nchunk_size = 0",
+            "[storage]\nsegment_size = 4294967295",
+            "[storage]\ncompression_level = 100",
+            "[usecases.attachments]\nttl = 0",
+            "[usecases.attachments]\ntti = 0",
+            "[usecases.attachments]\nmax_object_size = 0",
+            "[usecases.attachments]\nchunking = \"content-defined\"\nchunk_size = 512",
+            "[storage]\nchunking = \"content-defined\"\nchunk_size = 33554432",
+            "log_level = \"kycok=loud\"",
+            "lifecycle_interval = 0",
+            "[usecases.attachments]\ncompression_level = -1000000",
+            "public_url = \"ftp://localhost\"",
+            "grpc_bind = \"127.0.0.1:50051\"",
+            "sentry_bind = \"127.0.0.1:8081\"",
+        ];
+        for contents in invalid {
+            let config = ServerConfig::from_toml(contents).unwrap();
+            assert!(config.validate().is_err(), "{contents}");
+        }
+    }
+}
@@ -1,12 +1,13 @@
 use std::pin::pin;
 
-use futures_util::{Stream, StreamExt};
+use futures_util::StreamExt;
 use tokio::io::AsyncRead;
 
 use crate::blobstore::{BlobId, BlobStore};
 use crate::chunker::{chunk_stream, ChunkingStrategy};
 use crate::metastore::{ChunkType, Compression, Metadata};
 
+#[allow(dead_code)]
 pub struct MetaId([u8; 32]);
 
 #[derive(Debug)]
@@ -16,6 +17,7 @@ pub struct FileStore<B> {
 
 pub use anyhow::Error;
 
+#[allow(dead_code)]
 struct StoredChunk {
     chunk_len: usize,
     chunk_meta: Metadata,
@@ -58,7 +60,7 @@ impl<B: BlobStore> FileStore<B> {
         let file_hash = file_hash.finalize();
         let file_hash = *file_hash.as_bytes();
 
-        let (metadata, blob_id) = if stored_chunks.len() == 1 {
+        let (metadata, _blob_id) = if stored_chunks.len() == 1 {
             (
                 Metadata {
                     chunk_type: ChunkType::SingleChunkFile,
@@ -83,7 +85,7 @@ impl<B: BlobStore> FileStore<B> {
         Ok(metadata)
     }
 
-    async fn store_blob(&self, metadata: &Metadata, blob: Vec<u8>) -> Result<BlobId, Error> {
+    async fn store_blob(&self, _metadata: &Metadata, blob: Vec<u8>) -> Result<BlobId, Error> {
         let blob_id = self.blobstore.allocate_id().await?;
 
         self.blobstore.store_blob(blob_id, blob).await?;
@@ -92,6 +94,6 @@ impl<B: BlobStore> FileStore<B> {
     }
 }
 
-fn create_chunk_index(stored_chunks: Vec<StoredChunk>) -> Vec<u8> {
+fn create_chunk_index(_stored_chunks: Vec<StoredChunk>) -> Vec<u8> {
     vec![]
 }
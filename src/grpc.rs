@@ -0,0 +1,189 @@
+//! The gRPC metadata service of `proto/storage.proto`, served on the separate `grpc_bind`
+//! listener.
+//!
+//! Blob contents are not transferred over gRPC. Instead, the service returns URLs signed for
+//! a single blob, which clients use to upload and download it through the S3 API.
+//!
+//! The service is unauthenticated, so it should only be reachable by trusted clients.
+
+use std::time::SystemTime;
+
+use axum::http::Method;
+use tonic::{Request, Response, Status};
+
+use crate::new_datamodel::{bucket, file, Error};
+use crate::server::{bucket_filestore, AppStateRef};
+use crate::{signed_url, sigv4};
+
+pub mod proto {
+    tonic::include_proto!("kycok.v1");
+}
+
+use proto::storage_server::{Storage, StorageServer};
+use proto::{
+    AllocateBlobRequest, AllocateBlobResponse, AssembleFromPartsRequest, AssembleFromPartsResponse,
+    GetBlobLocationRequest, GetBlobLocationResponse, Scope, StorageId,
+};
+
+pub(crate) fn router(state: AppStateRef) -> axum::Router {
+    tonic::service::Routes::new(StorageServer::new(Service { state })).into_axum_router()
+}
+
+struct Service {
+    state: AppStateRef,
+}
+
+#[tonic::async_trait]
+impl Storage for Service {
+    #[tracing::instrument(skip_all)]
+    async fn allocate_blob(
+        &self,
+        request: Request<AllocateBlobRequest>,
+    ) -> Result<Response<AllocateBlobResponse>, Status> {
+        let request = request.into_inner();
+        let bucket = self.scoped_bucket(request.scope)?;
+        let filestore = bucket_filestore(&self.state, &bucket);
+        let key = match request.id {
+            Some(id) => {
+                let key = parse_id(id)?;
+                filestore.reserve_filename(&key);
+                key
+            }
+            None => filestore.allocate_filename(file::IdFormat::Uuid),
+        };
+
+        let signed_put_url = self.signed_url(&Method::PUT, &bucket, &key);
+        Ok(Response::new(AllocateBlobResponse {
+            id: Some(StorageId { id: key.into() }),
+            signed_put_url,
+        }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_blob_location(
+        &self,
+        request: Request<GetBlobLocationRequest>,
+    ) -> Result<Response<GetBlobLocationResponse>, Status> {
+        let request = request.into_inner();
+        let bucket = self.scoped_bucket(request.scope)?;
+        let key = parse_id(
+            request
+                .id
+                .ok_or_else(|| Status::invalid_argument("missing id"))?,
+        )?;
+
+        let filestore = bucket_filestore(&self.state, &bucket);
+        if filestore.resolve_filename(&key).is_none() {
+            return Err(Status::not_found("no such blob"));
+        }
+
+        let signed_get_url = self.signed_url(&Method::GET, &bucket, &key);
+        Ok(Response::new(GetBlobLocationResponse { signed_get_url }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn assemble_from_parts(
+        &self,
+        request: Request<AssembleFromPartsRequest>,
+    ) -> Result<Response<AssembleFromPartsResponse>, Status> {
+        let request = request.into_inner();
+        let bucket = self.scoped_bucket(request.scope)?;
+        if request.parts.is_empty() {
+            return Err(Status::invalid_argument("missing parts"));
+        }
+
+        let filestore = bucket_filestore(&self.state, &bucket);
+        let key = match request.id {
+            Some(id) => parse_id(id)?,
+            None => filestore.allocate_filename(file::IdFormat::Uuid),
+        };
+        let mut contents = vec![];
+        for part in request.parts {
+            let part = parse_id(part)?;
+            let Some(file_id) = filestore.resolve_filename(&part) else {
+                return Err(Status::not_found(format!("no such part `{part}`")));
+            };
+            contents.extend_from_slice(&filestore.read_file(file_id));
+        }
+        if let Some(max_object_size) = filestore.config().max_object_size {
+            if contents.len() as u64 > max_object_size {
+                return Err(Status::invalid_argument(
+                    "the assembled blob exceeds the maximum object size",
+                ));
+            }
+        }
+        let file_id = filestore.upload_file(&contents);
+        if let Err(err) = filestore.associate_filename_if(file_id, &key, &Default::default()) {
+            filestore.discard_file(file_id);
+            return Err(match err {
+                Error::QuotaExceeded(_) => Status::resource_exhausted(err.to_string()),
+                err => Status::internal(err.to_string()),
+            });
+        }
+
+        let signed_get_url = self.signed_url(&Method::GET, &bucket, &key);
+        Ok(Response::new(AssembleFromPartsResponse {
+            signed_get_url,
+            id: Some(StorageId { id: key.into() }),
+        }))
+    }
+}
+
+impl Service {
+    /// The bucket of the usecase and scope, which is created on first use.
+    fn scoped_bucket(&self, scope: Option<Scope>) -> Result<bucket::Bucket, Status> {
+        let Scope { usecase, scope } =
+            scope.ok_or_else(|| Status::invalid_argument("missing scope"))?;
+        let name = bucket::scoped_name(&usecase, &scope);
+        let filestore = &self.state.filestore;
+
+        let bucket = match filestore.get_bucket(&name) {
+            Some(bucket) => bucket,
+            None => {
+                if !bucket::is_valid_name(&name) {
+                    return Err(Status::invalid_argument("invalid usecase or scope"));
+                }
+                let Some(registered) = filestore.get_usecase(&usecase) else {
+                    return Err(Status::invalid_argument("unknown usecase"));
+                };
+                let settings = bucket::BucketSettings::from_policy(&registered.policy);
+                match filestore.create_bucket(&name, &usecase, &scope, settings) {
+                    Ok(bucket) => bucket,
+                    // created concurrently
+                    Err(Error::BucketAlreadyExists) => filestore
+                        .get_bucket(&name)
+                        .ok_or_else(|| Status::unavailable("bucket was deleted concurrently"))?,
+                    Err(err) => return Err(Status::internal(err.to_string())),
+                }
+            }
+        };
+        // the bucket might have been created through the S3 API
+        if bucket.usecase != usecase || bucket.scope != scope {
+            return Err(Status::failed_precondition(format!(
+                "bucket `{name}` belongs to a different usecase or scope"
+            )));
+        }
+        Ok(bucket)
+    }
+
+    fn signed_url(&self, method: &Method, bucket: &bucket::Bucket, key: &str) -> String {
+        let expires = SystemTime::now() + signed_url::DEFAULT_TTL;
+        let signature = self
+            .state
+            .url_signer
+            .sign(method, bucket.namespace.0, key, expires);
+        let encoded_key = sigv4::uri_encode(key.as_bytes(), false);
+        format!(
+            "{}/{}/{encoded_key}?{signature}",
+            self.state.config.public_url(),
+            bucket.name
+        )
+    }
+}
+
+fn parse_id(id: StorageId) -> Result<String, Status> {
+    match String::from_utf8(id.id) {
+        Ok(id) if !id.is_empty() => Ok(id),
+        _ => Err(Status::invalid_argument("invalid id")),
+    }
+}
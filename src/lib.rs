@@ -2,9 +2,18 @@
 //     let cdc = cdc::make_stream(source);
 // }
 
+pub mod admin;
+pub mod aws_chunked;
 pub mod backend;
 pub mod blobstore;
 pub mod chunker;
+pub mod client;
+pub mod config;
 pub mod filestore;
+pub mod grpc;
 pub mod metastore;
 pub mod new_datamodel;
+pub mod sentry;
+pub mod server;
+pub mod signed_url;
+pub mod sigv4;
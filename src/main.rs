@@ -1,167 +1,139 @@
-use std::sync::{Arc, RwLock};
-
-use axum::body::{to_bytes, Body};
-use axum::extract::State;
-use axum::handler::Handler;
-use axum::http::{Method, Response, StatusCode, Uri};
-use axum::response::IntoResponse;
-// use kycok::new_datamodel::mem_impl::{FileStore, Namespace};
-use kycok::new_datamodel::fjall_impl::{FileStore, Namespace};
-
-// type FileStoreState = Arc<RwLock<FileStore>>;
-type FileStoreState = Arc<FileStore>;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Parser;
+use kycok::config::{Backend, LogFormat, ServerConfig};
+use kycok::server::Listeners;
+use tokio::net::TcpListener;
+use tracing_subscriber::EnvFilter;
+
+/// An S3 compatible storage server.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// The TOML config file.
+    #[arg(short, long, env = "KYCOK_CONFIG")]
+    config: Option<PathBuf>,
+    /// The address to listen on.
+    #[arg(long)]
+    bind: Option<SocketAddr>,
+    /// The address to serve the admin endpoints, like `/metrics`, on.
+    #[arg(long)]
+    admin_bind: Option<SocketAddr>,
+    /// The address to serve the gRPC metadata service on.
+    #[arg(long)]
+    grpc_bind: Option<SocketAddr>,
+    /// The address to serve the Sentry chunk upload endpoints on.
+    #[arg(long)]
+    sentry_bind: Option<SocketAddr>,
+    /// The directory to persist data in.
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// The storage backend to use.
+    #[arg(long)]
+    backend: Option<Backend>,
+    /// The auth config file, requiring all requests to be signed.
+    #[arg(long, env = "KYCOK_AUTH_CONFIG")]
+    auth_config: Option<PathBuf>,
+    /// The format of the log output.
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+}
 
 #[tokio::main]
-async fn main() {
-    // let app = Router::new().route("/{bucket}/{*path}", get(download_file).put(upload_file));
-
-    let filestore = FileStoreState::default();
-    let app = app.with_state(filestore).into_make_service();
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let mut config = match &args.config {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::default(),
+    };
+    if let Some(bind) = args.bind {
+        config.bind = bind;
+    }
+    if let Some(admin_bind) = args.admin_bind {
+        config.admin_bind = Some(admin_bind);
+    }
+    if let Some(grpc_bind) = args.grpc_bind {
+        config.grpc_bind = Some(grpc_bind);
+    }
+    if let Some(sentry_bind) = args.sentry_bind {
+        config.sentry_bind = Some(sentry_bind);
+    }
+    if let Some(data_dir) = args.data_dir {
+        config.data_dir = Some(data_dir);
+    }
+    if let Some(backend) = args.backend {
+        config.backend = backend;
+    }
+    if let Some(auth_config) = args.auth_config {
+        config.auth_config = Some(auth_config);
+    }
+    if let Some(log_format) = args.log_format {
+        config.log_format = log_format;
+    }
+    config.validate().context("invalid config")?;
+    init_logging(&config);
+
+    let listeners = Listeners {
+        s3: bind(config.bind).await?,
+        admin: match config.admin_bind {
+            Some(admin_bind) => Some(bind(admin_bind).await?),
+            None => None,
+        },
+        grpc: match config.grpc_bind {
+            Some(grpc_bind) => Some(bind(grpc_bind).await?),
+            None => None,
+        },
+        sentry: match config.sentry_bind {
+            Some(sentry_bind) => Some(bind(sentry_bind).await?),
+            None => None,
+        },
+    };
+    kycok::server::serve(listeners, config, shutdown_signal()).await
 }
 
-async fn app(
-    State(filestore): State<FileStoreState>,
-    method: Method,
-    uri: Uri,
-    body: Body,
-) -> Response<Body> {
-    let path = uri.path();
-    let mut splits = path.splitn(3, "/");
-    let _ = splits.next();
-
-    match method {
-        Method::GET => {
-            if let Some(query) = uri.query() {
-                if query.starts_with("location") {
-                    return r#"<LocationConstraint>whatever</LocationConstraint>"#.into_response();
-                }
-                if query.starts_with("object-lock") {
-                    return r#"<ObjectLockConfiguration />"#.into_response();
-                }
-                if query.starts_with("versioning") {
-                    return r#"<VersioningConfiguration />"#.into_response();
-                }
-            } else {
-                let namespace = splits.next().unwrap().parse().unwrap();
-                let filestore = FileStore::with_namespace(&filestore, Namespace(namespace));
-                let path = splits.next().unwrap();
-
-                let file = filestore.read_named_file(path);
-
-                return ([("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT")], file)
-                    .into_response();
-            }
-        }
-        Method::HEAD => return ().into_response(),
-        Method::PUT => {
-            let bytes = to_bytes(body, usize::MAX).await.unwrap();
-
-            let namespace = splits.next().unwrap().parse().unwrap();
-            let filestore = FileStore::with_namespace(&filestore, Namespace(namespace));
-            let path = splits.next().unwrap();
-
-            let file_id = filestore.upload_file(&bytes);
-            filestore.associate_filename(file_id, path);
+async fn bind(addr: SocketAddr) -> anyhow::Result<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind to `{addr}`"))
+}
 
-            return ().into_response();
-        }
-        _ => {}
+fn init_logging(config: &ServerConfig) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.log_level));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        LogFormat::Pretty => subscriber.pretty().init(),
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
     }
-
-    dbg!((&method, &uri));
-    StatusCode::BAD_REQUEST.into_response()
 }
 
-// async fn upload_file(
-//     Path(bucket): Path<u64>,
-//     Path(path): Path<String>,
-//     body: Body,
-// ) -> &'static str {
-//     "OK"
-// }
-
-// async fn download_file(Path(bucket): Path<u64>, Path(path): Path<String>, body: Body) -> Body {
-//     todo!()
-// }
-
-// async fn upload_none(body: BodyStream) -> &'static str {
-//     let chunker = Chunker::new(PrintBackend);
-//     let config = FileConfig {
-//         chunking_strategy: ChunkingStrategy::None,
-//     };
-//     chunker
-//         .save_file(&config, &(), make_read(body))
-//         .await
-//         .unwrap();
-
-//     "OK"
-// }
-
-// async fn upload_fixed(Path(chunk_size): Path<u32>, body: BodyStream) -> &'static str {
-//     let chunker = Chunker::new(PrintBackend);
-//     let config = FileConfig {
-//         chunking_strategy: ChunkingStrategy::Fixed(chunk_size * 1024),
-//     };
-//     chunker
-//         .save_file(&config, &(), make_read(body))
-//         .await
-//         .unwrap();
-
-//     "OK"
-// }
-
-// async fn upload_cdc(body: BodyStream) -> &'static str {
-//     let chunker = Chunker::new(PrintBackend);
-//     let config = FileConfig {
-//         chunking_strategy: Default::default(),
-//     };
-//     chunker
-//         .save_file(&config, &(), make_read(body))
-//         .await
-//         .unwrap();
-
-//     "OK"
-// }
-
-// fn make_read(body: BodyStream) -> impl AsyncRead {
-//     let stream = body
-//         .map(|result| result.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)));
-//     StreamReader::new(stream)
-// }
-
-// struct PrintBackend;
-
-// #[async_trait::async_trait]
-// impl Backend for PrintBackend {
-//     type Context = ();
-//     type StoredChunk = u64;
-//     type FileId = ();
-//     type Error = std::io::Error;
-
-//     async fn store_chunk(&self, _ctx: &Self::Context, chunk: Chunk) -> Result<u64, Self::Error> {
-//         let data = &chunk.data;
-//         let compressed = zstd::bulk::compress(data, 1).unwrap();
-
-//         let ratio = compressed.len() as f32 / data.len() as f32;
-//         println!("{}", chunk.hash.to_hex());
-//         println!("{} / {} ({:.2}x)", data.len(), compressed.len(), ratio);
-
-//         Ok(compressed.len() as u64)
-//     }
-
-//     async fn store_file(&self, _ctx: &Self::Context, file: File<u64>) -> Result<(), Self::Error> {
-//         println!("----");
-//         println!("{}", file.hash.to_hex());
-
-//         let total_size = file.len;
-//         let compressed_size: u64 = file.chunks.iter().map(|c| c.1).sum();
-//         let ratio = compressed_size as f32 / total_size as f32;
-//         println!("{total_size} / {compressed_size} / {:.2}x", ratio);
-//         println!("====");
-
-//         Ok(())
-//     }
-// }
+/// Completes on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install the SIGINT handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutting down");
+}
@@ -1,118 +1,773 @@
-use core::fmt;
 use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 
-use fjall::{TransactionalKeyspace, TransactionalPartitionHandle};
+use fjall::{PersistMode, TransactionalKeyspace, TransactionalPartitionHandle, WriteTransaction};
 use tempfile::TempDir;
 
 use super::*;
 
-#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
-pub struct Namespace(pub u64);
-impl fmt::Debug for Namespace {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Namespace({})", self.0)
-    }
-}
-
-#[derive(Default)]
-pub struct Segment(Vec<u8>);
-impl fmt::Debug for Segment {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let len = self.0.len().min(64);
-        let printed = String::from_utf8_lossy(&self.0[..len]);
-
-        write!(
-            f,
-            "Segment({printed:?}{})",
-            if len < self.0.len() { "…" } else { "" }
-        )
-    }
+/// The segment file chunks are currently appended to.
+struct ActiveSegment {
+    segment_id: segment::SegmentId,
+    file: std::fs::File,
+    len: u64,
 }
 
+#[allow(dead_code)]
 pub struct FileStore {
-    tempdir: TempDir,
     database: TransactionalKeyspace,
     chunks: TransactionalPartitionHandle,
     files: TransactionalPartitionHandle,
     named_files: TransactionalPartitionHandle,
+    refcounts: TransactionalPartitionHandle,
+    segment_refcounts: TransactionalPartitionHandle,
+    buckets: TransactionalPartitionHandle,
+    usecases: TransactionalPartitionHandle,
+    /// The `quota::Usage` of each `(usecase, scope)`.
+    scope_usage: TransactionalPartitionHandle,
+    /// The `gc::ChunkRef`s of chunks uploaded ahead of assembling them into files.
+    chunk_refs: TransactionalPartitionHandle,
+    /// The `gc::Timestamp` until which each allocated or reserved name is kept from being
+    /// allocated again.
+    reservations: TransactionalPartitionHandle,
+    /// The noncurrent `file::Version`s and delete markers of names in versioned buckets, keyed
+    /// by `(namespace, name, version_id)`.
+    versions: TransactionalPartitionHandle,
+    /// Holds global state, like the last allocated `Namespace`.
+    metadata: TransactionalPartitionHandle,
 
-    segments: Mutex<HashMap<segment::SegmentId, Segment>>,
-    last_segment: Mutex<Option<segment::SegmentId>>,
-
-    namespaced_refcounts: HashMap<(Namespace, refcounts::ReferenceCountType), u32>,
-    segment_refcounts: HashMap<segment::SegmentId, u32>,
+    /// Holds one file per segment, named after its id.
+    segments_dir: PathBuf,
+    segment_count: AtomicUsize,
+    last_segment: Mutex<Option<ActiveSegment>>,
 
-    chunk_refs: HashMap<(Namespace, chunk::ChunkId), gc::ChunkRef>,
     file_refs: HashMap<(Namespace, String), gc::FileReference>,
+
+    /// Declared last, so that it is removed only after the database is closed.
+    tempdir: Option<TempDir>,
 }
 
 impl FileStore {
+    /// Creates a store in a temporary directory, which is removed on drop.
     pub fn new() -> Self {
         let tempdir = tempfile::tempdir().unwrap();
-        let database = fjall::Config::new(&tempdir).open_transactional().unwrap();
-        let chunks = database
-            .open_partition("chunks", Default::default())
-            .unwrap();
-        let files = database
-            .open_partition("files", Default::default())
-            .unwrap();
-        let named_files = database
-            .open_partition("named_files", Default::default())
-            .unwrap();
+        let mut filestore = Self::open(tempdir.path()).unwrap();
+        filestore.tempdir = Some(tempdir);
+        filestore
+    }
+
+    /// Opens the store persisted in the `path` directory, creating it if necessary.
+    ///
+    /// If the store was not shut down cleanly, the segment that was last appended to is
+    /// repaired first.
+    pub fn open(path: &Path) -> fjall::Result<Self> {
+        let segments_dir = path.join("segments");
+        std::fs::create_dir_all(&segments_dir)?;
 
-        Self {
-            tempdir,
+        let database = fjall::Config::new(path).open_transactional()?;
+        let chunks = database.open_partition("chunks", Default::default())?;
+        let files = database.open_partition("files", Default::default())?;
+        let named_files = database.open_partition("named_files", Default::default())?;
+        let refcounts = database.open_partition("refcounts", Default::default())?;
+        let segment_refcounts = database.open_partition("segment_refcounts", Default::default())?;
+        let buckets = database.open_partition("buckets", Default::default())?;
+        let usecases = database.open_partition("usecases", Default::default())?;
+        let scope_usage = database.open_partition("scope_usage", Default::default())?;
+        let chunk_refs = database.open_partition("chunk_refs", Default::default())?;
+        let reservations = database.open_partition("reservations", Default::default())?;
+        let versions = database.open_partition("versions", Default::default())?;
+        let metadata = database.open_partition("metadata", Default::default())?;
+
+        let filestore = Self {
             database,
             chunks,
             files,
             named_files,
+            refcounts,
+            segment_refcounts,
+            buckets,
+            usecases,
+            scope_usage,
+            chunk_refs,
+            reservations,
+            versions,
+            metadata,
 
-            segments: Default::default(),
+            segments_dir,
+            segment_count: Default::default(),
             last_segment: Default::default(),
-            namespaced_refcounts: Default::default(),
-            segment_refcounts: Default::default(),
-            chunk_refs: Default::default(),
             file_refs: Default::default(),
-        }
+
+            tempdir: None,
+        };
+        filestore.repair_active_segment()?;
+
+        let segment_count = std::fs::read_dir(&filestore.segments_dir)?.count();
+        filestore
+            .segment_count
+            .store(segment_count, Ordering::Relaxed);
+        stats::segments(segment_count);
+        Ok(filestore)
     }
 
-    pub fn with_namespace(slf: &FileStore, namespace: Namespace) -> NamespacedFileStore {
+    pub fn with_namespace(slf: &FileStore, namespace: Namespace) -> NamespacedFileStore<'_> {
         NamespacedFileStore {
             filestore: slf,
             config: Config::default(),
             namespace,
+            content_namespace: namespace,
+            account: None,
+            versioned: false,
+            default_retention: None,
         }
     }
-}
-impl Default for FileStore {
-    fn default() -> Self {
-        Self::new()
+
+    /// Seals the segment currently being appended to, and persists the keyspace.
+    ///
+    /// This should be called once no more writes are in flight, right before shutting down.
+    pub fn shutdown(&self) -> fjall::Result<()> {
+        if let Some(segment) = self.last_segment.lock().unwrap().take() {
+            self.seal_segment(segment)?;
+        }
+        self.database.persist(PersistMode::SyncAll)
+    }
+
+    /// The space left on the disk holding the segments.
+    pub fn available_space(&self) -> io::Result<u64> {
+        fs4::available_space(&self.segments_dir)
+    }
+
+    /// Sums up the storage used by each namespace.
+    pub fn usage(&self) -> Vec<report::NamespaceUsage> {
+        let read_tx = self.database.read_tx();
+        let files = read_tx.iter(&self.files).map(|entry| {
+            let (key, file) = entry.unwrap();
+            let (namespace, _): (Namespace, file::FileId) = postcard::from_bytes(&key).unwrap();
+            (namespace, postcard::from_bytes(&file).unwrap())
+        });
+        let chunks = read_tx.iter(&self.chunks).map(|entry| {
+            let (key, chunk) = entry.unwrap();
+            let (namespace, _): (Namespace, chunk::ChunkId) = postcard::from_bytes(&key).unwrap();
+            (namespace, postcard::from_bytes(&chunk).unwrap())
+        });
+        report::NamespaceUsage::collect(files, chunks)
+    }
+
+    /// Releases expired chunk references, and removes segments which no chunk references
+    /// anymore, and which were not freed right away, like a segment whose chunks were all
+    /// released while it was still being appended to.
+    ///
+    /// Segments modified within the [`GC_GRACE_PERIOD`] are skipped, as chunks written to
+    /// them might not be committed yet.
+    #[tracing::instrument(level = "info", skip_all)]
+    pub fn collect_garbage(&self) -> io::Result<report::GcReport> {
+        let mut report = report::GcReport::default();
+        let now = SystemTime::now();
+        report.chunk_refs_expired = self.release_expired_chunk_refs(now);
+        report.reservations_expired = self.remove_expired_reservations(now);
+
+        for entry in std::fs::read_dir(&self.segments_dir)? {
+            let entry = entry?;
+            let Some(uuid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| uuid::Uuid::try_parse(name).ok())
+            else {
+                continue;
+            };
+            let segment_id = segment::SegmentId {
+                uuid: uuid.into_bytes(),
+            };
+            let metadata = entry.metadata()?;
+            let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+            if age < GC_GRACE_PERIOD {
+                continue;
+            }
+
+            let last_segment = self.last_segment.lock().unwrap();
+            if last_segment.as_ref().map(|segment| segment.segment_id) == Some(segment_id) {
+                continue;
+            }
+            let key = postcard::to_stdvec(&segment_id).unwrap();
+            if self
+                .segment_refcounts
+                .contains_key(key)
+                .map_err(io::Error::other)?
+            {
+                continue;
+            }
+            std::fs::remove_file(entry.path())?;
+            drop(last_segment);
+
+            report.segments_removed += 1;
+            report.bytes_freed += metadata.len();
+        }
+
+        let removed = report.segments_removed as usize;
+        stats::segments_collected(removed);
+        stats::segments(self.segment_count.fetch_sub(removed, Ordering::Relaxed) - removed);
+        stats::gc_finished();
+        tracing::info!(?report, "garbage collection finished");
+        Ok(report)
+    }
+
+    /// Releases the references of chunks which were not assembled into files in time,
+    /// returning how many were released.
+    fn release_expired_chunk_refs(&self, now: SystemTime) -> u64 {
+        let now = gc::Timestamp::at(now);
+        let expired: Vec<_> = self
+            .database
+            .read_tx()
+            .iter(&self.chunk_refs)
+            .filter_map(|entry| {
+                let (key, chunk_ref) = entry.unwrap();
+                let chunk_ref: gc::ChunkRef = postcard::from_bytes(&chunk_ref).unwrap();
+                (chunk_ref.expires <= now).then_some(key)
+            })
+            .collect();
+
+        let mut released = 0;
+        for key in expired {
+            loop {
+                let mut write_tx = self.database.write_tx().unwrap();
+                // the chunk might have been uploaded again in the meantime
+                let Some(chunk_ref) = write_tx.get(&self.chunk_refs, &key).unwrap() else {
+                    break;
+                };
+                let chunk_ref: gc::ChunkRef = postcard::from_bytes(&chunk_ref).unwrap();
+                if chunk_ref.expires > now {
+                    break;
+                }
+                let (namespace, chunk_id): (Namespace, chunk::ChunkId) =
+                    postcard::from_bytes(&key).unwrap();
+
+                write_tx.remove(&self.chunk_refs, key.clone());
+                let mut freed_segments = vec![];
+                self.release_chunk(&mut write_tx, namespace, chunk_id, &mut freed_segments);
+                if commit(write_tx).is_ok() {
+                    self.free_segments(&freed_segments);
+                    released += 1;
+                    break;
+                }
+            }
+        }
+        released
+    }
+
+    /// Removes the reservations of names which were not uploaded to in time, returning how
+    /// many were removed.
+    fn remove_expired_reservations(&self, now: SystemTime) -> u64 {
+        let now = gc::Timestamp::at(now);
+        let expired: Vec<_> = self
+            .database
+            .read_tx()
+            .iter(&self.reservations)
+            .filter_map(|entry| {
+                let (key, expires) = entry.unwrap();
+                let expires: gc::Timestamp = postcard::from_bytes(&expires).unwrap();
+                (expires <= now).then_some(key)
+            })
+            .collect();
+
+        let mut removed = 0;
+        for key in expired {
+            loop {
+                let mut write_tx = self.database.write_tx().unwrap();
+                // the name might have been reserved again in the meantime
+                let Some(expires) = write_tx.get(&self.reservations, &key).unwrap() else {
+                    break;
+                };
+                let expires: gc::Timestamp = postcard::from_bytes(&expires).unwrap();
+                if expires > now {
+                    break;
+                }
+                write_tx.remove(&self.reservations, key.clone());
+                if commit(write_tx).is_ok() {
+                    removed += 1;
+                    break;
+                }
+            }
+        }
+        removed
+    }
+
+    /// Releases a reference to the chunk, deleting it once it is no longer referenced.
+    ///
+    /// Segments which are no longer referenced are pushed to `freed_segments`, to be freed once
+    /// the transaction is committed.
+    fn release_chunk(
+        &self,
+        write_tx: &mut WriteTransaction,
+        namespace: Namespace,
+        chunk_id: chunk::ChunkId,
+        freed_segments: &mut Vec<segment::SegmentId>,
+    ) {
+        let key = postcard::to_stdvec(&(namespace, refcounts::ReferenceCountType::Chunk(chunk_id)))
+            .unwrap();
+        if decrement(write_tx, &self.refcounts, key) > 0 {
+            return;
+        }
+        let chunk_key = postcard::to_stdvec(&(namespace, chunk_id)).unwrap();
+        let chunk = write_tx.get(&self.chunks, &chunk_key).unwrap().unwrap();
+        let chunk: chunk::Chunk = postcard::from_bytes(&chunk).unwrap();
+        write_tx.remove(&self.chunks, chunk_key);
+
+        let segment_key = postcard::to_stdvec(&chunk.segment_id).unwrap();
+        if decrement(write_tx, &self.segment_refcounts, segment_key) == 0 {
+            freed_segments.push(chunk.segment_id);
+        }
+    }
+
+    /// Verifies the contents of all stored chunks against their IDs.
+    #[tracing::instrument(level = "info", skip_all)]
+    pub fn scrub(&self) -> report::ScrubReport {
+        let mut report = report::ScrubReport::default();
+        let read_tx = self.database.read_tx();
+        for entry in read_tx.iter(&self.chunks) {
+            let (key, chunk) = entry.unwrap();
+            let (namespace, chunk_id) = postcard::from_bytes(&key).unwrap();
+            let chunk: chunk::Chunk = postcard::from_bytes(&chunk).unwrap();
+
+            let stored = self.read_segment(&chunk);
+            // the chunk might have been released, and its segment removed in the meantime
+            if stored.is_err() && !self.chunks.contains_key(&key).unwrap() {
+                continue;
+            }
+            report.check(namespace, chunk_id, &chunk, stored);
+        }
+        stats::scrub_finished();
+        tracing::info!(
+            chunks_checked = report.chunks_checked,
+            corrupt_chunks = report.corrupt_chunks.len(),
+            "scrub finished"
+        );
+        report
+    }
+
+    /// Reads the chunk contents as stored in its segment.
+    #[tracing::instrument(level = "trace", skip_all, fields(segment_id = ?chunk.segment_id))]
+    fn read_segment(&self, chunk: &chunk::Chunk) -> io::Result<Vec<u8>> {
+        let mut segment = std::fs::File::open(self.segment_path(chunk.segment_id))?;
+        segment.seek(SeekFrom::Start(chunk.offset_in_segment as u64))?;
+        let mut stored = vec![0; chunk.compressed_size as usize];
+        segment.read_exact(&mut stored)?;
+        Ok(stored)
+    }
+
+    fn segment_path(&self, segment_id: segment::SegmentId) -> PathBuf {
+        let name = uuid::Uuid::from_bytes(segment_id.uuid).simple().to_string();
+        self.segments_dir.join(name)
+    }
+
+    /// Appends the chunk contents to the active segment, creating a new one if necessary,
+    /// and returns where they were written to.
+    ///
+    /// The segment is sealed once it grows beyond `segment_size`.
+    #[tracing::instrument(level = "trace", skip_all, fields(len = stored.len()))]
+    fn append_to_segment(&self, stored: &[u8], segment_size: u64) -> (segment::SegmentId, u32) {
+        let mut last_segment = self.last_segment.lock().unwrap();
+        let segment = match &mut *last_segment {
+            Some(segment) => segment,
+            None => last_segment.insert(self.create_segment().unwrap()),
+        };
+
+        let offset_in_segment = segment.len as u32;
+        segment.file.write_all(stored).unwrap();
+        segment.len += stored.len() as u64;
+        let segment_id = segment.segment_id;
+        stats::active_segment(segment.len);
+
+        if segment.len >= segment_size {
+            let segment = last_segment.take().unwrap();
+            self.seal_segment(segment).unwrap();
+        }
+        (segment_id, offset_in_segment)
+    }
+
+    /// Creates a new segment file, recording it as the active segment so that it can be
+    /// repaired in case of a crash.
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn create_segment(&self) -> fjall::Result<ActiveSegment> {
+        let segment_id = segment::SegmentId {
+            uuid: uuid::Uuid::new_v4().into_bytes(),
+        };
+        self.metadata.insert(
+            ACTIVE_SEGMENT_KEY,
+            postcard::to_stdvec(&segment_id).unwrap(),
+        )?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(self.segment_path(segment_id))?;
+        stats::segments(self.segment_count.fetch_add(1, Ordering::Relaxed) + 1);
+        Ok(ActiveSegment {
+            segment_id,
+            file,
+            len: 0,
+        })
+    }
+
+    /// Durably writes the segment, after which it is never appended to again.
+    #[tracing::instrument(level = "debug", skip_all, fields(segment_id = ?segment.segment_id, len = segment.len))]
+    fn seal_segment(&self, segment: ActiveSegment) -> fjall::Result<()> {
+        segment.file.sync_all()?;
+        std::fs::File::open(&self.segments_dir)?.sync_all()?;
+        self.metadata.remove(ACTIVE_SEGMENT_KEY)?;
+        stats::active_segment(0);
+        Ok(())
+    }
+
+    /// Repairs a torn active segment, left behind when the store was not shut down cleanly.
+    ///
+    /// Anything past the end of the last committed chunk was never referenced and is
+    /// truncated, and the segment is removed entirely if it holds no committed chunks.
+    fn repair_active_segment(&self) -> fjall::Result<()> {
+        let Some(segment_id) = self.metadata.get(ACTIVE_SEGMENT_KEY)? else {
+            return Ok(());
+        };
+        let segment_id: segment::SegmentId = postcard::from_bytes(&segment_id).unwrap();
+
+        let mut committed_len = 0;
+        let read_tx = self.database.read_tx();
+        for chunk in read_tx.values(&self.chunks) {
+            let chunk: chunk::Chunk = postcard::from_bytes(&chunk?).unwrap();
+            if chunk.segment_id == segment_id {
+                let end = chunk.offset_in_segment as u64 + chunk.compressed_size as u64;
+                committed_len = committed_len.max(end);
+            }
+        }
+
+        let path = self.segment_path(segment_id);
+        tracing::info!(?segment_id, committed_len, "repairing the active segment");
+        if committed_len == 0 {
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        } else {
+            let file = OpenOptions::new().write(true).open(&path)?;
+            let len = file.metadata()?.len();
+            if len > committed_len {
+                tracing::warn!(?segment_id, len, committed_len, "truncating a torn segment");
+                file.set_len(committed_len)?;
+            } else if len < committed_len {
+                tracing::error!(
+                    ?segment_id,
+                    len,
+                    committed_len,
+                    "segment is missing committed chunks"
+                );
+            }
+            file.sync_all()?;
+        }
+
+        self.metadata.remove(ACTIVE_SEGMENT_KEY)?;
+        self.database.persist(PersistMode::SyncAll)
+    }
+
+    /// Removes segments which are no longer referenced, except for the segment currently
+    /// being appended to.
+    ///
+    /// Chunks are appended to a segment before the transaction referencing them commits, and
+    /// a conflicting transaction is retried with the chunk it already appended. So segments
+    /// modified within the [`GC_GRACE_PERIOD`] are left to [`Self::collect_garbage`], like
+    /// segments which fail to be removed.
+    fn free_segments(&self, segment_ids: &[segment::SegmentId]) {
+        let last_segment = self.last_segment.lock().unwrap();
+        let active_segment = last_segment.as_ref().map(|segment| segment.segment_id);
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for segment_id in segment_ids {
+            if active_segment == Some(*segment_id) {
+                continue;
+            }
+            let path = self.segment_path(*segment_id);
+            let remove = || {
+                let modified = std::fs::metadata(&path)?.modified()?;
+                if now.duration_since(modified).unwrap_or_default() < GC_GRACE_PERIOD {
+                    return Ok(false);
+                }
+                std::fs::remove_file(&path).map(|()| true)
+            };
+            match remove() {
+                Ok(true) => removed += 1,
+                Ok(false) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => tracing::error!(
+                    ?segment_id,
+                    error = &err as &dyn std::error::Error,
+                    "failed to remove a freed segment"
+                ),
+            }
+        }
+        stats::segments_collected(removed);
+        stats::segments(self.segment_count.fetch_sub(removed, Ordering::Relaxed) - removed);
+    }
+
+    /// Creates a new bucket, allocating a fresh `Namespace` for it.
+    ///
+    /// Namespaces are never reused, so a re-created bucket does not see any leftovers of a
+    /// previous bucket with the same name.
+    #[tracing::instrument(level = "debug", skip(self, settings))]
+    pub fn create_bucket(
+        &self,
+        name: &str,
+        usecase: &str,
+        scope: &str,
+        settings: bucket::BucketSettings,
+    ) -> Result<bucket::Bucket, Error> {
+        let key = postcard::to_stdvec(name).unwrap();
+        loop {
+            let mut write_tx = self.database.write_tx().unwrap();
+            if write_tx.contains_key(&self.buckets, &key).unwrap() {
+                return Err(Error::BucketAlreadyExists);
+            }
+
+            let namespace = self.allocate_namespace(&mut write_tx);
+            let bucket = bucket::Bucket {
+                name: name.into(),
+                namespace,
+                usecase: usecase.into(),
+                scope: scope.into(),
+                created: unix_timestamp(),
+                settings: settings.clone(),
+            };
+            write_tx.insert(&self.buckets, &key, postcard::to_stdvec(&bucket).unwrap());
+            if commit(write_tx).is_ok() {
+                return Ok(bucket);
+            }
+        }
+    }
+
+    pub fn get_bucket(&self, name: &str) -> Option<bucket::Bucket> {
+        let key = postcard::to_stdvec(name).unwrap();
+
+        let bucket = self.buckets.get(key).unwrap()?;
+        Some(postcard::from_bytes(&bucket).unwrap())
+    }
+
+    pub fn list_buckets(&self) -> Vec<bucket::Bucket> {
+        let read_tx = self.database.read_tx();
+        let mut buckets: Vec<bucket::Bucket> = read_tx
+            .values(&self.buckets)
+            .map(|bucket| postcard::from_bytes(&bucket.unwrap()).unwrap())
+            .collect();
+        buckets.sort_by(|a, b| a.name.cmp(&b.name));
+        buckets
+    }
+
+    /// Deletes the bucket, which has to be empty.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn delete_bucket(&self, name: &str) -> Result<(), Error> {
+        let key = postcard::to_stdvec(name).unwrap();
+        loop {
+            let mut write_tx = self.database.write_tx().unwrap();
+            let bucket = write_tx
+                .get(&self.buckets, &key)
+                .unwrap()
+                .ok_or(Error::NoSuchBucket)?;
+            let bucket: bucket::Bucket = postcard::from_bytes(&bucket).unwrap();
+
+            // the namespace is the prefix of all the `named_files` and `versions` keys of the bucket
+            let prefix = postcard::to_stdvec(&bucket.namespace).unwrap();
+            if write_tx.prefix(&self.named_files, &prefix).next().is_some()
+                || write_tx.prefix(&self.versions, prefix).next().is_some()
+            {
+                return Err(Error::BucketNotEmpty);
+            }
+
+            write_tx.remove(&self.buckets, &key);
+            if commit(write_tx).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Enables versioning of the bucket, which keeps the previous files of overwritten and
+    /// deleted names from then on.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn enable_versioning(&self, name: &str) -> Result<bucket::Bucket, Error> {
+        let key = postcard::to_stdvec(name).unwrap();
+        loop {
+            let mut write_tx = self.database.write_tx().unwrap();
+            let bucket = write_tx
+                .get(&self.buckets, &key)
+                .unwrap()
+                .ok_or(Error::NoSuchBucket)?;
+            let mut bucket: bucket::Bucket = postcard::from_bytes(&bucket).unwrap();
+            bucket.settings.versioned = true;
+            write_tx.insert(&self.buckets, &key, postcard::to_stdvec(&bucket).unwrap());
+            if commit(write_tx).is_ok() {
+                return Ok(bucket);
+            }
+        }
+    }
+
+    /// Enables object lock on the bucket, along with versioning, and sets the retention of
+    /// files written to it from then on.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_object_lock(
+        &self,
+        name: &str,
+        default_retention: Option<bucket::DefaultRetention>,
+    ) -> Result<bucket::Bucket, Error> {
+        let key = postcard::to_stdvec(name).unwrap();
+        loop {
+            let mut write_tx = self.database.write_tx().unwrap();
+            let bucket = write_tx
+                .get(&self.buckets, &key)
+                .unwrap()
+                .ok_or(Error::NoSuchBucket)?;
+            let mut bucket: bucket::Bucket = postcard::from_bytes(&bucket).unwrap();
+            bucket.settings.versioned = true;
+            bucket.settings.object_lock = true;
+            bucket.settings.default_retention = default_retention;
+            write_tx.insert(&self.buckets, &key, postcard::to_stdvec(&bucket).unwrap());
+            if commit(write_tx).is_ok() {
+                return Ok(bucket);
+            }
+        }
+    }
+
+    /// Replaces the lifecycle rules of the bucket.
+    #[tracing::instrument(level = "debug", skip(self, rules), fields(rules = rules.len()))]
+    pub fn set_lifecycle(
+        &self,
+        name: &str,
+        rules: Vec<lifecycle::Rule>,
+    ) -> Result<bucket::Bucket, Error> {
+        let key = postcard::to_stdvec(name).unwrap();
+        loop {
+            let mut write_tx = self.database.write_tx().unwrap();
+            let bucket = write_tx
+                .get(&self.buckets, &key)
+                .unwrap()
+                .ok_or(Error::NoSuchBucket)?;
+            let mut bucket: bucket::Bucket = postcard::from_bytes(&bucket).unwrap();
+            bucket.settings.lifecycle = rules.clone();
+            write_tx.insert(&self.buckets, &key, postcard::to_stdvec(&bucket).unwrap());
+            if commit(write_tx).is_ok() {
+                return Ok(bucket);
+            }
+        }
+    }
+
+    /// Allocates a `Namespace` that was never handed out before.
+    fn allocate_namespace(&self, write_tx: &mut WriteTransaction) -> Namespace {
+        let last_namespace: u64 = write_tx
+            .get(&self.metadata, LAST_NAMESPACE_KEY)
+            .unwrap()
+            .map_or(0, |namespace| postcard::from_bytes(&namespace).unwrap());
+        let namespace = Namespace(last_namespace + 1);
+        let last_namespace = postcard::to_stdvec(&namespace.0).unwrap();
+        write_tx.insert(&self.metadata, LAST_NAMESPACE_KEY, last_namespace);
+        namespace
+    }
+
+    /// Registers the usecase, or updates the policy of an already registered one.
+    ///
+    /// A new usecase gets a fresh `Namespace`, which its buckets share when deduplicating per
+    /// usecase. The dedup scope of a registered usecase cannot change.
+    #[tracing::instrument(level = "debug", skip(self, policy))]
+    pub fn register_usecase(
+        &self,
+        name: &str,
+        policy: usecase::Policy,
+    ) -> Result<usecase::Usecase, Error> {
+        let key = postcard::to_stdvec(name).unwrap();
+
+        loop {
+            let mut write_tx = self.database.write_tx().unwrap();
+            let current = write_tx.get(&self.usecases, &key).unwrap();
+            let namespace = match current {
+                Some(current) => {
+                    let current: usecase::Usecase = postcard::from_bytes(&current).unwrap();
+                    if current.policy.dedup != policy.dedup {
+                        return Err(Error::DedupScopeChanged);
+                    }
+                    current.namespace
+                }
+                None => self.allocate_namespace(&mut write_tx),
+            };
+
+            let usecase = usecase::Usecase {
+                name: name.into(),
+                namespace,
+                policy: policy.clone(),
+            };
+            write_tx.insert(&self.usecases, &key, postcard::to_stdvec(&usecase).unwrap());
+            // a concurrent registration might have allocated the same namespace
+            if commit(write_tx).is_ok() {
+                return Ok(usecase);
+            }
+        }
+    }
+
+    pub fn get_usecase(&self, name: &str) -> Option<usecase::Usecase> {
+        let key = postcard::to_stdvec(name).unwrap();
+
+        let usecase = self.usecases.get(key).unwrap()?;
+        Some(postcard::from_bytes(&usecase).unwrap())
+    }
+
+    pub fn list_usecases(&self) -> Vec<usecase::Usecase> {
+        let read_tx = self.database.read_tx();
+        let mut usecases: Vec<usecase::Usecase> = read_tx
+            .values(&self.usecases)
+            .map(|usecase| postcard::from_bytes(&usecase.unwrap()).unwrap())
+            .collect();
+        usecases.sort_by(|a, b| a.name.cmp(&b.name));
+        usecases
     }
-}
 
-pub struct Config {
-    inline_size: u64,
-    chunk_size: u64,
-    segment_size: u64,
+    /// The named files of each `(usecase, scope)`, sorted by usecase and scope.
+    pub fn scope_usage(&self) -> Vec<quota::ScopeUsage> {
+        let read_tx = self.database.read_tx();
+        read_tx
+            .iter(&self.scope_usage)
+            .map(|kv| {
+                let (key, usage) = kv.unwrap();
+                let (usecase, scope) = postcard::from_bytes(&key).unwrap();
+                quota::ScopeUsage {
+                    usecase,
+                    scope,
+                    usage: postcard::from_bytes(&usage).unwrap(),
+                }
+            })
+            .collect()
+    }
 }
 
-impl Default for Config {
+const LAST_NAMESPACE_KEY: &str = "last_namespace";
+/// The segment currently being appended to, which is removed once the segment is sealed.
+const ACTIVE_SEGMENT_KEY: &str = "active_segment";
+/// How long a segment has to be left untouched before garbage collection may remove it.
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(60);
+impl Default for FileStore {
     fn default() -> Self {
-        const MEG: u64 = 2 ^ 20;
-        const GIG: u64 = 2 ^ 30;
-        Self {
-            inline_size: 256,
-            chunk_size: 8 * MEG,
-            segment_size: GIG,
-        }
+        Self::new()
     }
 }
 
 pub struct NamespacedFileStore<'fs> {
     filestore: &'fs FileStore,
     config: Config,
+    /// Holds the names.
     namespace: Namespace,
+    /// Holds the files and chunks the names point to.
+    content_namespace: Namespace,
+    /// The scope named files are accounted to.
+    account: Option<quota::Account>,
+    /// Whether names keep their previous files as versions.
+    versioned: bool,
+    /// The retention of newly written files.
+    default_retention: Option<bucket::DefaultRetention>,
 }
 
 impl NamespacedFileStore<'_> {
@@ -121,66 +776,316 @@ impl NamespacedFileStore<'_> {
         self
     }
 
-    // fn addref(&mut self, ty: refcounts::ReferenceCountType) {}
+    /// Stores the files and chunks in a separate namespace, which can be shared with other
+    /// namespaces to deduplicate contents across them.
+    pub fn with_content_namespace(mut self, content_namespace: Namespace) -> Self {
+        self.content_namespace = content_namespace;
+        self
+    }
+
+    /// Accounts the named files to the scope of `account`, enforcing its quotas.
+    pub fn with_account(mut self, account: quota::Account) -> Self {
+        self.account = Some(account);
+        self
+    }
+
+    /// Keeps the previous file of names which are overwritten or deleted as a version.
+    pub fn with_versioning(mut self, versioned: bool) -> Self {
+        self.versioned = versioned;
+        self
+    }
+
+    /// Retains newly written files according to `default_retention`.
+    pub fn with_default_retention(
+        mut self,
+        default_retention: Option<bucket::DefaultRetention>,
+    ) -> Self {
+        self.default_retention = default_retention;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Increments the reference count of `ty` within the namespace.
+    fn addref(&self, write_tx: &mut WriteTransaction, ty: refcounts::ReferenceCountType) {
+        let key = postcard::to_stdvec(&(self.content_namespace, ty)).unwrap();
+        increment(write_tx, &self.filestore.refcounts, key);
+    }
+
+    /// Increments the reference count of the segment, which is shared by all namespaces.
+    fn addref_segment(&self, write_tx: &mut WriteTransaction, segment_id: segment::SegmentId) {
+        let key = postcard::to_stdvec(&segment_id).unwrap();
+        increment(write_tx, &self.filestore.segment_refcounts, key);
+    }
+
+    /// Returns the current reference count of `ty`.
+    pub fn refcount(&self, ty: refcounts::ReferenceCountType) -> u32 {
+        let read_tx = self.filestore.database.read_tx();
+        let refcount = match ty {
+            refcounts::ReferenceCountType::Segment(segment_id) => {
+                let key = postcard::to_stdvec(&segment_id).unwrap();
+                read_tx.get(&self.filestore.segment_refcounts, key)
+            }
+            ty => {
+                let key = postcard::to_stdvec(&(self.content_namespace, ty)).unwrap();
+                read_tx.get(&self.filestore.refcounts, key)
+            }
+        };
+        refcount
+            .unwrap()
+            .map_or(0, |refcount| postcard::from_bytes(&refcount).unwrap())
+    }
 
+    #[tracing::instrument(level = "trace", skip_all, fields(namespace = self.namespace.0, size = contents.len()))]
     pub fn upload_chunk(&self, contents: &[u8]) -> chunk::ChunkId {
-        let chunk_id = chunk::ChunkId::from_contents(contents);
+        let chunk_id = chunk::ChunkId::from_contents(self.config.hash_algorithm, contents);
 
-        let chunk_key = postcard::to_stdvec(&(self.namespace, chunk_id)).unwrap();
+        let mut appended = None;
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let stored = self.store_chunk(
+                &mut write_tx,
+                chunk_id,
+                contents.len() as u32,
+                &mut appended,
+                || chunk::Compression::compress(contents, self.config.compression_level),
+            );
+            self.addref(
+                &mut write_tx,
+                refcounts::ReferenceCountType::Chunk(chunk_id),
+            );
+            if commit(write_tx).is_ok() {
+                record_chunk(stored, &appended);
+                return chunk_id;
+            }
+        }
+    }
 
-        let mut write_tx = self.filestore.database.write_tx().unwrap();
-        if !write_tx
+    /// Stores the chunk contents of the given `size`, unless the chunk is already stored,
+    /// returning whether it was stored.
+    ///
+    /// The `stored` contents are only produced when they are written. They are appended to a
+    /// segment once, and `appended` keeps track of where, so that retries of a conflicting
+    /// transaction reuse them.
+    fn store_chunk(
+        &self,
+        write_tx: &mut WriteTransaction,
+        chunk_id: chunk::ChunkId,
+        size: u32,
+        appended: &mut Option<chunk::Chunk>,
+        stored: impl Fn() -> (chunk::Compression, Vec<u8>),
+    ) -> bool {
+        let chunk_key = postcard::to_stdvec(&(self.content_namespace, chunk_id)).unwrap();
+        if write_tx
             .contains_key(&self.filestore.chunks, &chunk_key)
             .unwrap()
         {
-            let (segment_id, offset_in_segment) = {
-                let mut last_segment = self.filestore.last_segment.lock().unwrap();
-                let mut segments = self.filestore.segments.lock().unwrap();
+            return false;
+        }
 
-                let segment_id = *last_segment.get_or_insert_with(|| segment::SegmentId {
-                    uuid: uuid::Uuid::new_v4().into_bytes(),
-                });
+        let chunk = appended.get_or_insert_with(|| {
+            let (compression, stored) = stored();
+            let (segment_id, offset_in_segment) = self
+                .filestore
+                .append_to_segment(&stored, self.config.segment_size);
+            chunk::Chunk {
+                size,
+                compression,
+                compressed_size: stored.len() as u32,
+                segment_id,
+                offset_in_segment,
+            }
+        });
+        let segment_id = chunk.segment_id;
+        let chunk = postcard::to_stdvec(chunk).unwrap();
 
-                // TODO:
-                // self.addref(refcounts::ReferenceCountType::Segment(segment_id));
-                let segment = segments.entry(segment_id).or_default();
+        write_tx.insert(&self.filestore.chunks, chunk_key, chunk);
+        self.addref_segment(write_tx, segment_id);
+        true
+    }
 
-                let offset_in_segment = segment.0.len() as u32;
-                segment.0.extend_from_slice(contents);
+    /// Uploads a chunk ahead of assembling it into a file with [`Self::assemble_file`].
+    ///
+    /// The `chunk_id` has to match the contents, which the caller is responsible for. The
+    /// chunk is kept around for the [`gc::PENDING_CHUNK_TTL`], and uploading it again
+    /// extends that.
+    #[tracing::instrument(level = "trace", skip_all, fields(namespace = self.namespace.0, size = contents.len()))]
+    pub fn upload_pending_chunk(&self, chunk_id: chunk::ChunkId, contents: &[u8]) {
+        self.add_pending_chunk(chunk_id, contents.len() as u32, || {
+            chunk::Compression::compress(contents, self.config.compression_level)
+        });
+    }
 
-                if segment.0.len() as u64 >= self.config.segment_size {
-                    last_segment.take();
-                }
-                (segment_id, offset_in_segment)
-            };
+    /// Like [`Self::upload_pending_chunk`], but with contents which are already compressed as
+    /// a zstd frame, and stored verbatim.
+    ///
+    /// The frame has to decompress to `size` bytes matching the `chunk_id`, which the caller
+    /// is responsible for.
+    #[tracing::instrument(level = "trace", skip_all, fields(namespace = self.namespace.0, size))]
+    pub fn upload_pending_chunk_zstd(&self, chunk_id: chunk::ChunkId, size: u32, zstd: &[u8]) {
+        self.add_pending_chunk(chunk_id, size, || (chunk::Compression::Zstd, zstd.into()));
+    }
 
-            let chunk = chunk::Chunk {
-                size: contents.len() as u32,
-                compression: chunk::Compression::None,
-                compressed_size: contents.len() as u32,
-                segment_id,
-                offset_in_segment,
-            };
-            let chunk = postcard::to_stdvec(&chunk).unwrap();
+    fn add_pending_chunk(
+        &self,
+        chunk_id: chunk::ChunkId,
+        size: u32,
+        stored: impl Fn() -> (chunk::Compression, Vec<u8>),
+    ) {
+        let ref_key = postcard::to_stdvec(&(self.content_namespace, chunk_id)).unwrap();
+        let chunk_ref = gc::ChunkRef {
+            chunk_id,
+            expires: gc::Timestamp::after(gc::PENDING_CHUNK_TTL),
+        };
+        let chunk_ref = postcard::to_stdvec(&chunk_ref).unwrap();
 
-            write_tx.insert(&self.filestore.chunks, chunk_key, chunk);
+        // the same chunk is commonly uploaded in parallel, which conflicts
+        let mut appended = None;
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let stored = self.store_chunk(&mut write_tx, chunk_id, size, &mut appended, &stored);
+            if !write_tx
+                .contains_key(&self.filestore.chunk_refs, &ref_key)
+                .unwrap()
+            {
+                self.addref(
+                    &mut write_tx,
+                    refcounts::ReferenceCountType::Chunk(chunk_id),
+                );
+            }
+            write_tx.insert(&self.filestore.chunk_refs, &ref_key, &chunk_ref);
+            if commit(write_tx).is_ok() {
+                record_chunk(stored, &appended);
+                return;
+            }
         }
-        // TODO:
-        // self.addref(refcounts::ReferenceCountType::Chunk(chunk_id));
-        write_tx.commit().unwrap().unwrap();
+    }
+
+    /// The chunks which are not stored, in the order they were given.
+    pub fn find_missing_chunks(&self, chunk_ids: &[chunk::ChunkId]) -> Vec<chunk::ChunkId> {
+        let read_tx = self.filestore.database.read_tx();
+        let mut seen = std::collections::HashSet::new();
+        chunk_ids
+            .iter()
+            .copied()
+            .filter(|chunk_id| {
+                let chunk_key = postcard::to_stdvec(&(self.content_namespace, chunk_id)).unwrap();
+                seen.insert(*chunk_id)
+                    && !read_tx
+                        .contains_key(&self.filestore.chunks, chunk_key)
+                        .unwrap()
+            })
+            .collect()
+    }
 
-        chunk_id
+    /// Assembles a file from already stored chunks, returning a new reference to it.
+    ///
+    /// The chunks are read to compute the ID of the file, but no contents are written.
+    #[tracing::instrument(level = "debug", skip_all, fields(namespace = self.namespace.0, chunks = chunk_ids.len()))]
+    pub fn assemble_file(&self, chunk_ids: &[chunk::ChunkId]) -> Result<file::FileId, Error> {
+        'retry: loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+
+            // the chunks are read from the snapshot of the transaction, as they might have
+            // been released in the meantime
+            let mut hasher = ContentHasher::new(self.config.hash_algorithm);
+            let mut file_size = 0;
+            let mut chunks = Vec::with_capacity(chunk_ids.len());
+            let mut missing = vec![];
+            for &chunk_id in chunk_ids {
+                let chunk_key = postcard::to_stdvec(&(self.content_namespace, chunk_id)).unwrap();
+                let Some(chunk) = write_tx.get(&self.filestore.chunks, chunk_key).unwrap() else {
+                    if !missing.contains(&chunk_id) {
+                        missing.push(chunk_id);
+                    }
+                    continue;
+                };
+                let chunk: chunk::Chunk = postcard::from_bytes(&chunk).unwrap();
+                let stored = match self.filestore.read_segment(&chunk) {
+                    Ok(stored) => stored,
+                    // the segment was freed after the snapshot was taken
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue 'retry,
+                    Err(err) => panic!("{err}"),
+                };
+                let contents = chunk.compression.decompress(&stored, chunk.size);
+                hasher.update(&contents);
+                file_size += contents.len() as u64;
+                chunks.push(file::FileChunk {
+                    chunk_size: contents.len() as u32,
+                    chunk_id,
+                });
+            }
+            if !missing.is_empty() {
+                return Err(Error::MissingChunks(missing));
+            }
+
+            let file_id = file::FileId(hasher.finalize());
+            let file_key = postcard::to_stdvec(&(self.content_namespace, file_id)).unwrap();
+            let deduplicated = write_tx
+                .contains_key(&self.filestore.files, &file_key)
+                .unwrap();
+            if !deduplicated {
+                for file::FileChunk { chunk_id, .. } in &chunks {
+                    self.addref(
+                        &mut write_tx,
+                        refcounts::ReferenceCountType::Chunk(*chunk_id),
+                    );
+                }
+                let file = file::File {
+                    size: file_size,
+                    contents: file::FileContents::Chunked(chunks),
+                };
+                let file = postcard::to_stdvec(&file).unwrap();
+                write_tx.insert(&self.filestore.files, &file_key, &file);
+            }
+            self.addref(&mut write_tx, refcounts::ReferenceCountType::File(file_id));
+            if commit(write_tx).is_ok() {
+                if !deduplicated {
+                    stats::file_stored(0);
+                }
+                stats::file_uploaded(file_size, deduplicated);
+                return Ok(file_id);
+            }
+        }
     }
 
+    /// Uploads the file, returning a new reference to it.
+    ///
+    /// The contents are only stored once per namespace, uploading the same contents again
+    /// only adds another reference to the existing file.
+    #[tracing::instrument(level = "debug", skip_all, fields(namespace = self.namespace.0, size = contents.len()))]
     pub fn upload_file(&self, contents: &[u8]) -> file::FileId {
-        let file_id = file::FileId::from_contents(contents);
+        let file_id = file::FileId::from_contents(self.config.hash_algorithm, contents);
+        let file_key = postcard::to_stdvec(&(self.content_namespace, file_id)).unwrap();
+
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            if !write_tx
+                .contains_key(&self.filestore.files, &file_key)
+                .unwrap()
+            {
+                break;
+            }
+            self.addref(&mut write_tx, refcounts::ReferenceCountType::File(file_id));
+            if commit(write_tx).is_ok() {
+                stats::file_uploaded(contents.len() as u64, true);
+                return file_id;
+            }
+        }
+        stats::file_uploaded(contents.len() as u64, false);
 
         let file_size = contents.len() as u64;
         let contents = if file_size <= self.config.inline_size {
             file::FileContents::Inline(contents.into())
         } else {
-            let chunks = contents
-                .chunks(self.config.chunk_size as usize)
+            let _span =
+                tracing::debug_span!("chunking", chunk_size = self.config.chunk_size).entered();
+            let chunks = self
+                .config
+                .split(contents)
+                .into_iter()
                 .map(|chunk| file::FileChunk {
                     chunk_size: chunk.len() as u32,
                     chunk_id: self.upload_chunk(chunk),
@@ -189,25 +1094,124 @@ impl NamespacedFileStore<'_> {
             file::FileContents::Chunked(chunks)
         };
 
+        let inline_size = match &contents {
+            file::FileContents::Inline(contents) => contents.len() as u64,
+            file::FileContents::Chunked(_) => 0,
+        };
         let file = file::File {
             size: file_size,
             contents,
         };
-        let file = postcard::to_stdvec(&file).unwrap();
+        let serialized = postcard::to_stdvec(&file).unwrap();
+
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let mut freed_segments = vec![];
+            // a concurrent upload of the same contents might have stored the file meanwhile,
+            // in which case the references to the chunks uploaded here are released again
+            let stored = !write_tx
+                .contains_key(&self.filestore.files, &file_key)
+                .unwrap();
+            if stored {
+                write_tx.insert(&self.filestore.files, &file_key, &serialized);
+            } else if let file::FileContents::Chunked(chunks) = &file.contents {
+                for file::FileChunk { chunk_id, .. } in chunks {
+                    self.filestore.release_chunk(
+                        &mut write_tx,
+                        self.content_namespace,
+                        *chunk_id,
+                        &mut freed_segments,
+                    );
+                }
+            }
+            self.addref(&mut write_tx, refcounts::ReferenceCountType::File(file_id));
+            if commit(write_tx).is_ok() {
+                self.filestore.free_segments(&freed_segments);
+                if stored {
+                    stats::file_stored(inline_size);
+                }
+                return file_id;
+            }
+        }
+    }
 
-        let file_key = postcard::to_stdvec(&(self.namespace, file_id)).unwrap();
-        let mut write_tx = self.filestore.database.write_tx().unwrap();
-        write_tx.insert(&self.filestore.files, file_key, file);
+    /// Copies a file from the `source` namespace, returning a new reference to it.
+    ///
+    /// No file contents are copied. Within the same namespace, this only adds a reference to
+    /// the file. Across namespaces, the chunks are referenced from the destination namespace,
+    /// sharing the segment data they are stored in. Only copies between different
+    /// `FileStore`s, which share no storage, have to upload the contents again.
+    ///
+    /// Returns `None` if the source file does not exist (anymore).
+    #[tracing::instrument(level = "debug", skip(self, source), fields(namespace = self.namespace.0))]
+    pub fn copy_file(
+        &self,
+        source: &NamespacedFileStore<'_>,
+        file_id: file::FileId,
+    ) -> Option<file::FileId> {
+        if !std::ptr::eq(self.filestore, source.filestore) {
+            return Some(self.upload_file(&source.read_file(file_id)));
+        }
 
-        // TODO:
-        // self.addref(refcounts::ReferenceCountType::File(file_id));
-        write_tx.commit().unwrap().unwrap();
+        let file_key = postcard::to_stdvec(&(self.content_namespace, file_id)).unwrap();
+        let source_key = postcard::to_stdvec(&(source.content_namespace, file_id)).unwrap();
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            if !write_tx
+                .contains_key(&self.filestore.files, &file_key)
+                .unwrap()
+            {
+                // the source file might have been deleted since it was resolved
+                let file = write_tx.get(&self.filestore.files, &source_key).unwrap()?;
+                let file: file::File = postcard::from_bytes(&file).unwrap();
+                if let file::FileContents::Chunked(chunks) = &file.contents {
+                    for file::FileChunk { chunk_id, .. } in chunks {
+                        self.copy_chunk(&mut write_tx, source, *chunk_id)?;
+                    }
+                }
 
-        file_id
+                let file = postcard::to_stdvec(&file).unwrap();
+                write_tx.insert(&self.filestore.files, &file_key, file);
+            }
+            self.addref(&mut write_tx, refcounts::ReferenceCountType::File(file_id));
+            if commit(write_tx).is_ok() {
+                return Some(file_id);
+            }
+        }
     }
 
-    pub fn read_chunk(&self, chunk_id: chunk::ChunkId) -> Vec<u8> {
-        let chunk_key = postcard::to_stdvec(&(self.namespace, chunk_id)).unwrap();
+    /// Adds a reference to the chunk, referencing the chunk of the `source` namespace if it is
+    /// not stored in this namespace yet.
+    ///
+    /// Returns `None` if neither namespace stores the chunk.
+    fn copy_chunk(
+        &self,
+        write_tx: &mut WriteTransaction,
+        source: &NamespacedFileStore<'_>,
+        chunk_id: chunk::ChunkId,
+    ) -> Option<()> {
+        let chunk_key = postcard::to_stdvec(&(self.content_namespace, chunk_id)).unwrap();
+        if !write_tx
+            .contains_key(&self.filestore.chunks, &chunk_key)
+            .unwrap()
+        {
+            let source_key = postcard::to_stdvec(&(source.content_namespace, chunk_id)).unwrap();
+            let chunk = write_tx.get(&self.filestore.chunks, source_key).unwrap()?;
+            let segment_id = postcard::from_bytes::<chunk::Chunk>(&chunk)
+                .unwrap()
+                .segment_id;
+
+            write_tx.insert(&self.filestore.chunks, chunk_key, chunk);
+            self.addref_segment(write_tx, segment_id);
+        }
+        self.addref(write_tx, refcounts::ReferenceCountType::Chunk(chunk_id));
+        Some(())
+    }
+
+    /// Reads the chunk metadata, along with the chunk contents as stored in its segment.
+    #[tracing::instrument(level = "trace", skip(self), fields(namespace = self.namespace.0))]
+    fn read_stored_chunk(&self, chunk_id: chunk::ChunkId) -> (chunk::Chunk, Vec<u8>) {
+        let chunk_key = postcard::to_stdvec(&(self.content_namespace, chunk_id)).unwrap();
         let read_tx = self.filestore.database.read_tx();
 
         let chunk = read_tx
@@ -216,22 +1220,79 @@ impl NamespacedFileStore<'_> {
             .unwrap();
         let chunk: chunk::Chunk = postcard::from_bytes(&chunk).unwrap();
 
-        let segments = self.filestore.segments.lock().unwrap();
-        let segment = &segments[&chunk.segment_id];
-        let start = chunk.offset_in_segment as usize;
-        let range = start..start + chunk.size as usize;
-        (&segment.0[range]).into()
+        let stored = self.filestore.read_segment(&chunk).unwrap();
+        (chunk, stored)
     }
 
-    pub fn read_file(&self, file_id: file::FileId) -> Vec<u8> {
-        let file_key = postcard::to_stdvec(&(self.namespace, file_id)).unwrap();
+    pub fn read_chunk(&self, chunk_id: chunk::ChunkId) -> Vec<u8> {
+        let (chunk, stored) = self.read_stored_chunk(chunk_id);
+        chunk.compression.decompress(&stored, chunk.size)
+    }
+
+    /// Reads the chunk as a zstd frame, avoiding a roundtrip through the uncompressed contents.
+    pub fn read_chunk_zstd(&self, chunk_id: chunk::ChunkId) -> Vec<u8> {
+        let (chunk, stored) = self.read_stored_chunk(chunk_id);
+        chunk.compression.to_zstd(stored)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self), fields(namespace = self.namespace.0))]
+    /// Looks up a file by its ID, among the files stored in the content namespace.
+    ///
+    /// With a separate content namespace, this includes files uploaded to any namespace
+    /// sharing it.
+    pub fn find_file(&self, file_id: file::FileId) -> Option<file::File> {
+        let file_key = postcard::to_stdvec(&(self.content_namespace, file_id)).unwrap();
+        let file = self.filestore.files.get(file_key).unwrap()?;
+        Some(postcard::from_bytes(&file).unwrap())
+    }
+
+    pub fn get_file(&self, file_id: file::FileId) -> file::File {
+        let file_key = postcard::to_stdvec(&(self.content_namespace, file_id)).unwrap();
         let read_tx = self.filestore.database.read_tx();
 
         let file = read_tx
             .get(&self.filestore.files, file_key)
             .unwrap()
             .unwrap();
-        let file: file::File = postcard::from_bytes(&file).unwrap();
+        postcard::from_bytes(&file).unwrap()
+    }
+
+    /// Looks up the file along with its chunks, and all their reference counts.
+    pub fn inspect_file(&self, file_id: file::FileId) -> Option<report::FileInfo> {
+        let file_key = postcard::to_stdvec(&(self.content_namespace, file_id)).unwrap();
+        let read_tx = self.filestore.database.read_tx();
+        let file = read_tx.get(&self.filestore.files, file_key).unwrap()?;
+        let file: file::File = postcard::from_bytes(&file).unwrap();
+
+        let mut chunks = vec![];
+        if let file::FileContents::Chunked(file_chunks) = &file.contents {
+            for file::FileChunk { chunk_id, .. } in file_chunks {
+                let chunk_key = postcard::to_stdvec(&(self.content_namespace, chunk_id)).unwrap();
+                let chunk = read_tx
+                    .get(&self.filestore.chunks, chunk_key)
+                    .unwrap()
+                    .unwrap();
+                let chunk: chunk::Chunk = postcard::from_bytes(&chunk).unwrap();
+                chunks.push(report::ChunkInfo {
+                    chunk_id: *chunk_id,
+                    refcount: self.refcount(refcounts::ReferenceCountType::Chunk(*chunk_id)),
+                    segment_refcount: self
+                        .refcount(refcounts::ReferenceCountType::Segment(chunk.segment_id)),
+                    chunk,
+                });
+            }
+        }
+
+        Some(report::FileInfo {
+            file_id,
+            refcount: self.refcount(refcounts::ReferenceCountType::File(file_id)),
+            file,
+            chunks,
+        })
+    }
+
+    pub fn read_file(&self, file_id: file::FileId) -> Vec<u8> {
+        let file = self.get_file(file_id);
 
         match &file.contents {
             file::FileContents::Inline(contents) => contents.clone(),
@@ -245,63 +1306,790 @@ impl NamespacedFileStore<'_> {
         }
     }
 
-    // pub fn assemble_file_from_chunks(&self, chunks: &[chunk::ChunkId]) -> file::FileId {
-    //     let mut file_size = 0;
-    //     let mut file_hash = blake3::Hasher::new();
-    //     let chunks = chunks
-    //         .into_iter()
-    //         .copied()
-    //         .map(|chunk_id| {
-    //             let chunk_content = self.read_chunk(chunk_id);
+    /// Generates a name which is neither associated nor reserved, and reserves it for the
+    /// [`gc::RESERVATION_TTL`].
+    #[tracing::instrument(level = "debug", skip(self), fields(namespace = self.namespace.0))]
+    pub fn allocate_filename(&self, format: file::IdFormat) -> String {
+        let value = postcard::to_stdvec(&gc::Timestamp::after(gc::RESERVATION_TTL)).unwrap();
+        loop {
+            let name = format.generate();
+            let key = postcard::to_stdvec(&(self.namespace, &name)).unwrap();
+
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            if write_tx
+                .contains_key(&self.filestore.named_files, &key)
+                .unwrap()
+                || write_tx
+                    .contains_key(&self.filestore.reservations, &key)
+                    .unwrap()
+            {
+                continue;
+            }
+            write_tx.insert(&self.filestore.reservations, key, &value);
+            if commit(write_tx).is_ok() {
+                return name;
+            }
+        }
+    }
+
+    /// Reserves a client-provided name for the [`gc::RESERVATION_TTL`], so that it is not
+    /// allocated until it is uploaded to. Reserving a name again extends its reservation.
+    #[tracing::instrument(level = "debug", skip(self), fields(namespace = self.namespace.0))]
+    pub fn reserve_filename(&self, name: &str) {
+        let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+        let value = postcard::to_stdvec(&gc::Timestamp::after(gc::RESERVATION_TTL)).unwrap();
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            if write_tx
+                .contains_key(&self.filestore.named_files, &key)
+                .unwrap()
+            {
+                return;
+            }
+            write_tx.insert(&self.filestore.reservations, &key, &value);
+            if commit(write_tx).is_ok() {
+                return;
+            }
+        }
+    }
+
+    /// Whether the name is reserved, without having been uploaded to yet.
+    pub fn is_reserved(&self, name: &str) -> bool {
+        let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+        self.filestore.reservations.contains_key(key).unwrap()
+    }
+
+    /// Removes the reservations of names starting with `prefix` which were reserved before
+    /// `reserved_before`, returning how many were removed.
+    #[tracing::instrument(level = "debug", skip(self), fields(namespace = self.namespace.0))]
+    pub fn remove_reservations(&self, prefix: &str, reserved_before: SystemTime) -> u64 {
+        let expires = gc::Timestamp::at(reserved_before + gc::RESERVATION_TTL);
+        let namespace_prefix = postcard::to_stdvec(&self.namespace).unwrap();
+        let stale: Vec<_> = self
+            .filestore
+            .database
+            .read_tx()
+            .prefix(&self.filestore.reservations, namespace_prefix)
+            .filter_map(|entry| {
+                let (key, reservation) = entry.unwrap();
+                let (_, name): (Namespace, String) = postcard::from_bytes(&key).unwrap();
+                let reservation: gc::Timestamp = postcard::from_bytes(&reservation).unwrap();
+                (name.starts_with(prefix) && reservation <= expires).then_some(key)
+            })
+            .collect();
+
+        let mut removed = 0;
+        for key in stale {
+            loop {
+                let mut write_tx = self.filestore.database.write_tx().unwrap();
+                // the name might have been reserved again in the meantime
+                let Some(reservation) = write_tx.get(&self.filestore.reservations, &key).unwrap()
+                else {
+                    break;
+                };
+                let reservation: gc::Timestamp = postcard::from_bytes(&reservation).unwrap();
+                if reservation > expires {
+                    break;
+                }
+                write_tx.remove(&self.filestore.reservations, key.clone());
+                if commit(write_tx).is_ok() {
+                    removed += 1;
+                    break;
+                }
+            }
+        }
+        removed
+    }
+
+    pub fn associate_filename(&self, file_id: file::FileId, name: &str) -> file::NamedFile {
+        self.associate_filename_if(file_id, name, &Default::default())
+            .unwrap()
+    }
 
-    //             file_size += chunk_content.len() as u64;
-    //             file_hash.update(&chunk_content);
+    pub fn associate_filename_if(
+        &self,
+        file_id: file::FileId,
+        name: &str,
+        preconditions: &file::Preconditions,
+    ) -> Result<file::NamedFile, Error> {
+        self.associate_tagged_filename_if(file_id, name, preconditions, vec![])
+    }
 
-    //             file::FileChunk {
-    //                 chunk_size: chunk_content.len() as u32,
-    //                 chunk_id,
-    //             }
-    //         })
-    //         .collect();
+    /// Points the name to the file, tagged with `tags`, if the file it currently points to
+    /// meets the `preconditions`. The check and the update happen atomically, and consume a
+    /// reservation of the name.
+    ///
+    /// An idempotent write of the file the name already points to is a retry, which releases
+    /// the reference to `file_id` and succeeds without changing the name.
+    #[tracing::instrument(level = "debug", skip(self, preconditions, tags), fields(namespace = self.namespace.0))]
+    pub fn associate_tagged_filename_if(
+        &self,
+        file_id: file::FileId,
+        name: &str,
+        preconditions: &file::Preconditions,
+        tags: Vec<file::Tag>,
+    ) -> Result<file::NamedFile, Error> {
+        let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let current_file = write_tx
+                .get(&self.filestore.named_files, &key)
+                .unwrap()
+                .map(|current| postcard::from_bytes::<file::NamedFile>(&current).unwrap());
+            let current = current_file.as_ref().map(|current| current.file_id);
+            if !preconditions.check(current) {
+                if !preconditions.is_retry(current, file_id) {
+                    return Err(Error::PreconditionFailed);
+                }
+                let mut freed_segments = vec![];
+                self.release_file(&mut write_tx, file_id, &mut freed_segments);
+                if commit(write_tx).is_ok() {
+                    self.filestore.free_segments(&freed_segments);
+                    return Ok(current_file.unwrap());
+                }
+                continue;
+            }
+            // the current file is kept as a version, rather than replaced
+            let replaced = current.filter(|_| !self.versioned);
+            if self.account.is_some() {
+                let added = self.file_usage(&mut write_tx, file_id);
+                let removed = replaced
+                    .map(|current| self.file_usage(&mut write_tx, current))
+                    .unwrap_or_default();
+                self.update_usage(&mut write_tx, added, removed)?;
+            }
+
+            let version_id = match self.versioned {
+                true => {
+                    let latest = match &current_file {
+                        Some(current_file) => Some(current_file.version_id),
+                        None => self.latest_version(&mut write_tx, name),
+                    };
+                    file::VersionId::next(latest)
+                }
+                false => file::VersionId::NULL,
+            };
+            let last_modified = unix_timestamp();
+            let named_file = file::NamedFile {
+                file_id,
+                last_modified,
+                last_accessed: last_modified,
+                version_id,
+                lock: file::Lock {
+                    retention: self
+                        .default_retention
+                        .map(|retention| retention.retention_from(last_modified)),
+                    legal_hold: false,
+                },
+                tags: tags.clone(),
+            };
+            let value = postcard::to_stdvec(&named_file).unwrap();
+            write_tx.insert(&self.filestore.named_files, &key, &value);
+            write_tx.remove(&self.filestore.reservations, key.clone());
+            let mut freed_segments = vec![];
+            if let Some(current_file) = current_file.filter(|_| self.versioned) {
+                self.insert_version(
+                    &mut write_tx,
+                    name,
+                    current_file.version_id,
+                    current_file.into(),
+                );
+            }
+            if let Some(current) = replaced {
+                self.release_file(&mut write_tx, current, &mut freed_segments);
+            }
+            // on a conflicting concurrent write, check the preconditions again
+            if commit(write_tx).is_ok() {
+                self.filestore.free_segments(&freed_segments);
+                return Ok(named_file);
+            }
+        }
+    }
+
+    pub fn delete_filename(&self, name: &str) -> Option<file::FileId> {
+        let deleted = self.delete_filenames(&[name]).pop().unwrap();
+        deleted.map(|deleted| deleted.file_id)
+    }
+
+    /// Removes all the names within a single transaction, releasing the files they pointed to.
+    /// In versioned buckets, the files are kept as versions instead, and a delete marker
+    /// becomes the current version of each name.
+    ///
+    /// Returns the file each name pointed to and its delete marker, or `None` if it did not
+    /// exist.
+    #[tracing::instrument(level = "debug", skip_all, fields(namespace = self.namespace.0, count = names.len()))]
+    pub fn delete_filenames(&self, names: &[&str]) -> Vec<Option<file::DeletedName>> {
+        self.delete_filenames_where(names, |_| true)
+    }
+
+    /// Like [`Self::delete_filename`], but only deletes the name if its current version is
+    /// still `version_id` with the given `version`, checked within the same transaction.
+    ///
+    /// Returns whether the name was deleted.
+    #[tracing::instrument(level = "debug", skip(self, version), fields(namespace = self.namespace.0))]
+    pub fn delete_filename_if_current(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        version: &file::Version,
+    ) -> bool {
+        let deleted = self.delete_filenames_where(&[name], |current| {
+            current.version_id == version_id && file::Version::from(current.clone()) == *version
+        });
+        deleted[0].is_some()
+    }
+
+    /// Removes the names whose current file matches `condition`, see
+    /// [`Self::delete_filenames`].
+    fn delete_filenames_where(
+        &self,
+        names: &[&str],
+        condition: impl Fn(&file::NamedFile) -> bool,
+    ) -> Vec<Option<file::DeletedName>> {
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let mut freed_segments = vec![];
+            let mut removed = quota::Usage::default();
+
+            let deleted = names
+                .iter()
+                .map(|name| {
+                    let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+                    let current = write_tx.get(&self.filestore.named_files, &key).unwrap()?;
+                    let current: file::NamedFile = postcard::from_bytes(&current).unwrap();
+                    if !condition(&current) {
+                        return None;
+                    }
+                    let file_id = current.file_id;
+                    let current_version_id = current.version_id;
+
+                    write_tx.remove(&self.filestore.named_files, key);
+                    if self.versioned {
+                        self.insert_version(
+                            &mut write_tx,
+                            name,
+                            current_version_id,
+                            current.into(),
+                        );
+                        let delete_marker = file::Version::delete_marker(unix_timestamp());
+                        let version_id = file::VersionId::next(Some(current_version_id));
+                        self.insert_version(&mut write_tx, name, version_id, delete_marker);
+                        return Some(file::DeletedName {
+                            file_id,
+                            delete_marker: Some(version_id),
+                        });
+                    }
+                    if self.account.is_some() {
+                        removed = removed.add(self.file_usage(&mut write_tx, file_id));
+                    }
+                    self.release_file(&mut write_tx, file_id, &mut freed_segments);
+                    Some(file::DeletedName {
+                        file_id,
+                        delete_marker: None,
+                    })
+                })
+                .collect();
+            // shrinking never exceeds a quota
+            self.update_usage(&mut write_tx, Default::default(), removed)
+                .unwrap();
+
+            if commit(write_tx).is_ok() {
+                self.filestore.free_segments(&freed_segments);
+                return deleted;
+            }
+        }
+    }
+
+    /// Looks up a version of the name, which might be its current one.
+    pub fn get_version(&self, name: &str, version_id: file::VersionId) -> Option<file::Version> {
+        if let Some(current) = self.get_named_file(name) {
+            if current.version_id == version_id {
+                return Some(current.into());
+            }
+        }
+        let key = postcard::to_stdvec(&(self.namespace, name, version_id)).unwrap();
+        let version = self.filestore.versions.get(key).unwrap()?;
+        Some(postcard::from_bytes(&version).unwrap())
+    }
+
+    /// Removes a version of the name for good, releasing its file, unless the file is locked.
+    ///
+    /// When the current version is removed, the previous one becomes current, unless that is
+    /// a delete marker. Returns the removed version, or `None` if it did not exist.
+    #[tracing::instrument(level = "debug", skip(self), fields(namespace = self.namespace.0))]
+    pub fn delete_version(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        bypass_governance: bool,
+    ) -> Result<Option<file::Version>, Error> {
+        let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+        let version_key = postcard::to_stdvec(&(self.namespace, name, version_id)).unwrap();
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let current = write_tx
+                .get(&self.filestore.named_files, &key)
+                .unwrap()
+                .map(|current| postcard::from_bytes::<file::NamedFile>(&current).unwrap());
+
+            let deleted: file::Version = match current {
+                Some(current) if current.version_id == version_id => {
+                    write_tx.remove(&self.filestore.named_files, key.clone());
+                    current.into()
+                }
+                _ => {
+                    let Some(version) = write_tx
+                        .get(&self.filestore.versions, &version_key)
+                        .unwrap()
+                    else {
+                        return Ok(None);
+                    };
+                    write_tx.remove(&self.filestore.versions, version_key.clone());
+                    postcard::from_bytes(&version).unwrap()
+                }
+            };
+            if deleted.lock.is_locked(unix_timestamp(), bypass_governance) {
+                return Err(Error::ObjectLocked);
+            }
+
+            let mut freed_segments = vec![];
+            if let Some(file_id) = deleted.file_id {
+                if self.account.is_some() {
+                    let removed = self.file_usage(&mut write_tx, file_id);
+                    // shrinking never exceeds a quota
+                    self.update_usage(&mut write_tx, Default::default(), removed)
+                        .unwrap();
+                }
+                self.release_file(&mut write_tx, file_id, &mut freed_segments);
+            }
+            if !write_tx
+                .contains_key(&self.filestore.named_files, &key)
+                .unwrap()
+            {
+                self.promote_latest_version(&mut write_tx, name);
+            }
+
+            if commit(write_tx).is_ok() {
+                self.filestore.free_segments(&freed_segments);
+                return Ok(Some(deleted));
+            }
+        }
+    }
+
+    /// Updates the lock of a version of the name, which might be its current one.
+    ///
+    /// Returns the updated lock, or `None` if the version does not exist or is a delete
+    /// marker.
+    #[tracing::instrument(level = "debug", skip(self, update), fields(namespace = self.namespace.0))]
+    pub fn update_lock(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        update: impl Fn(file::Lock) -> Result<file::Lock, Error>,
+    ) -> Result<Option<file::Lock>, Error> {
+        let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+        let version_key = postcard::to_stdvec(&(self.namespace, name, version_id)).unwrap();
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let current = write_tx
+                .get(&self.filestore.named_files, &key)
+                .unwrap()
+                .map(|current| postcard::from_bytes::<file::NamedFile>(&current).unwrap());
+
+            let lock = match current {
+                Some(mut current) if current.version_id == version_id => {
+                    current.lock = update(current.lock)?;
+                    let value = postcard::to_stdvec(&current).unwrap();
+                    write_tx.insert(&self.filestore.named_files, &key, value);
+                    current.lock
+                }
+                _ => {
+                    let Some(version) = write_tx
+                        .get(&self.filestore.versions, &version_key)
+                        .unwrap()
+                    else {
+                        return Ok(None);
+                    };
+                    let mut version: file::Version = postcard::from_bytes(&version).unwrap();
+                    if version.file_id.is_none() {
+                        return Ok(None);
+                    }
+                    version.lock = update(version.lock)?;
+                    let value = postcard::to_stdvec(&version).unwrap();
+                    write_tx.insert(&self.filestore.versions, &version_key, value);
+                    version.lock
+                }
+            };
+            if commit(write_tx).is_ok() {
+                return Ok(Some(lock));
+            }
+        }
+    }
+
+    /// Replaces the tags of a version of the name, which might be its current one.
+    ///
+    /// Returns `false` if the version does not exist or is a delete marker.
+    #[tracing::instrument(level = "debug", skip(self, tags), fields(namespace = self.namespace.0))]
+    pub fn set_tags(&self, name: &str, version_id: file::VersionId, tags: Vec<file::Tag>) -> bool {
+        let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+        let version_key = postcard::to_stdvec(&(self.namespace, name, version_id)).unwrap();
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let current = write_tx
+                .get(&self.filestore.named_files, &key)
+                .unwrap()
+                .map(|current| postcard::from_bytes::<file::NamedFile>(&current).unwrap());
+
+            match current {
+                Some(mut current) if current.version_id == version_id => {
+                    current.tags = tags.clone();
+                    let value = postcard::to_stdvec(&current).unwrap();
+                    write_tx.insert(&self.filestore.named_files, &key, value);
+                }
+                _ => {
+                    let Some(version) = write_tx
+                        .get(&self.filestore.versions, &version_key)
+                        .unwrap()
+                    else {
+                        return false;
+                    };
+                    let mut version: file::Version = postcard::from_bytes(&version).unwrap();
+                    if version.file_id.is_none() {
+                        return false;
+                    }
+                    version.tags = tags.clone();
+                    let value = postcard::to_stdvec(&version).unwrap();
+                    write_tx.insert(&self.filestore.versions, &version_key, value);
+                }
+            }
+            if commit(write_tx).is_ok() {
+                return true;
+            }
+        }
+    }
+
+    /// Records that the current file of the name was read at `accessed`, unless the name was
+    /// written again since, or its recorded access is within [`file::ACCESS_RESOLUTION`].
+    #[tracing::instrument(level = "debug", skip(self), fields(namespace = self.namespace.0))]
+    pub fn record_access(&self, name: &str, version_id: file::VersionId, accessed: u64) {
+        let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let Some(mut current) = write_tx
+                .get(&self.filestore.named_files, &key)
+                .unwrap()
+                .map(|current| postcard::from_bytes::<file::NamedFile>(&current).unwrap())
+                .filter(|current| {
+                    current.version_id == version_id
+                        && current.last_accessed + file::ACCESS_RESOLUTION <= accessed
+                })
+            else {
+                return;
+            };
+            current.last_accessed = accessed;
+            let value = postcard::to_stdvec(&current).unwrap();
+            write_tx.insert(&self.filestore.named_files, &key, value);
+            if commit(write_tx).is_ok() {
+                return;
+            }
+        }
+    }
 
-    //     let file_id = file::FileId {
-    //         hash_algorithm: HashAlgorithm::Blake3,
-    //         _padding: [0; 3],
-    //         hash: *file_hash.finalize().as_bytes(),
-    //     };
+    /// Lists all versions of the names starting with `prefix`, including the current ones,
+    /// ordered by name and then from the latest to the oldest version.
+    pub fn list_versions(&self, prefix: &str) -> Vec<file::ListedVersion> {
+        let read_tx = self.filestore.database.read_tx();
+        let namespace_prefix = postcard::to_stdvec(&self.namespace).unwrap();
 
-    //     let file = file::File {
-    //         size: file_size,
-    //         contents: file::FileContents::Chunked(chunks),
-    //     };
-    //     let file = postcard::to_stdvec(&file).unwrap();
+        let current = read_tx
+            .prefix(&self.filestore.named_files, &namespace_prefix)
+            .map(|entry| {
+                let (key, named_file) = entry.unwrap();
+                let (_, name): (Namespace, String) = postcard::from_bytes(&key).unwrap();
+                let named_file: file::NamedFile = postcard::from_bytes(&named_file).unwrap();
+                (name, named_file.version_id, named_file.into())
+            });
+        let noncurrent = read_tx
+            .prefix(&self.filestore.versions, &namespace_prefix)
+            .map(|entry| {
+                let (key, version) = entry.unwrap();
+                let (_, name, version_id): (Namespace, String, file::VersionId) =
+                    postcard::from_bytes(&key).unwrap();
+                (name, version_id, postcard::from_bytes(&version).unwrap())
+            });
+        let versions = current
+            .chain(noncurrent)
+            .filter(|(name, _, _)| name.starts_with(prefix))
+            .collect();
+        file::ListedVersion::sorted(versions)
+    }
 
-    //     let file_key = postcard::to_stdvec(&(self.namespace, file_id)).unwrap();
-    //     let mut write_tx = self.filestore.database.write_tx().unwrap();
-    //     write_tx.insert(&self.filestore.files, file_key, file);
+    /// The latest of the versions kept for the name, not considering its current file.
+    fn latest_version(
+        &self,
+        write_tx: &mut WriteTransaction,
+        name: &str,
+    ) -> Option<file::VersionId> {
+        self.versions(write_tx, name)
+            .into_iter()
+            .map(|(version_id, _)| version_id)
+            .max()
+    }
 
-    //     // TODO:
-    //     // self.addref(refcounts::ReferenceCountType::File(file_id));
-    //     write_tx.commit().unwrap().unwrap();
+    /// The versions kept for the name, not including its current file.
+    fn versions(
+        &self,
+        write_tx: &mut WriteTransaction,
+        name: &str,
+    ) -> Vec<(file::VersionId, file::Version)> {
+        // the name is length-prefixed, so this does not match any other names
+        let prefix = postcard::to_stdvec(&(self.namespace, name)).unwrap();
+        write_tx
+            .prefix(&self.filestore.versions, prefix)
+            .map(|entry| {
+                let (key, version) = entry.unwrap();
+                let (_, _, version_id): (Namespace, String, file::VersionId) =
+                    postcard::from_bytes(&key).unwrap();
+                (version_id, postcard::from_bytes(&version).unwrap())
+            })
+            .collect()
+    }
 
-    //     file_id
-    // }
+    /// Keeps a version of the name, which takes over the reference the name held on its file.
+    fn insert_version(
+        &self,
+        write_tx: &mut WriteTransaction,
+        name: &str,
+        version_id: file::VersionId,
+        version: file::Version,
+    ) {
+        let key = postcard::to_stdvec(&(self.namespace, name, version_id)).unwrap();
+        write_tx.insert(
+            &self.filestore.versions,
+            key,
+            postcard::to_stdvec(&version).unwrap(),
+        );
+    }
 
-    pub fn associate_filename(&self, file_id: file::FileId, name: &str) {
+    /// Makes the latest version of a name without a current file its current one, unless that
+    /// version is a delete marker.
+    fn promote_latest_version(&self, write_tx: &mut WriteTransaction, name: &str) {
+        let latest = self
+            .versions(write_tx, name)
+            .into_iter()
+            .max_by_key(|(version_id, _)| *version_id);
+        let Some((
+            version_id,
+            file::Version {
+                file_id: Some(file_id),
+                last_modified,
+                lock,
+                tags,
+            },
+        )) = latest
+        else {
+            return;
+        };
+        let key = postcard::to_stdvec(&(self.namespace, name, version_id)).unwrap();
+        write_tx.remove(&self.filestore.versions, key);
+        let named_file = file::NamedFile {
+            file_id,
+            last_modified,
+            last_accessed: last_modified,
+            version_id,
+            lock,
+            tags,
+        };
         let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
-        let value = postcard::to_stdvec(&file_id).unwrap();
+        write_tx.insert(
+            &self.filestore.named_files,
+            key,
+            postcard::to_stdvec(&named_file).unwrap(),
+        );
+    }
+
+    /// Releases a reference returned by [`Self::upload_file`] or [`Self::copy_file`], when the
+    /// file ends up not being associated with a name.
+    pub fn discard_file(&self, file_id: file::FileId) {
+        loop {
+            let mut write_tx = self.filestore.database.write_tx().unwrap();
+            let mut freed_segments = vec![];
+            self.release_file(&mut write_tx, file_id, &mut freed_segments);
+            if commit(write_tx).is_ok() {
+                self.filestore.free_segments(&freed_segments);
+                return;
+            }
+        }
+    }
+
+    /// The usage accounted for a name pointing to the file.
+    fn file_usage(&self, write_tx: &mut WriteTransaction, file_id: file::FileId) -> quota::Usage {
+        let file_key = postcard::to_stdvec(&(self.content_namespace, file_id)).unwrap();
+        let file = write_tx
+            .get(&self.filestore.files, file_key)
+            .unwrap()
+            .unwrap();
+        let file: file::File = postcard::from_bytes(&file).unwrap();
+
+        let physical_bytes = match &file.contents {
+            file::FileContents::Inline(contents) => contents.len() as u64,
+            file::FileContents::Chunked(chunks) => chunks
+                .iter()
+                .map(|file::FileChunk { chunk_id, .. }| {
+                    let chunk_key =
+                        postcard::to_stdvec(&(self.content_namespace, chunk_id)).unwrap();
+                    let chunk = write_tx
+                        .get(&self.filestore.chunks, chunk_key)
+                        .unwrap()
+                        .unwrap();
+                    postcard::from_bytes::<chunk::Chunk>(&chunk)
+                        .unwrap()
+                        .compressed_size as u64
+                })
+                .sum(),
+        };
+        quota::Usage::of_file(&file, physical_bytes)
+    }
+
+    /// Accounts `added` and `removed` named files to the scope, failing if that exceeds its
+    /// hard quota.
+    fn update_usage(
+        &self,
+        write_tx: &mut WriteTransaction,
+        added: quota::Usage,
+        removed: quota::Usage,
+    ) -> Result<(), Error> {
+        let Some(account) = &self.account else {
+            return Ok(());
+        };
+        let key = postcard::to_stdvec(&(&account.usecase, &account.scope)).unwrap();
+        let previous: quota::Usage = write_tx
+            .get(&self.filestore.scope_usage, &key)
+            .unwrap()
+            .map_or_else(Default::default, |usage| {
+                postcard::from_bytes(&usage).unwrap()
+            });
+
+        let usage = previous.add(added).sub(removed);
+        account.check(&previous, &usage)?;
+        write_tx.insert(
+            &self.filestore.scope_usage,
+            key,
+            postcard::to_stdvec(&usage).unwrap(),
+        );
+        Ok(())
+    }
+
+    /// Decrements the reference count of `ty` within the namespace, returning the new count.
+    fn release(&self, write_tx: &mut WriteTransaction, ty: refcounts::ReferenceCountType) -> u32 {
+        let key = postcard::to_stdvec(&(self.content_namespace, ty)).unwrap();
+        decrement(write_tx, &self.filestore.refcounts, key)
+    }
+
+    /// Releases a reference to the file, deleting it once it is no longer referenced, which
+    /// in turn releases its chunks.
+    ///
+    /// Segments which are no longer referenced are pushed to `freed_segments`, to be freed once
+    /// the transaction is committed.
+    fn release_file(
+        &self,
+        write_tx: &mut WriteTransaction,
+        file_id: file::FileId,
+        freed_segments: &mut Vec<segment::SegmentId>,
+    ) {
+        if self.release(write_tx, refcounts::ReferenceCountType::File(file_id)) > 0 {
+            return;
+        }
+        let file_key = postcard::to_stdvec(&(self.content_namespace, file_id)).unwrap();
+        let file = write_tx
+            .get(&self.filestore.files, &file_key)
+            .unwrap()
+            .unwrap();
+        let file: file::File = postcard::from_bytes(&file).unwrap();
+        write_tx.remove(&self.filestore.files, file_key);
 
-        self.filestore.named_files.insert(key, value).unwrap();
+        let file::FileContents::Chunked(chunks) = file.contents else {
+            return;
+        };
+        for file::FileChunk { chunk_id, .. } in chunks {
+            self.filestore.release_chunk(
+                write_tx,
+                self.content_namespace,
+                chunk_id,
+                freed_segments,
+            );
+        }
     }
 
-    pub fn read_named_file(&self, name: &str) -> Vec<u8> {
+    #[tracing::instrument(level = "debug", skip(self), fields(namespace = self.namespace.0))]
+    pub fn get_named_file(&self, name: &str) -> Option<file::NamedFile> {
         let key = postcard::to_stdvec(&(self.namespace, name)).unwrap();
 
-        let file_id = self.filestore.named_files.get(key).unwrap().unwrap();
-        let file_id: file::FileId = postcard::from_bytes(&file_id).unwrap();
+        let named_file = self.filestore.named_files.get(key).unwrap()?;
+        Some(postcard::from_bytes(&named_file).unwrap())
+    }
+
+    pub fn resolve_filename(&self, name: &str) -> Option<file::FileId> {
+        Some(self.get_named_file(name)?.file_id)
+    }
+
+    pub fn read_named_file(&self, name: &str) -> Option<Vec<u8>> {
+        Some(self.read_file(self.resolve_filename(name)?))
+    }
+}
+
+/// Records the stats of a committed [`NamespacedFileStore::store_chunk`].
+fn record_chunk(stored: bool, appended: &Option<chunk::Chunk>) {
+    match appended {
+        Some(chunk) if stored => {
+            stats::chunk_stored(chunk.size as u64, chunk.compressed_size as u64)
+        }
+        _ => stats::chunk_deduplicated(),
+    }
+}
+
+/// Commits the transaction, which fails if it conflicts with a concurrent one.
+#[tracing::instrument(level = "trace", skip_all)]
+fn commit(write_tx: WriteTransaction) -> Result<(), impl std::error::Error> {
+    write_tx.commit().unwrap()
+}
+
+fn unix_timestamp() -> u64 {
+    let now = std::time::SystemTime::now();
+    now.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+}
 
-        self.read_file(file_id)
+/// Decrements the reference count, removing it once it drops to zero.
+fn decrement(
+    write_tx: &mut WriteTransaction,
+    partition: &TransactionalPartitionHandle,
+    key: Vec<u8>,
+) -> u32 {
+    let refcount: u32 = write_tx
+        .get(partition, &key)
+        .unwrap()
+        .map_or(0, |refcount| postcard::from_bytes(&refcount).unwrap());
+    let refcount = refcount.saturating_sub(1);
+    if refcount == 0 {
+        write_tx.remove(partition, key);
+    } else {
+        write_tx.insert(partition, key, postcard::to_stdvec(&refcount).unwrap());
     }
+    refcount
+}
+
+fn increment(
+    write_tx: &mut WriteTransaction,
+    partition: &TransactionalPartitionHandle,
+    key: Vec<u8>,
+) {
+    let refcount: u32 = write_tx
+        .get(partition, &key)
+        .unwrap()
+        .map_or(0, |refcount| postcard::from_bytes(&refcount).unwrap());
+    let refcount = postcard::to_stdvec(&(refcount + 1)).unwrap();
+    write_tx.insert(partition, key, refcount);
 }
 
 #[cfg(test)]
@@ -315,11 +2103,18 @@ mod tests {
         let fs = FileStore::with_namespace(&global_fs, Namespace(0));
         let file_id = fs.upload_file(b"inlined file");
         assert_eq!(fs.read_file(file_id), b"inlined file");
+        assert_eq!(fs.find_file(file_id).unwrap().size, 12);
+        assert!(FileStore::with_namespace(&global_fs, Namespace(1))
+            .find_file(file_id)
+            .is_none());
+        assert_eq!(file_id.to_string().parse(), Ok(file_id));
 
         let fs = FileStore::with_namespace(&global_fs, Namespace(1)).with_config(Config {
             inline_size: 4,
             chunk_size: 16,
             segment_size: 32,
+            compression_level: None,
+            ..Default::default()
         });
         let contents = b"chunked, and deduped file contents...";
 
@@ -330,15 +2125,1075 @@ mod tests {
         assert_eq!(fs.read_file(file_id), contents);
     }
 
+    #[test]
+    fn test_filestore_compression() {
+        let global_fs = FileStore::new();
+
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 64,
+            segment_size: 1024,
+            compression_level: Some(3),
+            ..Default::default()
+        });
+        let contents = [b"highly compressible ".as_slice(); 10].concat();
+
+        let file_id = fs.upload_file(&contents);
+        assert_eq!(fs.read_file(file_id), contents);
+
+        let file::FileContents::Chunked(chunks) = fs.get_file(file_id).contents else {
+            panic!("file should be chunked");
+        };
+        let mut zstd_stream = vec![];
+        for chunk in chunks {
+            zstd_stream.extend_from_slice(&fs.read_chunk_zstd(chunk.chunk_id));
+        }
+        assert!(zstd_stream.len() < contents.len());
+        assert_eq!(zstd::decode_all(&zstd_stream[..]).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_filestore_copy() {
+        use refcounts::ReferenceCountType as Ref;
+
+        let global_fs = FileStore::new();
+        let config = || Config {
+            inline_size: 4,
+            chunk_size: 16,
+            segment_size: 1024,
+            compression_level: None,
+            ..Default::default()
+        };
+
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config());
+        let contents = b"some file contents, which are copied around";
+        let file_id = fs.upload_file(contents);
+        let file::FileContents::Chunked(chunks) = fs.get_file(file_id).contents else {
+            panic!("file should be chunked");
+        };
+        let chunk_id = chunks[0].chunk_id;
+        let segment_id = fs.read_stored_chunk(chunk_id).0.segment_id;
+        assert_eq!(fs.refcount(Ref::File(file_id)), 1);
+        assert_eq!(fs.refcount(Ref::Chunk(chunk_id)), 1);
+        assert_eq!(fs.refcount(Ref::Segment(segment_id)), 3);
+
+        assert_eq!(fs.copy_file(&fs, file_id), Some(file_id));
+        assert_eq!(fs.refcount(Ref::File(file_id)), 2);
+        assert_eq!(fs.refcount(Ref::Chunk(chunk_id)), 1);
+
+        let other_fs = FileStore::with_namespace(&global_fs, Namespace(1)).with_config(config());
+        assert_eq!(other_fs.copy_file(&fs, file_id), Some(file_id));
+        assert_eq!(other_fs.read_file(file_id), contents);
+        assert_eq!(other_fs.refcount(Ref::File(file_id)), 1);
+        assert_eq!(other_fs.refcount(Ref::Chunk(chunk_id)), 1);
+        // the chunks are shared, and not written again
+        assert_eq!(fs.refcount(Ref::Segment(segment_id)), 6);
+
+        assert_eq!(other_fs.upload_file(contents), file_id);
+        assert_eq!(other_fs.refcount(Ref::File(file_id)), 2);
+        assert_eq!(other_fs.refcount(Ref::Chunk(chunk_id)), 1);
+        assert_eq!(fs.refcount(Ref::Segment(segment_id)), 6);
+
+        // a deleted source file is not copied
+        fs.discard_file(file_id);
+        fs.discard_file(file_id);
+        let third_fs = FileStore::with_namespace(&global_fs, Namespace(2)).with_config(config());
+        assert_eq!(third_fs.copy_file(&fs, file_id), None);
+        assert_eq!(fs.refcount(Ref::Segment(segment_id)), 3);
+    }
+
+    #[test]
+    fn test_filestore_preconditions() {
+        use file::{FileMatch, Preconditions};
+
+        let global_fs = FileStore::new();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0));
+        let file_a = fs.upload_file(b"file a");
+        let file_b = fs.upload_file(b"file b");
+
+        let create_only = Preconditions {
+            if_none_match: Some(FileMatch::Any),
+            ..Default::default()
+        };
+        assert!(fs
+            .associate_filename_if(file_a, "name", &create_only)
+            .is_ok());
+        assert_eq!(
+            fs.associate_filename_if(file_b, "name", &create_only)
+                .unwrap_err(),
+            Error::PreconditionFailed
+        );
+
+        let if_match = |file_id| Preconditions {
+            if_match: Some(FileMatch::Files(vec![file_id])),
+            ..Default::default()
+        };
+        assert_eq!(
+            fs.associate_filename_if(file_a, "name", &if_match(file_b))
+                .unwrap_err(),
+            Error::PreconditionFailed
+        );
+        let named_file = fs
+            .associate_filename_if(file_b, "name", &if_match(file_a))
+            .unwrap();
+        assert_eq!(named_file.file_id, file_b);
+        assert_eq!(fs.resolve_filename("name"), Some(file_b));
+
+        let if_exists = Preconditions {
+            if_match: Some(FileMatch::Any),
+            ..Default::default()
+        };
+        assert!(fs
+            .associate_filename_if(file_a, "other", &if_exists)
+            .is_err());
+        assert_eq!(fs.resolve_filename("other"), None);
+    }
+
+    #[test]
+    fn test_filestore_delete() {
+        use refcounts::ReferenceCountType as Ref;
+
+        let global_fs = FileStore::new();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 16,
+            segment_size: 16,
+            compression_level: None,
+            ..Default::default()
+        });
+
+        // both files share their first chunk
+        let file_a = fs.upload_file(b"a shared chunk: file a");
+        fs.associate_filename(file_a, "a");
+        assert_eq!(fs.upload_file(b"a shared chunk: file a"), file_a);
+        fs.associate_filename(file_a, "a copy");
+        let file_b = fs.upload_file(b"a shared chunk: file b");
+        fs.associate_filename(file_b, "b");
+
+        let file::FileContents::Chunked(chunks) = fs.get_file(file_a).contents else {
+            panic!("file should be chunked");
+        };
+        let shared_chunk = chunks[0].chunk_id;
+        let unique_chunk = chunks[1].chunk_id;
+        assert_eq!(fs.refcount(Ref::File(file_a)), 2);
+        assert_eq!(fs.refcount(Ref::Chunk(shared_chunk)), 2);
+
+        let deleted = |file_id| {
+            Some(file::DeletedName {
+                file_id,
+                delete_marker: None,
+            })
+        };
+        assert_eq!(
+            fs.delete_filenames(&["a", "missing", "b"]),
+            [deleted(file_a), None, deleted(file_b)]
+        );
+        assert_eq!(fs.refcount(Ref::File(file_a)), 1);
+        assert_eq!(fs.refcount(Ref::File(file_b)), 0);
+        assert_eq!(fs.refcount(Ref::Chunk(shared_chunk)), 1);
+        assert_eq!(
+            fs.read_named_file("a copy").unwrap(),
+            b"a shared chunk: file a"
+        );
+
+        // overwriting a name releases the previous file
+        let file_c = fs.upload_file(b"file c");
+        fs.associate_filename(file_c, "a copy");
+        assert_eq!(fs.refcount(Ref::File(file_a)), 0);
+        assert_eq!(fs.refcount(Ref::Chunk(shared_chunk)), 0);
+        assert_eq!(fs.refcount(Ref::Chunk(unique_chunk)), 0);
+
+        assert_eq!(fs.delete_filename("a copy"), Some(file_c));
+        assert_eq!(fs.delete_filename("a copy"), None);
+        assert_eq!(fs.refcount(Ref::File(file_c)), 0);
+    }
+
+    #[test]
+    fn test_filestore_reopen() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let config = Config {
+            inline_size: 4,
+            chunk_size: 8,
+            segment_size: 1024,
+            compression_level: None,
+            ..Default::default()
+        };
+        let contents = b"some file contents spanning chunks";
+
+        let (file_id, segment_path, committed_len) = {
+            let global_fs = FileStore::open(tempdir.path()).unwrap();
+            let fs =
+                FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config.clone());
+            let file_id = fs.upload_file(contents);
+            fs.associate_filename(file_id, "file");
+
+            let file::FileContents::Chunked(chunks) = fs.get_file(file_id).contents else {
+                panic!("file should be chunked");
+            };
+            let segment_id = fs.read_stored_chunk(chunks[0].chunk_id).0.segment_id;
+            let segment_path = global_fs.segment_path(segment_id);
+            let committed_len = std::fs::metadata(&segment_path).unwrap().len();
+            // dropped without a `shutdown`, leaving the active segment behind
+            (file_id, segment_path, committed_len)
+        };
+
+        // simulate a torn write of a chunk that was never committed
+        let mut segment = OpenOptions::new().append(true).open(&segment_path).unwrap();
+        segment.write_all(b"torn").unwrap();
+        drop(segment);
+
+        let global_fs = FileStore::open(tempdir.path()).unwrap();
+        assert_eq!(
+            std::fs::metadata(&segment_path).unwrap().len(),
+            committed_len
+        );
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config.clone());
+        assert_eq!(fs.read_named_file("file").unwrap(), contents);
+
+        // new chunks go into a fresh segment, which is sealed on shutdown
+        let other_file = fs.upload_file(b"another file");
+        global_fs.shutdown().unwrap();
+        drop(global_fs);
+
+        let global_fs = FileStore::open(tempdir.path()).unwrap();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config);
+        assert_eq!(fs.read_file(file_id), contents);
+        assert_eq!(fs.read_file(other_file), b"another file");
+    }
+
+    #[test]
+    fn test_filestore_maintenance() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let global_fs = FileStore::open(tempdir.path()).unwrap();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 16,
+            segment_size: 1024,
+            compression_level: None,
+            ..Default::default()
+        });
+        let contents = b"a file made of two chunks";
+        let file_id = fs.upload_file(contents);
+        fs.associate_filename(file_id, "file");
+
+        let info = fs.inspect_file(file_id).unwrap();
+        assert_eq!(info.refcount, 1);
+        assert_eq!(info.chunks.len(), 2);
+        assert!(info.chunks.iter().all(|chunk| chunk.refcount == 1));
+        assert!(info.chunks.iter().all(|chunk| chunk.segment_refcount == 2));
+        let segment_id = info.chunks[0].chunk.segment_id;
+        assert!(FileStore::with_namespace(&global_fs, Namespace(1))
+            .inspect_file(file_id)
+            .is_none());
+
+        let [usage] = global_fs.usage().try_into().unwrap();
+        assert_eq!(
+            (usage.namespace, usage.files, usage.chunks),
+            (Namespace(0), 1, 2)
+        );
+        assert_eq!(usage.file_bytes, contents.len() as u64);
+        assert_eq!(usage.stored_bytes, contents.len() as u64);
+
+        let report = global_fs.scrub();
+        assert_eq!((report.chunks_checked, report.corrupt_chunks.len()), (2, 0));
+
+        let segment_path = global_fs.segment_path(segment_id);
+        let mut segment = OpenOptions::new().write(true).open(&segment_path).unwrap();
+        segment.write_all(b"X").unwrap();
+        drop(segment);
+        let report = global_fs.scrub();
+        assert_eq!(
+            report.corrupt_chunks,
+            [(Namespace(0), info.chunks[0].chunk_id)]
+        );
+
+        // the released segment is still being appended to, so it is only collected once sealed
+        fs.delete_filename("file");
+        assert!(segment_path.exists());
+        global_fs.shutdown().unwrap();
+        assert_eq!(global_fs.collect_garbage().unwrap(), Default::default());
+
+        let segment = OpenOptions::new().write(true).open(&segment_path).unwrap();
+        segment
+            .set_modified(SystemTime::now() - GC_GRACE_PERIOD * 2)
+            .unwrap();
+        drop(segment);
+        let report = global_fs.collect_garbage().unwrap();
+        assert_eq!(report.segments_removed, 1);
+        assert_eq!(report.bytes_freed, contents.len() as u64);
+        assert!(!segment_path.exists());
+        assert!(global_fs.usage().is_empty());
+    }
+
+    #[test]
+    fn test_free_segments() {
+        let global_fs = FileStore::new();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 16,
+            segment_size: 16,
+            compression_level: None,
+            ..Default::default()
+        });
+        let segment_path = |file_id| {
+            let info = fs.inspect_file(file_id).unwrap();
+            global_fs.segment_path(info.chunks[0].chunk.segment_id)
+        };
+
+        // a sealed segment is freed right away once it is released ...
+        let file_id = fs.upload_file(b"an old segment..");
+        fs.associate_filename(file_id, "old");
+        let old_segment = segment_path(file_id);
+        let segment = OpenOptions::new().write(true).open(&old_segment).unwrap();
+        segment
+            .set_modified(SystemTime::now() - GC_GRACE_PERIOD * 2)
+            .unwrap();
+        drop(segment);
+        fs.delete_filename("old");
+        assert!(!old_segment.exists());
+
+        // ... unless it was modified recently, as it might still gain references
+        let file_id = fs.upload_file(b"a new segment..!");
+        fs.associate_filename(file_id, "new");
+        let new_segment = segment_path(file_id);
+        fs.delete_filename("new");
+        assert!(new_segment.exists());
+        assert_eq!(global_fs.collect_garbage().unwrap(), Default::default());
+
+        let segment = OpenOptions::new().write(true).open(&new_segment).unwrap();
+        segment
+            .set_modified(SystemTime::now() - GC_GRACE_PERIOD * 2)
+            .unwrap();
+        drop(segment);
+        assert_eq!(global_fs.collect_garbage().unwrap().segments_removed, 1);
+        assert!(!new_segment.exists());
+    }
+
+    #[test]
+    fn test_buckets() {
+        let global_fs = FileStore::new();
+        let settings = bucket::BucketSettings::default;
+
+        let bucket = global_fs
+            .create_bucket("attachments", "attachments", "org-1", settings())
+            .unwrap();
+        assert_eq!(
+            global_fs
+                .create_bucket("attachments", "attachments", "org-2", settings())
+                .unwrap_err(),
+            Error::BucketAlreadyExists
+        );
+        global_fs
+            .create_bucket("debug-files", "debug-files", "org-1", settings())
+            .unwrap();
+
+        let buckets = global_fs.list_buckets();
+        let names: Vec<_> = buckets.iter().map(|bucket| bucket.name.as_str()).collect();
+        assert_eq!(names, ["attachments", "debug-files"]);
+        assert_ne!(buckets[0].namespace, buckets[1].namespace);
+
+        let fs = FileStore::with_namespace(&global_fs, bucket.namespace);
+        let file_id = fs.upload_file(b"some file");
+        fs.associate_filename(file_id, "file");
+        assert_eq!(
+            global_fs.delete_bucket("attachments").unwrap_err(),
+            Error::BucketNotEmpty
+        );
+
+        global_fs.delete_bucket("debug-files").unwrap();
+        assert!(global_fs.get_bucket("debug-files").is_none());
+        assert_eq!(
+            global_fs.delete_bucket("debug-files").unwrap_err(),
+            Error::NoSuchBucket
+        );
+
+        // re-created buckets get a fresh namespace
+        let recreated = global_fs
+            .create_bucket("debug-files", "debug-files", "org-1", settings())
+            .unwrap();
+        assert_ne!(recreated.namespace, buckets[1].namespace);
+    }
+
+    #[test]
+    fn test_buckets_concurrent() {
+        let global_fs = FileStore::new();
+
+        // concurrent creates all allocate a namespace, so they conflict and are retried
+        let buckets: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|i| {
+                    let global_fs = &global_fs;
+                    scope.spawn(move || {
+                        let name = format!("bucket-{i}");
+                        global_fs
+                            .create_bucket(&name, "attachments", "org-1", Default::default())
+                            .unwrap()
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        let namespaces: std::collections::HashSet<_> =
+            buckets.iter().map(|bucket| bucket.namespace).collect();
+        assert_eq!(namespaces.len(), 8);
+        assert_eq!(global_fs.list_buckets().len(), 8);
+
+        std::thread::scope(|scope| {
+            for bucket in &buckets {
+                let global_fs = &global_fs;
+                scope.spawn(move || global_fs.delete_bucket(&bucket.name).unwrap());
+            }
+        });
+        assert!(global_fs.list_buckets().is_empty());
+    }
+
+    #[test]
+    fn test_usecases_concurrent() {
+        let global_fs = FileStore::new();
+        let policy = usecase::Policy::from_config(&Config::default());
+
+        // concurrent registrations, also along with buckets, all allocate a namespace
+        let namespaces: std::collections::HashSet<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|i| {
+                    let (global_fs, policy) = (&global_fs, &policy);
+                    scope.spawn(move || match i % 2 {
+                        0 => {
+                            let name = format!("usecase-{i}");
+                            let usecase = global_fs.register_usecase(&name, policy.clone());
+                            usecase.unwrap().namespace
+                        }
+                        _ => {
+                            let name = format!("bucket-{i}");
+                            let bucket = global_fs
+                                .create_bucket(&name, "default", "org-1", Default::default())
+                                .unwrap();
+                            bucket.namespace
+                        }
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        assert_eq!(namespaces.len(), 8);
+        assert_eq!(global_fs.list_usecases().len(), 4);
+    }
+
+    #[test]
+    fn test_usecases() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let policy = usecase::Policy {
+            ttl: Some(3600),
+            dedup: usecase::DedupScope::Usecase,
+            ..usecase::Policy::from_config(&Config::default())
+        };
+
+        let registered = {
+            let global_fs = FileStore::open(tempdir.path()).unwrap();
+            let registered = global_fs
+                .register_usecase("attachments", policy.clone())
+                .unwrap();
+            global_fs
+                .register_usecase(
+                    "debug-files",
+                    usecase::Policy::from_config(&Config::default()),
+                )
+                .unwrap();
+            let bucket = global_fs
+                .create_bucket("bucket", "attachments", "org-1", Default::default())
+                .unwrap();
+            assert_ne!(bucket.namespace, registered.namespace);
+            global_fs.shutdown().unwrap();
+            registered
+        };
+
+        let global_fs = FileStore::open(tempdir.path()).unwrap();
+        let usecases = global_fs.list_usecases();
+        let names: Vec<_> = usecases
+            .iter()
+            .map(|usecase| usecase.name.as_str())
+            .collect();
+        assert_eq!(names, ["attachments", "debug-files"]);
+        assert!(global_fs.get_usecase("unknown").is_none());
+
+        // updating the policy keeps the namespace
+        let updated = global_fs
+            .register_usecase(
+                "attachments",
+                usecase::Policy {
+                    ttl: None,
+                    ..policy.clone()
+                },
+            )
+            .unwrap();
+        assert_eq!(updated.namespace, registered.namespace);
+        assert_eq!(
+            global_fs.get_usecase("attachments").unwrap().policy.ttl,
+            None
+        );
+        assert_eq!(
+            global_fs
+                .register_usecase(
+                    "attachments",
+                    usecase::Policy {
+                        dedup: usecase::DedupScope::Bucket,
+                        ..policy
+                    },
+                )
+                .unwrap_err(),
+            Error::DedupScopeChanged
+        );
+    }
+
+    #[test]
+    fn test_filestore_content_namespace() {
+        let global_fs = FileStore::new();
+        let config = Config {
+            inline_size: 4,
+            chunk_size: 8,
+            compression_level: None,
+            ..Default::default()
+        };
+        let contents = b"contents shared by two namespaces";
+
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0))
+            .with_content_namespace(Namespace(2))
+            .with_config(config.clone());
+        let other_fs = FileStore::with_namespace(&global_fs, Namespace(1))
+            .with_content_namespace(Namespace(2))
+            .with_config(config);
+        let file_id = fs.upload_file(contents);
+        fs.associate_filename(file_id, "file");
+        assert_eq!(other_fs.upload_file(contents), file_id);
+        other_fs.associate_filename(file_id, "file");
+        assert_eq!(fs.refcount(refcounts::ReferenceCountType::File(file_id)), 2);
+        // shared files are found in either namespace
+        assert_eq!(fs.find_file(file_id).unwrap().size, contents.len() as u64);
+        assert!(other_fs.find_file(file_id).is_some());
+
+        let usage = global_fs.usage();
+        assert_eq!(usage.len(), 1);
+        assert_eq!((usage[0].namespace, usage[0].files), (Namespace(2), 1));
+
+        // the names stay separate
+        fs.delete_filename("file");
+        assert!(fs.resolve_filename("file").is_none());
+        assert_eq!(other_fs.read_named_file("file").unwrap(), contents);
+        other_fs.delete_filename("file");
+        assert!(global_fs.usage().is_empty());
+    }
+
+    #[test]
+    fn test_filestore_chunking() {
+        let global_fs = FileStore::new();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 1024,
+            chunking: Chunking::ContentDefined,
+            compression_level: None,
+            hash_algorithm: HashAlgorithm::Sha1,
+            ..Default::default()
+        });
+        let contents: Vec<u8> = (0..32 * 1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let chunk_ids = |file_id| {
+            let file::FileContents::Chunked(chunks) = fs.get_file(file_id).contents else {
+                panic!("file should be chunked");
+            };
+            chunks
+                .into_iter()
+                .map(|chunk| chunk.chunk_id)
+                .collect::<Vec<_>>()
+        };
+
+        let file_id = fs.upload_file(&contents);
+        assert_eq!(file_id.0.hash_algorithm, HashAlgorithm::Sha1);
+        assert_eq!(fs.read_file(file_id), contents);
+        let chunks = chunk_ids(file_id);
+        assert!(chunks.len() > 1);
+
+        // only the chunks around an insertion change
+        let mut inserted = b"inserted".to_vec();
+        inserted.extend_from_slice(&contents);
+        let inserted_id = fs.upload_file(&inserted);
+        assert_eq!(fs.read_file(inserted_id), inserted);
+        let inserted_chunks = chunk_ids(inserted_id);
+        assert_eq!(inserted_chunks[1..], chunks[1..]);
+    }
+
+    #[test]
+    fn test_quotas() {
+        let global_fs = FileStore::new();
+        let account = quota::Account {
+            usecase: "attachments".into(),
+            scope: "org-1".into(),
+            hard_quota: quota::Quota {
+                objects: Some(2),
+                logical_bytes: Some(64),
+                ..Default::default()
+            },
+            soft_quota: quota::Quota {
+                objects: Some(1),
+                ..Default::default()
+            },
+        };
+        // both namespaces are accounted to the same scope
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_account(account.clone());
+        let other_fs = FileStore::with_namespace(&global_fs, Namespace(1)).with_account(account);
+
+        let file_id = fs.upload_file(b"small file");
+        fs.associate_filename(file_id, "a");
+        let file_id = other_fs.upload_file(b"small file");
+        other_fs.associate_filename(file_id, "b");
+
+        let rejected = fs.upload_file(b"one file too many");
+        assert_eq!(
+            fs.associate_filename_if(rejected, "c", &Default::default())
+                .unwrap_err(),
+            Error::QuotaExceeded(quota::Limit::Objects)
+        );
+        fs.discard_file(rejected);
+        assert_eq!(
+            fs.refcount(refcounts::ReferenceCountType::File(rejected)),
+            0
+        );
+
+        // overwriting a name only counts the difference
+        let large = fs.upload_file(&[0; 60]);
+        assert_eq!(
+            fs.associate_filename_if(large, "a", &Default::default())
+                .unwrap_err(),
+            Error::QuotaExceeded(quota::Limit::LogicalBytes)
+        );
+        fs.discard_file(large);
+        let file_id = fs.upload_file(&[1; 40]);
+        fs.associate_filename(file_id, "a");
+
+        other_fs.delete_filename("b");
+        let expected = quota::ScopeUsage {
+            usecase: "attachments".into(),
+            scope: "org-1".into(),
+            usage: quota::Usage {
+                objects: 1,
+                logical_bytes: 40,
+                physical_bytes: 40,
+            },
+        };
+        assert_eq!(global_fs.scope_usage(), [expected]);
+    }
+
     // #[test]
-    // fn test_filestore_prechunked() {
-    //     let mut global_fs = FileStore::new();
+    #[test]
+    fn test_filestore_prechunked() {
+        let global_fs = FileStore::new();
+        let config = Config {
+            inline_size: 4,
+            chunk_size: 16,
+            compression_level: None,
+            ..Default::default()
+        };
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config.clone());
+        let chunks: [&[u8]; 2] = [b"some pre-chunked", b" content"];
+        let chunk_ids =
+            chunks.map(|chunk| chunk::ChunkId::from_contents(config.hash_algorithm, chunk));
+
+        let missing = fs.find_missing_chunks(&[chunk_ids[0], chunk_ids[1], chunk_ids[0]]);
+        assert_eq!(missing, chunk_ids);
+        fs.upload_pending_chunk(chunk_ids[0], chunks[0]);
+        assert_eq!(fs.find_missing_chunks(&chunk_ids), [chunk_ids[1]]);
+        assert!(matches!(
+            fs.assemble_file(&chunk_ids),
+            Err(Error::MissingChunks(missing)) if missing == [chunk_ids[1]]
+        ));
+
+        fs.upload_pending_chunk(chunk_ids[1], chunks[1]);
+        // uploading again only extends the expiration
+        fs.upload_pending_chunk(chunk_ids[1], chunks[1]);
+        let file_id = fs.assemble_file(&chunk_ids).unwrap();
+        let contents = b"some pre-chunked content";
+        assert_eq!(
+            file_id,
+            file::FileId::from_contents(config.hash_algorithm, contents)
+        );
+        assert_eq!(fs.read_file(file_id), contents);
+        assert_eq!(fs.upload_file(contents), file_id);
+        fs.discard_file(file_id);
+        fs.associate_filename(file_id, "file");
+        let chunk_ref = refcounts::ReferenceCountType::Chunk(chunk_ids[1]);
+        assert_eq!(fs.refcount(chunk_ref), 2);
+
+        assert_eq!(global_fs.collect_garbage().unwrap().chunk_refs_expired, 0);
+        let expired = SystemTime::now() + gc::PENDING_CHUNK_TTL * 2;
+        assert_eq!(global_fs.release_expired_chunk_refs(expired), 2);
+        assert_eq!(fs.refcount(chunk_ref), 1);
+        fs.delete_filename("file");
+        assert!(global_fs.usage().is_empty());
+
+        // chunks which are never assembled are released once they expire
+        fs.upload_pending_chunk(chunk_ids[0], chunks[0]);
+        assert_eq!(global_fs.release_expired_chunk_refs(expired), 1);
+        assert!(global_fs.usage().is_empty());
+    }
+
+    #[test]
+    fn test_filestore_concurrent() {
+        let global_fs = FileStore::new();
+        let config = Config {
+            inline_size: 4,
+            chunk_size: 16,
+            compression_level: None,
+            ..Default::default()
+        };
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config.clone());
+        let other_fs = FileStore::with_namespace(&global_fs, Namespace(1)).with_config(config);
+        let contents = b"contents uploaded concurrently";
+        let chunk_id = chunk::ChunkId::from_contents(fs.config.hash_algorithm, contents);
+
+        // concurrent writes of the same keys conflict, and are retried
+        let file_ids: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        fs.upload_pending_chunk(chunk_id, contents);
+                        let uploaded = fs.upload_file(contents);
+                        let assembled = fs.assemble_file(&[chunk_id]).unwrap();
+                        let copied = other_fs.copy_file(&fs, uploaded).unwrap();
+                        [uploaded, assembled, copied]
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        let file_id = file_ids[0][0];
+        assert_eq!(
+            fs.refcount(refcounts::ReferenceCountType::File(file_id)),
+            16
+        );
+        assert_eq!(
+            other_fs.refcount(refcounts::ReferenceCountType::File(file_id)),
+            8
+        );
+
+        for [uploaded, assembled, copied] in file_ids {
+            fs.discard_file(uploaded);
+            fs.discard_file(assembled);
+            other_fs.discard_file(copied);
+        }
+        let expired = SystemTime::now() + gc::PENDING_CHUNK_TTL * 2;
+        assert_eq!(global_fs.release_expired_chunk_refs(expired), 1);
+        assert!(global_fs.usage().is_empty());
+    }
 
-    //     let mut fs = FileStore::with_namespace(&global_fs, Namespace(0));
-    //     let chunk_1 = fs.upload_chunk(b"some pre-chunked");
-    //     let chunk_2 = fs.upload_chunk(b" content");
+    #[test]
+    fn test_assemble_released_chunks() {
+        let global_fs = FileStore::new();
+        let config = Config {
+            inline_size: 4,
+            chunk_size: 16,
+            compression_level: None,
+            ..Default::default()
+        };
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config);
+        let contents = b"contents released concurrently";
+        let chunk_id = chunk::ChunkId::from_contents(fs.config.hash_algorithm, contents);
+        let expired = SystemTime::now() + gc::PENDING_CHUNK_TTL * 2;
 
-    //     let file_id = fs.assemble_file_from_chunks(&[chunk_1, chunk_2]);
-    //     assert_eq!(fs.read_file(file_id), b"some pre-chunked content");
-    // }
+        // the pending chunk expires while it is assembled, which fails instead of panicking
+        for _ in 0..16 {
+            fs.upload_pending_chunk(chunk_id, contents);
+            let assembled = std::thread::scope(|scope| {
+                let assembled = scope.spawn(|| fs.assemble_file(&[chunk_id]));
+                global_fs.release_expired_chunk_refs(expired);
+                assembled.join().unwrap()
+            });
+            match assembled {
+                Ok(file_id) => {
+                    assert_eq!(fs.read_file(file_id), contents);
+                    fs.discard_file(file_id);
+                }
+                Err(Error::MissingChunks(missing)) => assert_eq!(missing, [chunk_id]),
+                Err(err) => panic!("{err}"),
+            }
+            assert!(global_fs.usage().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_release_appended_segment() {
+        let global_fs = FileStore::new();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 16,
+            segment_size: 32,
+            compression_level: None,
+            ..Default::default()
+        });
+
+        // the uploads append to the segment of the released file before they commit, and
+        // conflict with each other, so they are retried with the already appended chunk
+        for i in 0..16 {
+            let released = fs.upload_file(format!("released file {i:02}").as_bytes());
+            fs.associate_filename(released, "released");
+            let contents = format!("uploaded file {i:02}");
+            let uploaded: Vec<_> = std::thread::scope(|scope| {
+                let uploads: Vec<_> = (0..4)
+                    .map(|_| scope.spawn(|| fs.upload_file(contents.as_bytes())))
+                    .collect();
+                fs.delete_filename("released");
+                uploads
+                    .into_iter()
+                    .map(|upload| upload.join().unwrap())
+                    .collect()
+            });
+            for file_id in uploaded {
+                assert_eq!(fs.read_file(file_id), contents.as_bytes());
+                fs.discard_file(file_id);
+            }
+            assert!(global_fs.usage().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_filestore_allocate() {
+        let global_fs = FileStore::new();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0));
+
+        let name = fs.allocate_filename(file::IdFormat::Uuid);
+        assert_eq!(name.len(), 32);
+        assert!(fs.is_reserved(&name));
+        let compact = fs.allocate_filename(file::IdFormat::Compact);
+        assert_eq!(compact.len(), 22);
+        assert_ne!(name, compact);
+
+        let create_only = file::Preconditions {
+            if_none_match: Some(file::FileMatch::Any),
+            ..Default::default()
+        };
+        let idempotent = file::Preconditions {
+            if_none_match: Some(file::FileMatch::Any),
+            idempotent: true,
+            ..Default::default()
+        };
+        let file_id = fs.upload_file(b"uploaded once");
+        let named_file = fs
+            .associate_filename_if(file_id, &name, &idempotent)
+            .unwrap();
+        assert!(!fs.is_reserved(&name));
+
+        // create-only uploads fail on an existing name, even with the same contents
+        let retried = fs.upload_file(b"uploaded once");
+        assert_eq!(
+            fs.associate_filename_if(retried, &name, &create_only)
+                .unwrap_err(),
+            Error::PreconditionFailed
+        );
+
+        // retrying an idempotent upload of the same contents succeeds
+        let retried = fs
+            .associate_filename_if(retried, &name, &idempotent)
+            .unwrap();
+        assert_eq!(retried.last_modified, named_file.last_modified);
+        let file_ref = refcounts::ReferenceCountType::File(file_id);
+        assert_eq!(fs.refcount(file_ref), 1);
+        let other = fs.upload_file(b"other contents");
+        assert_eq!(
+            fs.associate_filename_if(other, &name, &idempotent)
+                .unwrap_err(),
+            Error::PreconditionFailed
+        );
+        fs.discard_file(other);
+
+        // names which already exist are not reserved
+        fs.reserve_filename(&name);
+        assert!(!fs.is_reserved(&name));
+        fs.reserve_filename("client-provided");
+        assert!(fs.is_reserved("client-provided"));
+
+        assert_eq!(global_fs.collect_garbage().unwrap().reservations_expired, 0);
+        let expired = SystemTime::now() + gc::RESERVATION_TTL * 2;
+        assert_eq!(global_fs.remove_expired_reservations(expired), 2);
+        assert!(!fs.is_reserved(&compact));
+    }
+
+    #[test]
+    fn test_versioning() {
+        let global_fs = FileStore::new();
+        let bucket = global_fs
+            .create_bucket("versioned", "versioned", "org-1", Default::default())
+            .unwrap();
+        let fs = FileStore::with_namespace(&global_fs, bucket.namespace);
+        let unversioned = fs.upload_file(b"unversioned");
+        let null_version = fs.associate_filename(unversioned, "file");
+        assert_eq!(null_version.version_id, file::VersionId::NULL);
+
+        let bucket = global_fs.enable_versioning("versioned").unwrap();
+        assert!(bucket.settings.versioned);
+        let fs = fs.with_versioning(true);
+        let first = fs.upload_file(b"first");
+        let first_version = fs.associate_filename(first, "file").version_id;
+        assert!(first_version > file::VersionId::NULL);
+        let second = fs.upload_file(b"second");
+        let second_version = fs.associate_filename(second, "file").version_id;
+        assert!(second_version > first_version);
+
+        // previous versions keep their files
+        assert_eq!(fs.resolve_filename("file"), Some(second));
+        let version = fs.get_version("file", file::VersionId::NULL).unwrap();
+        assert_eq!(version.file_id, Some(unversioned));
+        let version = fs.get_version("file", first_version).unwrap();
+        assert_eq!(fs.read_file(version.file_id.unwrap()), b"first");
+
+        // deleting adds a delete marker as the latest version
+        assert_eq!(fs.delete_filename("file"), Some(second));
+        assert!(fs.resolve_filename("file").is_none());
+        let versions = fs.list_versions("");
+        let version_ids: Vec<_> = versions.iter().map(|version| version.version_id).collect();
+        assert_eq!(
+            version_ids[1..],
+            [second_version, first_version, file::VersionId::NULL]
+        );
+        assert!(versions[0].is_latest && versions[0].version.file_id.is_none());
+        assert!(versions[1..].iter().all(|version| !version.is_latest));
+        assert!(fs.list_versions("other").is_empty());
+        assert_eq!(
+            global_fs.delete_bucket("versioned").unwrap_err(),
+            Error::BucketNotEmpty
+        );
+
+        // removing the delete marker, or the current version, restores the previous one
+        fs.delete_version("file", version_ids[0], false).unwrap();
+        assert_eq!(fs.resolve_filename("file"), Some(second));
+        fs.delete_version("file", second_version, false).unwrap();
+        assert_eq!(fs.resolve_filename("file"), Some(first));
+        assert_eq!(fs.refcount(refcounts::ReferenceCountType::File(second)), 0);
+        assert_eq!(fs.delete_version("file", second_version, false), Ok(None));
+
+        fs.delete_version("file", first_version, false).unwrap();
+        fs.delete_version("file", file::VersionId::NULL, false)
+            .unwrap();
+        assert!(fs.list_versions("").is_empty());
+        assert!(global_fs.usage().is_empty());
+        global_fs.delete_bucket("versioned").unwrap();
+    }
+
+    #[test]
+    fn test_tags() {
+        let global_fs = FileStore::new();
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_versioning(true);
+        let tag = |key: &str, value: &str| file::Tag {
+            key: key.into(),
+            value: value.into(),
+        };
+        let first = fs.upload_file(b"first");
+        let first = fs
+            .associate_tagged_filename_if(first, "file", &Default::default(), vec![tag("a", "1")])
+            .unwrap();
+        assert_eq!(first.tags, [tag("a", "1")]);
+
+        // overwriting keeps the tags with the previous version
+        let second = fs.upload_file(b"second");
+        let second = fs.associate_filename(second, "file");
+        assert!(second.tags.is_empty());
+        let version = fs.get_version("file", first.version_id).unwrap();
+        assert_eq!(version.tags, [tag("a", "1")]);
+
+        assert!(fs.set_tags("file", second.version_id, vec![tag("b", "2")]));
+        assert_eq!(fs.get_named_file("file").unwrap().tags, [tag("b", "2")]);
+        assert!(fs.set_tags("file", first.version_id, vec![]));
+        assert!(fs
+            .get_version("file", first.version_id)
+            .unwrap()
+            .tags
+            .is_empty());
+
+        // delete markers cannot be tagged
+        fs.delete_filename("file");
+        let delete_marker = fs.list_versions("file")[0].version_id;
+        assert!(!fs.set_tags("file", delete_marker, vec![tag("c", "3")]));
+        assert!(!fs.set_tags("other", second.version_id, vec![]));
+    }
+
+    #[test]
+    fn test_object_lock() {
+        let global_fs = FileStore::new();
+        global_fs
+            .create_bucket("locked", "locked", "org-1", Default::default())
+            .unwrap();
+        let default_retention = Some(bucket::DefaultRetention {
+            mode: file::RetentionMode::Governance,
+            days: 1,
+        });
+        let bucket = global_fs
+            .set_object_lock("locked", default_retention)
+            .unwrap();
+        assert!(bucket.settings.versioned && bucket.settings.object_lock);
+        let fs = FileStore::with_namespace(&global_fs, bucket.namespace)
+            .with_versioning(true)
+            .with_default_retention(bucket.settings.default_retention);
+        let first = fs.upload_file(b"first");
+        let first = fs.associate_filename(first, "file");
+        let retention = first.lock.retention.unwrap();
+        assert_eq!(retention.mode, file::RetentionMode::Governance);
+        assert!(retention.retain_until >= first.last_modified + 24 * 60 * 60);
+
+        // deleting the name only adds a delete marker, but the version itself is retained
+        fs.delete_filename("file");
+        let delete_marker = fs.list_versions("")[0].version_id;
+        assert_eq!(
+            fs.delete_version("file", first.version_id, false),
+            Err(Error::ObjectLocked)
+        );
+        fs.delete_version("file", delete_marker, false).unwrap();
+
+        // legal holds apply regardless of retention
+        let legal_hold = |legal_hold| move |lock| Ok(file::Lock { legal_hold, ..lock });
+        let lock = fs.update_lock("file", first.version_id, legal_hold(true));
+        assert!(lock.unwrap().unwrap().legal_hold);
+        assert_eq!(
+            fs.delete_version("file", first.version_id, true),
+            Err(Error::ObjectLocked)
+        );
+        fs.update_lock("file", first.version_id, legal_hold(false))
+            .unwrap();
+        fs.delete_version("file", first.version_id, true)
+            .unwrap()
+            .unwrap();
+        assert!(fs
+            .update_lock("file", first.version_id, legal_hold(true))
+            .unwrap()
+            .is_none());
+
+        // compliance retention cannot be bypassed or shortened
+        let second = fs.upload_file(b"second");
+        let second = fs.associate_filename(second, "file");
+        let now = second.last_modified;
+        let compliance = file::Retention {
+            mode: file::RetentionMode::Compliance,
+            retain_until: now + 2 * 24 * 60 * 60,
+        };
+        fs.update_lock("file", second.version_id, |lock| {
+            lock.with_retention(Some(compliance), now, false)
+        })
+        .unwrap();
+        let shortened = |lock: file::Lock| lock.with_retention(None, now, true);
+        assert_eq!(
+            fs.update_lock("file", second.version_id, shortened),
+            Err(Error::ObjectLocked)
+        );
+        assert_eq!(
+            fs.delete_version("file", second.version_id, true),
+            Err(Error::ObjectLocked)
+        );
+        assert_eq!(fs.resolve_filename("file"), Some(second.file_id));
+    }
 }
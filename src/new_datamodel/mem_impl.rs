@@ -1,17 +1,10 @@
 use core::fmt;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::RwLock;
+use std::time::SystemTime;
 
 use super::*;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub struct Namespace(pub u64);
-impl fmt::Debug for Namespace {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Namespace({})", self.0)
-    }
-}
-
 #[derive(Default)]
 pub struct Segment(Vec<u8>);
 impl fmt::Debug for Segment {
@@ -27,11 +20,18 @@ impl fmt::Debug for Segment {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Default)]
 pub struct FileStore {
     chunks: HashMap<(Namespace, chunk::ChunkId), chunk::Chunk>,
     files: HashMap<(Namespace, file::FileId), file::File>,
-    named_files: HashMap<(Namespace, String), file::FileId>,
+    named_files: HashMap<(Namespace, String), file::NamedFile>,
+    /// The noncurrent versions and delete markers of names in versioned buckets.
+    versions: HashMap<(Namespace, String), BTreeMap<file::VersionId, file::Version>>,
+    buckets: HashMap<String, bucket::Bucket>,
+    usecases: HashMap<String, usecase::Usecase>,
+    scope_usage: HashMap<(String, String), quota::Usage>,
+    last_namespace: u64,
 
     segments: HashMap<segment::SegmentId, Segment>,
     last_segment: Option<segment::SegmentId>,
@@ -40,41 +40,387 @@ pub struct FileStore {
     segment_refcounts: HashMap<segment::SegmentId, u32>,
 
     chunk_refs: HashMap<(Namespace, chunk::ChunkId), gc::ChunkRef>,
+    reservations: HashMap<(Namespace, String), gc::Timestamp>,
     file_refs: HashMap<(Namespace, String), gc::FileReference>,
 }
 
 impl FileStore {
-    pub fn with_namespace(slf: &RwLock<Self>, namespace: Namespace) -> NamespacedFileStore {
+    fn addref(&mut self, namespace: Namespace, ty: refcounts::ReferenceCountType) {
+        *self
+            .namespaced_refcounts
+            .entry((namespace, ty))
+            .or_default() += 1;
+    }
+
+    fn addref_segment(&mut self, segment_id: segment::SegmentId) {
+        *self.segment_refcounts.entry(segment_id).or_default() += 1;
+    }
+
+    /// Decrements the reference count of `ty`, returning the new count.
+    fn release(&mut self, namespace: Namespace, ty: refcounts::ReferenceCountType) -> u32 {
+        let key = (namespace, ty);
+        let refcount = self.namespaced_refcounts.get(&key).copied().unwrap_or(0);
+        let refcount = refcount.saturating_sub(1);
+        if refcount == 0 {
+            self.namespaced_refcounts.remove(&key);
+        } else {
+            self.namespaced_refcounts.insert(key, refcount);
+        }
+        refcount
+    }
+
+    /// Releases a reference to the file, deleting it once it is no longer referenced, which
+    /// in turn releases its chunks and frees unreferenced segments.
+    fn release_file(&mut self, namespace: Namespace, file_id: file::FileId) {
+        if self.release(namespace, refcounts::ReferenceCountType::File(file_id)) > 0 {
+            return;
+        }
+        let file = self.files.remove(&(namespace, file_id)).unwrap();
+
+        let file::FileContents::Chunked(chunks) = file.contents else {
+            return;
+        };
+        for file::FileChunk { chunk_id, .. } in chunks {
+            self.release_chunk(namespace, chunk_id);
+        }
+    }
+
+    /// Releases a reference to the chunk, deleting it once it is no longer referenced, which
+    /// in turn frees its segment once that is unreferenced.
+    fn release_chunk(&mut self, namespace: Namespace, chunk_id: chunk::ChunkId) {
+        if self.release(namespace, refcounts::ReferenceCountType::Chunk(chunk_id)) > 0 {
+            return;
+        }
+        let chunk = self.chunks.remove(&(namespace, chunk_id)).unwrap();
+
+        let segment_id = chunk.segment_id;
+        let refcount = self.segment_refcounts.get_mut(&segment_id).unwrap();
+        *refcount -= 1;
+        if *refcount == 0 {
+            self.segment_refcounts.remove(&segment_id);
+            // the segment currently being written to is kept around
+            if self.last_segment != Some(segment_id) {
+                self.segments.remove(&segment_id);
+                stats::segments_collected(1);
+                stats::segments(self.segments.len());
+            }
+        }
+    }
+
+    pub fn with_namespace(slf: &RwLock<Self>, namespace: Namespace) -> NamespacedFileStore<'_> {
         NamespacedFileStore {
             filestore: slf,
             config: Config::default(),
             namespace,
+            content_namespace: namespace,
+            account: None,
+            versioned: false,
+            default_retention: None,
         }
     }
-}
 
-pub struct Config {
-    inline_size: u64,
-    chunk_size: u64,
-    segment_size: u64,
-}
+    /// Creates a new bucket, allocating a fresh `Namespace` for it.
+    ///
+    /// Namespaces are never reused, so a re-created bucket does not see any leftovers of a
+    /// previous bucket with the same name.
+    pub fn create_bucket(
+        &mut self,
+        name: &str,
+        usecase: &str,
+        scope: &str,
+        settings: bucket::BucketSettings,
+    ) -> Result<bucket::Bucket, Error> {
+        if self.buckets.contains_key(name) {
+            return Err(Error::BucketAlreadyExists);
+        }
+
+        let namespace = self.allocate_namespace();
+        let now = SystemTime::now();
+        let bucket = bucket::Bucket {
+            name: name.into(),
+            namespace,
+            usecase: usecase.into(),
+            scope: scope.into(),
+            created: now.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+            settings,
+        };
+        self.buckets.insert(name.into(), bucket.clone());
+
+        Ok(bucket)
+    }
+
+    pub fn get_bucket(&self, name: &str) -> Option<bucket::Bucket> {
+        self.buckets.get(name).cloned()
+    }
+
+    pub fn list_buckets(&self) -> Vec<bucket::Bucket> {
+        let mut buckets: Vec<_> = self.buckets.values().cloned().collect();
+        buckets.sort_by(|a, b| a.name.cmp(&b.name));
+        buckets
+    }
+
+    /// Deletes the bucket, which has to be empty.
+    pub fn delete_bucket(&mut self, name: &str) -> Result<(), Error> {
+        let bucket = self.buckets.get(name).ok_or(Error::NoSuchBucket)?;
+
+        let namespace = bucket.namespace;
+        if self.named_files.keys().any(|(ns, _)| *ns == namespace)
+            || self.versions.keys().any(|(ns, _)| *ns == namespace)
+        {
+            return Err(Error::BucketNotEmpty);
+        }
+
+        self.buckets.remove(name);
+        Ok(())
+    }
+
+    /// Enables versioning of the bucket, which keeps the previous files of overwritten and
+    /// deleted names from then on.
+    pub fn enable_versioning(&mut self, name: &str) -> Result<bucket::Bucket, Error> {
+        let bucket = self.buckets.get_mut(name).ok_or(Error::NoSuchBucket)?;
+        bucket.settings.versioned = true;
+        Ok(bucket.clone())
+    }
+
+    /// Enables object lock on the bucket, along with versioning, and sets the retention of
+    /// files written to it from then on.
+    pub fn set_object_lock(
+        &mut self,
+        name: &str,
+        default_retention: Option<bucket::DefaultRetention>,
+    ) -> Result<bucket::Bucket, Error> {
+        let bucket = self.buckets.get_mut(name).ok_or(Error::NoSuchBucket)?;
+        bucket.settings.versioned = true;
+        bucket.settings.object_lock = true;
+        bucket.settings.default_retention = default_retention;
+        Ok(bucket.clone())
+    }
+
+    /// Replaces the lifecycle rules of the bucket.
+    pub fn set_lifecycle(
+        &mut self,
+        name: &str,
+        rules: Vec<lifecycle::Rule>,
+    ) -> Result<bucket::Bucket, Error> {
+        let bucket = self.buckets.get_mut(name).ok_or(Error::NoSuchBucket)?;
+        bucket.settings.lifecycle = rules;
+        Ok(bucket.clone())
+    }
+
+    /// Makes the latest version of a name without a current file its current one, unless that
+    /// version is a delete marker.
+    fn promote_latest_version(&mut self, key: &(Namespace, String)) {
+        let Some(versions) = self.versions.get_mut(key) else {
+            return;
+        };
+        let Some(latest) = versions.last_entry() else {
+            return;
+        };
+        let Some(file_id) = latest.get().file_id else {
+            return;
+        };
+        let named_file = file::NamedFile {
+            file_id,
+            last_modified: latest.get().last_modified,
+            last_accessed: latest.get().last_modified,
+            version_id: *latest.key(),
+            lock: latest.get().lock,
+            tags: latest.get().tags.clone(),
+        };
+        latest.remove();
+        if versions.is_empty() {
+            self.versions.remove(key);
+        }
+        self.named_files.insert(key.clone(), named_file);
+    }
 
-impl Default for Config {
-    fn default() -> Self {
-        const MEG: u64 = 2 ^ 20;
-        const GIG: u64 = 2 ^ 30;
-        Self {
-            inline_size: 256,
-            chunk_size: 8 * MEG,
-            segment_size: GIG,
+    /// Allocates a `Namespace` that was never handed out before.
+    fn allocate_namespace(&mut self) -> Namespace {
+        self.last_namespace += 1;
+        Namespace(self.last_namespace)
+    }
+
+    /// Registers the usecase, or updates the policy of an already registered one.
+    ///
+    /// A new usecase gets a fresh `Namespace`, which its buckets share when deduplicating per
+    /// usecase. The dedup scope of a registered usecase cannot change.
+    pub fn register_usecase(
+        &mut self,
+        name: &str,
+        policy: usecase::Policy,
+    ) -> Result<usecase::Usecase, Error> {
+        let namespace = match self.usecases.get(name) {
+            Some(current) if current.policy.dedup != policy.dedup => {
+                return Err(Error::DedupScopeChanged);
+            }
+            Some(current) => current.namespace,
+            None => self.allocate_namespace(),
+        };
+
+        let usecase = usecase::Usecase {
+            name: name.into(),
+            namespace,
+            policy,
+        };
+        self.usecases.insert(name.into(), usecase.clone());
+        Ok(usecase)
+    }
+
+    pub fn get_usecase(&self, name: &str) -> Option<usecase::Usecase> {
+        self.usecases.get(name).cloned()
+    }
+
+    pub fn list_usecases(&self) -> Vec<usecase::Usecase> {
+        let mut usecases: Vec<_> = self.usecases.values().cloned().collect();
+        usecases.sort_by(|a, b| a.name.cmp(&b.name));
+        usecases
+    }
+
+    /// The named files of each `(usecase, scope)`, sorted by usecase and scope.
+    pub fn scope_usage(&self) -> Vec<quota::ScopeUsage> {
+        let mut scope_usage: Vec<_> = self
+            .scope_usage
+            .iter()
+            .map(|((usecase, scope), usage)| quota::ScopeUsage {
+                usecase: usecase.clone(),
+                scope: scope.clone(),
+                usage: *usage,
+            })
+            .collect();
+        scope_usage.sort_by(|a, b| (&a.usecase, &a.scope).cmp(&(&b.usecase, &b.scope)));
+        scope_usage
+    }
+
+    /// The usage accounted for a name pointing to the file.
+    fn file_usage(&self, namespace: Namespace, file_id: file::FileId) -> quota::Usage {
+        let file = &self.files[&(namespace, file_id)];
+        let physical_bytes = match &file.contents {
+            file::FileContents::Inline(contents) => contents.len() as u64,
+            file::FileContents::Chunked(chunks) => chunks
+                .iter()
+                .map(|file::FileChunk { chunk_id, .. }| {
+                    self.chunks[&(namespace, *chunk_id)].compressed_size as u64
+                })
+                .sum(),
+        };
+        quota::Usage::of_file(file, physical_bytes)
+    }
+
+    /// Accounts `added` and `removed` named files to the scope of `account`, failing if that
+    /// exceeds its hard quota.
+    fn update_usage(
+        &mut self,
+        account: &quota::Account,
+        added: quota::Usage,
+        removed: quota::Usage,
+    ) -> Result<(), Error> {
+        let key = (account.usecase.clone(), account.scope.clone());
+        let previous = self.scope_usage.get(&key).copied().unwrap_or_default();
+
+        let usage = previous.add(added).sub(removed);
+        account.check(&previous, &usage)?;
+        self.scope_usage.insert(key, usage);
+        Ok(())
+    }
+
+    /// Sums up the storage used by each namespace.
+    pub fn usage(&self) -> Vec<report::NamespaceUsage> {
+        let files = self
+            .files
+            .iter()
+            .map(|((namespace, _), file)| (*namespace, file.clone()));
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|((namespace, _), chunk)| (*namespace, chunk.clone()));
+        report::NamespaceUsage::collect(files, chunks)
+    }
+
+    /// Releases expired chunk references and name reservations, and removes segments which no chunk references
+    /// anymore, and which were not freed right away, like a segment whose chunks were all
+    /// released while it was still being appended to.
+    pub fn collect_garbage(&mut self) -> report::GcReport {
+        let mut report = report::GcReport {
+            chunk_refs_expired: self.release_expired_chunk_refs(SystemTime::now()),
+            reservations_expired: self.remove_expired_reservations(SystemTime::now()),
+            ..Default::default()
+        };
+        let last_segment = self.last_segment;
+        let segment_refcounts = &self.segment_refcounts;
+        self.segments.retain(|segment_id, segment| {
+            let keep =
+                last_segment == Some(*segment_id) || segment_refcounts.contains_key(segment_id);
+            if !keep {
+                report.segments_removed += 1;
+                report.bytes_freed += segment.0.len() as u64;
+            }
+            keep
+        });
+
+        stats::segments_collected(report.segments_removed as usize);
+        stats::segments(self.segments.len());
+        stats::gc_finished();
+        report
+    }
+
+    /// Releases the references of chunks which were not assembled into files in time,
+    /// returning how many were released.
+    fn release_expired_chunk_refs(&mut self, now: SystemTime) -> u64 {
+        let now = gc::Timestamp::at(now);
+        let expired: Vec<_> = self
+            .chunk_refs
+            .iter()
+            .filter(|(_, chunk_ref)| chunk_ref.expires <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        for (namespace, chunk_id) in &expired {
+            self.chunk_refs.remove(&(*namespace, *chunk_id));
+            self.release_chunk(*namespace, *chunk_id);
+        }
+        expired.len() as u64
+    }
+
+    /// Removes the reservations of names which were not uploaded to in time, returning how
+    /// many were removed.
+    fn remove_expired_reservations(&mut self, now: SystemTime) -> u64 {
+        let now = gc::Timestamp::at(now);
+        let reserved = self.reservations.len();
+        self.reservations.retain(|_, expires| *expires > now);
+        (reserved - self.reservations.len()) as u64
+    }
+
+    /// Verifies the contents of all stored chunks against their IDs.
+    pub fn scrub(&self) -> report::ScrubReport {
+        let mut report = report::ScrubReport::default();
+        for ((namespace, chunk_id), chunk) in &self.chunks {
+            let start = chunk.offset_in_segment as usize;
+            let range = start..start + chunk.compressed_size as usize;
+            let stored = self
+                .segments
+                .get(&chunk.segment_id)
+                .and_then(|segment| segment.0.get(range))
+                .map(Vec::from)
+                .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into());
+            report.check(*namespace, *chunk_id, chunk, stored);
         }
+        stats::scrub_finished();
+        report
     }
 }
 
 pub struct NamespacedFileStore<'fs> {
     filestore: &'fs RwLock<FileStore>,
     config: Config,
+    /// Holds the names.
     namespace: Namespace,
+    /// Holds the files and chunks the names point to.
+    content_namespace: Namespace,
+    /// The scope named files are accounted to.
+    account: Option<quota::Account>,
+    /// Whether names keep their previous files as versions.
+    versioned: bool,
+    /// The retention of newly written files.
+    default_retention: Option<bucket::DefaultRetention>,
 }
 
 impl NamespacedFileStore<'_> {
@@ -83,54 +429,267 @@ impl NamespacedFileStore<'_> {
         self
     }
 
-    // fn addref(&mut self, ty: refcounts::ReferenceCountType) {}
+    /// Stores the files and chunks in a separate namespace, which can be shared with other
+    /// namespaces to deduplicate contents across them.
+    pub fn with_content_namespace(mut self, content_namespace: Namespace) -> Self {
+        self.content_namespace = content_namespace;
+        self
+    }
 
+    /// Accounts the named files to the scope of `account`, enforcing its quotas.
+    pub fn with_account(mut self, account: quota::Account) -> Self {
+        self.account = Some(account);
+        self
+    }
+
+    /// Keeps the previous file of names which are overwritten or deleted as a version.
+    pub fn with_versioning(mut self, versioned: bool) -> Self {
+        self.versioned = versioned;
+        self
+    }
+
+    /// Retains newly written files according to `default_retention`.
+    pub fn with_default_retention(
+        mut self,
+        default_retention: Option<bucket::DefaultRetention>,
+    ) -> Self {
+        self.default_retention = default_retention;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the current reference count of `ty`.
+    pub fn refcount(&self, ty: refcounts::ReferenceCountType) -> u32 {
+        let fs = self.filestore.read().unwrap();
+        match ty {
+            refcounts::ReferenceCountType::Segment(segment_id) => {
+                fs.segment_refcounts.get(&segment_id).copied()
+            }
+            ty => fs
+                .namespaced_refcounts
+                .get(&(self.content_namespace, ty))
+                .copied(),
+        }
+        .unwrap_or_default()
+    }
+
+    #[tracing::instrument(level = "trace", skip_all, fields(namespace = self.namespace.0, size = contents.len()))]
     pub fn upload_chunk(&self, contents: &[u8]) -> chunk::ChunkId {
-        let chunk_id = chunk::ChunkId::from_contents(contents);
-        let key = (self.namespace, chunk_id);
+        let chunk_id = chunk::ChunkId::from_contents(self.config.hash_algorithm, contents);
 
         let mut fs = self.filestore.write().unwrap();
-        if !fs.chunks.contains_key(&key) {
-            let segment_id = *fs.last_segment.get_or_insert_with(|| segment::SegmentId {
-                uuid: uuid::Uuid::new_v4().into_bytes(),
-            });
+        self.store_chunk(&mut fs, chunk_id, contents.len() as u32, || {
+            chunk::Compression::compress(contents, self.config.compression_level)
+        });
+        fs.addref(
+            self.content_namespace,
+            refcounts::ReferenceCountType::Chunk(chunk_id),
+        );
 
-            // TODO:
-            // self.addref(refcounts::ReferenceCountType::Segment(segment_id));
-            let segment = fs.segments.entry(segment_id).or_default();
+        chunk_id
+    }
 
-            let offset_in_segment = segment.0.len() as u32;
-            segment.0.extend_from_slice(contents);
+    /// Stores the chunk contents of the given `size`, unless the chunk is already stored.
+    ///
+    /// The `stored` contents are only produced when they are written.
+    fn store_chunk(
+        &self,
+        fs: &mut FileStore,
+        chunk_id: chunk::ChunkId,
+        size: u32,
+        stored: impl FnOnce() -> (chunk::Compression, Vec<u8>),
+    ) {
+        let key = (self.content_namespace, chunk_id);
+        if fs.chunks.contains_key(&key) {
+            stats::chunk_deduplicated();
+            return;
+        }
 
-            if segment.0.len() as u64 >= self.config.segment_size {
-                fs.last_segment.take();
-            }
+        let (compression, stored) = stored();
+        let segment_id = *fs.last_segment.get_or_insert_with(|| segment::SegmentId {
+            uuid: uuid::Uuid::new_v4().into_bytes(),
+        });
 
-            let chunk = chunk::Chunk {
-                size: contents.len() as u32,
-                compression: chunk::Compression::None,
-                compressed_size: contents.len() as u32,
-                segment_id,
-                offset_in_segment,
-            };
+        let segment = fs.segments.entry(segment_id).or_default();
 
-            fs.chunks.insert(key, chunk);
+        let offset_in_segment = segment.0.len() as u32;
+        segment.0.extend_from_slice(&stored);
+        let segment_len = segment.0.len() as u64;
+
+        stats::segments(fs.segments.len());
+        if segment_len >= self.config.segment_size {
+            fs.last_segment.take();
+            stats::active_segment(0);
+        } else {
+            stats::active_segment(segment_len);
         }
-        // TODO:
-        // self.addref(refcounts::ReferenceCountType::Chunk(chunk_id));
 
-        chunk_id
+        let chunk = chunk::Chunk {
+            size,
+            compression,
+            compressed_size: stored.len() as u32,
+            segment_id,
+            offset_in_segment,
+        };
+
+        fs.chunks.insert(key, chunk);
+        fs.addref_segment(segment_id);
+        stats::chunk_stored(size as u64, stored.len() as u64);
+    }
+
+    /// Uploads a chunk ahead of assembling it into a file with [`Self::assemble_file`].
+    ///
+    /// The `chunk_id` has to match the contents, which the caller is responsible for. The
+    /// chunk is kept around for the [`gc::PENDING_CHUNK_TTL`], and uploading it again
+    /// extends that.
+    #[tracing::instrument(level = "trace", skip_all, fields(namespace = self.namespace.0, size = contents.len()))]
+    pub fn upload_pending_chunk(&self, chunk_id: chunk::ChunkId, contents: &[u8]) {
+        self.add_pending_chunk(chunk_id, contents.len() as u32, || {
+            chunk::Compression::compress(contents, self.config.compression_level)
+        });
+    }
+
+    /// Like [`Self::upload_pending_chunk`], but with contents which are already compressed as
+    /// a zstd frame, and stored verbatim.
+    ///
+    /// The frame has to decompress to `size` bytes matching the `chunk_id`, which the caller
+    /// is responsible for.
+    #[tracing::instrument(level = "trace", skip_all, fields(namespace = self.namespace.0, size))]
+    pub fn upload_pending_chunk_zstd(&self, chunk_id: chunk::ChunkId, size: u32, zstd: &[u8]) {
+        self.add_pending_chunk(chunk_id, size, || (chunk::Compression::Zstd, zstd.into()));
+    }
+
+    fn add_pending_chunk(
+        &self,
+        chunk_id: chunk::ChunkId,
+        size: u32,
+        stored: impl FnOnce() -> (chunk::Compression, Vec<u8>),
+    ) {
+        let chunk_ref = gc::ChunkRef {
+            chunk_id,
+            expires: gc::Timestamp::after(gc::PENDING_CHUNK_TTL),
+        };
+
+        let mut fs = self.filestore.write().unwrap();
+        self.store_chunk(&mut fs, chunk_id, size, stored);
+        let previous = fs
+            .chunk_refs
+            .insert((self.content_namespace, chunk_id), chunk_ref);
+        if previous.is_none() {
+            fs.addref(
+                self.content_namespace,
+                refcounts::ReferenceCountType::Chunk(chunk_id),
+            );
+        }
+    }
+
+    /// The chunks which are not stored, in the order they were given.
+    pub fn find_missing_chunks(&self, chunk_ids: &[chunk::ChunkId]) -> Vec<chunk::ChunkId> {
+        let fs = self.filestore.read().unwrap();
+        let mut seen = std::collections::HashSet::new();
+        chunk_ids
+            .iter()
+            .copied()
+            .filter(|chunk_id| {
+                seen.insert(*chunk_id)
+                    && !fs.chunks.contains_key(&(self.content_namespace, *chunk_id))
+            })
+            .collect()
+    }
+
+    /// Assembles a file from already stored chunks, returning a new reference to it.
+    ///
+    /// The chunks are read to compute the ID of the file, but no contents are written.
+    #[tracing::instrument(level = "debug", skip_all, fields(namespace = self.namespace.0, chunks = chunk_ids.len()))]
+    pub fn assemble_file(&self, chunk_ids: &[chunk::ChunkId]) -> Result<file::FileId, Error> {
+        let mut fs = self.filestore.write().unwrap();
+        let missing: Vec<_> = chunk_ids
+            .iter()
+            .copied()
+            .filter(|chunk_id| !fs.chunks.contains_key(&(self.content_namespace, *chunk_id)))
+            .collect();
+        if !missing.is_empty() {
+            drop(fs);
+            return Err(Error::MissingChunks(self.find_missing_chunks(&missing)));
+        }
+
+        let mut hasher = ContentHasher::new(self.config.hash_algorithm);
+        let mut file_size = 0;
+        let chunks: Vec<_> = chunk_ids
+            .iter()
+            .map(|&chunk_id| {
+                let chunk = &fs.chunks[&(self.content_namespace, chunk_id)];
+                let start = chunk.offset_in_segment as usize;
+                let stored = &fs.segments[&chunk.segment_id].0
+                    [start..start + chunk.compressed_size as usize];
+                let contents = chunk.compression.decompress(stored, chunk.size);
+                hasher.update(&contents);
+                file_size += contents.len() as u64;
+                file::FileChunk {
+                    chunk_size: contents.len() as u32,
+                    chunk_id,
+                }
+            })
+            .collect();
+        let file_id = file::FileId(hasher.finalize());
+
+        let deduplicated = fs.files.contains_key(&(self.content_namespace, file_id));
+        if !deduplicated {
+            for file::FileChunk { chunk_id, .. } in &chunks {
+                fs.addref(
+                    self.content_namespace,
+                    refcounts::ReferenceCountType::Chunk(*chunk_id),
+                );
+            }
+            let file = file::File {
+                size: file_size,
+                contents: file::FileContents::Chunked(chunks),
+            };
+            fs.files.insert((self.content_namespace, file_id), file);
+            stats::file_stored(0);
+        }
+        fs.addref(
+            self.content_namespace,
+            refcounts::ReferenceCountType::File(file_id),
+        );
+        stats::file_uploaded(file_size, deduplicated);
+
+        Ok(file_id)
     }
 
+    /// Uploads the file, returning a new reference to it.
+    ///
+    /// The contents are only stored once per namespace, uploading the same contents again
+    /// only adds another reference to the existing file.
+    #[tracing::instrument(level = "debug", skip_all, fields(namespace = self.namespace.0, size = contents.len()))]
     pub fn upload_file(&self, contents: &[u8]) -> file::FileId {
-        let file_id = file::FileId::from_contents(contents);
+        let file_id = file::FileId::from_contents(self.config.hash_algorithm, contents);
+        {
+            let mut fs = self.filestore.write().unwrap();
+            if fs.files.contains_key(&(self.content_namespace, file_id)) {
+                fs.addref(
+                    self.content_namespace,
+                    refcounts::ReferenceCountType::File(file_id),
+                );
+                stats::file_uploaded(contents.len() as u64, true);
+                return file_id;
+            }
+        }
+        stats::file_uploaded(contents.len() as u64, false);
 
         let file_size = contents.len() as u64;
         let contents = if file_size <= self.config.inline_size {
             file::FileContents::Inline(contents.into())
         } else {
-            let chunks = contents
-                .chunks(self.config.chunk_size as usize)
+            let _span =
+                tracing::debug_span!("chunking", chunk_size = self.config.chunk_size).entered();
+            let chunks = self
+                .config
+                .split(contents)
+                .into_iter()
                 .map(|chunk| file::FileChunk {
                     chunk_size: chunk.len() as u32,
                     chunk_id: self.upload_chunk(chunk),
@@ -139,32 +698,148 @@ impl NamespacedFileStore<'_> {
             file::FileContents::Chunked(chunks)
         };
 
+        let inline_size = match &contents {
+            file::FileContents::Inline(contents) => contents.len() as u64,
+            file::FileContents::Chunked(_) => 0,
+        };
+        stats::file_stored(inline_size);
+
         let file = file::File {
             size: file_size,
             contents,
         };
         let mut fs = self.filestore.write().unwrap();
-        fs.files.insert((self.namespace, file_id), file);
-        // TODO:
-        // self.addref(refcounts::ReferenceCountType::File(file_id));
+        fs.files.insert((self.content_namespace, file_id), file);
+        fs.addref(
+            self.content_namespace,
+            refcounts::ReferenceCountType::File(file_id),
+        );
 
         file_id
     }
 
-    pub fn read_chunk(&self, chunk_id: chunk::ChunkId) -> Vec<u8> {
+    /// Copies a file from the `source` namespace, returning a new reference to it.
+    ///
+    /// No file contents are copied. Within the same namespace, this only adds a reference to
+    /// the file. Across namespaces, the chunks are referenced from the destination namespace,
+    /// sharing the segment data they are stored in. Only copies between different
+    /// `FileStore`s, which share no storage, have to upload the contents again.
+    ///
+    /// Returns `None` if the source file does not exist (anymore).
+    pub fn copy_file(
+        &self,
+        source: &NamespacedFileStore<'_>,
+        file_id: file::FileId,
+    ) -> Option<file::FileId> {
+        if !std::ptr::eq(self.filestore, source.filestore) {
+            return Some(self.upload_file(&source.read_file(file_id)));
+        }
+
+        let mut fs = self.filestore.write().unwrap();
+        let fs = &mut *fs;
+        if !fs.files.contains_key(&(self.content_namespace, file_id)) {
+            // the source file might have been deleted since it was resolved
+            let file = fs.files.get(&(source.content_namespace, file_id))?.clone();
+            if let file::FileContents::Chunked(chunks) = &file.contents {
+                for file::FileChunk { chunk_id, .. } in chunks {
+                    let key = (self.content_namespace, *chunk_id);
+                    if !fs.chunks.contains_key(&key) {
+                        let chunk = fs.chunks[&(source.content_namespace, *chunk_id)].clone();
+                        fs.addref_segment(chunk.segment_id);
+                        fs.chunks.insert(key, chunk);
+                    }
+                    fs.addref(
+                        self.content_namespace,
+                        refcounts::ReferenceCountType::Chunk(*chunk_id),
+                    );
+                }
+            }
+            fs.files.insert((self.content_namespace, file_id), file);
+        }
+        fs.addref(
+            self.content_namespace,
+            refcounts::ReferenceCountType::File(file_id),
+        );
+
+        Some(file_id)
+    }
+
+    /// Reads the chunk contents as stored in its segment, along with its compression.
+    fn read_stored_chunk(&self, chunk_id: chunk::ChunkId) -> (chunk::Compression, u32, Vec<u8>) {
         let fs = self.filestore.read().unwrap();
 
-        let chunk = &fs.chunks[&(self.namespace, chunk_id)];
+        let chunk = &fs.chunks[&(self.content_namespace, chunk_id)];
         let segment = &fs.segments[&chunk.segment_id];
         let start = chunk.offset_in_segment as usize;
-        let range = start..start + chunk.size as usize;
-        (&segment.0[range]).into()
+        let range = start..start + chunk.compressed_size as usize;
+        (chunk.compression, chunk.size, segment.0[range].into())
+    }
+
+    /// Looks up the file along with its chunks, and all their reference counts.
+    pub fn inspect_file(&self, file_id: file::FileId) -> Option<report::FileInfo> {
+        let fs = self.filestore.read().unwrap();
+        let file = fs.files.get(&(self.content_namespace, file_id))?.clone();
+        let refcount = |ty| {
+            fs.namespaced_refcounts
+                .get(&(self.content_namespace, ty))
+                .copied()
+                .unwrap_or_default()
+        };
+
+        let mut chunks = vec![];
+        if let file::FileContents::Chunked(file_chunks) = &file.contents {
+            for file::FileChunk { chunk_id, .. } in file_chunks {
+                let chunk = fs.chunks[&(self.content_namespace, *chunk_id)].clone();
+                chunks.push(report::ChunkInfo {
+                    chunk_id: *chunk_id,
+                    refcount: refcount(refcounts::ReferenceCountType::Chunk(*chunk_id)),
+                    segment_refcount: fs
+                        .segment_refcounts
+                        .get(&chunk.segment_id)
+                        .copied()
+                        .unwrap_or_default(),
+                    chunk,
+                });
+            }
+        }
+
+        Some(report::FileInfo {
+            file_id,
+            refcount: refcount(refcounts::ReferenceCountType::File(file_id)),
+            file,
+            chunks,
+        })
+    }
+
+    pub fn read_chunk(&self, chunk_id: chunk::ChunkId) -> Vec<u8> {
+        let (compression, size, stored) = self.read_stored_chunk(chunk_id);
+        compression.decompress(&stored, size)
+    }
+
+    /// Reads the chunk as a zstd frame, avoiding a roundtrip through the uncompressed contents.
+    pub fn read_chunk_zstd(&self, chunk_id: chunk::ChunkId) -> Vec<u8> {
+        let (compression, _size, stored) = self.read_stored_chunk(chunk_id);
+        compression.to_zstd(stored)
+    }
+
+    /// Looks up a file by its ID, among the files stored in the content namespace.
+    ///
+    /// With a separate content namespace, this includes files uploaded to any namespace
+    /// sharing it.
+    pub fn find_file(&self, file_id: file::FileId) -> Option<file::File> {
+        let fs = self.filestore.read().unwrap();
+        fs.files.get(&(self.content_namespace, file_id)).cloned()
+    }
+
+    pub fn get_file(&self, file_id: file::FileId) -> file::File {
+        let fs = self.filestore.read().unwrap();
+        fs.files[&(self.content_namespace, file_id)].clone()
     }
 
     pub fn read_file(&self, file_id: file::FileId) -> Vec<u8> {
         let fs = self.filestore.read().unwrap();
 
-        let file = &fs.files[&(self.namespace, file_id)];
+        let file = &fs.files[&(self.content_namespace, file_id)];
 
         match &file.contents {
             file::FileContents::Inline(contents) => contents.clone(),
@@ -178,56 +853,406 @@ impl NamespacedFileStore<'_> {
         }
     }
 
-    // pub fn assemble_file_from_chunks(&self, chunks: &[chunk::ChunkId]) -> file::FileId {
-    //     let mut file_size = 0;
-    //     let mut file_hash = blake3::Hasher::new();
-    //     let chunks = chunks
-    //         .into_iter()
-    //         .copied()
-    //         .map(|chunk_id| {
-    //             let chunk_content = self.read_chunk(chunk_id);
+    /// Generates a name which is neither associated nor reserved, and reserves it for the
+    /// [`gc::RESERVATION_TTL`].
+    pub fn allocate_filename(&self, format: file::IdFormat) -> String {
+        let mut fs = self.filestore.write().unwrap();
+        loop {
+            let key = (self.namespace, format.generate());
+            if fs.named_files.contains_key(&key) || fs.reservations.contains_key(&key) {
+                continue;
+            }
+            let name = key.1.clone();
+            fs.reservations
+                .insert(key, gc::Timestamp::after(gc::RESERVATION_TTL));
+            return name;
+        }
+    }
 
-    //             file_size += chunk_content.len() as u64;
-    //             file_hash.update(&chunk_content);
+    /// Reserves a client-provided name for the [`gc::RESERVATION_TTL`], so that it is not
+    /// allocated until it is uploaded to. Reserving a name again extends its reservation.
+    pub fn reserve_filename(&self, name: &str) {
+        let mut fs = self.filestore.write().unwrap();
+        let key = (self.namespace, name.to_string());
+        if !fs.named_files.contains_key(&key) {
+            fs.reservations
+                .insert(key, gc::Timestamp::after(gc::RESERVATION_TTL));
+        }
+    }
 
-    //             file::FileChunk {
-    //                 chunk_size: chunk_content.len() as u32,
-    //                 chunk_id,
-    //             }
-    //         })
-    //         .collect();
+    /// Whether the name is reserved, without having been uploaded to yet.
+    pub fn is_reserved(&self, name: &str) -> bool {
+        let fs = self.filestore.read().unwrap();
+        fs.reservations
+            .contains_key(&(self.namespace, name.to_string()))
+    }
 
-    //     let file_id = file::FileId {
-    //         hash_algorithm: HashAlgorithm::Blake3,
-    //         _padding: [0; 3],
-    //         hash: *file_hash.finalize().as_bytes(),
-    //     };
+    /// Removes the reservations of names starting with `prefix` which were reserved before
+    /// `reserved_before`, returning how many were removed.
+    pub fn remove_reservations(&self, prefix: &str, reserved_before: SystemTime) -> u64 {
+        let expires = gc::Timestamp::at(reserved_before + gc::RESERVATION_TTL);
+        let mut fs = self.filestore.write().unwrap();
+        let reserved = fs.reservations.len();
+        fs.reservations.retain(|(namespace, name), reservation| {
+            *namespace != self.namespace || !name.starts_with(prefix) || *reservation > expires
+        });
+        (reserved - fs.reservations.len()) as u64
+    }
 
-    //     let file = file::File {
-    //         size: file_size,
-    //         contents: file::FileContents::Chunked(chunks),
-    //     };
+    pub fn associate_filename(&self, file_id: file::FileId, name: &str) -> file::NamedFile {
+        self.associate_filename_if(file_id, name, &Default::default())
+            .unwrap()
+    }
 
-    //     let mut fs = self.filestore.write().unwrap();
-    //     fs.files.insert((self.namespace, file_id), file);
-    //     // TODO:
-    //     // self.addref(refcounts::ReferenceCountType::File(file_id));
+    pub fn associate_filename_if(
+        &self,
+        file_id: file::FileId,
+        name: &str,
+        preconditions: &file::Preconditions,
+    ) -> Result<file::NamedFile, Error> {
+        self.associate_tagged_filename_if(file_id, name, preconditions, vec![])
+    }
 
-    //     file_id
-    // }
+    /// Points the name to the file, tagged with `tags`, if the file it currently points to
+    /// meets the `preconditions`. The check and the update happen atomically, and consume a
+    /// reservation of the name.
+    ///
+    /// An idempotent write of the file the name already points to is a retry, which releases
+    /// the reference to `file_id` and succeeds without changing the name.
+    pub fn associate_tagged_filename_if(
+        &self,
+        file_id: file::FileId,
+        name: &str,
+        preconditions: &file::Preconditions,
+        tags: Vec<file::Tag>,
+    ) -> Result<file::NamedFile, Error> {
+        let mut fs = self.filestore.write().unwrap();
+        let key = (self.namespace, name.to_string());
+
+        let current_file = fs.named_files.get(&key).cloned();
+        let current = current_file.as_ref().map(|current| current.file_id);
+        if !preconditions.check(current) {
+            if !preconditions.is_retry(current, file_id) {
+                return Err(Error::PreconditionFailed);
+            }
+            fs.release_file(self.content_namespace, file_id);
+            return Ok(current_file.unwrap());
+        }
+        // the current file is kept as a version, rather than replaced
+        let replaced = current.filter(|_| !self.versioned);
+        if let Some(account) = &self.account {
+            let added = fs.file_usage(self.content_namespace, file_id);
+            let removed = replaced
+                .map(|current| fs.file_usage(self.content_namespace, current))
+                .unwrap_or_default();
+            fs.update_usage(account, added, removed)?;
+        }
+
+        let version_id = match self.versioned {
+            true => {
+                let latest = match current_file {
+                    Some(ref current_file) => Some(current_file.version_id),
+                    None => fs
+                        .versions
+                        .get(&key)
+                        .and_then(|versions| versions.keys().next_back().copied()),
+                };
+                file::VersionId::next(latest)
+            }
+            false => file::VersionId::NULL,
+        };
+        let last_modified = unix_timestamp();
+        let named_file = file::NamedFile {
+            file_id,
+            last_modified,
+            last_accessed: last_modified,
+            version_id,
+            lock: file::Lock {
+                retention: self
+                    .default_retention
+                    .map(|retention| retention.retention_from(last_modified)),
+                legal_hold: false,
+            },
+            tags,
+        };
+        fs.reservations.remove(&key);
+        if let Some(current) = fs.named_files.insert(key.clone(), named_file.clone()) {
+            match self.versioned {
+                true => {
+                    let versions = fs.versions.entry(key).or_default();
+                    versions.insert(current.version_id, current.into());
+                }
+                false => fs.release_file(self.content_namespace, current.file_id),
+            }
+        }
+        Ok(named_file)
+    }
 
-    pub fn associate_filename(&self, file_id: file::FileId, name: &str) {
+    /// Releases a reference returned by [`Self::upload_file`] or [`Self::copy_file`], when the
+    /// file ends up not being associated with a name.
+    pub fn discard_file(&self, file_id: file::FileId) {
         let mut fs = self.filestore.write().unwrap();
-        fs.named_files
-            .insert((self.namespace, name.into()), file_id);
+        fs.release_file(self.content_namespace, file_id);
+    }
+
+    pub fn delete_filename(&self, name: &str) -> Option<file::FileId> {
+        let deleted = self.delete_filenames(&[name]).pop().unwrap();
+        deleted.map(|deleted| deleted.file_id)
+    }
+
+    /// Removes all the names at once, releasing the files they pointed to. In versioned
+    /// buckets, the files are kept as versions instead, and a delete marker becomes the
+    /// current version of each name.
+    ///
+    /// Returns the file each name pointed to and its delete marker, or `None` if it did not
+    /// exist.
+    pub fn delete_filenames(&self, names: &[&str]) -> Vec<Option<file::DeletedName>> {
+        self.delete_filenames_where(names, |_| true)
+    }
+
+    /// Like [`Self::delete_filename`], but only deletes the name if its current version is
+    /// still `version_id` with the given `version`, checked while holding the lock.
+    ///
+    /// Returns whether the name was deleted.
+    pub fn delete_filename_if_current(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        version: &file::Version,
+    ) -> bool {
+        let deleted = self.delete_filenames_where(&[name], |current| {
+            current.version_id == version_id && file::Version::from(current.clone()) == *version
+        });
+        deleted[0].is_some()
+    }
+
+    /// Removes the names whose current file matches `condition`, see
+    /// [`Self::delete_filenames`].
+    fn delete_filenames_where(
+        &self,
+        names: &[&str],
+        condition: impl Fn(&file::NamedFile) -> bool,
+    ) -> Vec<Option<file::DeletedName>> {
+        let mut fs = self.filestore.write().unwrap();
+        let now = unix_timestamp();
+        names
+            .iter()
+            .map(|name| {
+                let key = (self.namespace, name.to_string());
+                if !condition(fs.named_files.get(&key)?) {
+                    return None;
+                }
+                let current = fs.named_files.remove(&key)?;
+                let file_id = current.file_id;
+                if self.versioned {
+                    let delete_marker = file::Version::delete_marker(now);
+                    let version_id = file::VersionId::next(Some(current.version_id));
+                    let versions = fs.versions.entry(key).or_default();
+                    versions.insert(current.version_id, current.clone().into());
+                    versions.insert(version_id, delete_marker);
+                    return Some(file::DeletedName {
+                        file_id,
+                        delete_marker: Some(version_id),
+                    });
+                }
+                if let Some(account) = &self.account {
+                    let removed = fs.file_usage(self.content_namespace, file_id);
+                    // shrinking never exceeds a quota
+                    fs.update_usage(account, Default::default(), removed)
+                        .unwrap();
+                }
+                fs.release_file(self.content_namespace, file_id);
+                Some(file::DeletedName {
+                    file_id,
+                    delete_marker: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Looks up a version of the name, which might be its current one.
+    pub fn get_version(&self, name: &str, version_id: file::VersionId) -> Option<file::Version> {
+        let fs = self.filestore.read().unwrap();
+        let key = (self.namespace, name.to_string());
+        match fs.named_files.get(&key) {
+            Some(current) if current.version_id == version_id => Some(current.clone().into()),
+            _ => fs.versions.get(&key)?.get(&version_id).cloned(),
+        }
+    }
+
+    /// Removes a version of the name for good, releasing its file, unless the file is locked.
+    ///
+    /// When the current version is removed, the previous one becomes current, unless that is
+    /// a delete marker. Returns the removed version, or `None` if it did not exist.
+    pub fn delete_version(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        bypass_governance: bool,
+    ) -> Result<Option<file::Version>, Error> {
+        let mut fs = self.filestore.write().unwrap();
+        let key = (self.namespace, name.to_string());
+
+        let is_current = fs
+            .named_files
+            .get(&key)
+            .is_some_and(|current| current.version_id == version_id);
+        let version = match is_current {
+            true => fs.named_files.get(&key).cloned().map(Into::into),
+            false => fs
+                .versions
+                .get(&key)
+                .and_then(|versions| versions.get(&version_id).cloned()),
+        };
+        let Some(deleted) = version else {
+            return Ok(None);
+        };
+        if deleted.lock.is_locked(unix_timestamp(), bypass_governance) {
+            return Err(Error::ObjectLocked);
+        }
+        if is_current {
+            fs.named_files.remove(&key);
+        } else {
+            let versions = fs.versions.get_mut(&key).unwrap();
+            versions.remove(&version_id);
+            if versions.is_empty() {
+                fs.versions.remove(&key);
+            }
+        }
+        if let Some(file_id) = deleted.file_id {
+            if let Some(account) = &self.account {
+                let removed = fs.file_usage(self.content_namespace, file_id);
+                // shrinking never exceeds a quota
+                fs.update_usage(account, Default::default(), removed)
+                    .unwrap();
+            }
+            fs.release_file(self.content_namespace, file_id);
+        }
+        if !fs.named_files.contains_key(&key) {
+            fs.promote_latest_version(&key);
+        }
+        Ok(Some(deleted))
+    }
+
+    /// Updates the lock of a version of the name, which might be its current one.
+    ///
+    /// Returns the updated lock, or `None` if the version does not exist or is a delete
+    /// marker.
+    pub fn update_lock(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        update: impl Fn(file::Lock) -> Result<file::Lock, Error>,
+    ) -> Result<Option<file::Lock>, Error> {
+        let mut fs = self.filestore.write().unwrap();
+        let key = (self.namespace, name.to_string());
+
+        if let Some(current) = fs.named_files.get_mut(&key) {
+            if current.version_id == version_id {
+                current.lock = update(current.lock)?;
+                return Ok(Some(current.lock));
+            }
+        }
+        let Some(version) = fs
+            .versions
+            .get_mut(&key)
+            .and_then(|versions| versions.get_mut(&version_id))
+            .filter(|version| version.file_id.is_some())
+        else {
+            return Ok(None);
+        };
+        version.lock = update(version.lock)?;
+        Ok(Some(version.lock))
+    }
+
+    /// Replaces the tags of a version of the name, which might be its current one.
+    ///
+    /// Returns `false` if the version does not exist or is a delete marker.
+    pub fn set_tags(&self, name: &str, version_id: file::VersionId, tags: Vec<file::Tag>) -> bool {
+        let mut fs = self.filestore.write().unwrap();
+        let key = (self.namespace, name.to_string());
+
+        if let Some(current) = fs.named_files.get_mut(&key) {
+            if current.version_id == version_id {
+                current.tags = tags;
+                return true;
+            }
+        }
+        let Some(version) = fs
+            .versions
+            .get_mut(&key)
+            .and_then(|versions| versions.get_mut(&version_id))
+            .filter(|version| version.file_id.is_some())
+        else {
+            return false;
+        };
+        version.tags = tags;
+        true
+    }
+
+    /// Records that the current file of the name was read at `accessed`, unless the name was
+    /// written again since, or its recorded access is within [`file::ACCESS_RESOLUTION`].
+    pub fn record_access(&self, name: &str, version_id: file::VersionId, accessed: u64) {
+        let mut fs = self.filestore.write().unwrap();
+        let key = (self.namespace, name.to_string());
+        if let Some(current) = fs.named_files.get_mut(&key).filter(|current| {
+            current.version_id == version_id
+                && current.last_accessed + file::ACCESS_RESOLUTION <= accessed
+        }) {
+            current.last_accessed = accessed;
+        }
     }
 
-    pub fn read_named_file(&self, name: &str) -> Vec<u8> {
+    /// Lists all versions of the names starting with `prefix`, including the current ones,
+    /// ordered by name and then from the latest to the oldest version.
+    pub fn list_versions(&self, prefix: &str) -> Vec<file::ListedVersion> {
         let fs = self.filestore.read().unwrap();
-        let file_id = &fs.named_files[&(self.namespace, name.to_string())];
+        let in_namespace = |(namespace, name): &(Namespace, String)| {
+            *namespace == self.namespace && name.starts_with(prefix)
+        };
 
-        self.read_file(*file_id)
+        let current = fs
+            .named_files
+            .iter()
+            .filter(|(key, _)| in_namespace(key))
+            .map(|((_, name), named_file)| {
+                (
+                    name.clone(),
+                    named_file.version_id,
+                    named_file.clone().into(),
+                )
+            });
+        let noncurrent = fs
+            .versions
+            .iter()
+            .filter(|(key, _)| in_namespace(key))
+            .flat_map(|((_, name), versions)| {
+                versions
+                    .iter()
+                    .map(|(version_id, version)| (name.clone(), *version_id, version.clone()))
+            });
+        file::ListedVersion::sorted(current.chain(noncurrent).collect())
+    }
+
+    pub fn get_named_file(&self, name: &str) -> Option<file::NamedFile> {
+        let fs = self.filestore.read().unwrap();
+        fs.named_files
+            .get(&(self.namespace, name.to_string()))
+            .cloned()
     }
+
+    pub fn resolve_filename(&self, name: &str) -> Option<file::FileId> {
+        Some(self.get_named_file(name)?.file_id)
+    }
+
+    pub fn read_named_file(&self, name: &str) -> Option<Vec<u8>> {
+        Some(self.read_file(self.resolve_filename(name)?))
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    let now = SystemTime::now();
+    now.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
 }
 
 #[cfg(test)]
@@ -241,6 +1266,11 @@ mod tests {
         let fs = FileStore::with_namespace(&global_fs, Namespace(0));
         let file_id = fs.upload_file(b"inlined file");
         assert_eq!(fs.read_file(file_id), b"inlined file");
+        assert_eq!(fs.find_file(file_id).unwrap().size, 12);
+        assert!(FileStore::with_namespace(&global_fs, Namespace(1))
+            .find_file(file_id)
+            .is_none());
+        assert_eq!(file_id.to_string().parse(), Ok(file_id));
 
         dbg!(&global_fs);
 
@@ -248,6 +1278,8 @@ mod tests {
             inline_size: 4,
             chunk_size: 16,
             segment_size: 32,
+            compression_level: None,
+            ..Default::default()
         });
         let contents = b"chunked, and deduped file contents...";
 
@@ -260,17 +1292,833 @@ mod tests {
         dbg!(&global_fs);
     }
 
+    #[test]
+    fn test_filestore_compression() {
+        let global_fs = RwLock::new(FileStore::default());
+
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 64,
+            segment_size: 1024,
+            compression_level: Some(3),
+            ..Default::default()
+        });
+        let contents = [b"highly compressible ".as_slice(); 10].concat();
+
+        let file_id = fs.upload_file(&contents);
+        assert_eq!(fs.read_file(file_id), contents);
+
+        let file::FileContents::Chunked(chunks) = fs.get_file(file_id).contents else {
+            panic!("file should be chunked");
+        };
+        let mut zstd_stream = vec![];
+        for chunk in chunks {
+            zstd_stream.extend_from_slice(&fs.read_chunk_zstd(chunk.chunk_id));
+        }
+        assert!(zstd_stream.len() < contents.len());
+        assert_eq!(zstd::decode_all(&zstd_stream[..]).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_filestore_copy() {
+        use refcounts::ReferenceCountType as Ref;
+
+        let global_fs = RwLock::new(FileStore::default());
+        let config = || Config {
+            inline_size: 4,
+            chunk_size: 16,
+            segment_size: 1024,
+            compression_level: None,
+            ..Default::default()
+        };
+
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config());
+        let contents = b"some file contents, which are copied around";
+        let file_id = fs.upload_file(contents);
+        let file::FileContents::Chunked(chunks) = fs.get_file(file_id).contents else {
+            panic!("file should be chunked");
+        };
+        let chunk_id = chunks[0].chunk_id;
+        let segment_id = global_fs.read().unwrap().chunks[&(Namespace(0), chunk_id)].segment_id;
+        assert_eq!(fs.refcount(Ref::File(file_id)), 1);
+        assert_eq!(fs.refcount(Ref::Chunk(chunk_id)), 1);
+        assert_eq!(fs.refcount(Ref::Segment(segment_id)), 3);
+
+        assert_eq!(fs.copy_file(&fs, file_id), Some(file_id));
+        assert_eq!(fs.refcount(Ref::File(file_id)), 2);
+        assert_eq!(fs.refcount(Ref::Chunk(chunk_id)), 1);
+
+        let other_fs = FileStore::with_namespace(&global_fs, Namespace(1)).with_config(config());
+        assert_eq!(other_fs.copy_file(&fs, file_id), Some(file_id));
+        assert_eq!(other_fs.read_file(file_id), contents);
+        assert_eq!(other_fs.refcount(Ref::File(file_id)), 1);
+        assert_eq!(other_fs.refcount(Ref::Chunk(chunk_id)), 1);
+        // the chunks are shared, and not written again
+        assert_eq!(fs.refcount(Ref::Segment(segment_id)), 6);
+
+        assert_eq!(other_fs.upload_file(contents), file_id);
+        assert_eq!(other_fs.refcount(Ref::File(file_id)), 2);
+        assert_eq!(other_fs.refcount(Ref::Chunk(chunk_id)), 1);
+        assert_eq!(fs.refcount(Ref::Segment(segment_id)), 6);
+
+        // a deleted source file is not copied
+        fs.discard_file(file_id);
+        fs.discard_file(file_id);
+        let third_fs = FileStore::with_namespace(&global_fs, Namespace(2)).with_config(config());
+        assert_eq!(third_fs.copy_file(&fs, file_id), None);
+        assert_eq!(fs.refcount(Ref::Segment(segment_id)), 3);
+    }
+
+    #[test]
+    fn test_filestore_preconditions() {
+        use file::{FileMatch, Preconditions};
+
+        let global_fs = RwLock::new(FileStore::default());
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0));
+        let file_a = fs.upload_file(b"file a");
+        let file_b = fs.upload_file(b"file b");
+
+        let create_only = Preconditions {
+            if_none_match: Some(FileMatch::Any),
+            ..Default::default()
+        };
+        assert!(fs
+            .associate_filename_if(file_a, "name", &create_only)
+            .is_ok());
+        assert_eq!(
+            fs.associate_filename_if(file_b, "name", &create_only)
+                .unwrap_err(),
+            Error::PreconditionFailed
+        );
+
+        let if_match = |file_id| Preconditions {
+            if_match: Some(FileMatch::Files(vec![file_id])),
+            ..Default::default()
+        };
+        assert_eq!(
+            fs.associate_filename_if(file_a, "name", &if_match(file_b))
+                .unwrap_err(),
+            Error::PreconditionFailed
+        );
+        let named_file = fs
+            .associate_filename_if(file_b, "name", &if_match(file_a))
+            .unwrap();
+        assert_eq!(named_file.file_id, file_b);
+        assert_eq!(fs.resolve_filename("name"), Some(file_b));
+
+        let if_exists = Preconditions {
+            if_match: Some(FileMatch::Any),
+            ..Default::default()
+        };
+        assert!(fs
+            .associate_filename_if(file_a, "other", &if_exists)
+            .is_err());
+        assert_eq!(fs.resolve_filename("other"), None);
+    }
+
+    #[test]
+    fn test_filestore_delete() {
+        use refcounts::ReferenceCountType as Ref;
+
+        let global_fs = RwLock::new(FileStore::default());
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 16,
+            segment_size: 16,
+            compression_level: None,
+            ..Default::default()
+        });
+
+        // both files share their first chunk
+        let file_a = fs.upload_file(b"a shared chunk: file a");
+        fs.associate_filename(file_a, "a");
+        assert_eq!(fs.upload_file(b"a shared chunk: file a"), file_a);
+        fs.associate_filename(file_a, "a copy");
+        let file_b = fs.upload_file(b"a shared chunk: file b");
+        fs.associate_filename(file_b, "b");
+
+        let file::FileContents::Chunked(chunks) = fs.get_file(file_a).contents else {
+            panic!("file should be chunked");
+        };
+        let shared_chunk = chunks[0].chunk_id;
+        let unique_chunk = chunks[1].chunk_id;
+        assert_eq!(fs.refcount(Ref::File(file_a)), 2);
+        assert_eq!(fs.refcount(Ref::Chunk(shared_chunk)), 2);
+
+        let deleted = |file_id| {
+            Some(file::DeletedName {
+                file_id,
+                delete_marker: None,
+            })
+        };
+        assert_eq!(
+            fs.delete_filenames(&["a", "missing", "b"]),
+            [deleted(file_a), None, deleted(file_b)]
+        );
+        assert_eq!(fs.refcount(Ref::File(file_a)), 1);
+        assert_eq!(fs.refcount(Ref::File(file_b)), 0);
+        assert_eq!(fs.refcount(Ref::Chunk(shared_chunk)), 1);
+        assert_eq!(
+            fs.read_named_file("a copy").unwrap(),
+            b"a shared chunk: file a"
+        );
+
+        // overwriting a name releases the previous file
+        let file_c = fs.upload_file(b"file c");
+        fs.associate_filename(file_c, "a copy");
+        assert_eq!(fs.refcount(Ref::File(file_a)), 0);
+        assert_eq!(fs.refcount(Ref::Chunk(shared_chunk)), 0);
+        assert_eq!(fs.refcount(Ref::Chunk(unique_chunk)), 0);
+
+        assert_eq!(fs.delete_filename("a copy"), Some(file_c));
+        assert_eq!(fs.delete_filename("a copy"), None);
+        assert_eq!(fs.refcount(Ref::File(file_c)), 0);
+    }
+
+    #[test]
+    fn test_filestore_maintenance() {
+        let global_fs = RwLock::new(FileStore::default());
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 16,
+            segment_size: 1024,
+            compression_level: None,
+            ..Default::default()
+        });
+        let contents = b"a file made of two chunks";
+        let file_id = fs.upload_file(contents);
+        fs.associate_filename(file_id, "file");
+
+        let info = fs.inspect_file(file_id).unwrap();
+        assert_eq!(info.refcount, 1);
+        assert_eq!(info.chunks.len(), 2);
+        assert!(info.chunks.iter().all(|chunk| chunk.refcount == 1));
+        assert!(info.chunks.iter().all(|chunk| chunk.segment_refcount == 2));
+        let segment_id = info.chunks[0].chunk.segment_id;
+        assert!(FileStore::with_namespace(&global_fs, Namespace(1))
+            .inspect_file(file_id)
+            .is_none());
+
+        let [usage] = global_fs.read().unwrap().usage().try_into().unwrap();
+        assert_eq!(
+            (usage.namespace, usage.files, usage.chunks),
+            (Namespace(0), 1, 2)
+        );
+        assert_eq!(usage.file_bytes, contents.len() as u64);
+        assert_eq!(usage.stored_bytes, contents.len() as u64);
+
+        let report = global_fs.read().unwrap().scrub();
+        assert_eq!((report.chunks_checked, report.corrupt_chunks.len()), (2, 0));
+
+        global_fs
+            .write()
+            .unwrap()
+            .segments
+            .get_mut(&segment_id)
+            .unwrap()
+            .0[0] = b'X';
+        let report = global_fs.read().unwrap().scrub();
+        assert_eq!(
+            report.corrupt_chunks,
+            [(Namespace(0), info.chunks[0].chunk_id)]
+        );
+
+        // the released segment is still being appended to, so it is only collected once full
+        fs.delete_filename("file");
+        let mut global = global_fs.write().unwrap();
+        assert_eq!(global.collect_garbage(), Default::default());
+        global.last_segment = None;
+        let report = global.collect_garbage();
+        assert_eq!(report.segments_removed, 1);
+        assert_eq!(report.bytes_freed, contents.len() as u64);
+        assert!(global.usage().is_empty());
+    }
+
+    #[test]
+    fn test_buckets() {
+        let global_fs = RwLock::new(FileStore::default());
+        let settings = bucket::BucketSettings::default;
+
+        let bucket = global_fs
+            .write()
+            .unwrap()
+            .create_bucket("attachments", "attachments", "org-1", settings())
+            .unwrap();
+        assert_eq!(
+            global_fs
+                .write()
+                .unwrap()
+                .create_bucket("attachments", "attachments", "org-2", settings())
+                .unwrap_err(),
+            Error::BucketAlreadyExists
+        );
+        global_fs
+            .write()
+            .unwrap()
+            .create_bucket("debug-files", "debug-files", "org-1", settings())
+            .unwrap();
+
+        let buckets = global_fs.read().unwrap().list_buckets();
+        let names: Vec<_> = buckets.iter().map(|bucket| bucket.name.as_str()).collect();
+        assert_eq!(names, ["attachments", "debug-files"]);
+        assert_ne!(buckets[0].namespace, buckets[1].namespace);
+
+        let fs = FileStore::with_namespace(&global_fs, bucket.namespace);
+        let file_id = fs.upload_file(b"some file");
+        fs.associate_filename(file_id, "file");
+        assert_eq!(
+            global_fs
+                .write()
+                .unwrap()
+                .delete_bucket("attachments")
+                .unwrap_err(),
+            Error::BucketNotEmpty
+        );
+
+        global_fs
+            .write()
+            .unwrap()
+            .delete_bucket("debug-files")
+            .unwrap();
+        assert!(global_fs
+            .read()
+            .unwrap()
+            .get_bucket("debug-files")
+            .is_none());
+        assert_eq!(
+            global_fs
+                .write()
+                .unwrap()
+                .delete_bucket("debug-files")
+                .unwrap_err(),
+            Error::NoSuchBucket
+        );
+
+        // re-created buckets get a fresh namespace
+        let recreated = global_fs
+            .write()
+            .unwrap()
+            .create_bucket("debug-files", "debug-files", "org-1", settings())
+            .unwrap();
+        assert_ne!(recreated.namespace, buckets[1].namespace);
+    }
+
+    #[test]
+    fn test_usecases() {
+        let policy = usecase::Policy {
+            ttl: Some(3600),
+            dedup: usecase::DedupScope::Usecase,
+            ..usecase::Policy::from_config(&Config::default())
+        };
+
+        let mut global_fs = FileStore::default();
+        let registered = global_fs
+            .register_usecase("attachments", policy.clone())
+            .unwrap();
+        global_fs
+            .register_usecase(
+                "debug-files",
+                usecase::Policy::from_config(&Config::default()),
+            )
+            .unwrap();
+        let bucket = global_fs
+            .create_bucket("bucket", "attachments", "org-1", Default::default())
+            .unwrap();
+        assert_ne!(bucket.namespace, registered.namespace);
+
+        let usecases = global_fs.list_usecases();
+        let names: Vec<_> = usecases
+            .iter()
+            .map(|usecase| usecase.name.as_str())
+            .collect();
+        assert_eq!(names, ["attachments", "debug-files"]);
+        assert!(global_fs.get_usecase("unknown").is_none());
+
+        // updating the policy keeps the namespace
+        let updated = global_fs
+            .register_usecase(
+                "attachments",
+                usecase::Policy {
+                    ttl: None,
+                    ..policy.clone()
+                },
+            )
+            .unwrap();
+        assert_eq!(updated.namespace, registered.namespace);
+        assert_eq!(
+            global_fs.get_usecase("attachments").unwrap().policy.ttl,
+            None
+        );
+        assert_eq!(
+            global_fs
+                .register_usecase(
+                    "attachments",
+                    usecase::Policy {
+                        dedup: usecase::DedupScope::Bucket,
+                        ..policy
+                    },
+                )
+                .unwrap_err(),
+            Error::DedupScopeChanged
+        );
+    }
+
+    #[test]
+    fn test_filestore_content_namespace() {
+        let global_fs = RwLock::new(FileStore::default());
+        let config = Config {
+            inline_size: 4,
+            chunk_size: 8,
+            compression_level: None,
+            ..Default::default()
+        };
+        let contents = b"contents shared by two namespaces";
+
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0))
+            .with_content_namespace(Namespace(2))
+            .with_config(config.clone());
+        let other_fs = FileStore::with_namespace(&global_fs, Namespace(1))
+            .with_content_namespace(Namespace(2))
+            .with_config(config);
+        let file_id = fs.upload_file(contents);
+        fs.associate_filename(file_id, "file");
+        assert_eq!(other_fs.upload_file(contents), file_id);
+        other_fs.associate_filename(file_id, "file");
+        assert_eq!(fs.refcount(refcounts::ReferenceCountType::File(file_id)), 2);
+        // shared files are found in either namespace
+        assert_eq!(fs.find_file(file_id).unwrap().size, contents.len() as u64);
+        assert!(other_fs.find_file(file_id).is_some());
+
+        let usage = global_fs.read().unwrap().usage();
+        assert_eq!(usage.len(), 1);
+        assert_eq!((usage[0].namespace, usage[0].files), (Namespace(2), 1));
+
+        // the names stay separate
+        fs.delete_filename("file");
+        assert!(fs.resolve_filename("file").is_none());
+        assert_eq!(other_fs.read_named_file("file").unwrap(), contents);
+        other_fs.delete_filename("file");
+        assert!(global_fs.read().unwrap().usage().is_empty());
+    }
+
+    #[test]
+    fn test_filestore_chunking() {
+        let global_fs = RwLock::new(FileStore::default());
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(Config {
+            inline_size: 4,
+            chunk_size: 1024,
+            chunking: Chunking::ContentDefined,
+            compression_level: None,
+            hash_algorithm: HashAlgorithm::Sha1,
+            ..Default::default()
+        });
+        let contents: Vec<u8> = (0..32 * 1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let chunk_ids = |file_id| {
+            let file::FileContents::Chunked(chunks) = fs.get_file(file_id).contents else {
+                panic!("file should be chunked");
+            };
+            chunks
+                .into_iter()
+                .map(|chunk| chunk.chunk_id)
+                .collect::<Vec<_>>()
+        };
+
+        let file_id = fs.upload_file(&contents);
+        assert_eq!(file_id.0.hash_algorithm, HashAlgorithm::Sha1);
+        assert_eq!(fs.read_file(file_id), contents);
+        let chunks = chunk_ids(file_id);
+        assert!(chunks.len() > 1);
+
+        // only the chunks around an insertion change
+        let mut inserted = b"inserted".to_vec();
+        inserted.extend_from_slice(&contents);
+        let inserted_id = fs.upload_file(&inserted);
+        assert_eq!(fs.read_file(inserted_id), inserted);
+        let inserted_chunks = chunk_ids(inserted_id);
+        assert_eq!(inserted_chunks[1..], chunks[1..]);
+    }
+
+    #[test]
+    fn test_quotas() {
+        let global_fs = RwLock::new(FileStore::default());
+        let account = quota::Account {
+            usecase: "attachments".into(),
+            scope: "org-1".into(),
+            hard_quota: quota::Quota {
+                objects: Some(2),
+                logical_bytes: Some(64),
+                ..Default::default()
+            },
+            soft_quota: quota::Quota {
+                objects: Some(1),
+                ..Default::default()
+            },
+        };
+        // both namespaces are accounted to the same scope
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_account(account.clone());
+        let other_fs = FileStore::with_namespace(&global_fs, Namespace(1)).with_account(account);
+
+        let file_id = fs.upload_file(b"small file");
+        fs.associate_filename(file_id, "a");
+        let file_id = other_fs.upload_file(b"small file");
+        other_fs.associate_filename(file_id, "b");
+
+        let rejected = fs.upload_file(b"one file too many");
+        assert_eq!(
+            fs.associate_filename_if(rejected, "c", &Default::default())
+                .unwrap_err(),
+            Error::QuotaExceeded(quota::Limit::Objects)
+        );
+        fs.discard_file(rejected);
+        assert_eq!(
+            fs.refcount(refcounts::ReferenceCountType::File(rejected)),
+            0
+        );
+
+        // overwriting a name only counts the difference
+        let large = fs.upload_file(&[0; 60]);
+        assert_eq!(
+            fs.associate_filename_if(large, "a", &Default::default())
+                .unwrap_err(),
+            Error::QuotaExceeded(quota::Limit::LogicalBytes)
+        );
+        fs.discard_file(large);
+        let file_id = fs.upload_file(&[1; 40]);
+        fs.associate_filename(file_id, "a");
+
+        other_fs.delete_filename("b");
+        let expected = quota::ScopeUsage {
+            usecase: "attachments".into(),
+            scope: "org-1".into(),
+            usage: quota::Usage {
+                objects: 1,
+                logical_bytes: 40,
+                physical_bytes: 40,
+            },
+        };
+        assert_eq!(global_fs.read().unwrap().scope_usage(), [expected]);
+    }
+
     // #[test]
-    // fn test_filestore_prechunked() {
-    //     let mut global_fs = FileStore::default();
+    #[test]
+    fn test_filestore_prechunked() {
+        let global_fs = RwLock::new(FileStore::default());
+        let config = Config {
+            inline_size: 4,
+            chunk_size: 16,
+            compression_level: None,
+            ..Default::default()
+        };
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_config(config.clone());
+        let chunks: [&[u8]; 2] = [b"some pre-chunked", b" content"];
+        let chunk_ids =
+            chunks.map(|chunk| chunk::ChunkId::from_contents(config.hash_algorithm, chunk));
 
-    //     let fs = global_fs.with_namespace(Namespace(0));
-    //     let chunk_1 = fs.upload_chunk(b"some pre-chunked");
-    //     let chunk_2 = fs.upload_chunk(b" content");
+        let missing = fs.find_missing_chunks(&[chunk_ids[0], chunk_ids[1], chunk_ids[0]]);
+        assert_eq!(missing, chunk_ids);
+        fs.upload_pending_chunk(chunk_ids[0], chunks[0]);
+        assert_eq!(fs.find_missing_chunks(&chunk_ids), [chunk_ids[1]]);
+        assert!(matches!(
+            fs.assemble_file(&chunk_ids),
+            Err(Error::MissingChunks(missing)) if missing == [chunk_ids[1]]
+        ));
 
-    //     let file_id = fs.assemble_file_from_chunks(&[chunk_1, chunk_2]);
-    //     assert_eq!(fs.read_file(file_id), b"some pre-chunked content");
+        fs.upload_pending_chunk(chunk_ids[1], chunks[1]);
+        // uploading again only extends the expiration
+        fs.upload_pending_chunk(chunk_ids[1], chunks[1]);
+        let file_id = fs.assemble_file(&chunk_ids).unwrap();
+        let contents = b"some pre-chunked content";
+        assert_eq!(
+            file_id,
+            file::FileId::from_contents(config.hash_algorithm, contents)
+        );
+        assert_eq!(fs.read_file(file_id), contents);
+        assert_eq!(fs.upload_file(contents), file_id);
+        fs.discard_file(file_id);
+        fs.associate_filename(file_id, "file");
+        let chunk_ref = refcounts::ReferenceCountType::Chunk(chunk_ids[1]);
+        assert_eq!(fs.refcount(chunk_ref), 2);
+
+        let mut global = global_fs.write().unwrap();
+        assert_eq!(global.collect_garbage().chunk_refs_expired, 0);
+        let expired = SystemTime::now() + gc::PENDING_CHUNK_TTL * 2;
+        assert_eq!(global.release_expired_chunk_refs(expired), 2);
+        drop(global);
+        assert_eq!(fs.refcount(chunk_ref), 1);
+        fs.delete_filename("file");
+        assert!(global_fs.read().unwrap().usage().is_empty());
 
-    //     dbg!(&global_fs);
-    // }
+        // chunks which are never assembled are released once they expire
+        fs.upload_pending_chunk(chunk_ids[0], chunks[0]);
+        let mut global = global_fs.write().unwrap();
+        assert_eq!(global.release_expired_chunk_refs(expired), 1);
+        assert!(global.usage().is_empty());
+    }
+
+    #[test]
+    fn test_filestore_allocate() {
+        let global_fs = RwLock::new(FileStore::default());
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0));
+
+        let name = fs.allocate_filename(file::IdFormat::Uuid);
+        assert_eq!(name.len(), 32);
+        assert!(fs.is_reserved(&name));
+        let compact = fs.allocate_filename(file::IdFormat::Compact);
+        assert_eq!(compact.len(), 22);
+        assert_ne!(name, compact);
+
+        let create_only = file::Preconditions {
+            if_none_match: Some(file::FileMatch::Any),
+            ..Default::default()
+        };
+        let idempotent = file::Preconditions {
+            if_none_match: Some(file::FileMatch::Any),
+            idempotent: true,
+            ..Default::default()
+        };
+        let file_id = fs.upload_file(b"uploaded once");
+        let named_file = fs
+            .associate_filename_if(file_id, &name, &idempotent)
+            .unwrap();
+        assert!(!fs.is_reserved(&name));
+
+        // create-only uploads fail on an existing name, even with the same contents
+        let retried = fs.upload_file(b"uploaded once");
+        assert_eq!(
+            fs.associate_filename_if(retried, &name, &create_only)
+                .unwrap_err(),
+            Error::PreconditionFailed
+        );
+
+        // retrying an idempotent upload of the same contents succeeds
+        let retried = fs
+            .associate_filename_if(retried, &name, &idempotent)
+            .unwrap();
+        assert_eq!(retried.last_modified, named_file.last_modified);
+        let file_ref = refcounts::ReferenceCountType::File(file_id);
+        assert_eq!(fs.refcount(file_ref), 1);
+        let other = fs.upload_file(b"other contents");
+        assert_eq!(
+            fs.associate_filename_if(other, &name, &idempotent)
+                .unwrap_err(),
+            Error::PreconditionFailed
+        );
+        fs.discard_file(other);
+
+        // names which already exist are not reserved
+        fs.reserve_filename(&name);
+        assert!(!fs.is_reserved(&name));
+        fs.reserve_filename("client-provided");
+        assert!(fs.is_reserved("client-provided"));
+
+        assert_eq!(
+            global_fs
+                .write()
+                .unwrap()
+                .collect_garbage()
+                .reservations_expired,
+            0
+        );
+        let expired = SystemTime::now() + gc::RESERVATION_TTL * 2;
+        assert_eq!(
+            global_fs
+                .write()
+                .unwrap()
+                .remove_expired_reservations(expired),
+            2
+        );
+        assert!(!fs.is_reserved(&compact));
+    }
+
+    #[test]
+    fn test_versioning() {
+        let global_fs = RwLock::new(FileStore::default());
+        let bucket = global_fs
+            .write()
+            .unwrap()
+            .create_bucket("versioned", "versioned", "org-1", Default::default())
+            .unwrap();
+        let fs = FileStore::with_namespace(&global_fs, bucket.namespace);
+        let unversioned = fs.upload_file(b"unversioned");
+        let null_version = fs.associate_filename(unversioned, "file");
+        assert_eq!(null_version.version_id, file::VersionId::NULL);
+
+        let bucket = global_fs
+            .write()
+            .unwrap()
+            .enable_versioning("versioned")
+            .unwrap();
+        assert!(bucket.settings.versioned);
+        let fs = fs.with_versioning(true);
+        let first = fs.upload_file(b"first");
+        let first_version = fs.associate_filename(first, "file").version_id;
+        assert!(first_version > file::VersionId::NULL);
+        let second = fs.upload_file(b"second");
+        let second_version = fs.associate_filename(second, "file").version_id;
+        assert!(second_version > first_version);
+
+        // previous versions keep their files
+        assert_eq!(fs.resolve_filename("file"), Some(second));
+        let version = fs.get_version("file", file::VersionId::NULL).unwrap();
+        assert_eq!(version.file_id, Some(unversioned));
+        let version = fs.get_version("file", first_version).unwrap();
+        assert_eq!(fs.read_file(version.file_id.unwrap()), b"first");
+
+        // deleting adds a delete marker as the latest version
+        assert_eq!(fs.delete_filename("file"), Some(second));
+        assert!(fs.resolve_filename("file").is_none());
+        let versions = fs.list_versions("");
+        let version_ids: Vec<_> = versions.iter().map(|version| version.version_id).collect();
+        assert_eq!(
+            version_ids[1..],
+            [second_version, first_version, file::VersionId::NULL]
+        );
+        assert!(versions[0].is_latest && versions[0].version.file_id.is_none());
+        assert!(versions[1..].iter().all(|version| !version.is_latest));
+        assert!(fs.list_versions("other").is_empty());
+        assert_eq!(
+            global_fs
+                .write()
+                .unwrap()
+                .delete_bucket("versioned")
+                .unwrap_err(),
+            Error::BucketNotEmpty
+        );
+
+        // removing the delete marker, or the current version, restores the previous one
+        fs.delete_version("file", version_ids[0], false).unwrap();
+        assert_eq!(fs.resolve_filename("file"), Some(second));
+        fs.delete_version("file", second_version, false).unwrap();
+        assert_eq!(fs.resolve_filename("file"), Some(first));
+        assert_eq!(fs.refcount(refcounts::ReferenceCountType::File(second)), 0);
+        assert_eq!(fs.delete_version("file", second_version, false), Ok(None));
+
+        fs.delete_version("file", first_version, false).unwrap();
+        fs.delete_version("file", file::VersionId::NULL, false)
+            .unwrap();
+        assert!(fs.list_versions("").is_empty());
+        assert!(global_fs.read().unwrap().usage().is_empty());
+        global_fs
+            .write()
+            .unwrap()
+            .delete_bucket("versioned")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tags() {
+        let global_fs = RwLock::new(FileStore::default());
+        let fs = FileStore::with_namespace(&global_fs, Namespace(0)).with_versioning(true);
+        let tag = |key: &str, value: &str| file::Tag {
+            key: key.into(),
+            value: value.into(),
+        };
+        let first = fs.upload_file(b"first");
+        let first = fs
+            .associate_tagged_filename_if(first, "file", &Default::default(), vec![tag("a", "1")])
+            .unwrap();
+        assert_eq!(first.tags, [tag("a", "1")]);
+
+        // overwriting keeps the tags with the previous version
+        let second = fs.upload_file(b"second");
+        let second = fs.associate_filename(second, "file");
+        assert!(second.tags.is_empty());
+        let version = fs.get_version("file", first.version_id).unwrap();
+        assert_eq!(version.tags, [tag("a", "1")]);
+
+        assert!(fs.set_tags("file", second.version_id, vec![tag("b", "2")]));
+        assert_eq!(fs.get_named_file("file").unwrap().tags, [tag("b", "2")]);
+        assert!(fs.set_tags("file", first.version_id, vec![]));
+        assert!(fs
+            .get_version("file", first.version_id)
+            .unwrap()
+            .tags
+            .is_empty());
+
+        // delete markers cannot be tagged
+        fs.delete_filename("file");
+        let delete_marker = fs.list_versions("file")[0].version_id;
+        assert!(!fs.set_tags("file", delete_marker, vec![tag("c", "3")]));
+        assert!(!fs.set_tags("other", second.version_id, vec![]));
+    }
+
+    #[test]
+    fn test_object_lock() {
+        let global_fs = RwLock::new(FileStore::default());
+        global_fs
+            .write()
+            .unwrap()
+            .create_bucket("locked", "locked", "org-1", Default::default())
+            .unwrap();
+        let default_retention = Some(bucket::DefaultRetention {
+            mode: file::RetentionMode::Governance,
+            days: 1,
+        });
+        let bucket = global_fs
+            .write()
+            .unwrap()
+            .set_object_lock("locked", default_retention)
+            .unwrap();
+        assert!(bucket.settings.versioned && bucket.settings.object_lock);
+        let fs = FileStore::with_namespace(&global_fs, bucket.namespace)
+            .with_versioning(true)
+            .with_default_retention(bucket.settings.default_retention);
+        let first = fs.upload_file(b"first");
+        let first = fs.associate_filename(first, "file");
+        let retention = first.lock.retention.unwrap();
+        assert_eq!(retention.mode, file::RetentionMode::Governance);
+        assert!(retention.retain_until >= first.last_modified + 24 * 60 * 60);
+
+        // deleting the name only adds a delete marker, but the version itself is retained
+        fs.delete_filename("file");
+        let delete_marker = fs.list_versions("")[0].version_id;
+        assert_eq!(
+            fs.delete_version("file", first.version_id, false),
+            Err(Error::ObjectLocked)
+        );
+        fs.delete_version("file", delete_marker, false).unwrap();
+
+        // legal holds apply regardless of retention
+        let legal_hold = |legal_hold| move |lock| Ok(file::Lock { legal_hold, ..lock });
+        let lock = fs.update_lock("file", first.version_id, legal_hold(true));
+        assert!(lock.unwrap().unwrap().legal_hold);
+        assert_eq!(
+            fs.delete_version("file", first.version_id, true),
+            Err(Error::ObjectLocked)
+        );
+        fs.update_lock("file", first.version_id, legal_hold(false))
+            .unwrap();
+        fs.delete_version("file", first.version_id, true)
+            .unwrap()
+            .unwrap();
+        assert!(fs
+            .update_lock("file", first.version_id, legal_hold(true))
+            .unwrap()
+            .is_none());
+
+        // compliance retention cannot be bypassed or shortened
+        let second = fs.upload_file(b"second");
+        let second = fs.associate_filename(second, "file");
+        let now = second.last_modified;
+        let compliance = file::Retention {
+            mode: file::RetentionMode::Compliance,
+            retain_until: now + 2 * 24 * 60 * 60,
+        };
+        fs.update_lock("file", second.version_id, |lock| {
+            lock.with_retention(Some(compliance), now, false)
+        })
+        .unwrap();
+        let shortened = |lock: file::Lock| lock.with_retention(None, now, true);
+        assert_eq!(
+            fs.update_lock("file", second.version_id, shortened),
+            Err(Error::ObjectLocked)
+        );
+        assert_eq!(
+            fs.delete_version("file", second.version_id, true),
+            Err(Error::ObjectLocked)
+        );
+        assert_eq!(fs.resolve_filename("file"), Some(second.file_id));
+    }
 }
@@ -1,13 +1,159 @@
+use std::fmt;
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
 use sha1::{Digest as _, Sha1};
 
 pub mod fjall_impl;
 pub mod mem_impl;
+mod stats;
+pub mod store;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The preconditions on the current file of a name were not met.
+    PreconditionFailed,
+    NoSuchBucket,
+    BucketAlreadyExists,
+    /// Buckets can only be deleted once no names are left in them.
+    BucketNotEmpty,
+    /// The dedup scope of a registered usecase cannot be changed, as the files of its buckets
+    /// would no longer be found.
+    DedupScopeChanged,
+    /// The scope would exceed its hard quota.
+    QuotaExceeded(quota::Limit),
+    /// A file cannot be assembled, as these chunks are not stored.
+    MissingChunks(Vec<chunk::ChunkId>),
+    /// The file is protected by a retention period or a legal hold.
+    ObjectLocked,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PreconditionFailed => f.write_str("precondition failed"),
+            Self::NoSuchBucket => f.write_str("bucket does not exist"),
+            Self::BucketAlreadyExists => f.write_str("bucket already exists"),
+            Self::BucketNotEmpty => f.write_str("bucket is not empty"),
+            Self::DedupScopeChanged => f.write_str("the dedup scope of a usecase cannot change"),
+            Self::QuotaExceeded(limit) => write!(f, "the {limit} quota is exceeded"),
+            Self::MissingChunks(chunks) => write!(f, "{} chunks are missing", chunks.len()),
+            Self::ObjectLocked => f.write_str("the file is locked"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// How files are split into chunks, and how these are stored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Files up to this size are stored inline, instead of being split into chunks.
+    pub inline_size: u64,
+    /// The maximum size of a chunk.
+    pub chunk_size: u64,
+    pub chunking: Chunking,
+    /// Segments are sealed once they grow beyond this size.
+    pub segment_size: u64,
+    /// The zstd level used to compress chunks, or `None` to store them uncompressed.
+    #[serde(deserialize_with = "deserialize_compression_level")]
+    pub compression_level: Option<i32>,
+    /// The hash files and chunks are identified by.
+    pub hash_algorithm: HashAlgorithm,
+    /// Larger files are rejected, which is up to the caller to enforce.
+    pub max_object_size: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        const MEG: u64 = 1 << 20;
+        const GIG: u64 = 1 << 30;
+        Self {
+            inline_size: 256,
+            chunk_size: 8 * MEG,
+            chunking: Chunking::Fixed,
+            segment_size: GIG,
+            compression_level: Some(zstd::DEFAULT_COMPRESSION_LEVEL),
+            hash_algorithm: HashAlgorithm::Blake3,
+            max_object_size: None,
+        }
+    }
+}
+
+impl Config {
+    /// Splits the file contents into chunks of at most `chunk_size`.
+    pub fn split<'c>(&self, contents: &'c [u8]) -> Vec<&'c [u8]> {
+        match self.chunking {
+            Chunking::Fixed => contents.chunks(self.chunk_size as usize).collect(),
+            Chunking::ContentDefined => {
+                let max_size = self.chunk_size as u32;
+                fastcdc::v2020::FastCDC::new(contents, max_size / 16, max_size / 4, max_size)
+                    .map(|chunk| &contents[chunk.offset..chunk.offset + chunk.length])
+                    .collect()
+            }
+        }
+    }
+}
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+/// How files are split into chunks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Chunking {
+    /// Chunks of exactly `chunk_size`, apart from the last one.
+    #[default]
+    Fixed,
+    /// Chunk boundaries are picked based on the contents, using FastCDC. Inserting into a file
+    /// only changes the chunks around the insertion, so the others are still deduplicated.
+    ///
+    /// This requires a `chunk_size` between 1 KiB and 16 MiB, chunks being at least a
+    /// sixteenth and on average a quarter of that.
+    ContentDefined,
+}
+
+impl Chunking {
+    /// The `chunk_size`s the strategy supports.
+    pub fn chunk_sizes(self) -> std::ops::RangeInclusive<u64> {
+        match self {
+            Self::Fixed => 1..=u32::MAX as u64,
+            Self::ContentDefined => {
+                fastcdc::v2020::MAXIMUM_MIN as u64..=fastcdc::v2020::MAXIMUM_MAX as u64
+            }
+        }
+    }
+}
+
+/// Deserializes a zstd level, or `"none"` to store chunks uncompressed.
+pub fn deserialize_compression_level<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Level {
+        Level(i32),
+        Named(String),
+    }
+
+    match Level::deserialize(deserializer)? {
+        Level::Level(level) => Ok(Some(level)),
+        Level::Named(name) if name == "none" => Ok(None),
+        Level::Named(name) => Err(serde::de::Error::custom(format!(
+            "invalid compression level `{name}`, expected a zstd level or \"none\""
+        ))),
+    }
+}
+
+/// The isolated part of the `FileStore` that a bucket stores its files in.
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Namespace(pub u64);
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum HashAlgorithm {
     Sha1 = 0,
+    #[default]
     Blake3 = 1,
 }
 
@@ -20,22 +166,106 @@ pub struct ContentHash {
 }
 
 impl ContentHash {
-    pub fn new(contents: &[u8]) -> Self {
-        let hash_algorithm = HashAlgorithm::Blake3;
+    pub fn new(hash_algorithm: HashAlgorithm, contents: &[u8]) -> Self {
+        let mut hasher = ContentHasher::new(hash_algorithm);
+        hasher.update(contents);
+        hasher.finalize()
+    }
+
+    fn hash_len(hash_algorithm: HashAlgorithm) -> usize {
+        match hash_algorithm {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Blake3 => 28,
+        }
+    }
+
+    /// Formats the hash as lowercase hex, the hash algorithm being implied by its length.
+    pub fn to_hex(&self) -> String {
+        let hash_len = Self::hash_len(self.hash_algorithm);
+        base16ct::lower::encode_string(&self.hash_bytes[..hash_len])
+    }
+
+    /// Parses a hash formatted with [`ContentHash::to_hex`].
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hash_algorithm = [HashAlgorithm::Sha1, HashAlgorithm::Blake3]
+            .into_iter()
+            .find(|algorithm| Self::hash_len(*algorithm) * 2 == hex.len())?;
 
         let mut hash_bytes = [0; 28];
+        let hash_len = Self::hash_len(hash_algorithm);
+        base16ct::mixed::decode(hex, &mut hash_bytes[..hash_len]).ok()?;
+        Some(Self {
+            hash_algorithm,
+            _padding: [0; 3],
+            hash_bytes,
+        })
+    }
+}
+
+/// The stable textual form of a hash, as formatted by [`ContentHash::to_hex`].
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl FromStr for ContentHash {
+    type Err = InvalidHash;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(s).ok_or(InvalidHash)
+    }
+}
+
+/// The text is neither a SHA-1 nor a BLAKE3 hash in hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidHash;
+
+impl fmt::Display for InvalidHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid content hash")
+    }
+}
+
+impl std::error::Error for InvalidHash {}
+
+/// Computes a [`ContentHash`] incrementally, for contents which are not in memory at once.
+pub enum ContentHasher {
+    Sha1(Sha1),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl ContentHasher {
+    pub fn new(hash_algorithm: HashAlgorithm) -> Self {
         match hash_algorithm {
-            HashAlgorithm::Sha1 => {
-                let sha1_hash = Sha1::digest(contents);
-                (&mut hash_bytes[..20]).copy_from_slice(sha1_hash.as_slice());
-            }
-            HashAlgorithm::Blake3 => {
-                let blake3_hash = blake3::hash(contents);
-                hash_bytes.copy_from_slice(&blake3_hash.as_bytes()[..28]);
+            HashAlgorithm::Sha1 => Self::Sha1(Sha1::new()),
+            HashAlgorithm::Blake3 => Self::Blake3(Default::default()),
+        }
+    }
+
+    pub fn update(&mut self, contents: &[u8]) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(contents),
+            Self::Blake3(hasher) => {
+                hasher.update(contents);
             }
         }
-        Self {
-            hash_algorithm: hash_algorithm,
+    }
+
+    pub fn finalize(self) -> ContentHash {
+        let mut hash_bytes = [0; 28];
+        let hash_algorithm = match self {
+            Self::Sha1(hasher) => {
+                hash_bytes[..20].copy_from_slice(hasher.finalize().as_slice());
+                HashAlgorithm::Sha1
+            }
+            Self::Blake3(hasher) => {
+                hash_bytes.copy_from_slice(&hasher.finalize().as_bytes()[..28]);
+                HashAlgorithm::Blake3
+            }
+        };
+        ContentHash {
+            hash_algorithm,
             _padding: [0; 3],
             hash_bytes,
         }
@@ -45,25 +275,81 @@ impl ContentHash {
 pub mod chunk {
     use super::*;
 
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
     #[repr(u8)]
     pub enum Compression {
         None = 0,
         Zstd = 1,
     }
 
+    impl Compression {
+        /// Compresses the chunk contents with the given zstd level, falling back to
+        /// storing them uncompressed if that does not save any space.
+        #[tracing::instrument(level = "trace", skip(contents), fields(size = contents.len()))]
+        pub fn compress(contents: &[u8], level: Option<i32>) -> (Self, Vec<u8>) {
+            if let Some(level) = level {
+                let compressed = zstd::bulk::compress(contents, level).unwrap();
+                if compressed.len() < contents.len() {
+                    return (Self::Zstd, compressed);
+                }
+            }
+            (Self::None, contents.into())
+        }
+
+        /// Turns the stored chunk contents back into the original contents.
+        #[tracing::instrument(level = "trace", skip(stored))]
+        pub fn decompress(self, stored: &[u8], size: u32) -> Vec<u8> {
+            self.try_decompress(stored, size).unwrap()
+        }
+
+        /// Like [`Compression::decompress`], but fails on corrupted contents.
+        pub fn try_decompress(self, stored: &[u8], size: u32) -> std::io::Result<Vec<u8>> {
+            match self {
+                Self::None => Ok(stored.into()),
+                Self::Zstd => zstd::bulk::decompress(stored, size as usize),
+            }
+        }
+
+        /// Turns the stored chunk contents into a zstd frame, without recompressing
+        /// contents that are already stored compressed.
+        #[tracing::instrument(level = "trace", skip(stored), fields(size = stored.len()))]
+        pub fn to_zstd(self, stored: Vec<u8>) -> Vec<u8> {
+            match self {
+                Self::None => {
+                    zstd::bulk::compress(&stored, zstd::DEFAULT_COMPRESSION_LEVEL).unwrap()
+                }
+                Self::Zstd => stored,
+            }
+        }
+    }
+
     /// The content-addressable ID of a `Chunk`
     #[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
     #[repr(C)]
     pub struct ChunkId(pub ContentHash);
     impl ChunkId {
-        pub fn from_contents(contents: &[u8]) -> Self {
-            Self(ContentHash::new(contents))
+        #[tracing::instrument(name = "hash_chunk", level = "trace", skip_all, fields(size = contents.len()))]
+        pub fn from_contents(hash_algorithm: HashAlgorithm, contents: &[u8]) -> Self {
+            Self(ContentHash::new(hash_algorithm, contents))
+        }
+    }
+
+    impl fmt::Display for ChunkId {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl FromStr for ChunkId {
+        type Err = InvalidHash;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            s.parse().map(Self)
         }
     }
 
     /// Chunk metadata, in particular where it is stored
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct Chunk {
         pub size: u32,
         pub compression: Compression,
@@ -81,24 +367,337 @@ pub mod file {
     #[repr(C)]
     pub struct FileId(pub ContentHash);
     impl FileId {
-        pub fn from_contents(contents: &[u8]) -> Self {
-            Self(ContentHash::new(contents))
+        #[tracing::instrument(name = "hash_file", level = "trace", skip_all, fields(size = contents.len()))]
+        pub fn from_contents(hash_algorithm: HashAlgorithm, contents: &[u8]) -> Self {
+            Self(ContentHash::new(hash_algorithm, contents))
         }
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
+    impl fmt::Display for FileId {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl FromStr for FileId {
+        type Err = InvalidHash;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            s.parse().map(Self)
+        }
+    }
+
+    /// How outdated the recorded last access of a name can get, in seconds, so that reads
+    /// do not write on every request.
+    pub const ACCESS_RESOLUTION: u64 = 60 * 60;
+
+    /// The file a name points to.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct NamedFile {
+        pub file_id: FileId,
+        /// The time the name was last associated with a file, in seconds since the unix epoch.
+        pub last_modified: u64,
+        /// The time the name was last read, in seconds since the unix epoch. It is only
+        /// recorded in buckets with a TTI, and at most every [`ACCESS_RESOLUTION`].
+        pub last_accessed: u64,
+        /// The version of the name, which is [`VersionId::NULL`] unless it was written to a
+        /// versioned bucket.
+        pub version_id: VersionId,
+        /// Only ever set in buckets with object lock, which are versioned, so that the file
+        /// is kept as a version when the name is overwritten or deleted.
+        pub lock: Lock,
+        pub tags: Vec<Tag>,
+    }
+
+    /// Identifies a version of a name, with later versions of a name having larger IDs.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+    pub struct VersionId(pub u64);
+
+    impl VersionId {
+        /// The version of a name written before versioning was enabled, formatted as `null`.
+        pub const NULL: Self = Self(0);
+
+        /// The ID of a version written now, after the `latest` one.
+        pub fn next(latest: Option<Self>) -> Self {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64;
+            Self(now.max(latest.map_or(0, |latest| latest.0) + 1))
+        }
+    }
+
+    impl fmt::Display for VersionId {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match *self {
+                Self::NULL => f.write_str("null"),
+                Self(id) => write!(f, "{id:016x}"),
+            }
+        }
+    }
+
+    impl FromStr for VersionId {
+        type Err = std::num::ParseIntError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "null" => Ok(Self::NULL),
+                s => u64::from_str_radix(s, 16).map(Self),
+            }
+        }
+    }
+
+    /// A version of a name which is not the current one, or a delete marker, as kept in
+    /// versioned buckets.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Version {
+        /// The file of the version, or `None` for a delete marker.
+        pub file_id: Option<FileId>,
+        /// The time the version was written, in seconds since the unix epoch.
+        pub last_modified: u64,
+        pub lock: Lock,
+        pub tags: Vec<Tag>,
+    }
+
+    impl Version {
+        /// A delete marker written now.
+        pub fn delete_marker(last_modified: u64) -> Self {
+            Self {
+                file_id: None,
+                last_modified,
+                lock: Lock::default(),
+                tags: vec![],
+            }
+        }
+    }
+
+    /// A name removed by `delete_filenames`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DeletedName {
+        /// The file the name pointed to.
+        pub file_id: FileId,
+        /// The version of the delete marker which became the current version of the name, in
+        /// versioned buckets.
+        pub delete_marker: Option<VersionId>,
+    }
+
+    impl From<NamedFile> for Version {
+        fn from(named_file: NamedFile) -> Self {
+            Self {
+                file_id: Some(named_file.file_id),
+                last_modified: named_file.last_modified,
+                lock: named_file.lock,
+                tags: named_file.tags,
+            }
+        }
+    }
+
+    /// A key-value pair attached to a version of a name, as in S3 object tagging.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Tag {
+        pub key: String,
+        pub value: String,
+    }
+
+    /// The most tags a version can have.
+    pub const MAX_TAGS: usize = 10;
+    pub const MAX_TAG_KEY_LEN: usize = 128;
+    pub const MAX_TAG_VALUE_LEN: usize = 256;
+
+    /// Whether the tags are within the limits of S3, with unique keys.
+    pub fn are_valid_tags(tags: &[Tag]) -> bool {
+        tags.len() <= MAX_TAGS
+            && tags.iter().enumerate().all(|(i, tag)| {
+                !tag.key.is_empty()
+                    && tag.key.chars().count() <= MAX_TAG_KEY_LEN
+                    && tag.value.chars().count() <= MAX_TAG_VALUE_LEN
+                    && tags[..i].iter().all(|other| other.key != tag.key)
+            })
+    }
+
+    /// How strictly a retention period protects a file, as in S3 Object Lock.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "UPPERCASE")]
+    pub enum RetentionMode {
+        /// The retention can be shortened or removed by requests bypassing it explicitly.
+        Governance,
+        /// The retention can only ever be extended.
+        Compliance,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Retention {
+        pub mode: RetentionMode,
+        /// Until when the file is retained, in seconds since the unix epoch.
+        pub retain_until: u64,
+    }
+
+    /// What keeps the file of a version from being released, by deleting the version.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Lock {
+        pub retention: Option<Retention>,
+        /// A legal hold protects the file until it is lifted, regardless of any retention.
+        pub legal_hold: bool,
+    }
+
+    impl Lock {
+        /// Whether the file is protected at `now`, in seconds since the unix epoch.
+        ///
+        /// Governance retention does not protect the file from requests that
+        /// `bypass_governance`.
+        pub fn is_locked(&self, now: u64, bypass_governance: bool) -> bool {
+            self.legal_hold || self.is_retained(now, bypass_governance)
+        }
+
+        fn is_retained(&self, now: u64, bypass_governance: bool) -> bool {
+            self.retention.is_some_and(|retention| {
+                retention.retain_until > now
+                    && !(bypass_governance && retention.mode == RetentionMode::Governance)
+            })
+        }
+
+        /// Replaces the retention, which fails if that would shorten or remove an active
+        /// retention, or turn compliance retention into governance retention, unless that is
+        /// a governance retention which is bypassed.
+        pub fn with_retention(
+            self,
+            retention: Option<Retention>,
+            now: u64,
+            bypass_governance: bool,
+        ) -> Result<Self, Error> {
+            let weakens = match (self.retention, retention) {
+                (Some(current), Some(retention)) => {
+                    retention.retain_until < current.retain_until
+                        || (current.mode, retention.mode)
+                            == (RetentionMode::Compliance, RetentionMode::Governance)
+                }
+                (current, retention) => current.is_some() && retention.is_none(),
+            };
+            if weakens && self.is_retained(now, bypass_governance) {
+                return Err(Error::ObjectLocked);
+            }
+            Ok(Self { retention, ..self })
+        }
+    }
+
+    /// A version of a name, as listed by `ListObjectVersions`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ListedVersion {
+        pub name: String,
+        pub version_id: VersionId,
+        pub version: Version,
+        /// Whether this is the current version of the name, which might be a delete marker.
+        pub is_latest: bool,
+    }
+
+    impl ListedVersion {
+        /// Orders the versions by name and then from the latest to the oldest, marking the
+        /// latest version of each name.
+        pub fn sorted(mut versions: Vec<(String, VersionId, Version)>) -> Vec<Self> {
+            versions.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+            let mut listed: Vec<Self> = Vec::with_capacity(versions.len());
+            for (name, version_id, version) in versions {
+                let is_latest = listed.last().is_none_or(|last| last.name != name);
+                listed.push(Self {
+                    name,
+                    version_id,
+                    version,
+                    is_latest,
+                });
+            }
+            listed
+        }
+    }
+
+    /// Conditions on the file a name currently points to, as in the HTTP `If-Match` and
+    /// `If-None-Match` headers.
+    #[derive(Debug, Default)]
+    pub struct Preconditions {
+        pub if_match: Option<FileMatch>,
+        pub if_none_match: Option<FileMatch>,
+        /// Whether writing the file the name already points to is a retry, which succeeds even
+        /// if the other conditions fail.
+        pub idempotent: bool,
+    }
+
+    impl Preconditions {
+        pub fn check(&self, current: Option<FileId>) -> bool {
+            let if_match = self.if_match.as_ref();
+            let if_none_match = self.if_none_match.as_ref();
+            if_match.is_none_or(|files| files.matches(current))
+                && if_none_match.is_none_or(|files| !files.matches(current))
+        }
+
+        /// Whether writing `file_id` to a name currently pointing to `current` is a retry of
+        /// an idempotent write that already succeeded.
+        pub fn is_retry(&self, current: Option<FileId>, file_id: FileId) -> bool {
+            self.idempotent && current == Some(file_id)
+        }
+    }
+
+    #[derive(Debug)]
+    pub enum FileMatch {
+        /// Matches any file, but not a missing name.
+        Any,
+        Files(Vec<FileId>),
+    }
+
+    impl FileMatch {
+        pub fn matches(&self, current: Option<FileId>) -> bool {
+            match (self, current) {
+                (_, None) => false,
+                (Self::Any, Some(_)) => true,
+                (Self::Files(files), Some(current)) => files.contains(&current),
+            }
+        }
+    }
+
+    /// The format of names allocated by the store.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum IdFormat {
+        /// A random UUID, as 32 hex digits.
+        #[default]
+        Uuid,
+        /// A random UUID, as 22 characters of URL-safe base64.
+        Compact,
+    }
+
+    impl IdFormat {
+        pub fn parse(format: &str) -> Option<Self> {
+            match format {
+                "uuid" => Some(Self::Uuid),
+                "compact" => Some(Self::Compact),
+                _ => None,
+            }
+        }
+
+        /// Generates a new random name.
+        pub fn generate(self) -> String {
+            use base64::Engine as _;
+
+            let uuid = uuid::Uuid::new_v4();
+            match self {
+                Self::Uuid => uuid.simple().to_string(),
+                Self::Compact => {
+                    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(uuid.as_bytes())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct File {
         pub size: u64,
         pub contents: FileContents,
     }
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Serialize, Deserialize)]
     pub enum FileContents {
         Inline(Vec<u8>),
         Chunked(Vec<FileChunk>),
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct FileChunk {
         pub chunk_size: u32,
         pub chunk_id: chunk::ChunkId,
@@ -115,10 +714,318 @@ pub mod segment {
     }
 }
 
+pub mod bucket {
+    use super::*;
+
+    /// A named bucket, which maps to a usecase and scope, and the namespace holding its files.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Bucket {
+        pub name: String,
+        pub namespace: Namespace,
+        pub usecase: String,
+        pub scope: String,
+        /// The creation time, in seconds since the unix epoch.
+        pub created: u64,
+        pub settings: BucketSettings,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct BucketSettings {
+        /// How long files are kept after they were last modified, in seconds.
+        pub ttl: Option<u64>,
+        /// How long files are kept after they were last accessed, in seconds.
+        pub tti: Option<u64>,
+        /// The zstd level used to compress chunks, or `None` to store them uncompressed.
+        pub compression_level: Option<i32>,
+        /// Whether overwritten and deleted names keep their previous file as a version.
+        /// Versioning cannot be disabled once it was enabled.
+        pub versioned: bool,
+        /// Whether files can be locked, which requires versioning. Object lock cannot be
+        /// disabled once it was enabled.
+        pub object_lock: bool,
+        /// The retention of files written to a bucket with object lock, unless given
+        /// explicitly.
+        pub default_retention: Option<DefaultRetention>,
+        /// The rules expiring files and versions of the bucket.
+        pub lifecycle: Vec<lifecycle::Rule>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct DefaultRetention {
+        pub mode: file::RetentionMode,
+        pub days: u32,
+    }
+
+    impl DefaultRetention {
+        /// The retention of a file written at `now`, in seconds since the unix epoch.
+        pub fn retention_from(&self, now: u64) -> file::Retention {
+            file::Retention {
+                mode: self.mode,
+                retain_until: now + u64::from(self.days) * 24 * 60 * 60,
+            }
+        }
+    }
+
+    impl Default for BucketSettings {
+        fn default() -> Self {
+            Self {
+                ttl: None,
+                tti: None,
+                compression_level: Some(zstd::DEFAULT_COMPRESSION_LEVEL),
+                versioned: false,
+                object_lock: false,
+                default_retention: None,
+                lifecycle: Vec::new(),
+            }
+        }
+    }
+
+    impl BucketSettings {
+        /// The settings new buckets of a usecase start out with.
+        pub fn from_policy(policy: &usecase::Policy) -> Self {
+            Self {
+                ttl: policy.ttl,
+                tti: policy.tti,
+                compression_level: policy.compression_level,
+                versioned: false,
+                object_lock: false,
+                default_retention: None,
+                lifecycle: Vec::new(),
+            }
+        }
+    }
+
+    /// The name of the bucket holding the blobs of a usecase and scope, as used by the
+    /// [`client`](crate::client) and the [`grpc`](crate::grpc) service.
+    pub fn scoped_name(usecase: &str, scope: &str) -> String {
+        format!("{usecase}.{scope}")
+    }
+
+    /// Whether the name follows the S3 bucket naming rules: 3 to 63 lowercase letters, digits,
+    /// `.` and `-`, starting and ending with a letter or digit.
+    pub fn is_valid_name(name: &str) -> bool {
+        let is_alphanumeric = |c: u8| c.is_ascii_lowercase() || c.is_ascii_digit();
+        let bytes = name.as_bytes();
+        (3..=63).contains(&bytes.len())
+            && bytes
+                .iter()
+                .all(|&c| is_alphanumeric(c) || c == b'.' || c == b'-')
+            && is_alphanumeric(bytes[0])
+            && is_alphanumeric(bytes[bytes.len() - 1])
+    }
+}
+
+pub mod usecase {
+    use super::*;
+
+    /// Where identical contents are deduplicated.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum DedupScope {
+        /// Each bucket stores its own copy.
+        #[default]
+        Bucket,
+        /// All buckets of the usecase share a single copy, in the namespace of the usecase.
+        Usecase,
+    }
+
+    /// The storage policy of a usecase, applying to all of its buckets.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Policy {
+        /// How long files are kept after they were last modified, in seconds.
+        pub ttl: Option<u64>,
+        /// How long files are kept after they were last accessed, in seconds.
+        pub tti: Option<u64>,
+        pub chunking: Chunking,
+        pub chunk_size: u64,
+        /// The zstd level used to compress chunks, or `None` to store them uncompressed.
+        pub compression_level: Option<i32>,
+        pub max_object_size: Option<u64>,
+        pub dedup: DedupScope,
+        pub hash_algorithm: HashAlgorithm,
+        /// Uploads exceeding these limits are rejected.
+        pub hard_quota: quota::Quota,
+        /// Uploads exceeding these limits are only logged.
+        pub soft_quota: quota::Quota,
+    }
+
+    impl Policy {
+        /// The policy storing files according to `config`, without any expiration.
+        pub fn from_config(config: &Config) -> Self {
+            Self {
+                ttl: None,
+                tti: None,
+                chunking: config.chunking,
+                chunk_size: config.chunk_size,
+                compression_level: config.compression_level,
+                max_object_size: config.max_object_size,
+                dedup: DedupScope::default(),
+                hash_algorithm: config.hash_algorithm,
+                hard_quota: Default::default(),
+                soft_quota: Default::default(),
+            }
+        }
+
+        /// Overrides the chunking and storage settings of `config` with the ones of the policy.
+        pub fn apply(&self, config: Config) -> Config {
+            Config {
+                chunk_size: self.chunk_size,
+                chunking: self.chunking,
+                compression_level: self.compression_level,
+                hash_algorithm: self.hash_algorithm,
+                max_object_size: self.max_object_size,
+                ..config
+            }
+        }
+    }
+
+    /// A registered usecase, which buckets are created for.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Usecase {
+        pub name: String,
+        /// The namespace holding the files of all buckets, when deduplicating per usecase.
+        pub namespace: Namespace,
+        pub policy: Policy,
+    }
+}
+
+/// Accounting of the named files each `(usecase, scope)` holds, across all of its buckets.
+pub mod quota {
+    use super::*;
+
+    /// The limits of a scope, where `None` is unlimited.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct Quota {
+        pub objects: Option<u64>,
+        pub logical_bytes: Option<u64>,
+        pub physical_bytes: Option<u64>,
+    }
+
+    impl Quota {
+        /// The first limit `usage` exceeds, only considering counters which grew compared to
+        /// `previous`, so that scopes over quota can still shrink.
+        pub fn exceeded(&self, previous: &Usage, usage: &Usage) -> Option<Limit> {
+            let exceeds = |limit: Option<u64>, previous: u64, current: u64| {
+                current > previous && limit.is_some_and(|limit| current > limit)
+            };
+            if exceeds(self.objects, previous.objects, usage.objects) {
+                Some(Limit::Objects)
+            } else if exceeds(
+                self.logical_bytes,
+                previous.logical_bytes,
+                usage.logical_bytes,
+            ) {
+                Some(Limit::LogicalBytes)
+            } else if exceeds(
+                self.physical_bytes,
+                previous.physical_bytes,
+                usage.physical_bytes,
+            ) {
+                Some(Limit::PhysicalBytes)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Limit {
+        Objects,
+        LogicalBytes,
+        PhysicalBytes,
+    }
+
+    impl fmt::Display for Limit {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                Self::Objects => "objects",
+                Self::LogicalBytes => "logical bytes",
+                Self::PhysicalBytes => "physical bytes",
+            })
+        }
+    }
+
+    /// The named files of a scope.
+    ///
+    /// Every name counts the full size of its file, regardless of deduplication. The physical
+    /// bytes are the bytes stored for the file after compression.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Usage {
+        pub objects: u64,
+        pub logical_bytes: u64,
+        pub physical_bytes: u64,
+    }
+
+    impl Usage {
+        /// The usage of a single name pointing to a file.
+        pub(crate) fn of_file(file: &file::File, physical_bytes: u64) -> Self {
+            Self {
+                objects: 1,
+                logical_bytes: file.size,
+                physical_bytes,
+            }
+        }
+
+        pub(crate) fn add(self, other: Self) -> Self {
+            Self {
+                objects: self.objects + other.objects,
+                logical_bytes: self.logical_bytes + other.logical_bytes,
+                physical_bytes: self.physical_bytes + other.physical_bytes,
+            }
+        }
+
+        pub(crate) fn sub(self, other: Self) -> Self {
+            Self {
+                objects: self.objects.saturating_sub(other.objects),
+                logical_bytes: self.logical_bytes.saturating_sub(other.logical_bytes),
+                physical_bytes: self.physical_bytes.saturating_sub(other.physical_bytes),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ScopeUsage {
+        pub usecase: String,
+        pub scope: String,
+        pub usage: Usage,
+    }
+
+    /// The scope the named files of a `NamespacedFileStore` are accounted to.
+    #[derive(Debug, Clone)]
+    pub struct Account {
+        pub usecase: String,
+        pub scope: String,
+        pub hard_quota: Quota,
+        pub soft_quota: Quota,
+    }
+
+    impl Account {
+        /// Checks the quotas for a change from `previous` to `usage`, logging soft quota
+        /// violations.
+        pub(crate) fn check(&self, previous: &Usage, usage: &Usage) -> Result<(), Error> {
+            if let Some(limit) = self.hard_quota.exceeded(previous, usage) {
+                stats::quota_exceeded("hard");
+                return Err(Error::QuotaExceeded(limit));
+            }
+            if let Some(limit) = self.soft_quota.exceeded(previous, usage) {
+                tracing::warn!(
+                    usecase = self.usecase,
+                    scope = self.scope,
+                    %limit,
+                    "soft quota exceeded"
+                );
+                stats::quota_exceeded("soft");
+            }
+            Ok(())
+        }
+    }
+}
+
 pub mod refcounts {
     use super::*;
 
-    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
     pub enum ReferenceCountType {
         Chunk(chunk::ChunkId),
         File(file::FileId),
@@ -127,11 +1034,37 @@ pub mod refcounts {
 }
 
 pub mod gc {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
     use super::*;
 
-    #[derive(Debug, Serialize, Deserialize)]
+    /// How long a chunk uploaded ahead of assembling it is kept around for.
+    pub const PENDING_CHUNK_TTL: Duration = Duration::from_secs(60 * 60);
+
+    /// How long an allocated or reserved name is kept from being allocated again, without
+    /// anything being uploaded to it. This matches the longest-lived signed URL.
+    pub const RESERVATION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    /// A point in time, in seconds since the unix epoch.
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
     pub struct Timestamp(u32);
 
+    impl Timestamp {
+        pub fn at(time: SystemTime) -> Self {
+            let secs = time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            Self(secs.try_into().unwrap_or(u32::MAX))
+        }
+
+        /// The timestamp `duration` from now.
+        pub fn after(duration: Duration) -> Self {
+            Self::at(SystemTime::now() + duration)
+        }
+    }
+
+    /// A reference to a chunk, which is released once it `expires`.
     #[derive(Debug, Serialize, Deserialize)]
     pub struct ChunkRef {
         pub chunk_id: chunk::ChunkId,
@@ -146,10 +1079,204 @@ pub mod gc {
     }
 }
 
+/// Rules expiring the files of a bucket, as in S3 lifecycle configurations.
+pub mod lifecycle {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A lifecycle rule, whose actions apply to the names matching its filter.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Rule {
+        pub id: String,
+        /// Disabled rules are kept, but not applied.
+        pub enabled: bool,
+        pub filter: Filter,
+        /// Current files are deleted this many days after they were last modified, which
+        /// adds a delete marker in versioned buckets.
+        pub expiration_days: Option<u32>,
+        /// Noncurrent versions and delete markers are removed this many days after they were
+        /// replaced by a newer version, unless their file is locked.
+        pub noncurrent_expiration_days: Option<u32>,
+        /// Names which were reserved without being uploaded to are released this many days
+        /// after they were reserved, like abandoned multipart uploads.
+        pub abort_incomplete_upload_days: Option<u32>,
+    }
+
+    impl Rule {
+        /// Whether the rule has any action to apply.
+        pub fn has_actions(&self) -> bool {
+            self.expiration_days.is_some()
+                || self.noncurrent_expiration_days.is_some()
+                || self.abort_incomplete_upload_days.is_some()
+        }
+    }
+
+    /// Which names a rule applies to.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Filter {
+        pub prefix: String,
+        /// The tags a version needs to have, all of them.
+        pub tags: Vec<file::Tag>,
+    }
+
+    impl Filter {
+        /// Whether the filter matches a version of the name with the given tags.
+        pub fn matches(&self, name: &str, tags: &[file::Tag]) -> bool {
+            name.starts_with(&self.prefix) && self.tags.iter().all(|tag| tags.contains(tag))
+        }
+    }
+
+    pub fn days(days: u32) -> Duration {
+        Duration::from_secs(u64::from(days) * 24 * 60 * 60)
+    }
+
+    /// Whether `days` have passed at `now` since `since`, both in seconds since the unix
+    /// epoch.
+    pub fn has_passed(days: u32, since: u64, now: u64) -> bool {
+        since + self::days(days).as_secs() <= now
+    }
+}
+
+/// Introspection of the `FileStore`, as exposed by the admin endpoints.
+pub mod report {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// A file, along with its chunks and their reference counts.
+    #[derive(Debug)]
+    pub struct FileInfo {
+        pub file_id: file::FileId,
+        pub file: file::File,
+        pub refcount: u32,
+        pub chunks: Vec<ChunkInfo>,
+    }
+
+    #[derive(Debug)]
+    pub struct ChunkInfo {
+        pub chunk_id: chunk::ChunkId,
+        pub chunk: chunk::Chunk,
+        pub refcount: u32,
+        pub segment_refcount: u32,
+    }
+
+    /// The storage used by the files and chunks of a namespace.
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct NamespaceUsage {
+        pub namespace: Namespace,
+        pub files: u64,
+        /// The total size of all files.
+        pub file_bytes: u64,
+        pub chunks: u64,
+        /// The total size of all chunks, before compression.
+        pub chunk_bytes: u64,
+        /// The bytes physically stored, for compressed chunks and inline files.
+        pub stored_bytes: u64,
+    }
+
+    impl NamespaceUsage {
+        /// Sums up the usage of each namespace, sorted by namespace.
+        pub(crate) fn collect(
+            files: impl Iterator<Item = (Namespace, file::File)>,
+            chunks: impl Iterator<Item = (Namespace, chunk::Chunk)>,
+        ) -> Vec<Self> {
+            let empty = |namespace| Self {
+                namespace,
+                files: 0,
+                file_bytes: 0,
+                chunks: 0,
+                chunk_bytes: 0,
+                stored_bytes: 0,
+            };
+            let mut usage = HashMap::new();
+            for (namespace, file) in files {
+                let usage = usage.entry(namespace).or_insert_with(|| empty(namespace));
+                usage.files += 1;
+                usage.file_bytes += file.size;
+                if let file::FileContents::Inline(contents) = file.contents {
+                    usage.stored_bytes += contents.len() as u64;
+                }
+            }
+            for (namespace, chunk) in chunks {
+                let usage = usage.entry(namespace).or_insert_with(|| empty(namespace));
+                usage.chunks += 1;
+                usage.chunk_bytes += chunk.size as u64;
+                usage.stored_bytes += chunk.compressed_size as u64;
+            }
+
+            let mut usage: Vec<_> = usage.into_values().collect();
+            usage.sort_by_key(|usage| usage.namespace.0);
+            usage
+        }
+    }
+
+    /// The outcome of a garbage collection, which releases expired chunk references and name
+    /// reservations, and removes unreferenced segments.
+    #[derive(Debug, Default, PartialEq, Eq)]
+    pub struct GcReport {
+        pub chunk_refs_expired: u64,
+        pub reservations_expired: u64,
+        pub segments_removed: u64,
+        pub bytes_freed: u64,
+    }
+
+    /// The outcome of applying the lifecycle rules of all buckets.
+    #[derive(Debug, Default, PartialEq, Eq)]
+    pub struct LifecycleReport {
+        /// Current files which were deleted, or replaced by a delete marker.
+        pub objects_expired: u64,
+        /// Noncurrent versions and delete markers which were removed.
+        pub versions_expired: u64,
+        /// Noncurrent versions which expired, but were kept as their file is locked.
+        pub versions_locked: u64,
+        /// Reservations of names which were never uploaded to that were released.
+        pub uploads_aborted: u64,
+    }
+
+    /// The outcome of a scrub, which verifies the contents of all stored chunks.
+    #[derive(Debug, Default, PartialEq, Eq)]
+    pub struct ScrubReport {
+        pub chunks_checked: u64,
+        /// Chunks which cannot be read, or whose contents do not match their ID.
+        pub corrupt_chunks: Vec<(Namespace, chunk::ChunkId)>,
+    }
+
+    impl ScrubReport {
+        /// Verifies the stored contents of a chunk, or records the chunk as corrupt.
+        pub(crate) fn check(
+            &mut self,
+            namespace: Namespace,
+            chunk_id: chunk::ChunkId,
+            chunk: &chunk::Chunk,
+            stored: std::io::Result<Vec<u8>>,
+        ) {
+            let contents =
+                stored.and_then(|stored| chunk.compression.try_decompress(&stored, chunk.size));
+            self.chunks_checked += 1;
+            stats::chunk_scrubbed();
+            let hash_algorithm = chunk_id.0.hash_algorithm;
+            if !contents.is_ok_and(|contents| {
+                chunk::ChunkId::from_contents(hash_algorithm, &contents) == chunk_id
+            }) {
+                tracing::error!(?namespace, ?chunk_id, "found a corrupt chunk");
+                stats::corrupt_chunk();
+                self.corrupt_chunks.push((namespace, chunk_id));
+            }
+        }
+    }
+}
+
 mod dbg {
     use super::*;
     use core::fmt;
 
+    impl fmt::Debug for Namespace {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Namespace({})", self.0)
+        }
+    }
+
     impl fmt::Debug for segment::SegmentId {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             write!(f, "SegmentId({:x})", base16ct::HexDisplay(&self.uuid))
@@ -159,12 +1286,8 @@ mod dbg {
     impl fmt::Debug for ContentHash {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             match self.hash_algorithm {
-                HashAlgorithm::Sha1 => {
-                    write!(f, "SHA1:{:x}", base16ct::HexDisplay(&self.hash_bytes[..20]))
-                }
-                HashAlgorithm::Blake3 => {
-                    write!(f, "BLAKE3:{:x}", base16ct::HexDisplay(&self.hash_bytes))
-                }
+                HashAlgorithm::Sha1 => write!(f, "SHA1:{self}"),
+                HashAlgorithm::Blake3 => write!(f, "BLAKE3:{self}"),
             }
         }
     }
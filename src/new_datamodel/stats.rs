@@ -0,0 +1,83 @@
+//! Metrics recorded by both `FileStore` implementations.
+//!
+//! The dedup ratio is `kycok_uploaded_bytes_total` over `kycok_stored_bytes_total`, and the
+//! compression ratio is `kycok_chunk_bytes_total` over `kycok_stored_bytes_total{kind="chunk"}`.
+
+use metrics::{counter, gauge};
+
+use super::report;
+
+/// A file with the given size was uploaded, and `deduplicated` if it was already stored.
+pub(crate) fn file_uploaded(size: u64, deduplicated: bool) {
+    counter!("kycok_uploaded_bytes_total").increment(size);
+    if deduplicated {
+        counter!("kycok_file_dedup_hits_total").increment(1);
+    }
+}
+
+/// A new file was stored, with `inline_size` bytes stored inline.
+pub(crate) fn file_stored(inline_size: u64) {
+    counter!("kycok_files_stored_total").increment(1);
+    counter!("kycok_stored_bytes_total", "kind" => "inline").increment(inline_size);
+}
+
+/// A chunk was uploaded which is already stored.
+pub(crate) fn chunk_deduplicated() {
+    counter!("kycok_chunk_dedup_hits_total").increment(1);
+}
+
+/// A new chunk of `size` bytes was stored as `stored_size` bytes in a segment.
+pub(crate) fn chunk_stored(size: u64, stored_size: u64) {
+    counter!("kycok_chunks_stored_total").increment(1);
+    counter!("kycok_chunk_bytes_total").increment(size);
+    counter!("kycok_stored_bytes_total", "kind" => "chunk").increment(stored_size);
+}
+
+/// The number of segments currently stored.
+pub(crate) fn segments(count: usize) {
+    gauge!("kycok_segments").set(count as f64);
+}
+
+/// The fill level of the segment currently being appended to, which is `0` once it is sealed.
+pub(crate) fn active_segment(len: u64) {
+    gauge!("kycok_active_segment_bytes").set(len as f64);
+}
+
+/// Unreferenced segments were removed.
+pub(crate) fn segments_collected(count: usize) {
+    counter!("kycok_gc_collected_segments_total").increment(count as u64);
+}
+
+/// Garbage collection finished.
+pub(crate) fn gc_finished() {
+    counter!("kycok_gc_runs_total").increment(1);
+}
+
+/// A chunk was verified by a scrub.
+pub(crate) fn chunk_scrubbed() {
+    counter!("kycok_scrub_chunks_checked_total").increment(1);
+}
+
+/// A scrub found a chunk which cannot be read, or whose contents do not match its ID.
+pub(crate) fn corrupt_chunk() {
+    counter!("kycok_scrub_corrupt_chunks_total").increment(1);
+}
+
+/// A scrub finished.
+pub(crate) fn scrub_finished() {
+    counter!("kycok_scrub_runs_total").increment(1);
+}
+
+/// A named file exceeded the `"hard"` or `"soft"` quota of its scope.
+pub(crate) fn quota_exceeded(kind: &'static str) {
+    counter!("kycok_quota_exceeded_total", "kind" => kind).increment(1);
+}
+
+/// The lifecycle rules of all buckets were applied.
+pub(crate) fn lifecycle_applied(report: &report::LifecycleReport) {
+    let expired = |kind| counter!("kycok_lifecycle_expired_total", "kind" => kind);
+    expired("object").increment(report.objects_expired);
+    expired("version").increment(report.versions_expired);
+    expired("upload").increment(report.uploads_aborted);
+    counter!("kycok_lifecycle_runs_total").increment(1);
+}
@@ -0,0 +1,827 @@
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::*;
+
+/// Either of the `FileStore` implementations, picked at runtime.
+// there is a single store per process, so its size does not matter
+#[allow(clippy::large_enum_variant)]
+pub enum FileStore {
+    Mem(RwLock<mem_impl::FileStore>),
+    Fjall(fjall_impl::FileStore),
+}
+
+pub enum NamespacedFileStore<'fs> {
+    Mem(mem_impl::NamespacedFileStore<'fs>),
+    Fjall(fjall_impl::NamespacedFileStore<'fs>),
+}
+
+macro_rules! dispatch {
+    ($slf:expr, $fs:ident => $body:expr) => {
+        match $slf {
+            Self::Mem($fs) => $body,
+            Self::Fjall($fs) => $body,
+        }
+    };
+}
+
+impl FileStore {
+    pub fn mem() -> Self {
+        Self::Mem(Default::default())
+    }
+
+    /// Opens the fjall store persisted in `path`, or a temporary one when `None`.
+    pub fn fjall(path: Option<&Path>) -> fjall::Result<Self> {
+        let filestore = match path {
+            Some(path) => fjall_impl::FileStore::open(path)?,
+            None => fjall_impl::FileStore::new(),
+        };
+        Ok(Self::Fjall(filestore))
+    }
+
+    /// Durably persists everything written so far, see [`fjall_impl::FileStore::shutdown`].
+    pub fn shutdown(&self) -> fjall::Result<()> {
+        match self {
+            Self::Mem(_) => Ok(()),
+            Self::Fjall(fs) => fs.shutdown(),
+        }
+    }
+
+    /// The space left on the disk, or `None` if the store is not persisted to disk.
+    pub fn available_space(&self) -> Option<std::io::Result<u64>> {
+        match self {
+            Self::Mem(_) => None,
+            Self::Fjall(fs) => Some(fs.available_space()),
+        }
+    }
+
+    pub fn usage(&self) -> Vec<report::NamespaceUsage> {
+        match self {
+            Self::Mem(fs) => fs.read().unwrap().usage(),
+            Self::Fjall(fs) => fs.usage(),
+        }
+    }
+
+    pub fn collect_garbage(&self) -> std::io::Result<report::GcReport> {
+        match self {
+            Self::Mem(fs) => Ok(fs.write().unwrap().collect_garbage()),
+            Self::Fjall(fs) => fs.collect_garbage(),
+        }
+    }
+
+    pub fn scrub(&self) -> report::ScrubReport {
+        match self {
+            Self::Mem(fs) => fs.read().unwrap().scrub(),
+            Self::Fjall(fs) => fs.scrub(),
+        }
+    }
+
+    pub fn with_namespace(slf: &FileStore, namespace: Namespace) -> NamespacedFileStore<'_> {
+        match slf {
+            Self::Mem(fs) => {
+                NamespacedFileStore::Mem(mem_impl::FileStore::with_namespace(fs, namespace))
+            }
+            Self::Fjall(fs) => {
+                NamespacedFileStore::Fjall(fjall_impl::FileStore::with_namespace(fs, namespace))
+            }
+        }
+    }
+
+    /// The store of the bucket, configured according to the policy of its usecase and the
+    /// bucket settings, with `defaults` for everything else. Named files are accounted to the
+    /// usecase and scope of the bucket.
+    ///
+    /// Buckets of unregistered usecases use the `defaults`, without any quotas.
+    pub fn with_bucket<'fs>(
+        slf: &'fs FileStore,
+        bucket: &bucket::Bucket,
+        defaults: Config,
+    ) -> NamespacedFileStore<'fs> {
+        let mut account = quota::Account {
+            usecase: bucket.usecase.clone(),
+            scope: bucket.scope.clone(),
+            hard_quota: Default::default(),
+            soft_quota: Default::default(),
+        };
+        let filestore = Self::with_namespace(slf, bucket.namespace);
+        let (filestore, config) = match slf.get_usecase(&bucket.usecase) {
+            Some(usecase) => {
+                let filestore = match usecase.policy.dedup {
+                    usecase::DedupScope::Bucket => filestore,
+                    usecase::DedupScope::Usecase => {
+                        filestore.with_content_namespace(usecase.namespace)
+                    }
+                };
+                account.hard_quota = usecase.policy.hard_quota.clone();
+                account.soft_quota = usecase.policy.soft_quota.clone();
+                (filestore, usecase.policy.apply(defaults))
+            }
+            None => (filestore, defaults),
+        };
+        let filestore = filestore
+            .with_account(account)
+            .with_versioning(bucket.settings.versioned)
+            .with_default_retention(bucket.settings.default_retention);
+        filestore.with_config(Config {
+            compression_level: bucket.settings.compression_level,
+            ..config
+        })
+    }
+
+    pub fn create_bucket(
+        &self,
+        name: &str,
+        usecase: &str,
+        scope: &str,
+        settings: bucket::BucketSettings,
+    ) -> Result<bucket::Bucket, Error> {
+        match self {
+            Self::Mem(fs) => fs
+                .write()
+                .unwrap()
+                .create_bucket(name, usecase, scope, settings),
+            Self::Fjall(fs) => fs.create_bucket(name, usecase, scope, settings),
+        }
+    }
+
+    pub fn get_bucket(&self, name: &str) -> Option<bucket::Bucket> {
+        match self {
+            Self::Mem(fs) => fs.read().unwrap().get_bucket(name),
+            Self::Fjall(fs) => fs.get_bucket(name),
+        }
+    }
+
+    pub fn list_buckets(&self) -> Vec<bucket::Bucket> {
+        match self {
+            Self::Mem(fs) => fs.read().unwrap().list_buckets(),
+            Self::Fjall(fs) => fs.list_buckets(),
+        }
+    }
+
+    pub fn delete_bucket(&self, name: &str) -> Result<(), Error> {
+        match self {
+            Self::Mem(fs) => fs.write().unwrap().delete_bucket(name),
+            Self::Fjall(fs) => fs.delete_bucket(name),
+        }
+    }
+
+    pub fn enable_versioning(&self, name: &str) -> Result<bucket::Bucket, Error> {
+        match self {
+            Self::Mem(fs) => fs.write().unwrap().enable_versioning(name),
+            Self::Fjall(fs) => fs.enable_versioning(name),
+        }
+    }
+
+    pub fn set_object_lock(
+        &self,
+        name: &str,
+        default_retention: Option<bucket::DefaultRetention>,
+    ) -> Result<bucket::Bucket, Error> {
+        match self {
+            Self::Mem(fs) => fs.write().unwrap().set_object_lock(name, default_retention),
+            Self::Fjall(fs) => fs.set_object_lock(name, default_retention),
+        }
+    }
+
+    pub fn set_lifecycle(
+        &self,
+        name: &str,
+        rules: Vec<lifecycle::Rule>,
+    ) -> Result<bucket::Bucket, Error> {
+        match self {
+            Self::Mem(fs) => fs.write().unwrap().set_lifecycle(name, rules),
+            Self::Fjall(fs) => fs.set_lifecycle(name, rules),
+        }
+    }
+
+    /// Applies the enabled lifecycle rules of all buckets at `now`, with `defaults` for the
+    /// stores of the buckets, as in [`Self::with_bucket`]. The TTL of a bucket applies like
+    /// an additional rule expiring all of its files, and so does its TTI for the files which
+    /// were not read since.
+    ///
+    /// The files of expired names and versions are released right away, and the segments
+    /// they free are removed by the next garbage collection.
+    #[tracing::instrument(level = "info", skip_all)]
+    pub fn apply_lifecycle(&self, defaults: &Config, now: SystemTime) -> report::LifecycleReport {
+        let mut report = report::LifecycleReport::default();
+        for bucket in self.list_buckets() {
+            let rules: Vec<_> = bucket
+                .settings
+                .lifecycle
+                .iter()
+                .filter(|rule| rule.enabled)
+                .collect();
+            if rules.is_empty() && bucket.settings.ttl.is_none() && bucket.settings.tti.is_none() {
+                continue;
+            }
+            let filestore = Self::with_bucket(self, &bucket, defaults.clone());
+            if let Some(ttl) = bucket.settings.ttl {
+                filestore.expire_files(ttl, now, &mut report);
+            }
+            if let Some(tti) = bucket.settings.tti {
+                filestore.expire_idle_files(tti, now, &mut report);
+            }
+            for rule in rules {
+                filestore.apply_lifecycle_rule(rule, now, &mut report);
+            }
+        }
+        stats::lifecycle_applied(&report);
+        tracing::info!(?report, "lifecycle rules applied");
+        report
+    }
+
+    pub fn register_usecase(
+        &self,
+        name: &str,
+        policy: usecase::Policy,
+    ) -> Result<usecase::Usecase, Error> {
+        match self {
+            Self::Mem(fs) => fs.write().unwrap().register_usecase(name, policy),
+            Self::Fjall(fs) => fs.register_usecase(name, policy),
+        }
+    }
+
+    pub fn get_usecase(&self, name: &str) -> Option<usecase::Usecase> {
+        match self {
+            Self::Mem(fs) => fs.read().unwrap().get_usecase(name),
+            Self::Fjall(fs) => fs.get_usecase(name),
+        }
+    }
+
+    pub fn list_usecases(&self) -> Vec<usecase::Usecase> {
+        match self {
+            Self::Mem(fs) => fs.read().unwrap().list_usecases(),
+            Self::Fjall(fs) => fs.list_usecases(),
+        }
+    }
+
+    pub fn scope_usage(&self) -> Vec<quota::ScopeUsage> {
+        match self {
+            Self::Mem(fs) => fs.read().unwrap().scope_usage(),
+            Self::Fjall(fs) => fs.scope_usage(),
+        }
+    }
+}
+
+impl NamespacedFileStore<'_> {
+    pub fn with_config(self, config: Config) -> Self {
+        match self {
+            Self::Mem(fs) => Self::Mem(fs.with_config(config)),
+            Self::Fjall(fs) => Self::Fjall(fs.with_config(config)),
+        }
+    }
+
+    pub fn with_content_namespace(self, content_namespace: Namespace) -> Self {
+        match self {
+            Self::Mem(fs) => Self::Mem(fs.with_content_namespace(content_namespace)),
+            Self::Fjall(fs) => Self::Fjall(fs.with_content_namespace(content_namespace)),
+        }
+    }
+
+    pub fn with_account(self, account: quota::Account) -> Self {
+        match self {
+            Self::Mem(fs) => Self::Mem(fs.with_account(account)),
+            Self::Fjall(fs) => Self::Fjall(fs.with_account(account)),
+        }
+    }
+
+    pub fn with_versioning(self, versioned: bool) -> Self {
+        match self {
+            Self::Mem(fs) => Self::Mem(fs.with_versioning(versioned)),
+            Self::Fjall(fs) => Self::Fjall(fs.with_versioning(versioned)),
+        }
+    }
+
+    pub fn with_default_retention(
+        self,
+        default_retention: Option<bucket::DefaultRetention>,
+    ) -> Self {
+        match self {
+            Self::Mem(fs) => Self::Mem(fs.with_default_retention(default_retention)),
+            Self::Fjall(fs) => Self::Fjall(fs.with_default_retention(default_retention)),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        dispatch!(self, fs => fs.config())
+    }
+
+    pub fn refcount(&self, ty: refcounts::ReferenceCountType) -> u32 {
+        dispatch!(self, fs => fs.refcount(ty))
+    }
+
+    pub fn upload_chunk(&self, contents: &[u8]) -> chunk::ChunkId {
+        dispatch!(self, fs => fs.upload_chunk(contents))
+    }
+
+    /// Uploads a chunk ahead of assembling it into a file with [`Self::assemble_file`].
+    pub fn upload_pending_chunk(&self, chunk_id: chunk::ChunkId, contents: &[u8]) {
+        dispatch!(self, fs => fs.upload_pending_chunk(chunk_id, contents))
+    }
+
+    /// Like [`Self::upload_pending_chunk`], but stores an already compressed zstd frame verbatim.
+    pub fn upload_pending_chunk_zstd(&self, chunk_id: chunk::ChunkId, size: u32, zstd: &[u8]) {
+        dispatch!(self, fs => fs.upload_pending_chunk_zstd(chunk_id, size, zstd))
+    }
+
+    /// The chunks which are not stored, in the order they were given.
+    pub fn find_missing_chunks(&self, chunk_ids: &[chunk::ChunkId]) -> Vec<chunk::ChunkId> {
+        dispatch!(self, fs => fs.find_missing_chunks(chunk_ids))
+    }
+
+    pub fn upload_file(&self, contents: &[u8]) -> file::FileId {
+        dispatch!(self, fs => fs.upload_file(contents))
+    }
+
+    /// Assembles a file from already stored chunks, returning a new reference to it.
+    pub fn assemble_file(&self, chunk_ids: &[chunk::ChunkId]) -> Result<file::FileId, Error> {
+        dispatch!(self, fs => fs.assemble_file(chunk_ids))
+    }
+
+    /// Copies a file from the `source` namespace, returning a new reference to it.
+    ///
+    /// Copies between different implementations upload the contents again. Returns `None` if
+    /// the source file does not exist (anymore).
+    pub fn copy_file(
+        &self,
+        source: &NamespacedFileStore<'_>,
+        file_id: file::FileId,
+    ) -> Option<file::FileId> {
+        match (self, source) {
+            (Self::Mem(fs), NamespacedFileStore::Mem(source)) => fs.copy_file(source, file_id),
+            (Self::Fjall(fs), NamespacedFileStore::Fjall(source)) => fs.copy_file(source, file_id),
+            _ => Some(self.upload_file(&source.read_file(file_id))),
+        }
+    }
+
+    pub fn inspect_file(&self, file_id: file::FileId) -> Option<report::FileInfo> {
+        dispatch!(self, fs => fs.inspect_file(file_id))
+    }
+
+    pub fn read_chunk(&self, chunk_id: chunk::ChunkId) -> Vec<u8> {
+        dispatch!(self, fs => fs.read_chunk(chunk_id))
+    }
+
+    pub fn read_chunk_zstd(&self, chunk_id: chunk::ChunkId) -> Vec<u8> {
+        dispatch!(self, fs => fs.read_chunk_zstd(chunk_id))
+    }
+
+    pub fn find_file(&self, file_id: file::FileId) -> Option<file::File> {
+        dispatch!(self, fs => fs.find_file(file_id))
+    }
+
+    pub fn get_file(&self, file_id: file::FileId) -> file::File {
+        dispatch!(self, fs => fs.get_file(file_id))
+    }
+
+    pub fn read_file(&self, file_id: file::FileId) -> Vec<u8> {
+        dispatch!(self, fs => fs.read_file(file_id))
+    }
+
+    pub fn associate_filename(&self, file_id: file::FileId, name: &str) -> file::NamedFile {
+        dispatch!(self, fs => fs.associate_filename(file_id, name))
+    }
+
+    pub fn allocate_filename(&self, format: file::IdFormat) -> String {
+        dispatch!(self, fs => fs.allocate_filename(format))
+    }
+
+    pub fn reserve_filename(&self, name: &str) {
+        dispatch!(self, fs => fs.reserve_filename(name))
+    }
+
+    pub fn is_reserved(&self, name: &str) -> bool {
+        dispatch!(self, fs => fs.is_reserved(name))
+    }
+
+    pub fn remove_reservations(&self, prefix: &str, reserved_before: SystemTime) -> u64 {
+        dispatch!(self, fs => fs.remove_reservations(prefix, reserved_before))
+    }
+
+    pub fn associate_filename_if(
+        &self,
+        file_id: file::FileId,
+        name: &str,
+        preconditions: &file::Preconditions,
+    ) -> Result<file::NamedFile, Error> {
+        dispatch!(self, fs => fs.associate_filename_if(file_id, name, preconditions))
+    }
+
+    pub fn associate_tagged_filename_if(
+        &self,
+        file_id: file::FileId,
+        name: &str,
+        preconditions: &file::Preconditions,
+        tags: Vec<file::Tag>,
+    ) -> Result<file::NamedFile, Error> {
+        dispatch!(self, fs => fs.associate_tagged_filename_if(file_id, name, preconditions, tags))
+    }
+
+    pub fn get_version(&self, name: &str, version_id: file::VersionId) -> Option<file::Version> {
+        dispatch!(self, fs => fs.get_version(name, version_id))
+    }
+
+    pub fn delete_version(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        bypass_governance: bool,
+    ) -> Result<Option<file::Version>, Error> {
+        dispatch!(self, fs => fs.delete_version(name, version_id, bypass_governance))
+    }
+
+    pub fn update_lock(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        update: impl Fn(file::Lock) -> Result<file::Lock, Error>,
+    ) -> Result<Option<file::Lock>, Error> {
+        dispatch!(self, fs => fs.update_lock(name, version_id, update))
+    }
+
+    pub fn set_tags(&self, name: &str, version_id: file::VersionId, tags: Vec<file::Tag>) -> bool {
+        dispatch!(self, fs => fs.set_tags(name, version_id, tags))
+    }
+
+    /// Records that the current file of the name was read at `now`, for the TTI of the
+    /// bucket. Accesses within [`file::ACCESS_RESOLUTION`] of the recorded one are skipped.
+    pub fn record_access(&self, name: &str, named_file: &file::NamedFile, now: SystemTime) {
+        let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if named_file.last_accessed + file::ACCESS_RESOLUTION <= timestamp {
+            dispatch!(self, fs => fs.record_access(name, named_file.version_id, timestamp))
+        }
+    }
+
+    pub fn list_versions(&self, prefix: &str) -> Vec<file::ListedVersion> {
+        dispatch!(self, fs => fs.list_versions(prefix))
+    }
+
+    pub fn discard_file(&self, file_id: file::FileId) {
+        dispatch!(self, fs => fs.discard_file(file_id))
+    }
+
+    pub fn delete_filename(&self, name: &str) -> Option<file::FileId> {
+        dispatch!(self, fs => fs.delete_filename(name))
+    }
+
+    pub fn delete_filenames(&self, names: &[&str]) -> Vec<Option<file::DeletedName>> {
+        dispatch!(self, fs => fs.delete_filenames(names))
+    }
+
+    pub fn delete_filename_if_current(
+        &self,
+        name: &str,
+        version_id: file::VersionId,
+        version: &file::Version,
+    ) -> bool {
+        dispatch!(self, fs => fs.delete_filename_if_current(name, version_id, version))
+    }
+
+    pub fn get_named_file(&self, name: &str) -> Option<file::NamedFile> {
+        dispatch!(self, fs => fs.get_named_file(name))
+    }
+
+    pub fn resolve_filename(&self, name: &str) -> Option<file::FileId> {
+        dispatch!(self, fs => fs.resolve_filename(name))
+    }
+
+    pub fn read_named_file(&self, name: &str) -> Option<Vec<u8>> {
+        dispatch!(self, fs => fs.read_named_file(name))
+    }
+
+    /// Deletes the current files which were last modified more than `ttl` seconds before
+    /// `now`.
+    fn expire_files(&self, ttl: u64, now: SystemTime, report: &mut report::LifecycleReport) {
+        let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        for listed in self.list_versions("") {
+            if listed.is_latest
+                && listed.version.file_id.is_some()
+                && listed.version.last_modified + ttl <= timestamp
+            {
+                self.expire_current(&listed, report);
+            }
+        }
+    }
+
+    /// Deletes the current files which were last read, or last modified if they were not read
+    /// since, more than `tti` seconds before `now`.
+    fn expire_idle_files(&self, tti: u64, now: SystemTime, report: &mut report::LifecycleReport) {
+        let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        for listed in self.list_versions("") {
+            if !listed.is_latest || listed.version.file_id.is_none() {
+                continue;
+            }
+            let Some(current) = self.get_named_file(&listed.name) else {
+                continue;
+            };
+            if current.last_accessed + tti <= timestamp {
+                self.expire_current(&listed, report);
+            }
+        }
+    }
+
+    /// Deletes the listed current file, unless the name was written again since it was
+    /// listed, which keeps the `null` version in unversioned buckets.
+    fn expire_current(&self, listed: &file::ListedVersion, report: &mut report::LifecycleReport) {
+        if self.delete_filename_if_current(&listed.name, listed.version_id, &listed.version) {
+            report.objects_expired += 1;
+        }
+    }
+
+    /// Applies the actions of a lifecycle rule to the names of the store.
+    fn apply_lifecycle_rule(
+        &self,
+        rule: &lifecycle::Rule,
+        now: SystemTime,
+        report: &mut report::LifecycleReport,
+    ) {
+        let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let filter = &rule.filter;
+
+        // the versions of each name are listed from the latest to the oldest, so each one
+        // became noncurrent when the one listed before it was written
+        let mut replaced_at = None;
+        for listed in self.list_versions(&filter.prefix) {
+            let noncurrent_since = match listed.is_latest {
+                true => None,
+                false => replaced_at,
+            };
+            replaced_at = Some(listed.version.last_modified);
+            if !filter.matches(&listed.name, &listed.version.tags) {
+                continue;
+            }
+
+            match noncurrent_since {
+                None => {
+                    let Some(days) = rule.expiration_days else {
+                        continue;
+                    };
+                    if listed.version.file_id.is_none()
+                        || !lifecycle::has_passed(days, listed.version.last_modified, timestamp)
+                    {
+                        continue;
+                    }
+                    self.expire_current(&listed, report);
+                }
+                Some(noncurrent_since) => {
+                    let Some(days) = rule.noncurrent_expiration_days else {
+                        continue;
+                    };
+                    if !lifecycle::has_passed(days, noncurrent_since, timestamp) {
+                        continue;
+                    }
+                    match self.delete_version(&listed.name, listed.version_id, false) {
+                        Ok(Some(_)) => report.versions_expired += 1,
+                        Ok(None) => {}
+                        // only locked files keep their versions from being removed
+                        Err(_) => report.versions_locked += 1,
+                    }
+                }
+            }
+        }
+
+        // reserved names do not carry tags, so only rules without tags abort their uploads
+        if let Some(days) = rule
+            .abort_incomplete_upload_days
+            .filter(|_| filter.tags.is_empty())
+        {
+            let reserved_before = now.checked_sub(lifecycle::days(days)).unwrap_or(UNIX_EPOCH);
+            report.uploads_aborted += self.remove_reservations(&filter.prefix, reserved_before);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn expire_after(prefix: &str, days: u32) -> lifecycle::Rule {
+        lifecycle::Rule {
+            id: prefix.into(),
+            enabled: true,
+            filter: lifecycle::Filter {
+                prefix: prefix.into(),
+                tags: vec![],
+            },
+            expiration_days: Some(days),
+            noncurrent_expiration_days: None,
+            abort_incomplete_upload_days: None,
+        }
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        for global_fs in [FileStore::mem(), FileStore::fjall(None).unwrap()] {
+            let defaults = Config::default();
+            let bucket = global_fs
+                .create_bucket("lifecycle", "default", "org-1", Default::default())
+                .unwrap();
+            let fs = FileStore::with_bucket(&global_fs, &bucket, defaults.clone());
+            let tmp = fs.upload_file(b"temporary");
+            let tmp = fs.associate_filename(tmp, "tmp/file");
+            let kept = fs.upload_file(b"kept");
+            fs.associate_filename(kept, "kept/file");
+            let expire_tag = file::Tag {
+                key: "expire".into(),
+                value: "true".into(),
+            };
+            let tagged = fs.upload_file(b"tagged");
+            let tags = vec![expire_tag.clone()];
+            fs.associate_tagged_filename_if(tagged, "kept/tagged", &Default::default(), tags)
+                .unwrap();
+            let reserved = fs.allocate_filename(file::IdFormat::Uuid);
+
+            let tagged_rule = lifecycle::Rule {
+                filter: lifecycle::Filter {
+                    prefix: "kept/".into(),
+                    tags: vec![expire_tag],
+                },
+                ..expire_after("kept/", 1)
+            };
+            let abort = lifecycle::Rule {
+                expiration_days: None,
+                abort_incomplete_upload_days: Some(1),
+                ..expire_after("", 1)
+            };
+            let rules = vec![expire_after("tmp/", 2), tagged_rule, abort];
+            global_fs.set_lifecycle("lifecycle", rules).unwrap();
+
+            // nothing expires before its time
+            let now = SystemTime::now();
+            let report = global_fs.apply_lifecycle(&defaults, now);
+            assert_eq!(report, Default::default());
+
+            let report = global_fs.apply_lifecycle(&defaults, now + DAY * 3);
+            assert_eq!(
+                report,
+                report::LifecycleReport {
+                    objects_expired: 2,
+                    uploads_aborted: 1,
+                    ..Default::default()
+                }
+            );
+            assert!(fs.get_named_file("tmp/file").is_none());
+            assert!(fs.get_named_file("kept/tagged").is_none());
+            assert!(fs.find_file(tmp.file_id).is_none());
+            assert_eq!(fs.resolve_filename("kept/file"), Some(kept));
+            assert!(!fs.is_reserved(&reserved));
+
+            // disabled rules are not applied
+            let mut rule = expire_after("", 1);
+            rule.enabled = false;
+            global_fs.set_lifecycle("lifecycle", vec![rule]).unwrap();
+            let report = global_fs.apply_lifecycle(&defaults, now + DAY * 3);
+            assert_eq!(report, Default::default());
+        }
+    }
+
+    #[test]
+    fn test_delete_filename_if_current() {
+        for global_fs in [FileStore::mem(), FileStore::fjall(None).unwrap()] {
+            let fs = FileStore::with_namespace(&global_fs, Namespace(0));
+            let file_id = fs.upload_file(b"listed");
+            fs.associate_filename(file_id, "file");
+            let [listed] = fs.list_versions("").try_into().unwrap();
+
+            // the name was written again since it was listed
+            let tagged = file::Tag {
+                key: "tagged".into(),
+                value: "true".into(),
+            };
+            let file_id = fs.upload_file(b"listed");
+            fs.associate_tagged_filename_if(file_id, "file", &Default::default(), vec![tagged])
+                .unwrap();
+            assert!(!fs.delete_filename_if_current("file", listed.version_id, &listed.version));
+            assert_eq!(fs.resolve_filename("file"), Some(file_id));
+
+            let [listed] = fs.list_versions("").try_into().unwrap();
+            assert!(fs.delete_filename_if_current("file", listed.version_id, &listed.version));
+            assert!(fs.get_named_file("file").is_none());
+            assert!(!fs.delete_filename_if_current("file", listed.version_id, &listed.version));
+            assert!(global_fs.usage().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_lifecycle_versions() {
+        for global_fs in [FileStore::mem(), FileStore::fjall(None).unwrap()] {
+            let defaults = Config::default();
+            global_fs
+                .create_bucket("versioned", "default", "org-1", Default::default())
+                .unwrap();
+            let bucket = global_fs.set_object_lock("versioned", None).unwrap();
+            let fs = FileStore::with_bucket(&global_fs, &bucket, defaults.clone());
+            let first = fs.upload_file(b"first");
+            let first = fs.associate_filename(first, "file");
+            let second = fs.upload_file(b"second");
+            let second = fs.associate_filename(second, "file");
+            let locked = fs.upload_file(b"locked");
+            let locked = fs.associate_filename(locked, "locked");
+            let legal_hold = |lock| {
+                Ok(file::Lock {
+                    legal_hold: true,
+                    ..lock
+                })
+            };
+            fs.update_lock("locked", locked.version_id, legal_hold)
+                .unwrap();
+
+            let rule = lifecycle::Rule {
+                noncurrent_expiration_days: Some(1),
+                ..expire_after("", 2)
+            };
+            global_fs.set_lifecycle("versioned", vec![rule]).unwrap();
+
+            // the first version became noncurrent now, so it expires after a day
+            let now = SystemTime::now();
+            let report = global_fs.apply_lifecycle(&defaults, now + DAY);
+            assert_eq!(report.versions_expired, 1);
+            assert!(fs.get_version("file", first.version_id).is_none());
+            assert_eq!(fs.resolve_filename("file"), Some(second.file_id));
+
+            // expiring the current version only adds a delete marker, which becomes noncurrent
+            // once the expired versions are removed
+            let report = global_fs.apply_lifecycle(&defaults, now + DAY * 2);
+            assert_eq!(report.objects_expired, 2);
+            assert!(fs.get_named_file("file").is_none());
+            assert!(fs.get_version("file", second.version_id).is_some());
+
+            let report = global_fs.apply_lifecycle(&defaults, now + DAY * 3);
+            assert_eq!(
+                report,
+                report::LifecycleReport {
+                    versions_expired: 1,
+                    versions_locked: 1,
+                    ..Default::default()
+                }
+            );
+            assert!(fs.get_version("file", second.version_id).is_none());
+            assert!(fs.get_version("locked", locked.version_id).is_some());
+        }
+    }
+
+    #[test]
+    fn test_bucket_ttl() {
+        for global_fs in [FileStore::mem(), FileStore::fjall(None).unwrap()] {
+            let defaults = Config::default();
+            let settings = bucket::BucketSettings {
+                ttl: Some(DAY.as_secs()),
+                ..Default::default()
+            };
+            let bucket = global_fs
+                .create_bucket("expiring", "default", "org-1", settings)
+                .unwrap();
+            let fs = FileStore::with_bucket(&global_fs, &bucket, defaults.clone());
+            let file_id = fs.upload_file(b"expiring");
+            fs.associate_filename(file_id, "file");
+
+            let now = SystemTime::now();
+            let report = global_fs.apply_lifecycle(&defaults, now);
+            assert_eq!(report, Default::default());
+            assert_eq!(fs.resolve_filename("file"), Some(file_id));
+
+            let report = global_fs.apply_lifecycle(&defaults, now + DAY);
+            assert_eq!(report.objects_expired, 1);
+            assert!(fs.get_named_file("file").is_none());
+            assert!(fs.find_file(file_id).is_none());
+        }
+    }
+
+    #[test]
+    fn test_bucket_tti() {
+        for global_fs in [FileStore::mem(), FileStore::fjall(None).unwrap()] {
+            let defaults = Config::default();
+            let settings = bucket::BucketSettings {
+                tti: Some(DAY.as_secs()),
+                ..Default::default()
+            };
+            let bucket = global_fs
+                .create_bucket("idle", "default", "org-1", settings)
+                .unwrap();
+            let fs = FileStore::with_bucket(&global_fs, &bucket, defaults.clone());
+            let file_id = fs.upload_file(b"idle");
+            let named_file = fs.associate_filename(file_id, "file");
+
+            // reads within the resolution of the recorded access are skipped
+            let now = SystemTime::now();
+            fs.record_access("file", &named_file, now);
+            let unread = fs.get_named_file("file").unwrap();
+            assert_eq!(unread.last_accessed, named_file.last_modified);
+
+            fs.record_access("file", &named_file, now + DAY / 2);
+            let report = global_fs.apply_lifecycle(&defaults, now + DAY);
+            assert_eq!(report, Default::default());
+            assert_eq!(fs.resolve_filename("file"), Some(file_id));
+
+            let report = global_fs.apply_lifecycle(&defaults, now + DAY + DAY / 2);
+            assert_eq!(report.objects_expired, 1);
+            assert!(fs.get_named_file("file").is_none());
+            assert!(fs.find_file(file_id).is_none());
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! The chunk upload protocol of `sentry-cli`, served on the separate `sentry_bind` listener.
+//!
+//! - `GET /api/0/organizations/{org}/chunk-upload/`: The chunk size and concurrency clients
+//!   should upload with.
+//! - `POST /api/0/organizations/{org}/chunk-upload/`: Uploads chunks as `multipart/form-data`,
+//!   with one optionally gzipped part per chunk, named by its SHA-1 checksum.
+//! - `POST /api/0/projects/{org}/{project}/files/difs/assemble/`: Assembles debug files from
+//!   their chunks, returning the state of each file.
+//! - `POST /api/0/organizations/{org}/artifactbundle/assemble/`: Assembles an artifact bundle
+//!   from its chunks.
+//!
+//! Each organization is a scope of the [`USECASE`], which has to be configured. Files are
+//! stored under their checksum, and always hashed with SHA-1, as the checksums are.
+//!
+//! Like the gRPC service, the endpoints are unauthenticated, so they should only be reachable
+//! by trusted clients.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
+use axum::http::header::HOST;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use crate::new_datamodel::store::NamespacedFileStore;
+use crate::new_datamodel::{bucket, chunk, file, ContentHash, Error, HashAlgorithm};
+use crate::server::{bucket_filestore, AppStateRef};
+
+/// The usecase the organizations are scopes of.
+pub const USECASE: &str = "sentry";
+
+/// How many chunks clients send per request.
+const CHUNKS_PER_REQUEST: usize = 64;
+/// The maximum size of a chunk upload request.
+const MAX_REQUEST_SIZE: usize = 32 * 1024 * 1024;
+/// How many chunk upload requests clients send concurrently.
+const CONCURRENCY: usize = 8;
+/// The maximum size of an assembled file, unless the usecase has a `max_object_size`.
+const DEFAULT_MAX_FILE_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+/// The kinds of files `sentry-cli` may upload in chunks.
+const ACCEPT: &[&str] = &[
+    "debug_files",
+    "pdbs",
+    "portablepdbs",
+    "sources",
+    "bcsymbolmaps",
+    "il2cpp",
+    "proguard",
+    "artifact_bundles",
+];
+
+pub(crate) fn router(state: AppStateRef) -> Router {
+    Router::new()
+        .route(
+            "/api/0/organizations/{org}/chunk-upload/",
+            get(chunk_upload_options).post(upload_chunks),
+        )
+        .route(
+            "/api/0/projects/{org}/{project}/files/difs/assemble/",
+            post(assemble_difs),
+        )
+        .route(
+            "/api/0/organizations/{org}/artifactbundle/assemble/",
+            post(assemble_artifact_bundle),
+        )
+        .layer(DefaultBodyLimit::max(MAX_REQUEST_SIZE))
+        .with_state(state)
+}
+
+/// Responds with a `{"detail": ...}` error, as the Sentry API does.
+fn error(status: StatusCode, detail: impl Into<String>) -> Response {
+    #[derive(Serialize)]
+    struct Error {
+        detail: String,
+    }
+    let detail = detail.into();
+    (status, Json(Error { detail })).into_response()
+}
+
+/// The filestore of the bucket of the organization, which is created on first use.
+///
+/// On failure, returns the status and detail of the error.
+fn org_filestore<'s>(
+    state: &'s AppStateRef,
+    org: &str,
+) -> Result<NamespacedFileStore<'s>, (StatusCode, String)> {
+    let name = bucket::scoped_name(USECASE, org);
+    let filestore = &state.filestore;
+
+    let bucket = match filestore.get_bucket(&name) {
+        Some(bucket) => bucket,
+        None => {
+            if !bucket::is_valid_name(&name) {
+                return Err((StatusCode::BAD_REQUEST, "invalid organization".into()));
+            }
+            let Some(registered) = filestore.get_usecase(USECASE) else {
+                let detail = "chunk uploads are not configured".into();
+                return Err((StatusCode::SERVICE_UNAVAILABLE, detail));
+            };
+            let settings = bucket::BucketSettings::from_policy(&registered.policy);
+            match filestore.create_bucket(&name, USECASE, org, settings) {
+                Ok(bucket) => bucket,
+                // created concurrently
+                Err(Error::BucketAlreadyExists) => {
+                    filestore.get_bucket(&name).ok_or_else(|| {
+                        let detail = "bucket was deleted concurrently".into();
+                        (StatusCode::SERVICE_UNAVAILABLE, detail)
+                    })?
+                }
+                Err(err) => return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+            }
+        }
+    };
+    // the bucket might have been created through the S3 API
+    if bucket.usecase != USECASE || bucket.scope != org {
+        let detail = format!("bucket `{name}` belongs to a different usecase or scope");
+        return Err((StatusCode::CONFLICT, detail));
+    }
+
+    let filestore = bucket_filestore(state, &bucket);
+    let mut config = filestore.config().clone();
+    config.hash_algorithm = HashAlgorithm::Sha1;
+    Ok(filestore.with_config(config))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChunkUploadOptions {
+    url: String,
+    chunk_size: u64,
+    chunks_per_request: usize,
+    max_file_size: u64,
+    max_request_size: usize,
+    concurrency: usize,
+    hash_algorithm: &'static str,
+    compression: &'static [&'static str],
+    accept: &'static [&'static str],
+}
+
+#[tracing::instrument(skip_all)]
+async fn chunk_upload_options(
+    State(state): State<AppStateRef>,
+    Path(org): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let filestore = match org_filestore(&state, &org) {
+        Ok(filestore) => filestore,
+        Err((status, detail)) => return error(status, detail),
+    };
+    let Some(host) = headers.get(HOST).and_then(|host| host.to_str().ok()) else {
+        return error(StatusCode::BAD_REQUEST, "missing Host");
+    };
+
+    let config = filestore.config();
+    Json(ChunkUploadOptions {
+        url: format!("http://{host}/api/0/organizations/{org}/chunk-upload/"),
+        chunk_size: config.chunk_size,
+        chunks_per_request: CHUNKS_PER_REQUEST,
+        max_file_size: config.max_object_size.unwrap_or(DEFAULT_MAX_FILE_SIZE),
+        max_request_size: MAX_REQUEST_SIZE,
+        concurrency: CONCURRENCY,
+        hash_algorithm: "sha1",
+        compression: &["gzip"],
+        accept: ACCEPT,
+    })
+    .into_response()
+}
+
+#[tracing::instrument(skip_all)]
+async fn upload_chunks(
+    State(state): State<AppStateRef>,
+    Path(org): Path<String>,
+    mut multipart: Multipart,
+) -> Response {
+    let filestore = match org_filestore(&state, &org) {
+        Ok(filestore) => filestore,
+        Err((status, detail)) => return error(status, detail),
+    };
+    let chunk_size = filestore.config().chunk_size;
+
+    let mut chunks = vec![];
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return error(StatusCode::BAD_REQUEST, err.body_text()),
+        };
+        let gzip = match field.name() {
+            Some("file") => false,
+            Some("file_gzip") => true,
+            _ => continue,
+        };
+        let checksum = field
+            .file_name()
+            .and_then(|name| name.parse::<ContentHash>().ok());
+        let Some(checksum) = checksum.filter(|hash| hash.hash_algorithm == HashAlgorithm::Sha1)
+        else {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "chunks need to be named by their SHA-1",
+            );
+        };
+        let bytes = match field.bytes().await {
+            Ok(bytes) => bytes,
+            Err(err) => return error(StatusCode::BAD_REQUEST, err.body_text()),
+        };
+
+        let contents = if gzip {
+            // one more byte than allowed to detect oversized chunks
+            let mut decoder = flate2::read::GzDecoder::new(&bytes[..]).take(chunk_size + 1);
+            let mut contents = vec![];
+            if decoder.read_to_end(&mut contents).is_err() {
+                return error(StatusCode::BAD_REQUEST, "invalid gzip contents");
+            }
+            contents
+        } else {
+            bytes.to_vec()
+        };
+        if contents.len() as u64 > chunk_size {
+            return error(StatusCode::BAD_REQUEST, "chunk exceeds the chunk size");
+        }
+        let chunk_id = chunk::ChunkId(checksum);
+        if chunk::ChunkId::from_contents(HashAlgorithm::Sha1, &contents) != chunk_id {
+            return error(StatusCode::BAD_REQUEST, "chunk does not match its checksum");
+        }
+
+        chunks.push((chunk_id, contents));
+        if chunks.len() > CHUNKS_PER_REQUEST {
+            return error(StatusCode::BAD_REQUEST, "too many chunks");
+        }
+    }
+
+    for (chunk_id, contents) in chunks {
+        filestore.upload_pending_chunk(chunk_id, &contents);
+    }
+    StatusCode::OK.into_response()
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AssembleState {
+    /// Some chunks have not been uploaded yet.
+    NotFound,
+    /// The file was assembled.
+    Ok,
+    /// The file cannot be assembled, see the `detail`.
+    Error,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AssembleResponse {
+    state: AssembleState,
+    missing_chunks: Vec<String>,
+    detail: Option<String>,
+}
+
+impl AssembleResponse {
+    fn new(state: AssembleState) -> Self {
+        Self {
+            state,
+            missing_chunks: vec![],
+            detail: None,
+        }
+    }
+
+    fn missing(missing_chunks: Vec<chunk::ChunkId>) -> Self {
+        let missing_chunks = missing_chunks.iter().map(ToString::to_string).collect();
+        Self {
+            missing_chunks,
+            ..Self::new(AssembleState::NotFound)
+        }
+    }
+
+    fn error(detail: impl Into<String>) -> Self {
+        Self {
+            detail: Some(detail.into()),
+            ..Self::new(AssembleState::Error)
+        }
+    }
+}
+
+/// Assembles the file with the `checksum` from its `chunks`, and stores it under `key`.
+///
+/// Files which were already assembled are not assembled again, and an empty list of chunks
+/// only queries the state of the file.
+fn assemble(
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    checksum: &str,
+    chunks: &[String],
+) -> AssembleResponse {
+    let Ok(checksum) = checksum.parse::<ContentHash>() else {
+        return AssembleResponse::error("invalid checksum");
+    };
+    let Ok(chunk_ids) = chunks
+        .iter()
+        .map(|chunk| chunk.parse())
+        .collect::<Result<Vec<chunk::ChunkId>, _>>()
+    else {
+        return AssembleResponse::error("invalid chunk checksum");
+    };
+    if filestore.resolve_filename(key).is_some() {
+        return AssembleResponse::new(AssembleState::Ok);
+    }
+    if chunk_ids.is_empty() {
+        return AssembleResponse::missing(vec![]);
+    }
+
+    let file_id = match filestore.assemble_file(&chunk_ids) {
+        Ok(file_id) => file_id,
+        Err(Error::MissingChunks(missing)) => return AssembleResponse::missing(missing),
+        Err(err) => return AssembleResponse::error(err.to_string()),
+    };
+    if file_id != file::FileId(checksum) {
+        filestore.discard_file(file_id);
+        return AssembleResponse::error("the chunks do not match the checksum");
+    }
+    let size = filestore.get_file(file_id).size;
+    let max_file_size = filestore.config().max_object_size;
+    if size > max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE) {
+        filestore.discard_file(file_id);
+        return AssembleResponse::error("the file exceeds the maximum file size");
+    }
+    if let Err(err) = filestore.associate_filename_if(file_id, key, &Default::default()) {
+        filestore.discard_file(file_id);
+        return AssembleResponse::error(err.to_string());
+    }
+    AssembleResponse::new(AssembleState::Ok)
+}
+
+#[derive(Deserialize)]
+struct DifAssembleRequest {
+    name: String,
+    #[serde(default)]
+    chunks: Vec<String>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn assemble_difs(
+    State(state): State<AppStateRef>,
+    Path((org, project)): Path<(String, String)>,
+    Json(files): Json<BTreeMap<String, DifAssembleRequest>>,
+) -> Response {
+    let filestore = match org_filestore(&state, &org) {
+        Ok(filestore) => filestore,
+        Err((status, detail)) => return error(status, detail),
+    };
+
+    let states: BTreeMap<_, _> = files
+        .into_iter()
+        .map(|(checksum, file)| {
+            let key = format!("difs/{project}/{checksum}");
+            let response = assemble(&filestore, &key, &checksum, &file.chunks);
+            tracing::debug!(checksum, name = file.name, "assembled debug file");
+            (checksum, response)
+        })
+        .collect();
+    Json(states).into_response()
+}
+
+#[derive(Deserialize)]
+struct ArtifactBundleAssembleRequest {
+    checksum: String,
+    #[serde(default)]
+    chunks: Vec<String>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn assemble_artifact_bundle(
+    State(state): State<AppStateRef>,
+    Path(org): Path<String>,
+    Json(request): Json<ArtifactBundleAssembleRequest>,
+) -> Response {
+    let filestore = match org_filestore(&state, &org) {
+        Ok(filestore) => filestore,
+        Err((status, detail)) => return error(status, detail),
+    };
+
+    let key = format!("artifact-bundles/{}", request.checksum);
+    Json(assemble(
+        &filestore,
+        &key,
+        &request.checksum,
+        &request.chunks,
+    ))
+    .into_response()
+}
@@ -0,0 +1,3316 @@
+use std::future::{Future, IntoFuture};
+use std::io::Read;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::handler::Handler;
+use axum::http::header::{
+    ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, HOST, IF_MATCH,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE, LAST_MODIFIED, VARY,
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Response, StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::Json;
+use bytes::{Bytes, BytesMut};
+use chrono::DateTime;
+use futures_util::{FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tracing::Instrument;
+
+use crate::admin::{self, AdminStateRef};
+use crate::aws_chunked::{self, DecodeError, DecodeOptions};
+use crate::config::{Backend, ServerConfig, DEFAULT_USECASE};
+use crate::new_datamodel::store::{FileStore, NamespacedFileStore};
+use crate::new_datamodel::{bucket, chunk, file, lifecycle, Error};
+use crate::signed_url::{self, UrlSigner};
+use crate::sigv4::{self, AuthConfig, AuthError, VerifiedRequest};
+use crate::{grpc, sentry};
+
+pub(crate) struct AppState {
+    pub(crate) config: ServerConfig,
+    pub(crate) filestore: FileStore,
+    /// When configured, all requests have to be signed with one of these credentials.
+    auth: Option<AuthConfig>,
+    pub(crate) url_signer: UrlSigner,
+}
+
+pub(crate) type AppStateRef = Arc<AppState>;
+
+impl AppState {
+    fn new(config: ServerConfig) -> anyhow::Result<Self> {
+        let filestore = match config.backend {
+            Backend::Mem => FileStore::mem(),
+            Backend::Fjall => FileStore::fjall(config.data_dir.as_deref())
+                .context("failed to open the fjall store")?,
+        };
+        for (name, policy) in config.usecase_policies() {
+            filestore
+                .register_usecase(name, policy)
+                .with_context(|| format!("failed to register usecase `{name}`"))?;
+        }
+        let auth = match &config.auth_config {
+            Some(path) => Some(
+                AuthConfig::load(path)
+                    .with_context(|| format!("failed to load auth config `{}`", path.display()))?,
+            ),
+            None => None,
+        };
+        let url_signer = match auth.as_ref().and_then(|auth| auth.url_signing_key.as_ref()) {
+            Some(key) => UrlSigner::new(key.as_bytes()),
+            None => {
+                tracing::warn!(
+                    "no `url_signing_key` is configured, signed URLs are invalidated on restart"
+                );
+                UrlSigner::random()
+            }
+        };
+
+        Ok(Self {
+            config,
+            filestore,
+            auth,
+            url_signer,
+        })
+    }
+}
+
+/// The listeners the server accepts connections on.
+pub struct Listeners {
+    /// Serves the S3 API.
+    pub s3: TcpListener,
+    /// Serves the [`admin`] endpoints.
+    pub admin: Option<TcpListener>,
+    /// Serves the [`grpc`] metadata service.
+    pub grpc: Option<TcpListener>,
+    /// Serves the [`sentry`] chunk upload endpoints.
+    pub sentry: Option<TcpListener>,
+}
+
+/// Serves kycok on the `listeners`, according to the validated `config`.
+///
+/// Once `shutdown` completes, no new connections are accepted, and in-flight requests are
+/// drained before the store is durably persisted.
+pub async fn serve(
+    listeners: Listeners,
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    let shutdown = shutdown.shared();
+
+    let metrics = admin::prometheus_handle();
+    let upkeep = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            metrics.run_upkeep();
+        }
+    });
+
+    // the admin endpoints are served right away, so `/ready` can report the store being opened
+    let admin_state = AdminStateRef::default();
+    let serve_admin = match listeners.admin {
+        Some(admin_listener) => {
+            tracing::info!(addr = %admin_listener.local_addr()?, "serving the admin endpoints");
+            let admin = admin::router(admin_state.clone()).into_make_service();
+            let serve = axum::serve(admin_listener, admin).with_graceful_shutdown(shutdown.clone());
+            Some(tokio::spawn(serve.into_future()))
+        }
+        None => None,
+    };
+
+    let opened = tokio::task::spawn_blocking(move || AppState::new(config)).await?;
+    let state = match opened {
+        Ok(state) => AppStateRef::new(state),
+        Err(err) => {
+            upkeep.abort();
+            if let Some(serve_admin) = serve_admin {
+                serve_admin.abort();
+            }
+            return Err(err);
+        }
+    };
+    let _ = admin_state.app.set(state.clone());
+    let lifecycle = tokio::spawn(apply_lifecycle(state.clone(), shutdown.clone()));
+
+    tracing::info!(addr = %listeners.s3.local_addr()?, "serving the S3 API");
+    let app = app.with_state(state.clone()).into_make_service();
+    let serve_app = axum::serve(listeners.s3, app).with_graceful_shutdown(shutdown.clone());
+    let serve_grpc = async {
+        let Some(grpc_listener) = listeners.grpc else {
+            return Ok(());
+        };
+        tracing::info!(addr = %grpc_listener.local_addr()?, "serving the gRPC service");
+        let grpc = grpc::router(state.clone()).into_make_service();
+        axum::serve(grpc_listener, grpc)
+            .with_graceful_shutdown(shutdown.clone())
+            .await
+    };
+    let serve_sentry = async {
+        let Some(sentry_listener) = listeners.sentry else {
+            return Ok(());
+        };
+        tracing::info!(addr = %sentry_listener.local_addr()?, "serving the Sentry endpoints");
+        let sentry = sentry::router(state.clone()).into_make_service();
+        axum::serve(sentry_listener, sentry)
+            .with_graceful_shutdown(shutdown.clone())
+            .await
+    };
+    let result = tokio::try_join!(serve_app.into_future(), serve_grpc, serve_sentry);
+    let admin_result = match serve_admin {
+        Some(serve_admin) => serve_admin.await?,
+        None => Ok(()),
+    };
+    upkeep.abort();
+    lifecycle.await?;
+    result?;
+    admin_result?;
+
+    tokio::task::spawn_blocking(move || state.filestore.shutdown())
+        .await?
+        .context("failed to persist the store")
+}
+
+/// Applies the lifecycle rules of all buckets every `lifecycle_interval`, and collects the
+/// garbage they leave behind, until `shutdown` completes.
+///
+/// A run which is in progress on shutdown is finished, so that the store is only persisted
+/// afterwards.
+async fn apply_lifecycle(state: AppStateRef, shutdown: impl Future<Output = ()>) {
+    let mut shutdown = pin!(shutdown);
+    let period = Duration::from_secs(state.config.lifecycle_interval);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = &mut shutdown => return,
+        }
+        let state = state.clone();
+        let collected = tokio::task::spawn_blocking(move || {
+            let now = SystemTime::now();
+            state.filestore.apply_lifecycle(&state.config.storage, now);
+            state.filestore.collect_garbage()
+        })
+        .await
+        .unwrap();
+        if let Err(err) = collected {
+            tracing::error!(
+                error = &err as &dyn std::error::Error,
+                "garbage collection failed"
+            );
+        }
+    }
+}
+
+/// Records the request metrics and span around [`handle_request`], and returns the request ID
+/// in `x-amz-request-id`.
+async fn app(State(state): State<AppStateRef>, request: Request) -> Response<Body> {
+    let start = Instant::now();
+    let operation = s3_operation(&request);
+    let (bucket_name, key) = bucket_and_key(request.uri());
+    let namespace = bucket_name
+        .and_then(|bucket_name| state.filestore.get_bucket(&bucket_name))
+        .map(|bucket| bucket.namespace.0.to_string())
+        .unwrap_or_default();
+
+    let request_id = uuid::Uuid::new_v4().simple().to_string().to_uppercase();
+    let span = tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        operation,
+        namespace,
+        key,
+    );
+    let mut response = handle_request(state, request)
+        .instrument(span.clone())
+        .await;
+
+    let elapsed = start.elapsed();
+    span.in_scope(|| {
+        tracing::info!(
+            status = response.status().as_u16(),
+            elapsed_ms = elapsed.as_secs_f64() * 1000.,
+            "request finished"
+        )
+    });
+    response.headers_mut().insert(
+        "x-amz-request-id",
+        HeaderValue::from_str(&request_id).unwrap(),
+    );
+
+    let status = response.status().as_u16().to_string();
+    let labels = [
+        ("operation", operation.to_owned()),
+        ("namespace", namespace),
+    ];
+    metrics::counter!("kycok_requests_total", &labels).increment(1);
+    metrics::counter!("kycok_responses_total", "operation" => operation, "status" => status)
+        .increment(1);
+    metrics::histogram!("kycok_request_duration_seconds", &labels).record(elapsed);
+
+    response
+}
+
+/// The bucket name and key the request is addressed to, if any.
+fn bucket_and_key(uri: &Uri) -> (Option<String>, Option<String>) {
+    let decode = |s: &str| String::from_utf8(sigv4::percent_decode(s)).ok();
+    let mut splits = uri.path().splitn(3, '/').skip(1);
+    let bucket_name = splits.next().filter(|name| !name.is_empty());
+    let key = splits.next().filter(|key| !key.is_empty());
+    (bucket_name.and_then(decode), key.and_then(decode))
+}
+
+/// The name of the S3 operation, used as a metrics label.
+fn s3_operation(request: &Request) -> &'static str {
+    let (method, uri) = (request.method(), request.uri());
+    let query = uri.query().unwrap_or_default();
+    let mut splits = uri.path().splitn(3, '/').skip(1);
+    let has_bucket = splits.next().is_some_and(|bucket| !bucket.is_empty());
+    let key = splits.next().unwrap_or_default();
+    let has_key = !key.is_empty();
+
+    match (method, has_bucket, has_key) {
+        (&Method::GET, false, _) => "ListBuckets",
+        (&Method::POST, true, _) if query_param(query, "presign").is_some() => "Presign",
+        (&Method::POST, true, _) if query_param(query, "delete").is_some() => "DeleteObjects",
+        (&Method::POST, true, false) if query_param(query, "missing-chunks").is_some() => {
+            "FindMissingChunks"
+        }
+        (&Method::POST, true, false) if query_param(query, "chunk").is_some() => "UploadChunk",
+        (&Method::POST, true, false) if query_param(query, "allocate").is_some() => {
+            "AllocateObjectId"
+        }
+        (&Method::POST, true, true) if query_param(query, "assemble").is_some() => "AssembleObject",
+        (&Method::PUT, true, false) if query_param(query, "versioning").is_some() => {
+            "PutBucketVersioning"
+        }
+        (&Method::PUT, true, false) if query_param(query, "object-lock").is_some() => {
+            "PutObjectLockConfiguration"
+        }
+        (&Method::PUT, true, false) if query_param(query, "lifecycle").is_some() => {
+            "PutBucketLifecycleConfiguration"
+        }
+        (&Method::PUT, true, false) => "CreateBucket",
+        (&Method::DELETE, true, false) if query_param(query, "lifecycle").is_some() => {
+            "DeleteBucketLifecycle"
+        }
+        (&Method::DELETE, true, false) => "DeleteBucket",
+        (&Method::HEAD, true, false) => "HeadBucket",
+        (&Method::GET, true, false) if query.starts_with("location") => "GetBucketLocation",
+        (&Method::GET, true, false) if query.starts_with("object-lock") => {
+            "GetObjectLockConfiguration"
+        }
+        (&Method::GET, true, false) if query.starts_with("versioning") => "GetBucketVersioning",
+        (&Method::GET, true, false) if query.starts_with("lifecycle") => {
+            "GetBucketLifecycleConfiguration"
+        }
+        (&Method::GET, true, false) if query_param(query, "versions").is_some() => {
+            "ListObjectVersions"
+        }
+        (&Method::GET, true, false) => "ListObjects",
+        (&Method::GET, true, true) if query_param(query, "retention").is_some() => {
+            "GetObjectRetention"
+        }
+        (&Method::GET, true, true) if query_param(query, "legal-hold").is_some() => {
+            "GetObjectLegalHold"
+        }
+        (&Method::PUT, true, true) if query_param(query, "retention").is_some() => {
+            "PutObjectRetention"
+        }
+        (&Method::PUT, true, true) if query_param(query, "legal-hold").is_some() => {
+            "PutObjectLegalHold"
+        }
+        (&Method::GET, true, true) if query_param(query, "tagging").is_some() => "GetObjectTagging",
+        (&Method::PUT, true, true) if query_param(query, "tagging").is_some() => "PutObjectTagging",
+        (&Method::DELETE, true, true) if query_param(query, "tagging").is_some() => {
+            "DeleteObjectTagging"
+        }
+        (&Method::GET, true, true) if key.starts_with(BY_HASH_PREFIX) => "GetObjectByHash",
+        (&Method::HEAD, true, true) if key.starts_with(BY_HASH_PREFIX) => "HeadObjectByHash",
+        (&Method::GET, true, true) => "GetObject",
+        (&Method::HEAD, true, true) => "HeadObject",
+        (&Method::PUT, true, true) if request.headers().contains_key("x-amz-copy-source") => {
+            "CopyObject"
+        }
+        (&Method::PUT, true, true) => "PutObject",
+        (&Method::DELETE, true, true) => "DeleteObject",
+        _ => "Unknown",
+    }
+}
+
+/// The key prefix under which `GET` and `HEAD` requests read files by their ID, as in
+/// `/{bucket}/by-hash/{file_id}`, rather than by name.
+const BY_HASH_PREFIX: &str = "by-hash/";
+
+const VERSION_ID: HeaderName = HeaderName::from_static("x-amz-version-id");
+const DELETE_MARKER: HeaderName = HeaderName::from_static("x-amz-delete-marker");
+const BYPASS_GOVERNANCE: HeaderName = HeaderName::from_static("x-amz-bypass-governance-retention");
+const COPY_SOURCE_VERSION_ID: HeaderName = HeaderName::from_static("x-amz-copy-source-version-id");
+const TAGGING: HeaderName = HeaderName::from_static("x-amz-tagging");
+const TAGGING_COUNT: HeaderName = HeaderName::from_static("x-amz-tagging-count");
+
+async fn handle_request(state: AppStateRef, request: Request) -> Response<Body> {
+    let (parts, body) = request.into_parts();
+    let (method, uri) = (parts.method, parts.uri);
+
+    let now = SystemTime::now();
+    let query = uri.query().unwrap_or_default();
+
+    // Signed URLs replace the SigV4 signature, and are verified per object below.
+    let is_signed_url = signed_url::is_signed_url(query);
+    let verified = match &state.auth {
+        Some(auth) if !is_signed_url => {
+            match sigv4::verify_request(auth, &method, &uri, &parts.headers, now) {
+                Ok(verified) => Some(verified),
+                Err(err) => return auth_error(err),
+            }
+        }
+        _ => None,
+    };
+    let authorize_bucket = |bucket: &str| {
+        if is_signed_url {
+            return Err(AuthError::AccessDenied);
+        }
+        match &verified {
+            Some(verified) => verified.authorize(bucket),
+            None => Ok(()),
+        }
+    };
+    // Object lock sub-resources can lift the protection of objects, so they require SigV4.
+    let is_object_lock =
+        query_param(query, "retention").is_some() || query_param(query, "legal-hold").is_some();
+    let authorize = |bucket: &bucket::Bucket, key: Option<&str>| {
+        if is_signed_url && !is_object_lock {
+            let key = key.ok_or(AuthError::AccessDenied)?;
+            let namespace = bucket.namespace.0;
+            return state.url_signer.verify(&method, namespace, key, query, now);
+        }
+        authorize_bucket(&bucket.name)
+    };
+
+    let Ok(path) = String::from_utf8(sigv4::percent_decode(uri.path())) else {
+        return s3_error(StatusCode::BAD_REQUEST, "InvalidURI", "Invalid URI");
+    };
+    let mut splits = path.splitn(3, "/");
+    let _ = splits.next();
+    let bucket_name = splits.next().unwrap_or_default();
+    let key = splits.next().filter(|key| !key.is_empty());
+
+    if bucket_name.is_empty() {
+        if method != Method::GET {
+            return method_not_allowed();
+        }
+        if is_signed_url {
+            return auth_error(AuthError::AccessDenied);
+        }
+        return list_buckets(&state, verified.as_ref());
+    }
+    if key.is_none() && (method == Method::PUT || method == Method::DELETE) {
+        if let Err(err) = authorize_bucket(bucket_name) {
+            return auth_error(err);
+        }
+        if method == Method::PUT && query_param(query, "versioning").is_some() {
+            let max_size = state.config.storage.max_object_size;
+            let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+            return put_bucket_versioning(&state, bucket_name, &bytes);
+        }
+        if method == Method::PUT && query_param(query, "object-lock").is_some() {
+            let max_size = state.config.storage.max_object_size;
+            let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+            return put_object_lock_configuration(&state, bucket_name, &bytes);
+        }
+        if method == Method::PUT && query_param(query, "lifecycle").is_some() {
+            let max_size = state.config.storage.max_object_size;
+            let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+            return put_bucket_lifecycle(&state, bucket_name, &bytes);
+        }
+        if method == Method::DELETE && query_param(query, "lifecycle").is_some() {
+            return match state.filestore.set_lifecycle(bucket_name, vec![]) {
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(err) => store_error(err),
+            };
+        }
+        if method == Method::PUT {
+            return create_bucket(&state, &parts.headers, bucket_name);
+        }
+        return match state.filestore.delete_bucket(bucket_name) {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(err) => store_error(err),
+        };
+    }
+
+    let Some(bucket) = state.filestore.get_bucket(bucket_name) else {
+        return store_error(Error::NoSuchBucket);
+    };
+
+    match method {
+        Method::GET | Method::HEAD => {
+            let Some(path) = key else {
+                if let Err(err) = authorize_bucket(bucket_name) {
+                    return auth_error(err);
+                }
+                if method == Method::HEAD {
+                    return bucket_headers(&bucket).into_response();
+                }
+                if query.starts_with("location") {
+                    return r#"<LocationConstraint>whatever</LocationConstraint>"#.into_response();
+                }
+                if query.starts_with("object-lock") {
+                    return get_object_lock_configuration(&bucket);
+                }
+                if query.starts_with("lifecycle") {
+                    return get_bucket_lifecycle(&bucket);
+                }
+                if query.starts_with("versioning") {
+                    if bucket.settings.versioned {
+                        return r#"<VersioningConfiguration><Status>Enabled</Status></VersioningConfiguration>"#.into_response();
+                    }
+                    return r#"<VersioningConfiguration />"#.into_response();
+                }
+                if query_param(query, "versions").is_some() {
+                    return list_object_versions(&state, &bucket, query);
+                }
+                return s3_error(
+                    StatusCode::NOT_IMPLEMENTED,
+                    "NotImplemented",
+                    "Listing objects is not supported",
+                );
+            };
+            if let Err(err) = authorize(&bucket, Some(path)) {
+                return auth_error(err);
+            }
+            let filestore = bucket_filestore(&state, &bucket);
+            let version_id = match query_param(query, "versionId").map(str::parse) {
+                None => None,
+                Some(Ok(version_id)) => Some(version_id),
+                Some(Err(_)) => return invalid_version_id(),
+            };
+            if method == Method::GET && query_param(query, "retention").is_some() {
+                return get_object_retention(&filestore, path, version_id);
+            }
+            if method == Method::GET && query_param(query, "legal-hold").is_some() {
+                return get_object_legal_hold(&filestore, path, version_id);
+            }
+            if method == Method::GET && query_param(query, "tagging").is_some() {
+                return get_object_tagging(&bucket, &filestore, path, version_id);
+            }
+
+            let mut headers = HeaderMap::new();
+            let by_hash = path.strip_prefix(BY_HASH_PREFIX);
+            let (file_id, last_modified, lock, tags, file) =
+                match (by_hash.map(str::parse), version_id) {
+                    (Some(Ok(file_id)), None) => {
+                        let Some(file) = filestore.find_file(file_id) else {
+                            return no_such_key();
+                        };
+                        (file_id, None, None, vec![], Some(file))
+                    }
+                    (_, Some(version_id)) => {
+                        let Some(version) = filestore.get_version(path, version_id) else {
+                            return no_such_version();
+                        };
+                        insert_version_id(&mut headers, version_id);
+                        let Some(file_id) = version.file_id else {
+                            headers.insert(DELETE_MARKER, HeaderValue::from_static("true"));
+                            return (headers, method_not_allowed()).into_response();
+                        };
+                        (
+                            file_id,
+                            Some(version.last_modified),
+                            Some(version.lock),
+                            version.tags,
+                            None,
+                        )
+                    }
+                    _ => {
+                        let Some(named_file) = filestore.get_named_file(path) else {
+                            return no_such_key();
+                        };
+                        if method == Method::GET && bucket.settings.tti.is_some() {
+                            filestore.record_access(path, &named_file, now);
+                        }
+                        if bucket.settings.versioned {
+                            insert_version_id(&mut headers, named_file.version_id);
+                        }
+                        (
+                            named_file.file_id,
+                            Some(named_file.last_modified),
+                            Some(named_file.lock),
+                            named_file.tags,
+                            None,
+                        )
+                    }
+                };
+            if let Some(lock) = lock.filter(|_| bucket.settings.object_lock) {
+                insert_lock_headers(&mut headers, lock);
+            }
+            if !tags.is_empty() {
+                headers.insert(TAGGING_COUNT, tags.len().into());
+            }
+
+            headers.insert(ETAG, etag(file_id));
+            if let Some(last_modified) = last_modified {
+                headers.insert(LAST_MODIFIED, http_date(last_modified));
+            }
+            match check_read_preconditions(&parts.headers, file_id, last_modified) {
+                Some(StatusCode::NOT_MODIFIED) => {
+                    return (StatusCode::NOT_MODIFIED, headers).into_response()
+                }
+                Some(_) => return store_error(Error::PreconditionFailed),
+                None => {}
+            }
+
+            // the file might have been deleted since the name was resolved
+            let Some(file) = file.or_else(|| filestore.find_file(file_id)) else {
+                return no_such_key();
+            };
+            let zstd = accepts_zstd(&parts.headers);
+            headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+            if zstd {
+                headers.insert(CONTENT_ENCODING, HeaderValue::from_static("zstd"));
+            } else {
+                headers.insert(CONTENT_LENGTH, file.size.into());
+            }
+
+            if method == Method::HEAD {
+                return headers.into_response();
+            }
+            let body = file_body(state.clone(), bucket, file, zstd);
+            return (headers, body).into_response();
+        }
+        Method::POST if query_param(query, "presign").is_some() => {
+            if let Err(err) = authorize(&bucket, None) {
+                return auth_error(err);
+            }
+            return presign(&state, &parts.headers, &bucket, key, query, now);
+        }
+        Method::POST if query_param(query, "delete").is_some() => {
+            if let Err(err) = authorize(&bucket, None) {
+                return auth_error(err);
+            }
+            let max_size = max_body_size(&state, &bucket);
+            let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+            return delete_objects(&state, &parts.headers, &bucket, &bytes);
+        }
+        Method::POST if key.is_none() && query_param(query, "missing-chunks").is_some() => {
+            if let Err(err) = authorize(&bucket, None) {
+                return auth_error(err);
+            }
+            let max_size = max_body_size(&state, &bucket);
+            let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+            return find_missing_chunks(&state, &bucket, &bytes);
+        }
+        Method::POST if key.is_none() && query_param(query, "chunk").is_some() => {
+            if let Err(err) = authorize(&bucket, None) {
+                return auth_error(err);
+            }
+            let Some(Ok(chunk_id)) = query_param(query, "chunk").map(str::parse) else {
+                return s3_error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidArgument",
+                    "invalid chunk id",
+                );
+            };
+            let max_size = max_body_size(&state, &bucket);
+            let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+            let chunk_id = chunk::ChunkId(chunk_id);
+            return upload_chunk(&state, &parts.headers, &bucket, chunk_id, bytes);
+        }
+        Method::POST if key.is_none() && query_param(query, "allocate").is_some() => {
+            if let Err(err) = authorize(&bucket, None) {
+                return auth_error(err);
+            }
+            return allocate_object_id(&state, &bucket, query);
+        }
+        Method::POST if query_param(query, "assemble").is_some() => {
+            let Some(path) = key else {
+                return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", "missing key");
+            };
+            if let Err(err) = authorize(&bucket, Some(path)) {
+                return auth_error(err);
+            }
+            let max_size = max_body_size(&state, &bucket);
+            let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+            return assemble_object(&state, &parts.headers, &bucket, path, &bytes);
+        }
+        Method::DELETE => {
+            // bucket-level `DELETE`s are handled above
+            let Some(path) = key else {
+                return method_not_allowed();
+            };
+            if let Err(err) = authorize(&bucket, Some(path)) {
+                return auth_error(err);
+            }
+            let filestore = bucket_filestore(&state, &bucket);
+            if query_param(query, "tagging").is_some() {
+                let version_id = match query_param(query, "versionId").map(str::parse) {
+                    None => None,
+                    Some(Ok(version_id)) => Some(version_id),
+                    Some(Err(_)) => return invalid_version_id(),
+                };
+                return match set_object_tags(&bucket, &filestore, path, version_id, vec![]) {
+                    Ok(headers) => (StatusCode::NO_CONTENT, headers).into_response(),
+                    Err(response) => response,
+                };
+            }
+            let mut headers = HeaderMap::new();
+            match query_param(query, "versionId").map(str::parse) {
+                None => {
+                    let deleted = filestore.delete_filenames(&[path]).pop().flatten();
+                    if let Some(delete_marker) = deleted.and_then(|deleted| deleted.delete_marker) {
+                        headers.insert(DELETE_MARKER, HeaderValue::from_static("true"));
+                        insert_version_id(&mut headers, delete_marker);
+                    }
+                }
+                Some(Ok(version_id)) => {
+                    let bypass_governance = bypasses_governance(&parts.headers);
+                    let deleted =
+                        match filestore.delete_version(path, version_id, bypass_governance) {
+                            Ok(deleted) => deleted,
+                            Err(err) => return store_error(err),
+                        };
+                    insert_version_id(&mut headers, version_id);
+                    if deleted.is_some_and(|version| version.file_id.is_none()) {
+                        headers.insert(DELETE_MARKER, HeaderValue::from_static("true"));
+                    }
+                }
+                Some(Err(_)) => return invalid_version_id(),
+            }
+
+            return (StatusCode::NO_CONTENT, headers).into_response();
+        }
+        Method::PUT => {
+            // bucket-level `PUT`s are handled above
+            let Some(path) = key else {
+                return method_not_allowed();
+            };
+            if let Err(err) = authorize(&bucket, Some(path)) {
+                return auth_error(err);
+            }
+
+            if query_param(query, "retention").is_some()
+                || query_param(query, "legal-hold").is_some()
+                || query_param(query, "tagging").is_some()
+            {
+                let version_id = match query_param(query, "versionId").map(str::parse) {
+                    None => None,
+                    Some(Ok(version_id)) => Some(version_id),
+                    Some(Err(_)) => return invalid_version_id(),
+                };
+                let max_size = max_body_size(&state, &bucket);
+                let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await
+                {
+                    Ok(bytes) => bytes,
+                    Err(response) => return response,
+                };
+                let filestore = bucket_filestore(&state, &bucket);
+                if query_param(query, "tagging").is_some() {
+                    return put_object_tagging(&bucket, &filestore, path, version_id, &bytes);
+                }
+                if query_param(query, "retention").is_some() {
+                    let bypass_governance = bypasses_governance(&parts.headers);
+                    return put_object_retention(
+                        &bucket,
+                        &filestore,
+                        path,
+                        version_id,
+                        bypass_governance,
+                        &bytes,
+                    );
+                }
+                return put_object_legal_hold(&bucket, &filestore, path, version_id, &bytes);
+            }
+
+            if let Some(copy_source) = parts.headers.get("x-amz-copy-source") {
+                let Some((source_bucket, source_path, source_version)) =
+                    parse_copy_source(copy_source)
+                else {
+                    return s3_error(
+                        StatusCode::BAD_REQUEST,
+                        "InvalidArgument",
+                        "Invalid x-amz-copy-source",
+                    );
+                };
+                // A signed URL only grants access to its own object, so it cannot be a source.
+                if let Err(err) = authorize_bucket(&source_bucket) {
+                    return auth_error(err);
+                }
+                let Some(source_bucket) = state.filestore.get_bucket(&source_bucket) else {
+                    return store_error(Error::NoSuchBucket);
+                };
+
+                let source = bucket_filestore(&state, &source_bucket);
+                let (file_id, source_tags) = match source_version {
+                    Some(version_id) => {
+                        let Some(version) = source.get_version(&source_path, version_id) else {
+                            return no_such_version();
+                        };
+                        // delete markers cannot be copied
+                        let Some(file_id) = version.file_id else {
+                            return invalid_request(
+                                "The source of a copy request may not be a delete marker",
+                            );
+                        };
+                        (file_id, version.tags)
+                    }
+                    None => {
+                        let Some(named_file) = source.get_named_file(&source_path) else {
+                            return no_such_key();
+                        };
+                        (named_file.file_id, named_file.tags)
+                    }
+                };
+                let tags = match parts.headers.get("x-amz-tagging-directive") {
+                    None => source_tags,
+                    Some(directive) if directive == "COPY" => source_tags,
+                    Some(directive) if directive == "REPLACE" => {
+                        match parse_tagging_header(&parts.headers) {
+                            Ok(tags) => tags,
+                            Err(response) => return response,
+                        }
+                    }
+                    Some(_) => {
+                        return s3_error(
+                            StatusCode::BAD_REQUEST,
+                            "InvalidArgument",
+                            "Unknown tagging directive",
+                        )
+                    }
+                };
+
+                let filestore = bucket_filestore(&state, &bucket);
+                let preconditions = write_preconditions(&parts.headers);
+                if fails_early(&filestore, &preconditions, path) {
+                    return store_error(Error::PreconditionFailed);
+                }
+                // the source might have been deleted since it was resolved
+                let Some(file_id) = filestore.copy_file(&source, file_id) else {
+                    return no_such_key();
+                };
+                if exceeds_max_object_size(&filestore, filestore.get_file(file_id).size) {
+                    filestore.discard_file(file_id);
+                    return entity_too_large();
+                }
+                let named_file = match filestore.associate_tagged_filename_if(
+                    file_id,
+                    path,
+                    &preconditions,
+                    tags,
+                ) {
+                    Ok(named_file) => named_file,
+                    Err(err) => {
+                        filestore.discard_file(file_id);
+                        return store_error(err);
+                    }
+                };
+
+                let last_modified = iso8601(named_file.last_modified);
+                let etag = etag(file_id);
+                let etag = etag.to_str().unwrap();
+                let body = format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?><CopyObjectResult><LastModified>{last_modified}</LastModified><ETag>{}</ETag></CopyObjectResult>"#,
+                    etag.replace('"', "&quot;")
+                );
+                let mut headers = HeaderMap::new();
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+                if let Some(version_id) = source_version {
+                    headers.insert(COPY_SOURCE_VERSION_ID, version_header(version_id));
+                }
+                if bucket.settings.versioned {
+                    insert_version_id(&mut headers, named_file.version_id);
+                }
+                return (headers, body).into_response();
+            }
+
+            // fail early, before reading the whole body
+            let filestore = bucket_filestore(&state, &bucket);
+            let preconditions = write_preconditions(&parts.headers);
+            if fails_early(&filestore, &preconditions, path) {
+                return store_error(Error::PreconditionFailed);
+            }
+            let tags = match parse_tagging_header(&parts.headers) {
+                Ok(tags) => tags,
+                Err(response) => return response,
+            };
+
+            let max_size = filestore.config().max_object_size;
+            let bytes = match read_body(&parts.headers, verified.as_ref(), body, max_size).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+
+            let max_object_size = filestore.config().max_object_size;
+            let bytes = match decode_content_encoding(&parts.headers, bytes, max_object_size) {
+                Ok(bytes) => bytes,
+                Err(ContentEncodingError::Invalid(message)) => {
+                    return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", message);
+                }
+                Err(ContentEncodingError::TooLarge) => return entity_too_large(),
+            };
+            if exceeds_max_object_size(&filestore, bytes.len() as u64) {
+                return entity_too_large();
+            }
+
+            let file_id = filestore.upload_file(&bytes);
+            let named_file =
+                match filestore.associate_tagged_filename_if(file_id, path, &preconditions, tags) {
+                    Ok(named_file) => named_file,
+                    Err(err) => {
+                        filestore.discard_file(file_id);
+                        return store_error(err);
+                    }
+                };
+
+            return written_headers(&bucket, &named_file).into_response();
+        }
+        _ => {}
+    }
+
+    tracing::debug!(%uri, "unsupported request");
+    StatusCode::BAD_REQUEST.into_response()
+}
+
+/// The filestore of the bucket, configured according to its usecase and settings.
+pub(crate) fn bucket_filestore<'fs>(
+    state: &'fs AppState,
+    bucket: &bucket::Bucket,
+) -> NamespacedFileStore<'fs> {
+    FileStore::with_bucket(&state.filestore, bucket, state.config.storage.clone())
+}
+
+/// The largest request body accepted for the bucket, which is the `max_object_size` of its
+/// usecase.
+fn max_body_size(state: &AppState, bucket: &bucket::Bucket) -> Option<u64> {
+    bucket_filestore(state, bucket).config().max_object_size
+}
+
+/// Whether the file is larger than the `max_object_size` of the usecase.
+fn exceeds_max_object_size(filestore: &NamespacedFileStore<'_>, size: u64) -> bool {
+    filestore
+        .config()
+        .max_object_size
+        .is_some_and(|max_object_size| size > max_object_size)
+}
+
+/// Whether the write `preconditions` fail on the current file of the name, before the new
+/// file is even uploaded.
+///
+/// Idempotent writes are retries if they upload the same contents as the current file, so
+/// those are only checked once the file is known.
+fn fails_early(
+    filestore: &NamespacedFileStore<'_>,
+    preconditions: &file::Preconditions,
+    name: &str,
+) -> bool {
+    !preconditions.idempotent && !preconditions.check(filestore.resolve_filename(name))
+}
+
+/// Creates a bucket, via `PUT /{bucket}`.
+///
+/// The usecase and scope, as well as the bucket settings, can be given with the
+/// `x-kycok-usecase`, `x-kycok-scope`, `x-kycok-ttl` and `x-kycok-tti` (in seconds) and
+/// `x-kycok-compression` (a zstd level, or `none`) headers. The usecase defaults to `default`, and the scope to
+/// the bucket name. Settings not given explicitly are taken from the policy of the usecase.
+fn create_bucket(state: &AppState, headers: &HeaderMap, name: &str) -> Response<Body> {
+    if !bucket::is_valid_name(name) {
+        return s3_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidBucketName",
+            "The specified bucket is not valid",
+        );
+    }
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let usecase = header("x-kycok-usecase").unwrap_or(DEFAULT_USECASE);
+    let scope = header("x-kycok-scope").unwrap_or(name);
+
+    let Some(usecase) = state.filestore.get_usecase(usecase) else {
+        return s3_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidArgument",
+            "unknown usecase",
+        );
+    };
+    let mut settings = bucket::BucketSettings::from_policy(&usecase.policy);
+    if let Some(ttl) = header("x-kycok-ttl") {
+        let Ok(ttl @ 1..) = ttl.parse() else {
+            return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", "invalid ttl");
+        };
+        settings.ttl = Some(ttl);
+    }
+    if let Some(tti) = header("x-kycok-tti") {
+        let Ok(tti @ 1..) = tti.parse() else {
+            return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", "invalid tti");
+        };
+        settings.tti = Some(tti);
+    }
+    match header("x-kycok-compression") {
+        None => {}
+        Some("none") => settings.compression_level = None,
+        Some(level) => match level.parse() {
+            Ok(level) if zstd::compression_level_range().contains(&level) => {
+                settings.compression_level = Some(level)
+            }
+            _ => {
+                return s3_error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidArgument",
+                    "invalid compression",
+                )
+            }
+        },
+    }
+
+    // object lock can only be enabled along with versioning
+    if header("x-amz-bucket-object-lock-enabled").is_some_and(|enabled| enabled == "true") {
+        settings.versioned = true;
+        settings.object_lock = true;
+    }
+
+    match state
+        .filestore
+        .create_bucket(name, &usecase.name, scope, settings)
+    {
+        Ok(_) => [("Location", format!("/{name}"))].into_response(),
+        Err(err) => store_error(err),
+    }
+}
+
+/// The headers returned for a `HEAD /{bucket}`, describing the bucket.
+fn bucket_headers(bucket: &bucket::Bucket) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let mut insert = |name, value: String| {
+        headers.insert(name, HeaderValue::try_from(value).unwrap());
+    };
+    insert("x-kycok-usecase", bucket.usecase.clone());
+    insert("x-kycok-scope", bucket.scope.clone());
+    if let Some(ttl) = bucket.settings.ttl {
+        insert("x-kycok-ttl", ttl.to_string());
+    }
+    if let Some(tti) = bucket.settings.tti {
+        insert("x-kycok-tti", tti.to_string());
+    }
+    let compression = match bucket.settings.compression_level {
+        Some(level) => level.to_string(),
+        None => "none".into(),
+    };
+    insert("x-kycok-compression", compression);
+    headers
+}
+
+/// Lists all the buckets the credential has access to, via `GET /`.
+fn list_buckets(state: &AppState, verified: Option<&VerifiedRequest>) -> Response<Body> {
+    let mut buckets = String::new();
+    for bucket in state.filestore.list_buckets() {
+        if verified.is_some_and(|verified| !verified.credential.may_access(&bucket.name)) {
+            continue;
+        }
+        // bucket names are validated on creation, and need no escaping
+        let created = iso8601(bucket.created);
+        buckets.push_str(&format!(
+            "<Bucket><Name>{}</Name><CreationDate>{created}</CreationDate></Bucket>",
+            bucket.name
+        ));
+    }
+
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><ListAllMyBucketsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Owner><ID>kycok</ID><DisplayName>kycok</DisplayName></Owner><Buckets>{buckets}</Buckets></ListAllMyBucketsResult>"#
+    );
+    ([("Content-Type", "application/xml")], body).into_response()
+}
+
+/// The maximum number of keys in a single `DeleteObjects` request.
+const MAX_DELETE_KEYS: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeleteRequest {
+    #[serde(default)]
+    quiet: bool,
+    #[serde(rename = "Object", default)]
+    objects: Vec<ObjectIdentifier>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ObjectIdentifier {
+    key: String,
+    version_id: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "DeleteResult")]
+struct DeleteResult {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'static str,
+    #[serde(rename = "Deleted")]
+    deleted: Vec<DeletedObject>,
+    #[serde(rename = "Error")]
+    errors: Vec<DeleteError>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeletedObject {
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delete_marker: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delete_marker_version_id: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeleteError {
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_id: Option<String>,
+    code: &'static str,
+    message: &'static str,
+}
+
+/// Deletes up to 1000 objects or versions at once, via `POST /{bucket}?delete`.
+///
+/// All the keys without a version are deleted within a single transaction. Keys which do not
+/// exist are reported as deleted, as in S3, whereas versions which do not exist or are locked
+/// are reported as errors, which are listed in quiet mode as well.
+fn delete_objects(
+    state: &AppState,
+    headers: &HeaderMap,
+    bucket: &bucket::Bucket,
+    body: &[u8],
+) -> Response<Body> {
+    let Ok(body) = std::str::from_utf8(body) else {
+        return malformed_xml();
+    };
+    let Ok(request) = quick_xml::de::from_str::<DeleteRequest>(body) else {
+        return malformed_xml();
+    };
+    if request.objects.is_empty() || request.objects.len() > MAX_DELETE_KEYS {
+        return malformed_xml();
+    }
+
+    let keys: Vec<&str> = request
+        .objects
+        .iter()
+        .filter(|object| object.version_id.is_none())
+        .map(|object| object.key.as_str())
+        .collect();
+    let filestore = bucket_filestore(state, bucket);
+    let mut deleted_files = filestore.delete_filenames(&keys).into_iter();
+    let bypass_governance = bypasses_governance(headers);
+
+    let mut deleted = vec![];
+    let mut errors = vec![];
+    for object in request.objects {
+        let Some(version_id) = object.version_id else {
+            let delete_marker = deleted_files
+                .next()
+                .unwrap()
+                .and_then(|deleted| deleted.delete_marker);
+            deleted.push(DeletedObject {
+                key: object.key,
+                version_id: None,
+                delete_marker: delete_marker.map(|_| true),
+                delete_marker_version_id: delete_marker.map(|version_id| version_id.to_string()),
+            });
+            continue;
+        };
+        let result = match version_id.parse() {
+            Ok(parsed) => filestore.delete_version(&object.key, parsed, bypass_governance),
+            Err(_) => Ok(None),
+        };
+        let (code, message) = match result {
+            Ok(Some(version)) => {
+                deleted.push(DeletedObject {
+                    key: object.key,
+                    version_id: Some(version_id),
+                    delete_marker: version.file_id.is_none().then_some(true),
+                    delete_marker_version_id: None,
+                });
+                continue;
+            }
+            Ok(None) => ("NoSuchVersion", "The specified version does not exist."),
+            Err(Error::ObjectLocked) => (
+                "AccessDenied",
+                "Access Denied because object protected by object lock",
+            ),
+            Err(_) => (
+                "InternalError",
+                "We encountered an internal error. Please try again.",
+            ),
+        };
+        errors.push(DeleteError {
+            key: object.key,
+            version_id: Some(version_id),
+            code,
+            message,
+        });
+    }
+
+    if request.quiet {
+        deleted.clear();
+    }
+    let result = DeleteResult {
+        xmlns: "http://s3.amazonaws.com/doc/2006-03-01/",
+        deleted,
+        errors,
+    };
+    let body = quick_xml::se::to_string(&result).unwrap();
+    let body = format!(r#"<?xml version="1.0" encoding="UTF-8"?>{body}"#);
+    ([("Content-Type", "application/xml")], body).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VersioningConfiguration {
+    status: Option<String>,
+}
+
+/// Enables versioning of a bucket, via `PUT /{bucket}?versioning`.
+///
+/// Once enabled, versioning cannot be suspended again.
+fn put_bucket_versioning(state: &AppState, name: &str, body: &[u8]) -> Response<Body> {
+    let Ok(body) = std::str::from_utf8(body) else {
+        return malformed_xml();
+    };
+    let Ok(configuration) = quick_xml::de::from_str::<VersioningConfiguration>(body) else {
+        return malformed_xml();
+    };
+    match configuration.status.as_deref() {
+        Some("Enabled") => match state.filestore.enable_versioning(name) {
+            Ok(_) => StatusCode::OK.into_response(),
+            Err(err) => store_error(err),
+        },
+        Some("Suspended") => s3_error(
+            StatusCode::NOT_IMPLEMENTED,
+            "NotImplemented",
+            "Suspending versioning is not supported",
+        ),
+        _ => malformed_xml(),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename = "ListVersionsResult", rename_all = "PascalCase")]
+struct ListVersionsResult {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'static str,
+    name: String,
+    prefix: String,
+    is_truncated: bool,
+    #[serde(rename = "$value")]
+    versions: Vec<ListedVersion>,
+}
+
+#[derive(Serialize)]
+enum ListedVersion {
+    Version(ObjectVersion),
+    DeleteMarker(DeleteMarker),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ObjectVersion {
+    key: String,
+    version_id: String,
+    is_latest: bool,
+    last_modified: String,
+    #[serde(rename = "ETag")]
+    etag: String,
+    size: u64,
+    storage_class: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeleteMarker {
+    key: String,
+    version_id: String,
+    is_latest: bool,
+    last_modified: String,
+}
+
+/// Lists all versions and delete markers of the objects with the given `prefix`, via
+/// `GET /{bucket}?versions`.
+///
+/// The whole listing is returned at once, so it is never truncated.
+fn list_object_versions(state: &AppState, bucket: &bucket::Bucket, query: &str) -> Response<Body> {
+    let prefix = query_param(query, "prefix").unwrap_or_default();
+    let Ok(prefix) = String::from_utf8(sigv4::percent_decode(prefix)) else {
+        return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", "Invalid prefix");
+    };
+
+    let filestore = bucket_filestore(state, bucket);
+    let versions = filestore.list_versions(&prefix);
+    let versions = versions.into_iter().filter_map(|listed| {
+        let key = listed.name;
+        let version_id = listed.version_id.to_string();
+        let last_modified = iso8601(listed.version.last_modified);
+        let version = match listed.version.file_id {
+            Some(file_id) => ListedVersion::Version(ObjectVersion {
+                key,
+                version_id,
+                is_latest: listed.is_latest,
+                last_modified,
+                etag: format!("\"{file_id}\""),
+                // the version might have been deleted since it was listed
+                size: filestore.find_file(file_id)?.size,
+                storage_class: "STANDARD",
+            }),
+            None => ListedVersion::DeleteMarker(DeleteMarker {
+                key,
+                version_id,
+                is_latest: listed.is_latest,
+                last_modified,
+            }),
+        };
+        Some(version)
+    });
+    let result = ListVersionsResult {
+        xmlns: "http://s3.amazonaws.com/doc/2006-03-01/",
+        name: bucket.name.clone(),
+        prefix,
+        is_truncated: false,
+        versions: versions.collect(),
+    };
+    let body = quick_xml::se::to_string(&result).unwrap();
+    let body = format!(r#"<?xml version="1.0" encoding="UTF-8"?>{body}"#);
+    ([("Content-Type", "application/xml")], body).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ObjectLockConfiguration {
+    object_lock_enabled: Option<String>,
+    rule: Option<ObjectLockRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ObjectLockRule {
+    default_retention: DefaultRetention,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DefaultRetention {
+    mode: file::RetentionMode,
+    days: Option<u32>,
+    years: Option<u32>,
+}
+
+/// Enables object lock on a bucket, along with versioning, and sets its default retention,
+/// via `PUT /{bucket}?object-lock`.
+///
+/// Object lock cannot be disabled again, but the default retention can be changed.
+fn put_object_lock_configuration(state: &AppState, name: &str, body: &[u8]) -> Response<Body> {
+    let Ok(body) = std::str::from_utf8(body) else {
+        return malformed_xml();
+    };
+    let Ok(configuration) = quick_xml::de::from_str::<ObjectLockConfiguration>(body) else {
+        return malformed_xml();
+    };
+    if configuration.object_lock_enabled.as_deref() != Some("Enabled") {
+        return malformed_xml();
+    }
+    let default_retention = match configuration.rule.map(|rule| rule.default_retention) {
+        None => None,
+        Some(DefaultRetention {
+            mode,
+            days: Some(days),
+            years: None,
+        }) => Some(bucket::DefaultRetention { mode, days }),
+        Some(DefaultRetention {
+            mode,
+            days: None,
+            years: Some(years),
+        }) => Some(bucket::DefaultRetention {
+            mode,
+            days: years.saturating_mul(365),
+        }),
+        Some(_) => return malformed_xml(),
+    };
+    if default_retention.is_some_and(|retention| retention.days == 0) {
+        return s3_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidArgument",
+            "Default retention period must be a positive integer value",
+        );
+    }
+
+    match state.filestore.set_object_lock(name, default_retention) {
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(err) => store_error(err),
+    }
+}
+
+/// The object lock configuration of a bucket, via `GET /{bucket}?object-lock`.
+fn get_object_lock_configuration(bucket: &bucket::Bucket) -> Response<Body> {
+    if !bucket.settings.object_lock {
+        return s3_error(
+            StatusCode::NOT_FOUND,
+            "ObjectLockConfigurationNotFoundError",
+            "Object Lock configuration does not exist for this bucket",
+        );
+    }
+    let rule = match bucket.settings.default_retention {
+        Some(retention) => format!(
+            "<Rule><DefaultRetention><Mode>{}</Mode><Days>{}</Days></DefaultRetention></Rule>",
+            retention_mode(retention.mode),
+            retention.days
+        ),
+        None => String::new(),
+    };
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><ObjectLockConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><ObjectLockEnabled>Enabled</ObjectLockEnabled>{rule}</ObjectLockConfiguration>"#
+    );
+    ([("Content-Type", "application/xml")], body).into_response()
+}
+
+/// S3 rejects lifecycle configurations with more rules.
+const MAX_LIFECYCLE_RULES: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LifecycleConfiguration {
+    #[serde(default)]
+    rule: Vec<LifecycleRule>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "LifecycleConfiguration")]
+struct LifecycleConfigurationResult {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'static str,
+    #[serde(rename = "Rule")]
+    rules: Vec<LifecycleRule>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LifecycleRule {
+    #[serde(rename = "ID", skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filter: Option<LifecycleFilter>,
+    /// The filter of rules written before `Filter` was introduced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<Expiration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    noncurrent_version_expiration: Option<NoncurrentVersionExpiration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    abort_incomplete_multipart_upload: Option<AbortIncompleteMultipartUpload>,
+}
+
+/// Holds at most one of its fields, with `And` combining a prefix and several tags.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LifecycleFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<Tag>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    and: Option<LifecycleAnd>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LifecycleAnd {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+    #[serde(default, rename = "Tag")]
+    tags: Vec<Tag>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Tag {
+    key: String,
+    value: String,
+}
+
+impl From<Tag> for file::Tag {
+    fn from(tag: Tag) -> Self {
+        Self {
+            key: tag.key,
+            value: tag.value,
+        }
+    }
+}
+
+impl From<file::Tag> for Tag {
+    fn from(tag: file::Tag) -> Self {
+        Self {
+            key: tag.key,
+            value: tag.value,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Expiration {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    days: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expired_object_delete_marker: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NoncurrentVersionExpiration {
+    noncurrent_days: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AbortIncompleteMultipartUpload {
+    days_after_initiation: u32,
+}
+
+impl LifecycleRule {
+    /// Validates the rule, turning it into the rule stored with the bucket.
+    // the error is responded right away, so its size does not matter
+    #[allow(clippy::result_large_err)]
+    fn parse(self) -> Result<lifecycle::Rule, Response<Body>> {
+        let enabled = match self.status.as_str() {
+            "Enabled" => true,
+            "Disabled" => false,
+            _ => return Err(malformed_xml()),
+        };
+        let filter = match (self.filter, self.prefix) {
+            (Some(_), Some(_)) => return Err(malformed_xml()),
+            (None, prefix) => lifecycle::Filter {
+                prefix: prefix.unwrap_or_default(),
+                tags: vec![],
+            },
+            (Some(filter), None) => match (filter.prefix, filter.tag, filter.and) {
+                (prefix, None, None) => lifecycle::Filter {
+                    prefix: prefix.unwrap_or_default(),
+                    tags: vec![],
+                },
+                (None, Some(tag), None) => lifecycle::Filter {
+                    prefix: String::new(),
+                    tags: vec![tag.into()],
+                },
+                (None, None, Some(and)) => lifecycle::Filter {
+                    prefix: and.prefix.unwrap_or_default(),
+                    tags: and.tags.into_iter().map(Into::into).collect(),
+                },
+                _ => return Err(malformed_xml()),
+            },
+        };
+        if !file::are_valid_tags(&filter.tags) {
+            return Err(invalid_tag());
+        }
+
+        let expiration_days = match self.expiration {
+            None => None,
+            Some(Expiration {
+                days: Some(days),
+                date: None,
+                expired_object_delete_marker: None,
+            }) => Some(days),
+            Some(Expiration { days: None, .. }) => {
+                return Err(s3_error(
+                    StatusCode::NOT_IMPLEMENTED,
+                    "NotImplemented",
+                    "Only expiration after a number of days is supported",
+                ))
+            }
+            Some(_) => return Err(malformed_xml()),
+        };
+        let rule = lifecycle::Rule {
+            id: self.id.unwrap_or_else(|| file::IdFormat::Uuid.generate()),
+            enabled,
+            filter,
+            expiration_days,
+            noncurrent_expiration_days: self
+                .noncurrent_version_expiration
+                .map(|expiration| expiration.noncurrent_days),
+            abort_incomplete_upload_days: self
+                .abort_incomplete_multipart_upload
+                .map(|abort| abort.days_after_initiation),
+        };
+
+        if rule.id.len() > 255 {
+            return Err(s3_error(
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                "ID length should not exceed allowed limit of 255",
+            ));
+        }
+        if !rule.has_actions() {
+            return Err(invalid_request(
+                "At least one action needs to be specified in a rule",
+            ));
+        }
+        let days = [
+            rule.expiration_days,
+            rule.noncurrent_expiration_days,
+            rule.abort_incomplete_upload_days,
+        ];
+        if days.contains(&Some(0)) {
+            return Err(s3_error(
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                "Days must be a positive integer",
+            ));
+        }
+        if rule.abort_incomplete_upload_days.is_some() && !rule.filter.tags.is_empty() {
+            return Err(invalid_request(
+                "Tag-based filter cannot be used with AbortIncompleteMultipartUpload action",
+            ));
+        }
+        Ok(rule)
+    }
+}
+
+impl From<&lifecycle::Rule> for LifecycleRule {
+    fn from(rule: &lifecycle::Rule) -> Self {
+        let prefix = rule.filter.prefix.clone();
+        let mut tags = rule.filter.tags.iter().cloned().map(Tag::from);
+        let filter = match rule.filter.tags.len() {
+            0 => LifecycleFilter {
+                prefix: Some(prefix),
+                ..Default::default()
+            },
+            1 if prefix.is_empty() => LifecycleFilter {
+                tag: tags.next(),
+                ..Default::default()
+            },
+            _ => LifecycleFilter {
+                and: Some(LifecycleAnd {
+                    prefix: Some(prefix).filter(|prefix| !prefix.is_empty()),
+                    tags: tags.collect(),
+                }),
+                ..Default::default()
+            },
+        };
+        Self {
+            id: Some(rule.id.clone()),
+            filter: Some(filter),
+            prefix: None,
+            status: if rule.enabled { "Enabled" } else { "Disabled" }.into(),
+            expiration: rule.expiration_days.map(|days| Expiration {
+                days: Some(days),
+                date: None,
+                expired_object_delete_marker: None,
+            }),
+            noncurrent_version_expiration: rule
+                .noncurrent_expiration_days
+                .map(|noncurrent_days| NoncurrentVersionExpiration { noncurrent_days }),
+            abort_incomplete_multipart_upload: rule.abort_incomplete_upload_days.map(
+                |days_after_initiation| AbortIncompleteMultipartUpload {
+                    days_after_initiation,
+                },
+            ),
+        }
+    }
+}
+
+/// Replaces the lifecycle rules of a bucket, via `PUT /{bucket}?lifecycle`.
+///
+/// Rules filtering on tags match the object versions carrying all of the tags.
+/// `AbortIncompleteMultipartUpload` releases the keys reserved by `?allocate` and signed `PUT`
+/// URLs which were not uploaded to in time.
+fn put_bucket_lifecycle(state: &AppState, name: &str, body: &[u8]) -> Response<Body> {
+    let Ok(body) = std::str::from_utf8(body) else {
+        return malformed_xml();
+    };
+    let Ok(configuration) = quick_xml::de::from_str::<LifecycleConfiguration>(body) else {
+        return malformed_xml();
+    };
+    if configuration.rule.is_empty() || configuration.rule.len() > MAX_LIFECYCLE_RULES {
+        return malformed_xml();
+    }
+
+    let mut rules: Vec<lifecycle::Rule> = Vec::with_capacity(configuration.rule.len());
+    for rule in configuration.rule {
+        let rule = match rule.parse() {
+            Ok(rule) => rule,
+            Err(response) => return response,
+        };
+        if rules.iter().any(|other| other.id == rule.id) {
+            return s3_error(
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                "Rule ID must be unique. Found same ID for more than one rule",
+            );
+        }
+        rules.push(rule);
+    }
+
+    match state.filestore.set_lifecycle(name, rules) {
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(err) => store_error(err),
+    }
+}
+
+/// The lifecycle rules of a bucket, via `GET /{bucket}?lifecycle`.
+fn get_bucket_lifecycle(bucket: &bucket::Bucket) -> Response<Body> {
+    if bucket.settings.lifecycle.is_empty() {
+        return s3_error(
+            StatusCode::NOT_FOUND,
+            "NoSuchLifecycleConfiguration",
+            "The lifecycle configuration does not exist",
+        );
+    }
+    let result = LifecycleConfigurationResult {
+        xmlns: "http://s3.amazonaws.com/doc/2006-03-01/",
+        rules: bucket.settings.lifecycle.iter().map(Into::into).collect(),
+    };
+    let body = quick_xml::se::to_string(&result).unwrap();
+    let body = format!(r#"<?xml version="1.0" encoding="UTF-8"?>{body}"#);
+    ([("Content-Type", "application/xml")], body).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Retention {
+    mode: Option<file::RetentionMode>,
+    retain_until_date: Option<String>,
+}
+
+/// Sets the retention of an object version, via `PUT /{bucket}/{key}?retention`, with an
+/// empty `<Retention />` removing it.
+///
+/// Retention can only be extended, unless it is governance retention which is bypassed with
+/// the `x-amz-bypass-governance-retention` header.
+fn put_object_retention(
+    bucket: &bucket::Bucket,
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+    bypass_governance: bool,
+    body: &[u8],
+) -> Response<Body> {
+    if !bucket.settings.object_lock {
+        return invalid_request("Bucket is missing Object Lock Configuration");
+    }
+    let Ok(body) = std::str::from_utf8(body) else {
+        return malformed_xml();
+    };
+    let Ok(retention) = quick_xml::de::from_str::<Retention>(body) else {
+        return malformed_xml();
+    };
+    let retention = match (retention.mode, retention.retain_until_date) {
+        (None, None) => None,
+        (Some(mode), Some(retain_until)) => {
+            let Ok(retain_until) = DateTime::parse_from_rfc3339(&retain_until) else {
+                return malformed_xml();
+            };
+            let retain_until = retain_until.timestamp().max(0) as u64;
+            Some(file::Retention { mode, retain_until })
+        }
+        _ => return malformed_xml(),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if retention.is_some_and(|retention| retention.retain_until <= now) {
+        return s3_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidArgument",
+            "The retain until date must be in the future",
+        );
+    }
+    update_lock(filestore, key, version_id, |lock| {
+        lock.with_retention(retention, now, bypass_governance)
+    })
+}
+
+/// The retention of an object version, via `GET /{bucket}/{key}?retention`.
+fn get_object_retention(
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+) -> Response<Body> {
+    let Some(lock) = object_lock(filestore, key, version_id) else {
+        return no_such_object(version_id);
+    };
+    let Some(retention) = lock.retention else {
+        return s3_error(
+            StatusCode::NOT_FOUND,
+            "NoSuchObjectLockConfiguration",
+            "The specified object does not have a ObjectLock configuration",
+        );
+    };
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><Retention xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Mode>{}</Mode><RetainUntilDate>{}</RetainUntilDate></Retention>"#,
+        retention_mode(retention.mode),
+        iso8601(retention.retain_until)
+    );
+    ([("Content-Type", "application/xml")], body).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LegalHold {
+    status: String,
+}
+
+/// Places or lifts the legal hold of an object version, via `PUT /{bucket}/{key}?legal-hold`.
+fn put_object_legal_hold(
+    bucket: &bucket::Bucket,
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+    body: &[u8],
+) -> Response<Body> {
+    if !bucket.settings.object_lock {
+        return invalid_request("Bucket is missing Object Lock Configuration");
+    }
+    let Ok(body) = std::str::from_utf8(body) else {
+        return malformed_xml();
+    };
+    let Ok(legal_hold) = quick_xml::de::from_str::<LegalHold>(body) else {
+        return malformed_xml();
+    };
+    let legal_hold = match legal_hold.status.as_str() {
+        "ON" => true,
+        "OFF" => false,
+        _ => return malformed_xml(),
+    };
+    update_lock(filestore, key, version_id, |lock| {
+        Ok(file::Lock { legal_hold, ..lock })
+    })
+}
+
+/// The legal hold of an object version, via `GET /{bucket}/{key}?legal-hold`.
+fn get_object_legal_hold(
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+) -> Response<Body> {
+    let Some(lock) = object_lock(filestore, key, version_id) else {
+        return no_such_object(version_id);
+    };
+    let status = if lock.legal_hold { "ON" } else { "OFF" };
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><LegalHold xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Status>{status}</Status></LegalHold>"#
+    );
+    ([("Content-Type", "application/xml")], body).into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Tagging {
+    tag_set: TagSet,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Tagging")]
+struct TaggingResult {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'static str,
+    #[serde(rename = "TagSet")]
+    tag_set: TagSet,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TagSet {
+    #[serde(default, rename = "Tag")]
+    tags: Vec<Tag>,
+}
+
+/// Replaces the tags of an object version, via `PUT /{bucket}/{key}?tagging`.
+fn put_object_tagging(
+    bucket: &bucket::Bucket,
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+    body: &[u8],
+) -> Response<Body> {
+    let Ok(body) = std::str::from_utf8(body) else {
+        return malformed_xml();
+    };
+    let Ok(tagging) = quick_xml::de::from_str::<Tagging>(body) else {
+        return malformed_xml();
+    };
+    let tags: Vec<file::Tag> = tagging.tag_set.tags.into_iter().map(Into::into).collect();
+    if !file::are_valid_tags(&tags) {
+        return invalid_tag();
+    }
+    match set_object_tags(bucket, filestore, key, version_id, tags) {
+        Ok(headers) => headers.into_response(),
+        Err(response) => response,
+    }
+}
+
+/// The tags of an object version, via `GET /{bucket}/{key}?tagging`.
+fn get_object_tagging(
+    bucket: &bucket::Bucket,
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+) -> Response<Body> {
+    let (version_id, tags) = match version_id {
+        Some(version_id) => match filestore.get_version(key, version_id) {
+            Some(version) if version.file_id.is_some() => (version_id, version.tags),
+            _ => return no_such_version(),
+        },
+        None => match filestore.get_named_file(key) {
+            Some(named_file) => (named_file.version_id, named_file.tags),
+            None => return no_such_key(),
+        },
+    };
+    let result = TaggingResult {
+        xmlns: "http://s3.amazonaws.com/doc/2006-03-01/",
+        tag_set: TagSet {
+            tags: tags.into_iter().map(Into::into).collect(),
+        },
+    };
+    let body = quick_xml::se::to_string(&result).unwrap();
+    let body = format!(r#"<?xml version="1.0" encoding="UTF-8"?>{body}"#);
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+    if bucket.settings.versioned {
+        insert_version_id(&mut headers, version_id);
+    }
+    (headers, body).into_response()
+}
+
+/// Replaces the tags of the given version of the object, or of its current version, returning
+/// the headers naming the version.
+// the error is responded right away, so its size does not matter
+#[allow(clippy::result_large_err)]
+fn set_object_tags(
+    bucket: &bucket::Bucket,
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+    tags: Vec<file::Tag>,
+) -> Result<HeaderMap, Response<Body>> {
+    let version_id = match version_id {
+        Some(version_id) => version_id,
+        None => match filestore.get_named_file(key) {
+            Some(named_file) => named_file.version_id,
+            None => return Err(no_such_key()),
+        },
+    };
+    if !filestore.set_tags(key, version_id, tags) {
+        return Err(no_such_version());
+    }
+    let mut headers = HeaderMap::new();
+    if bucket.settings.versioned {
+        insert_version_id(&mut headers, version_id);
+    }
+    Ok(headers)
+}
+
+/// The tags of the `x-amz-tagging` header, which are URL query encoded, as in `k1=v1&k2=v2`.
+// the error is responded right away, so its size does not matter
+#[allow(clippy::result_large_err)]
+fn parse_tagging_header(headers: &HeaderMap) -> Result<Vec<file::Tag>, Response<Body>> {
+    let Some(tagging) = headers.get(TAGGING) else {
+        return Ok(vec![]);
+    };
+    let tagging = tagging.to_str().map_err(|_| invalid_tag())?;
+    let decode = |s: &str| String::from_utf8(sigv4::percent_decode(s)).map_err(|_| invalid_tag());
+    let tags = tagging
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok(file::Tag {
+                key: decode(key)?,
+                value: decode(value)?,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if !file::are_valid_tags(&tags) {
+        return Err(invalid_tag());
+    }
+    Ok(tags)
+}
+
+/// Updates the lock of the given version of the object, or of its current version.
+fn update_lock(
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+    update: impl Fn(file::Lock) -> Result<file::Lock, Error>,
+) -> Response<Body> {
+    let version_id = match version_id {
+        Some(version_id) => version_id,
+        None => match filestore.get_named_file(key) {
+            Some(named_file) => named_file.version_id,
+            None => return no_such_key(),
+        },
+    };
+    match filestore.update_lock(key, version_id, update) {
+        Ok(Some(_)) => StatusCode::OK.into_response(),
+        Ok(None) => no_such_version(),
+        Err(err) => store_error(err),
+    }
+}
+
+/// The lock of the given version of the object, or of its current version.
+fn object_lock(
+    filestore: &NamespacedFileStore<'_>,
+    key: &str,
+    version_id: Option<file::VersionId>,
+) -> Option<file::Lock> {
+    let Some(version_id) = version_id else {
+        return Some(filestore.get_named_file(key)?.lock);
+    };
+    let version = filestore.get_version(key, version_id)?;
+    version.file_id.map(|_| version.lock)
+}
+
+/// The error for a missing object, or for a missing version of it.
+fn no_such_object(version_id: Option<file::VersionId>) -> Response<Body> {
+    match version_id {
+        Some(_) => no_such_version(),
+        None => no_such_key(),
+    }
+}
+
+fn retention_mode(mode: file::RetentionMode) -> &'static str {
+    match mode {
+        file::RetentionMode::Governance => "GOVERNANCE",
+        file::RetentionMode::Compliance => "COMPLIANCE",
+    }
+}
+
+/// Whether the request bypasses governance retention, with the
+/// `x-amz-bypass-governance-retention` header.
+fn bypasses_governance(headers: &HeaderMap) -> bool {
+    headers
+        .get(BYPASS_GOVERNANCE)
+        .is_some_and(|bypass| bypass.as_bytes().eq_ignore_ascii_case(b"true"))
+}
+
+#[derive(Deserialize)]
+struct ChunkList {
+    /// The hex-encoded chunk IDs.
+    chunks: Vec<String>,
+}
+
+impl ChunkList {
+    /// Parses the chunk list.
+    ///
+    /// On failure, returns the message of the `InvalidArgument` error.
+    fn parse(body: &[u8]) -> Result<Vec<chunk::ChunkId>, &'static str> {
+        let Ok(Json(list)) = Json::<Self>::from_bytes(body) else {
+            return Err("expected a JSON object with a `chunks` list");
+        };
+        list.chunks
+            .iter()
+            .map(|chunk| chunk.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| "invalid chunk id")
+    }
+}
+
+#[derive(Serialize)]
+struct MissingChunks {
+    missing: Vec<String>,
+}
+
+/// Lists which of the given chunks still need to be uploaded, via
+/// `POST /{bucket}?missing-chunks` with a `{"chunks": [...]}` body.
+fn find_missing_chunks(state: &AppState, bucket: &bucket::Bucket, body: &[u8]) -> Response<Body> {
+    let chunk_ids = match ChunkList::parse(body) {
+        Ok(chunk_ids) => chunk_ids,
+        Err(message) => return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", message),
+    };
+    let filestore = bucket_filestore(state, bucket);
+    let missing = filestore.find_missing_chunks(&chunk_ids);
+    Json(MissingChunks {
+        missing: missing.iter().map(ToString::to_string).collect(),
+    })
+    .into_response()
+}
+
+/// Uploads a single chunk ahead of assembling it into an object, via
+/// `POST /{bucket}?chunk={chunk_id}`.
+///
+/// The chunk is kept around for a while even if it is not assembled into an object.
+///
+/// Chunks sent with `Content-Encoding: zstd` are only decompressed to verify them, and stored
+/// as they were sent, rather than compressed again.
+fn upload_chunk(
+    state: &AppState,
+    headers: &HeaderMap,
+    bucket: &bucket::Bucket,
+    chunk_id: chunk::ChunkId,
+    body: Bytes,
+) -> Response<Body> {
+    let filestore = bucket_filestore(state, bucket);
+    let chunk_size = filestore.config().chunk_size;
+    let zstd = headers
+        .get(CONTENT_ENCODING)
+        .is_some_and(|encoding| encoding == "zstd");
+    let contents = if zstd {
+        match zstd::bulk::decompress(&body, chunk_size as usize) {
+            Ok(contents) => contents.into(),
+            Err(_) => {
+                return s3_error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidArgument",
+                    "Invalid zstd encoded chunk, or it exceeds the maximum chunk size",
+                )
+            }
+        }
+    } else {
+        match decode_content_encoding(headers, body.clone(), Some(chunk_size)) {
+            Ok(contents) => contents,
+            Err(ContentEncodingError::Invalid(message)) => {
+                return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", message)
+            }
+            Err(ContentEncodingError::TooLarge) => return chunk_too_large(),
+        }
+    };
+    if contents.len() as u64 > chunk_size {
+        return chunk_too_large();
+    }
+    if chunk::ChunkId::from_contents(chunk_id.0.hash_algorithm, &contents) != chunk_id {
+        return s3_error(
+            StatusCode::BAD_REQUEST,
+            "BadDigest",
+            "The chunk does not match its ID.",
+        );
+    }
+    if zstd {
+        filestore.upload_pending_chunk_zstd(chunk_id, contents.len() as u32, &body);
+    } else {
+        filestore.upload_pending_chunk(chunk_id, &contents);
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Creates an object from already uploaded chunks, via `POST /{bucket}/{key}?assemble` with
+/// a `{"chunks": [...]}` body.
+///
+/// Fails with `MissingChunks` if any of the chunks has not been uploaded, in which case the
+/// missing ones have to be uploaded before retrying.
+fn assemble_object(
+    state: &AppState,
+    headers: &HeaderMap,
+    bucket: &bucket::Bucket,
+    key: &str,
+    body: &[u8],
+) -> Response<Body> {
+    let chunk_ids = match ChunkList::parse(body) {
+        Ok(chunk_ids) => chunk_ids,
+        Err(message) => return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", message),
+    };
+    let filestore = bucket_filestore(state, bucket);
+    let preconditions = write_preconditions(headers);
+    if fails_early(&filestore, &preconditions, key) {
+        return store_error(Error::PreconditionFailed);
+    }
+    let tags = match parse_tagging_header(headers) {
+        Ok(tags) => tags,
+        Err(response) => return response,
+    };
+
+    let file_id = match filestore.assemble_file(&chunk_ids) {
+        Ok(file_id) => file_id,
+        Err(err) => return store_error(err),
+    };
+    if exceeds_max_object_size(&filestore, filestore.get_file(file_id).size) {
+        filestore.discard_file(file_id);
+        return entity_too_large();
+    }
+    let named_file =
+        match filestore.associate_tagged_filename_if(file_id, key, &preconditions, tags) {
+            Ok(named_file) => named_file,
+            Err(err) => {
+                filestore.discard_file(file_id);
+                return store_error(err);
+            }
+        };
+
+    written_headers(bucket, &named_file).into_response()
+}
+
+#[derive(Serialize)]
+struct AllocateResponse {
+    id: String,
+}
+
+/// Allocates a new object key and reserves it for a later upload, via
+/// `POST /{bucket}?allocate[=uuid|compact]`.
+fn allocate_object_id(state: &AppState, bucket: &bucket::Bucket, query: &str) -> Response<Body> {
+    let format = match query_param(query, "allocate") {
+        Some("") | None => file::IdFormat::default(),
+        Some(format) => match file::IdFormat::parse(format) {
+            Some(format) => format,
+            None => {
+                return s3_error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidArgument",
+                    "allocate needs to be either uuid or compact",
+                )
+            }
+        },
+    };
+    let filestore = bucket_filestore(state, bucket);
+    Json(AllocateResponse {
+        id: filestore.allocate_filename(format),
+    })
+    .into_response()
+}
+
+#[derive(Serialize)]
+struct PresignResponse {
+    /// The object key, which was allocated by the server when none was given.
+    id: String,
+    url: String,
+    /// The expiration time of the URL, in seconds since the unix epoch.
+    expires: u64,
+}
+
+/// Issues a signed URL, via `POST /{bucket}/{key}?presign=GET|PUT&ttl={seconds}`.
+///
+/// For `PUT`, the key is reserved until the upload, and can be omitted, in which case a new
+/// one is allocated.
+fn presign(
+    state: &AppState,
+    headers: &HeaderMap,
+    bucket: &bucket::Bucket,
+    key: Option<&str>,
+    query: &str,
+    now: SystemTime,
+) -> Response<Body> {
+    let method = match query_param(query, "presign") {
+        Some("GET") => Method::GET,
+        Some("PUT") => Method::PUT,
+        _ => {
+            return s3_error(
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                "presign needs to be either GET or PUT",
+            )
+        }
+    };
+    let ttl = match query_param(query, "ttl").map(str::parse) {
+        None => signed_url::DEFAULT_TTL,
+        Some(Ok(ttl)) => Duration::from_secs(ttl).min(signed_url::MAX_TTL),
+        Some(Err(_)) => return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", "invalid ttl"),
+    };
+    let Some(base_url) = request_base_url(state, headers) else {
+        return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", "missing Host");
+    };
+    let filestore = bucket_filestore(state, bucket);
+    let key = match key.filter(|key| !key.is_empty()) {
+        Some(key) => {
+            if method == Method::PUT {
+                filestore.reserve_filename(key);
+            }
+            key.to_owned()
+        }
+        None if method == Method::PUT => filestore.allocate_filename(file::IdFormat::Uuid),
+        None => return s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", "missing key"),
+    };
+
+    let expires = now + ttl;
+    let signature = state
+        .url_signer
+        .sign(&method, bucket.namespace.0, &key, expires);
+    let encoded_key = sigv4::uri_encode(key.as_bytes(), false);
+    let url = format!("{base_url}/{}/{encoded_key}?{signature}", bucket.name);
+
+    Json(PresignResponse {
+        id: key,
+        url,
+        expires: expires.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+    })
+    .into_response()
+}
+
+/// The URL the client reached the S3 API at, without a trailing `/`.
+///
+/// This is the configured `public_url`, or otherwise derived from the `Host` and the
+/// `X-Forwarded-Proto` set by a proxy terminating TLS.
+fn request_base_url(state: &AppState, headers: &HeaderMap) -> Option<String> {
+    if state.config.public_url.is_some() {
+        return Some(state.config.public_url());
+    }
+    let host = headers.get(HOST)?.to_str().ok()?;
+    let scheme = match headers.get("x-forwarded-proto").map(HeaderValue::as_bytes) {
+        Some(b"https") => "https",
+        _ => "http",
+    };
+    Some(format!("{scheme}://{host}"))
+}
+
+/// The response headers of a write, with the version of the object in versioned buckets.
+fn written_headers(bucket: &bucket::Bucket, named_file: &file::NamedFile) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(ETAG, etag(named_file.file_id));
+    if bucket.settings.versioned {
+        insert_version_id(&mut headers, named_file.version_id);
+    }
+    headers
+}
+
+/// The `x-amz-object-lock-*` headers describing the lock of an object version.
+fn insert_lock_headers(headers: &mut HeaderMap, lock: file::Lock) {
+    if let Some(retention) = lock.retention {
+        let mode = HeaderValue::from_static(retention_mode(retention.mode));
+        headers.insert("x-amz-object-lock-mode", mode);
+        let retain_until = HeaderValue::try_from(iso8601(retention.retain_until)).unwrap();
+        headers.insert("x-amz-object-lock-retain-until-date", retain_until);
+    }
+    let legal_hold = HeaderValue::from_static(if lock.legal_hold { "ON" } else { "OFF" });
+    headers.insert("x-amz-object-lock-legal-hold", legal_hold);
+}
+
+fn insert_version_id(headers: &mut HeaderMap, version_id: file::VersionId) {
+    headers.insert(VERSION_ID, version_header(version_id));
+}
+
+fn version_header(version_id: file::VersionId) -> HeaderValue {
+    HeaderValue::try_from(version_id.to_string()).unwrap()
+}
+
+fn etag(file_id: file::FileId) -> HeaderValue {
+    let etag = format!("\"{file_id}\"");
+    HeaderValue::try_from(etag).unwrap()
+}
+
+/// Formats the timestamp like the dates in S3 XML responses.
+fn iso8601(timestamp: u64) -> String {
+    let date = DateTime::from_timestamp(timestamp as i64, 0).unwrap();
+    date.format("%Y-%m-%dT%H:%M:%S.000Z").to_string()
+}
+
+fn http_date(timestamp: u64) -> HeaderValue {
+    let date = DateTime::from_timestamp(timestamp as i64, 0).unwrap();
+    let date = date.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    HeaderValue::try_from(date).unwrap()
+}
+
+/// Parses an HTTP date header, as seconds since the unix epoch.
+fn parse_http_date(headers: &HeaderMap, name: HeaderName) -> Option<u64> {
+    let date = headers.get(name)?.to_str().ok()?;
+    let date = DateTime::parse_from_rfc2822(date).ok()?;
+    date.timestamp().try_into().ok()
+}
+
+/// Parses the list of ETags of an `If-Match` or `If-None-Match` header.
+///
+/// ETags which do not refer to any file can never match, and weak ETags are compared
+/// like strong ones.
+fn parse_file_match(headers: &HeaderMap, name: HeaderName) -> Option<file::FileMatch> {
+    let etags = headers.get(name)?.to_str().unwrap_or_default();
+    if etags.trim() == "*" {
+        return Some(file::FileMatch::Any);
+    }
+    let files = etags
+        .split(',')
+        .filter_map(|etag| {
+            let etag = etag.trim();
+            let etag = etag.strip_prefix("W/").unwrap_or(etag);
+            let hash = etag.strip_prefix('"')?.strip_suffix('"')?;
+            hash.parse().ok()
+        })
+        .collect();
+    Some(file::FileMatch::Files(files))
+}
+
+fn write_preconditions(headers: &HeaderMap) -> file::Preconditions {
+    file::Preconditions {
+        if_match: parse_file_match(headers, IF_MATCH),
+        if_none_match: parse_file_match(headers, IF_NONE_MATCH),
+        idempotent: headers
+            .get("x-kycok-idempotent")
+            .is_some_and(|idempotent| idempotent == "true"),
+    }
+}
+
+/// Evaluates the conditional headers of a `GET` or `HEAD` request, returning the status
+/// code to respond with instead of the file contents, if any.
+///
+/// As per RFC 9110, the date based conditions are only evaluated in the absence of the
+/// corresponding ETag based ones. Files read by their ID have no `last_modified` date, so
+/// only the ETag based conditions apply to them.
+fn check_read_preconditions(
+    headers: &HeaderMap,
+    file_id: file::FileId,
+    last_modified: Option<u64>,
+) -> Option<StatusCode> {
+    let current = Some(file_id);
+
+    let precondition_failed = match parse_file_match(headers, IF_MATCH) {
+        Some(files) => !files.matches(current),
+        None => parse_http_date(headers, IF_UNMODIFIED_SINCE)
+            .zip(last_modified)
+            .is_some_and(|(since, last_modified)| last_modified > since),
+    };
+    if precondition_failed {
+        return Some(StatusCode::PRECONDITION_FAILED);
+    }
+
+    let not_modified = match parse_file_match(headers, IF_NONE_MATCH) {
+        Some(files) => files.matches(current),
+        None => parse_http_date(headers, IF_MODIFIED_SINCE)
+            .zip(last_modified)
+            .is_some_and(|(since, last_modified)| last_modified <= since),
+    };
+    not_modified.then_some(StatusCode::NOT_MODIFIED)
+}
+
+/// Parses the `x-amz-copy-source` header, which is the URL-encoded `{bucket}/{key}`,
+/// optionally with a leading `/` and a trailing `?versionId=`.
+fn parse_copy_source(
+    copy_source: &HeaderValue,
+) -> Option<(String, String, Option<file::VersionId>)> {
+    let copy_source = copy_source.to_str().ok()?;
+    let (copy_source, version_id) = match copy_source.split_once('?') {
+        Some((path, query)) => (path, Some(query_param(query, "versionId")?.parse().ok()?)),
+        None => (copy_source, None),
+    };
+    let copy_source = String::from_utf8(sigv4::percent_decode(copy_source)).ok()?;
+
+    let copy_source = copy_source.strip_prefix('/').unwrap_or(&copy_source);
+    let (bucket, key) = copy_source.split_once('/')?;
+    Some((bucket.to_owned(), key.to_owned(), version_id))
+}
+
+fn query_param<'q>(query: &'q str, name: &str) -> Option<&'q str> {
+    query
+        .split('&')
+        .find_map(|pair| match pair.split_once('=') {
+            Some((key, value)) if key == name => Some(value),
+            None if pair == name => Some(""),
+            _ => None,
+        })
+}
+
+/// Reads the whole request body, decoding `aws-chunked` payloads and verifying
+/// the payload signature along the way.
+///
+/// A body which cannot be read completely, e.g. as the client disconnected, fails with
+/// `IncompleteBody`, and one whose decoded contents exceed `max_size` with `EntityTooLarge`,
+/// as soon as that is announced or read.
+async fn read_body(
+    headers: &HeaderMap,
+    verified: Option<&VerifiedRequest<'_>>,
+    body: Body,
+    max_size: Option<u64>,
+) -> Result<Bytes, Response<Body>> {
+    let max_size = max_size.unwrap_or(u64::MAX);
+    let announced_len = headers
+        .get("x-amz-decoded-content-length")
+        .or_else(|| headers.get(CONTENT_LENGTH))
+        .and_then(|len| len.to_str().ok()?.parse::<u64>().ok());
+    if announced_len.is_some_and(|len| len > max_size) {
+        return Err(entity_too_large());
+    }
+
+    let streaming_signature = verified.and_then(|verified| verified.streaming_signature.clone());
+    let decode_options =
+        DecodeOptions::from_headers(headers, streaming_signature).map_err(decode_error)?;
+    let verify_payload = verified.filter(|_| decode_options.is_none());
+
+    // chunks are decoded as they arrive, so that an invalid chunk signature fails the
+    // upload before the rest of it is read
+    let data = body.into_data_stream();
+    let mut data = pin!(match decode_options {
+        Some(decode_options) => aws_chunked::decode(data, decode_options).left_stream(),
+        None => data
+            .map(|data| data.map_err(|err| DecodeError::Body(err.into())))
+            .right_stream(),
+    });
+    let mut contents = BytesMut::new();
+    while let Some(data) = data.next().await {
+        contents.extend_from_slice(&data.map_err(decode_error)?);
+        if contents.len() as u64 > max_size {
+            return Err(entity_too_large());
+        }
+    }
+    let bytes = contents.freeze();
+
+    if let Some(verified) = verify_payload {
+        verified.verify_payload(&bytes).map_err(auth_error)?;
+    }
+    Ok(bytes)
+}
+
+/// Undoes the `Content-Encoding` of an uploaded body, as we always store and hash the
+/// plain contents.
+///
+/// On failure, returns the message of the `InvalidArgument` error.
+fn decode_content_encoding(
+    headers: &HeaderMap,
+    mut bytes: Bytes,
+    max_size: Option<u64>,
+) -> Result<Bytes, ContentEncodingError> {
+    let Some(encodings) = headers.get(CONTENT_ENCODING) else {
+        return Ok(bytes);
+    };
+    let unsupported = || ContentEncodingError::Invalid("Unsupported Content-Encoding");
+    let encodings = encodings.to_str().map_err(|_| unsupported())?;
+
+    // encodings are listed in the order they were applied, so undo them in reverse
+    for encoding in encodings.split(',').rev() {
+        match encoding.trim() {
+            // the `aws-chunked` framing is already removed by `read_body`
+            "" | "identity" | "aws-chunked" => {}
+            "zstd" => bytes = decode_zstd(&bytes, max_size)?,
+            _ => return Err(unsupported()),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decompresses a `zstd` encoded body, giving up as soon as it grows beyond `max_size`, so
+/// that a tiny body cannot inflate to an arbitrary amount of memory.
+fn decode_zstd(bytes: &[u8], max_size: Option<u64>) -> Result<Bytes, ContentEncodingError> {
+    let invalid = |_| ContentEncodingError::Invalid("Invalid zstd encoded body");
+    let limit = max_size.map_or(u64::MAX, |max_size| max_size.saturating_add(1));
+    let mut decoded = Vec::new();
+    zstd::Decoder::new(bytes)
+        .map_err(invalid)?
+        .take(limit)
+        .read_to_end(&mut decoded)
+        .map_err(invalid)?;
+    if max_size.is_some_and(|max_size| decoded.len() as u64 > max_size) {
+        return Err(ContentEncodingError::TooLarge);
+    }
+    Ok(decoded.into())
+}
+
+/// Why a body could not be decoded according to its `Content-Encoding`.
+enum ContentEncodingError {
+    Invalid(&'static str),
+    TooLarge,
+}
+
+/// Whether the `Accept-Encoding` header allows a `zstd` response.
+fn accepts_zstd(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut params = encoding.split(';');
+            let is_zstd = params.next().unwrap().trim() == "zstd";
+            let is_rejected = params.any(|param| {
+                let quality = param.trim().strip_prefix("q=");
+                quality.and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+            });
+            is_zstd && !is_rejected
+        })
+}
+
+/// Streams the file contents chunk by chunk, either as plain bytes, or as a stream of zstd
+/// frames, which reuses chunks already stored with zstd compression.
+fn file_body(state: AppStateRef, bucket: bucket::Bucket, file: file::File, zstd: bool) -> Body {
+    // the body is streamed after the handler returned, outside of the request span
+    let span = tracing::Span::current();
+    let stream = async_stream::stream! {
+        let filestore = bucket_filestore(&state, &bucket);
+        match file.contents {
+            file::FileContents::Inline(contents) if zstd => {
+                let compressed = zstd::bulk::compress(&contents, zstd::DEFAULT_COMPRESSION_LEVEL);
+                yield compressed;
+            }
+            file::FileContents::Inline(contents) => yield Ok(contents),
+            file::FileContents::Chunked(chunks) => {
+                for chunk in chunks {
+                    let contents = span.in_scope(|| if zstd {
+                        filestore.read_chunk_zstd(chunk.chunk_id)
+                    } else {
+                        filestore.read_chunk(chunk.chunk_id)
+                    });
+                    yield Ok(contents);
+                }
+            }
+        }
+    };
+    Body::from_stream(stream)
+}
+
+fn decode_error(err: DecodeError) -> Response<Body> {
+    s3_error(err.status_code(), err.code(), &err.to_string())
+}
+
+fn auth_error(err: AuthError) -> Response<Body> {
+    s3_error(err.status_code(), err.code(), &err.to_string())
+}
+
+fn store_error(err: Error) -> Response<Body> {
+    let (status, code, message) = match err {
+        Error::QuotaExceeded(limit) => {
+            let message = format!("The scope exceeds its quota of {limit}");
+            return s3_error(StatusCode::FORBIDDEN, "QuotaExceeded", &message);
+        }
+        Error::MissingChunks(missing) => {
+            let message = format!("{} of the chunks are not stored", missing.len());
+            return s3_error(StatusCode::BAD_REQUEST, "MissingChunks", &message);
+        }
+        Error::PreconditionFailed => (
+            StatusCode::PRECONDITION_FAILED,
+            "PreconditionFailed",
+            "At least one of the pre-conditions you specified did not hold",
+        ),
+        Error::NoSuchBucket => (
+            StatusCode::NOT_FOUND,
+            "NoSuchBucket",
+            "The specified bucket does not exist",
+        ),
+        Error::BucketAlreadyExists => (
+            StatusCode::CONFLICT,
+            "BucketAlreadyExists",
+            "The requested bucket name is not available",
+        ),
+        Error::BucketNotEmpty => (
+            StatusCode::CONFLICT,
+            "BucketNotEmpty",
+            "The bucket you tried to delete is not empty",
+        ),
+        Error::ObjectLocked => (
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            "Access Denied because object protected by object lock",
+        ),
+        Error::DedupScopeChanged => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "InternalError",
+            "We encountered an internal error. Please try again.",
+        ),
+    };
+    s3_error(status, code, message)
+}
+
+fn method_not_allowed() -> Response<Body> {
+    s3_error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "MethodNotAllowed",
+        "The specified method is not allowed against this resource",
+    )
+}
+
+fn entity_too_large() -> Response<Body> {
+    s3_error(
+        StatusCode::BAD_REQUEST,
+        "EntityTooLarge",
+        "Your proposed upload exceeds the maximum allowed object size.",
+    )
+}
+
+fn chunk_too_large() -> Response<Body> {
+    s3_error(
+        StatusCode::BAD_REQUEST,
+        "EntityTooLarge",
+        "The chunk exceeds the maximum chunk size.",
+    )
+}
+
+fn no_such_key() -> Response<Body> {
+    s3_error(
+        StatusCode::NOT_FOUND,
+        "NoSuchKey",
+        "The specified key does not exist.",
+    )
+}
+
+fn no_such_version() -> Response<Body> {
+    s3_error(
+        StatusCode::NOT_FOUND,
+        "NoSuchVersion",
+        "The specified version does not exist.",
+    )
+}
+
+fn invalid_tag() -> Response<Body> {
+    s3_error(
+        StatusCode::BAD_REQUEST,
+        "InvalidTag",
+        "The tag provided was not a valid tag",
+    )
+}
+
+fn invalid_version_id() -> Response<Body> {
+    s3_error(
+        StatusCode::BAD_REQUEST,
+        "InvalidArgument",
+        "Invalid version id specified",
+    )
+}
+
+fn invalid_request(message: &str) -> Response<Body> {
+    s3_error(StatusCode::BAD_REQUEST, "InvalidRequest", message)
+}
+
+fn malformed_xml() -> Response<Body> {
+    s3_error(
+        StatusCode::BAD_REQUEST,
+        "MalformedXML",
+        "The XML you provided was not well-formed or did not validate against our published schema",
+    )
+}
+
+fn s3_error(status: StatusCode, code: &str, message: &str) -> Response<Body> {
+    // messages may contain keys or other parts of the request, which need escaping
+    let message = quick_xml::escape::escape(message);
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>{code}</Code><Message>{message}</Message></Error>"#
+    );
+    (status, [("Content-Type", "application/xml")], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use axum::http::request::Builder;
+
+    use super::*;
+
+    /// The state of a server with the `first` and `second` buckets.
+    fn state() -> AppStateRef {
+        let config = ServerConfig {
+            backend: Backend::Mem,
+            ..Default::default()
+        };
+        let state = AppStateRef::new(AppState::new(config).unwrap());
+        for bucket in ["first", "second"] {
+            let settings = Default::default();
+            let created = state
+                .filestore
+                .create_bucket(bucket, "default", bucket, settings);
+            created.unwrap();
+        }
+        state
+    }
+
+    fn request(method: Method, uri: &str) -> Builder {
+        Request::builder().method(method).uri(uri)
+    }
+
+    async fn send(state: &AppStateRef, request: Request) -> Response<Body> {
+        app(State(state.clone()), request).await
+    }
+
+    async fn text(response: Response<Body>) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chunked_upload() {
+        let state = state();
+        let chunked = |body: &'static str| {
+            request(Method::PUT, "/first/chunked")
+                .header("x-amz-content-sha256", "STREAMING-UNSIGNED-PAYLOAD-TRAILER")
+                .header("content-encoding", "aws-chunked")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = send(&state, chunked("5\r\nhello\r\n0\r\n\r\n")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(
+            &state,
+            request(Method::GET, "/first/chunked")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(text(response).await, "hello");
+
+        // the framing ends in the middle of a chunk
+        let response = send(&state, chunked("9\r\ntrunc")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error = text(response).await;
+        assert!(error.contains("<Code>IncompleteBody</Code>"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_body() {
+        let state = state();
+        // e.g. the client disconnecting in the middle of the upload
+        let data: [Result<_, std::io::Error>; 2] = [
+            Ok(Bytes::from("some contents")),
+            Err(std::io::ErrorKind::ConnectionReset.into()),
+        ];
+        let upload = request(Method::PUT, "/first/object")
+            .body(Body::from_stream(futures_util::stream::iter(data)))
+            .unwrap();
+        let response = send(&state, upload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_code(response).await, "IncompleteBody");
+
+        let get = request(Method::GET, "/first/object")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&state, get).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_zstd_negotiation() {
+        let state = state();
+        let get = |accept_encoding: Option<&str>| {
+            let mut builder = request(Method::GET, "/first/object");
+            if let Some(accept_encoding) = accept_encoding {
+                builder = builder.header("accept-encoding", accept_encoding);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        // zstd uploads are stored as their plain contents
+        let contents = "contents compressed by the client, contents compressed by the client";
+        let compressed = zstd::bulk::compress(contents.as_bytes(), 3).unwrap();
+        let upload = request(Method::PUT, "/first/object")
+            .header("content-encoding", "zstd")
+            .body(Body::from(compressed))
+            .unwrap();
+        assert_eq!(send(&state, upload).await.status(), StatusCode::OK);
+
+        let response = send(&state, get(None)).await;
+        assert!(response.headers().get("content-encoding").is_none());
+        assert_eq!(response.headers()["vary"], "Accept-Encoding");
+        assert_eq!(text(response).await, contents);
+
+        let response = send(&state, get(Some("gzip, zstd"))).await;
+        assert_eq!(response.headers()["content-encoding"], "zstd");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(zstd::decode_all(&body[..]).unwrap(), contents.as_bytes());
+
+        // a zero quality rejects the encoding
+        let response = send(&state, get(Some("zstd;q=0"))).await;
+        assert!(response.headers().get("content-encoding").is_none());
+        assert_eq!(text(response).await, contents);
+
+        for encoding in ["br", "zstd"] {
+            let upload = request(Method::PUT, "/first/object")
+                .header("content-encoding", encoding)
+                .body(Body::from(contents))
+                .unwrap();
+            let response = send(&state, upload).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{encoding}");
+            assert!(text(response).await.contains("InvalidArgument"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zstd_decompression_limit() {
+        let config = ServerConfig {
+            backend: Backend::Mem,
+            storage: crate::new_datamodel::Config {
+                max_object_size: Some(1024 * 1024),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let state = AppStateRef::new(AppState::new(config).unwrap());
+        let create = request(Method::PUT, "/encoded")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&state, create).await.status(), StatusCode::OK);
+
+        // a small upload which decompresses beyond the maximum object size is rejected before
+        // it is decompressed completely
+        let bomb = zstd::bulk::compress(&vec![0; 64 * 1024 * 1024], 3).unwrap();
+        assert!(bomb.len() < 64 * 1024);
+        let upload = request(Method::PUT, "/encoded/bomb")
+            .header("content-encoding", "zstd")
+            .body(Body::from(bomb))
+            .unwrap();
+        let response = send(&state, upload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_code(response).await, "EntityTooLarge");
+
+        let get = request(Method::GET, "/encoded/bomb")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&state, get).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let config = ServerConfig {
+            backend: Backend::Mem,
+            storage: crate::new_datamodel::Config {
+                max_object_size: Some(16),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let state = AppStateRef::new(AppState::new(config).unwrap());
+        let create = request(Method::PUT, "/limited")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&state, create).await.status(), StatusCode::OK);
+
+        // the announced length is rejected before the body is read
+        let upload = request(Method::PUT, "/limited/announced")
+            .header("content-length", "1024")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&state, upload).await;
+        assert_eq!(error_code(response).await, "EntityTooLarge");
+
+        // and the body is not read any further once it exceeds the limit
+        let data: [Result<_, std::io::Error>; 2] = [
+            Ok(Bytes::from("contents beyond the limit")),
+            Err(std::io::ErrorKind::ConnectionReset.into()),
+        ];
+        let upload = request(Method::PUT, "/limited/streamed")
+            .body(Body::from_stream(futures_util::stream::iter(data)))
+            .unwrap();
+        let response = send(&state, upload).await;
+        assert_eq!(error_code(response).await, "EntityTooLarge");
+
+        let upload = request(Method::PUT, "/limited/small")
+            .body(Body::from("within the limit"))
+            .unwrap();
+        assert_eq!(send(&state, upload).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_copy_object() {
+        let state = state();
+        let copy = |target: &str, copy_source: &str| {
+            request(Method::PUT, target)
+                .header("x-amz-copy-source", copy_source)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let contents = "contents which are copied without being read";
+        let upload = request(Method::PUT, "/first/original")
+            .body(Body::from(contents))
+            .unwrap();
+        send(&state, upload).await;
+
+        for (copy_source, target) in [
+            ("first/original", "/first/copy"),
+            ("/first/original", "/second/copy"),
+        ] {
+            let response = send(&state, copy(target, copy_source)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let result = text(response).await;
+            assert!(result.contains("<CopyObjectResult>"), "{result}");
+            let response = send(
+                &state,
+                request(Method::GET, target).body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(text(response).await, contents);
+        }
+
+        // the copies share the file of the original
+        let resolve = |bucket, path| {
+            let bucket = state.filestore.get_bucket(bucket).unwrap();
+            FileStore::with_namespace(&state.filestore, bucket.namespace).resolve_filename(path)
+        };
+        let file_id = resolve("first", "original").unwrap();
+        assert_eq!(resolve("first", "copy"), Some(file_id));
+        assert_eq!(resolve("second", "copy"), Some(file_id));
+
+        // the copy is independent of the original
+        let delete = request(Method::DELETE, "/first/original")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&state, delete).await.status(), StatusCode::NO_CONTENT);
+        let get = request(Method::GET, "/second/copy")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(text(send(&state, get).await).await, contents);
+
+        for (copy_source, code) in [
+            ("first/original", "NoSuchKey"),
+            ("missing/original", "NoSuchBucket"),
+            ("first", "InvalidArgument"),
+        ] {
+            let response = send(&state, copy("/second/failed", copy_source)).await;
+            assert!(response.status().is_client_error());
+            let error = text(response).await;
+            assert!(error.contains(code), "{copy_source}: {error}");
+        }
+
+        // copies are written with the preconditions of the target, where an idempotent
+        // create-only copy of the same file is a retry
+        let upload = request(Method::PUT, "/first/other")
+            .body(Body::from("other contents"))
+            .unwrap();
+        send(&state, upload).await;
+        for (copy_source, idempotent, status) in [
+            ("first/copy", false, StatusCode::PRECONDITION_FAILED),
+            ("first/copy", true, StatusCode::OK),
+            ("first/other", true, StatusCode::PRECONDITION_FAILED),
+        ] {
+            let conditional = request(Method::PUT, "/second/copy")
+                .header("x-amz-copy-source", copy_source)
+                .header("if-none-match", "*")
+                .header("x-kycok-idempotent", idempotent.to_string())
+                .body(Body::empty())
+                .unwrap();
+            let response = send(&state, conditional).await;
+            assert_eq!(response.status(), status, "{copy_source} {idempotent}");
+        }
+    }
+
+    const PAST: &str = "Mon, 01 Jan 2001 00:00:00 GMT";
+
+    #[tokio::test]
+    async fn test_conditional_get() {
+        let state = state();
+        let upload = request(Method::PUT, "/first/object")
+            .body(Body::from("contents"))
+            .unwrap();
+        let response = send(&state, upload).await;
+        let etag = response.headers()["etag"].to_str().unwrap().to_owned();
+        let response = send(
+            &state,
+            request(Method::HEAD, "/first/object")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        let last_modified = response.headers()["last-modified"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let conditional = |method: Method, conditions: &[(&str, &str)]| {
+            let mut builder = request(method, "/first/object");
+            for (header, value) in conditions {
+                builder = builder.header(*header, *value);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let other = "\"0000000000000000000000000000000000000000000000000000000000000000\"";
+        for (header, value, status) in [
+            ("if-none-match", etag.as_str(), StatusCode::NOT_MODIFIED),
+            ("if-none-match", "*", StatusCode::NOT_MODIFIED),
+            ("if-none-match", other, StatusCode::OK),
+            ("if-match", etag.as_str(), StatusCode::OK),
+            ("if-match", other, StatusCode::PRECONDITION_FAILED),
+            (
+                "if-modified-since",
+                last_modified.as_str(),
+                StatusCode::NOT_MODIFIED,
+            ),
+            ("if-modified-since", PAST, StatusCode::OK),
+            (
+                "if-unmodified-since",
+                last_modified.as_str(),
+                StatusCode::OK,
+            ),
+            ("if-unmodified-since", PAST, StatusCode::PRECONDITION_FAILED),
+        ] {
+            for method in [Method::GET, Method::HEAD] {
+                let response = send(&state, conditional(method.clone(), &[(header, value)])).await;
+                assert_eq!(response.status(), status, "{method} {header}: {value}");
+                if status == StatusCode::NOT_MODIFIED {
+                    assert_eq!(response.headers()["etag"], etag.as_str());
+                    assert!(text(response).await.is_empty());
+                }
+            }
+        }
+
+        // the date based conditions only apply in the absence of the ETag based ones
+        let conditions = [("if-match", etag.as_str()), ("if-unmodified-since", PAST)];
+        let response = send(&state, conditional(Method::GET, &conditions)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let conditions = [
+            ("if-none-match", other),
+            ("if-modified-since", &last_modified),
+        ];
+        let response = send(&state, conditional(Method::GET, &conditions)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text(response).await, "contents");
+    }
+
+    #[tokio::test]
+    async fn test_conditional_put() {
+        let state = state();
+        let upload = |header: &str, value: &str, contents: &'static str| {
+            request(Method::PUT, "/first/object")
+                .header(header, value)
+                .body(Body::from(contents))
+                .unwrap()
+        };
+
+        let response = send(&state, upload("if-match", "*", "first")).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = send(&state, upload("if-none-match", "*", "first")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let first = response.headers()["etag"].to_str().unwrap().to_owned();
+
+        // only overwrites the object it was read as
+        let response = send(&state, upload("if-match", &first, "second")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(&state, upload("if-match", &first, "third")).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = send(
+            &state,
+            request(Method::GET, "/first/object")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(text(response).await, "second");
+    }
+
+    #[tokio::test]
+    async fn test_error_escaping() {
+        let response = s3_error(StatusCode::BAD_REQUEST, "InvalidArgument", "<key> & more");
+        let error = text(response).await;
+        assert!(
+            error.contains("<Message>&lt;key&gt; &amp; more</Message>"),
+            "{error}"
+        );
+    }
+
+    async fn error_code(response: Response<Body>) -> String {
+        let error = text(response).await;
+        let code = error
+            .split("<Code>")
+            .nth(1)
+            .and_then(|rest| rest.split_once('<'));
+        code.map(|(code, _)| code.to_owned()).unwrap_or(error)
+    }
+
+    #[tokio::test]
+    async fn test_bucket_crud() {
+        let state = state();
+        let head = |uri| request(Method::HEAD, uri).body(Body::empty()).unwrap();
+
+        let create = request(Method::PUT, "/third")
+            .header("x-kycok-scope", "org-1")
+            .header("x-kycok-ttl", "3600")
+            .header("x-kycok-tti", "600")
+            .header("x-kycok-compression", "none")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&state, create).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["location"], "/third");
+
+        let response = send(&state, head("/third")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for (name, value) in [
+            ("x-kycok-usecase", "default"),
+            ("x-kycok-scope", "org-1"),
+            ("x-kycok-ttl", "3600"),
+            ("x-kycok-tti", "600"),
+            ("x-kycok-compression", "none"),
+        ] {
+            assert_eq!(response.headers()[name], value);
+        }
+        let response = send(&state, head("/second")).await;
+        assert_eq!(response.headers()["x-kycok-scope"], "second");
+        assert!(response.headers().get("x-kycok-ttl").is_none());
+
+        let list = || request(Method::GET, "/").body(Body::empty()).unwrap();
+        let listing = text(send(&state, list()).await).await;
+        for bucket in ["first", "second", "third"] {
+            let listed = format!("<Bucket><Name>{bucket}</Name>");
+            assert!(listing.contains(&listed), "{listing}");
+        }
+
+        // only empty buckets can be deleted
+        let upload = request(Method::PUT, "/first/object")
+            .body(Body::from("contents"))
+            .unwrap();
+        send(&state, upload).await;
+        let delete = |uri| request(Method::DELETE, uri).body(Body::empty()).unwrap();
+        let response = send(&state, delete("/first")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(error_code(response).await, "BucketNotEmpty");
+        let response = send(&state, delete("/third")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = send(&state, head("/third")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let get = request(Method::GET, "/third/object")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(error_code(send(&state, get).await).await, "NoSuchBucket");
+        let response = send(&state, delete("/third")).await;
+        assert_eq!(error_code(response).await, "NoSuchBucket");
+        let listing = text(send(&state, list()).await).await;
+        assert!(!listing.contains("<Name>third</Name>"), "{listing}");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_buckets() {
+        let state = state();
+        for (bucket, header, code) in [
+            ("first", None, "BucketAlreadyExists"),
+            ("Invalid_Name", None, "InvalidBucketName"),
+            ("ab", None, "InvalidBucketName"),
+            (
+                "usecase",
+                Some(("x-kycok-usecase", "unknown")),
+                "InvalidArgument",
+            ),
+            ("ttl", Some(("x-kycok-ttl", "soon")), "InvalidArgument"),
+            ("zero", Some(("x-kycok-ttl", "0")), "InvalidArgument"),
+            ("tti", Some(("x-kycok-tti", "soon")), "InvalidArgument"),
+            (
+                "level",
+                Some(("x-kycok-compression", "100")),
+                "InvalidArgument",
+            ),
+        ] {
+            let mut create = request(Method::PUT, &format!("/{bucket}"));
+            if let Some((name, value)) = header {
+                create = create.header(name, value);
+            }
+            let response = send(&state, create.body(Body::empty()).unwrap()).await;
+            assert!(response.status().is_client_error());
+            assert_eq!(error_code(response).await, code, "{bucket}");
+        }
+
+        let list = request(Method::GET, "/").body(Body::empty()).unwrap();
+        let listing = text(send(&state, list).await).await;
+        assert_eq!(listing.matches("<Bucket>").count(), 2, "{listing}");
+    }
+
+    fn delete_request(keys: &[&str]) -> Request {
+        let objects: String = keys
+            .iter()
+            .map(|key| format!("<Object><Key>{key}</Key></Object>"))
+            .collect();
+        let body = format!("<Delete>{objects}</Delete>");
+        request(Method::POST, "/first?delete")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_delete_objects() {
+        let state = state();
+        let get = |key| {
+            let uri = format!("/first/{key}");
+            request(Method::GET, &uri).body(Body::empty()).unwrap()
+        };
+        for key in ["a", "b", "c"] {
+            let upload = request(Method::PUT, &format!("/first/{key}"))
+                .body(Body::from(key))
+                .unwrap();
+            send(&state, upload).await;
+        }
+
+        // keys which do not exist are reported as deleted
+        let response = send(&state, delete_request(&["a", "b", "missing"])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let result = text(response).await;
+        for key in ["a", "b", "missing"] {
+            let deleted = format!("<Deleted><Key>{key}</Key></Deleted>");
+            assert!(result.contains(&deleted), "{result}");
+        }
+        for (key, status) in [
+            ("a", StatusCode::NOT_FOUND),
+            ("b", StatusCode::NOT_FOUND),
+            ("c", StatusCode::OK),
+        ] {
+            assert_eq!(send(&state, get(key)).await.status(), status, "{key}");
+        }
+
+        // quiet mode only lists errors
+        let body = "<Delete><Quiet>true</Quiet><Object><Key>c</Key></Object></Delete>";
+        let quiet = request(Method::POST, "/first?delete")
+            .body(Body::from(body))
+            .unwrap();
+        let result = text(send(&state, quiet).await).await;
+        assert!(!result.contains("<Deleted>"), "{result}");
+        assert_eq!(send(&state, get("c")).await.status(), StatusCode::NOT_FOUND);
+
+        let too_many: Vec<_> = (0..1001).map(|i| i.to_string()).collect();
+        let too_many: Vec<_> = too_many.iter().map(String::as_str).collect();
+        let malformed = request(Method::POST, "/first?delete")
+            .body(Body::from("<Delete><Object>"))
+            .unwrap();
+        for request in [delete_request(&too_many), delete_request(&[]), malformed] {
+            let response = send(&state, request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(error_code(response).await, "MalformedXML");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_object_markers() {
+        let state = state();
+        let enable = request(Method::PUT, "/first?versioning")
+            .body(Body::from(
+                "<VersioningConfiguration><Status>Enabled</Status></VersioningConfiguration>",
+            ))
+            .unwrap();
+        assert_eq!(send(&state, enable).await.status(), StatusCode::OK);
+        let upload = request(Method::PUT, "/first/object")
+            .body(Body::from("contents"))
+            .unwrap();
+        let response = send(&state, upload).await;
+        let version = response.headers()["x-amz-version-id"].clone();
+
+        // deleting a key adds a delete marker, which can be deleted by its version
+        let result = text(send(&state, delete_request(&["object"])).await).await;
+        let marker = result
+            .split("<DeleteMarker>true</DeleteMarker><DeleteMarkerVersionId>")
+            .nth(1)
+            .and_then(|rest| rest.split_once('<'))
+            .map(|(marker, _)| marker.to_owned())
+            .unwrap();
+        assert_ne!(marker, version);
+        let get = request(Method::GET, &format!("/first/object?versionId={marker}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&state, get).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()["x-amz-delete-marker"], "true");
+
+        let delete = request(Method::POST, "/first?delete")
+            .body(Body::from(format!(
+                "<Delete><Object><Key>object</Key><VersionId>{marker}</VersionId></Object></Delete>"
+            )))
+            .unwrap();
+        let result = text(send(&state, delete).await).await;
+        let deleted = format!(
+            "<Deleted><Key>object</Key><VersionId>{marker}</VersionId><DeleteMarker>true</DeleteMarker></Deleted>"
+        );
+        assert!(result.contains(&deleted), "{result}");
+        let get = request(Method::GET, "/first/object")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&state, get).await;
+        assert_eq!(response.headers()["x-amz-version-id"], version);
+        assert_eq!(text(response).await, "contents");
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::Method;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::sigv4::AuthError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const EXPIRES_PARAM: &str = "X-Kycok-Expires";
+const SIGNATURE_PARAM: &str = "X-Kycok-Signature";
+
+/// The TTL of signed URLs, unless a different one was requested.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+/// The maximum TTL of signed URLs, which is 7 days.
+pub const MAX_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Issues and verifies expiring URLs, which grant access to a single object without credentials.
+///
+/// The signature covers the HTTP method, the namespace, the object key and the expiration time.
+/// A signed `GET` URL can also be used for `HEAD` requests. As the signature does not cover any
+/// other query parameters, requests carrying them (like `versionId` or sub-resources) are denied.
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+impl UrlSigner {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    /// Creates a signer with a random key, which means that URLs do not survive a restart.
+    pub fn random() -> Self {
+        let mut key = Vec::with_capacity(32);
+        key.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+        key.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+        Self { key }
+    }
+
+    /// Returns the query string granting `method` access to the object until `expires`.
+    pub fn sign(&self, method: &Method, namespace: u64, key: &str, expires: SystemTime) -> String {
+        let expires = expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let signature = self.mac(method, namespace, key, expires).finalize();
+        let signature = base16ct::lower::encode_string(&signature.into_bytes());
+
+        format!("{EXPIRES_PARAM}={expires}&{SIGNATURE_PARAM}={signature}")
+    }
+
+    /// Verifies the signed query string for the request.
+    pub fn verify(
+        &self,
+        method: &Method,
+        namespace: u64,
+        key: &str,
+        query: &str,
+        now: SystemTime,
+    ) -> Result<(), AuthError> {
+        let mut expires = None;
+        let mut signature = None;
+        let pairs = query.split('&').filter(|pair| !pair.is_empty());
+        for (param, value) in pairs.map(|pair| pair.split_once('=').unwrap_or((pair, ""))) {
+            match param {
+                EXPIRES_PARAM => expires = Some(value),
+                SIGNATURE_PARAM => signature = Some(value),
+                _ => return Err(AuthError::AccessDenied),
+            }
+        }
+        let expires: u64 = expires
+            .and_then(|expires| expires.parse().ok())
+            .ok_or(AuthError::Malformed("invalid X-Kycok-Expires"))?;
+        let signature = signature
+            .and_then(|signature| base16ct::mixed::decode_vec(signature).ok())
+            .ok_or(AuthError::Malformed("invalid X-Kycok-Signature"))?;
+
+        let method = if method == Method::HEAD {
+            &Method::GET
+        } else {
+            method
+        };
+        self.mac(method, namespace, key, expires)
+            .verify_slice(&signature)
+            .map_err(|_| AuthError::SignatureDoesNotMatch)?;
+
+        if now > UNIX_EPOCH + Duration::from_secs(expires) {
+            return Err(AuthError::Expired);
+        }
+        Ok(())
+    }
+
+    fn mac(&self, method: &Method, namespace: u64, key: &str, expires: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(format!("{method}\n{namespace}\n{key}\n{expires}").as_bytes());
+        mac
+    }
+}
+
+/// Whether the query string carries a signed URL signature.
+pub fn is_signed_url(query: &str) -> bool {
+    query
+        .split('&')
+        .any(|pair| pair.starts_with(SIGNATURE_PARAM))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    #[test]
+    fn test_signed_url() {
+        let signer = UrlSigner::new("secret");
+        let expires = now() + DEFAULT_TTL;
+
+        let query = signer.sign(&Method::PUT, 1, "some/file.txt", expires);
+        assert_eq!(
+            query,
+            "X-Kycok-Expires=1700003600&X-Kycok-Signature=cc415f7bd4ac307453f35c2a1286ca5c3ca70a4d59bdd88f540e00ae0ed17da6"
+        );
+        assert!(is_signed_url(&query));
+        assert!(!is_signed_url("versioning"));
+
+        assert!(signer
+            .verify(&Method::PUT, 1, "some/file.txt", &query, now())
+            .is_ok());
+        assert!(signer
+            .verify(&Method::PUT, 1, "some/file.txt", &query, expires)
+            .is_ok());
+        assert_eq!(
+            signer.verify(
+                &Method::PUT,
+                1,
+                "some/file.txt",
+                &query,
+                expires + Duration::from_secs(1)
+            ),
+            Err(AuthError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_signed_url_scope() {
+        let signer = UrlSigner::new("secret");
+        let query = signer.sign(&Method::GET, 1, "file.txt", now() + DEFAULT_TTL);
+
+        assert!(signer
+            .verify(&Method::HEAD, 1, "file.txt", &query, now())
+            .is_ok());
+
+        let mismatch = Err(AuthError::SignatureDoesNotMatch);
+        assert_eq!(
+            signer.verify(&Method::PUT, 1, "file.txt", &query, now()),
+            mismatch
+        );
+        assert_eq!(
+            signer.verify(&Method::GET, 2, "file.txt", &query, now()),
+            mismatch
+        );
+        assert_eq!(
+            signer.verify(&Method::GET, 1, "other.txt", &query, now()),
+            mismatch
+        );
+        assert_eq!(
+            UrlSigner::new("other secret").verify(&Method::GET, 1, "file.txt", &query, now()),
+            mismatch
+        );
+
+        // the signature does not cover any other query parameters
+        for extra in ["versionId=1", "retention", "legal-hold=", "x-id=GetObject"] {
+            assert_eq!(
+                signer.verify(&Method::GET, 1, "file.txt", &format!("{query}&{extra}"), now()),
+                Err(AuthError::AccessDenied)
+            );
+        }
+
+        // extending the expiration invalidates the signature
+        let extended = query.replace("X-Kycok-Expires=1700003600", "X-Kycok-Expires=1800000000");
+        assert_eq!(
+            signer.verify(&Method::GET, 1, "file.txt", &extended, now()),
+            mismatch
+        );
+    }
+}
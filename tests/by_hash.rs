@@ -0,0 +1,122 @@
+use kycok::config::{ServerConfig, Usecase};
+use kycok::new_datamodel::usecase::DedupScope;
+use kycok::new_datamodel::{file, HashAlgorithm};
+use reqwest::StatusCode;
+
+mod common;
+
+#[tokio::test]
+async fn test_get_by_hash() {
+    let url = common::spawn_server(Default::default()).await.url;
+    let http = reqwest::Client::new();
+    for bucket in ["hashes", "other"] {
+        let response = http.put(format!("{url}/{bucket}")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let contents = "contents addressed by their hash";
+    let file_id = file::FileId::from_contents(HashAlgorithm::Blake3, contents.as_bytes());
+    let by_hash = format!("{url}/hashes/by-hash/{file_id}");
+    let response = http.get(&by_hash).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = http
+        .put(format!("{url}/hashes/some/name"))
+        .body(contents)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["etag"].clone();
+    assert_eq!(etag, format!("\"{file_id}\"").as_str());
+
+    let response = http.get(&by_hash).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["etag"], etag);
+    assert!(!response.headers().contains_key("last-modified"));
+    assert_eq!(response.text().await.unwrap(), contents);
+
+    let response = http.head(&by_hash).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-length"], "32");
+    let response = http
+        .get(&by_hash)
+        .header("if-none-match", etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // files are only found within the bucket that stores them
+    let response = http
+        .get(format!("{url}/other/by-hash/{file_id}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // and only while a name points to them
+    http.delete(format!("{url}/hashes/some/name"))
+        .send()
+        .await
+        .unwrap();
+    let response = http.get(&by_hash).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // keys which are not a file id are read by name
+    let response = http
+        .put(format!("{url}/hashes/by-hash/readme"))
+        .body(contents)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .get(format!("{url}/hashes/by-hash/readme"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), contents);
+}
+
+#[tokio::test]
+async fn test_get_by_hash_shared() {
+    let usecase = Usecase {
+        dedup: DedupScope::Usecase,
+        ..Default::default()
+    };
+    let config = ServerConfig {
+        usecases: [("shared".into(), usecase)].into(),
+        ..Default::default()
+    };
+    let url = common::spawn_server(config).await.url;
+    let http = reqwest::Client::new();
+    for bucket in ["first", "second"] {
+        let response = http
+            .put(format!("{url}/{bucket}"))
+            .header("x-kycok-usecase", "shared")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let contents = "contents shared by the buckets of a usecase";
+    let file_id = file::FileId::from_contents(HashAlgorithm::Blake3, contents.as_bytes());
+    http.put(format!("{url}/first/name"))
+        .body(contents)
+        .send()
+        .await
+        .unwrap();
+
+    // the buckets of the usecase find the files of each other
+    for bucket in ["first", "second"] {
+        let response = http
+            .get(format!("{url}/{bucket}/by-hash/{file_id}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{bucket}");
+        assert_eq!(response.text().await.unwrap(), contents);
+    }
+}
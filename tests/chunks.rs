@@ -0,0 +1,124 @@
+use kycok::new_datamodel::{chunk, HashAlgorithm};
+use reqwest::StatusCode;
+
+mod common;
+
+fn chunk_hex(contents: &str) -> String {
+    chunk::ChunkId::from_contents(HashAlgorithm::Blake3, contents.as_bytes()).to_string()
+}
+
+fn chunk_list(chunks: &[&str]) -> String {
+    let chunks: Vec<_> = chunks
+        .iter()
+        .map(|chunk| format!("\"{}\"", chunk_hex(chunk)))
+        .collect();
+    format!(r#"{{"chunks":[{}]}}"#, chunks.join(","))
+}
+
+#[tokio::test]
+async fn test_missing_chunks_upload_assemble() {
+    let url = common::spawn_server(Default::default()).await.url;
+    let http = reqwest::Client::new();
+    let response = http.put(format!("{url}/chunks")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let chunks = ["some pre-chunked", " content"];
+    let response = http
+        .post(format!("{url}/chunks?missing-chunks"))
+        .body(chunk_list(&chunks))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let missing = format!(
+        r#"{{"missing":["{}","{}"]}}"#,
+        chunk_hex(chunks[0]),
+        chunk_hex(chunks[1])
+    );
+    assert_eq!(response.text().await.unwrap(), missing);
+
+    let response = http
+        .post(format!("{url}/chunks/file?assemble"))
+        .body(chunk_list(&chunks))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().contains("MissingChunks"));
+
+    // the contents have to match the chunk id
+    let response = http
+        .post(format!("{url}/chunks?chunk={}", chunk_hex(chunks[0])))
+        .body(chunks[1])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().contains("BadDigest"));
+
+    for chunk in chunks {
+        let response = http
+            .post(format!("{url}/chunks?chunk={}", chunk_hex(chunk)))
+            .body(chunk)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    let response = http
+        .post(format!("{url}/chunks?missing-chunks"))
+        .body(chunk_list(&chunks))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), r#"{"missing":[]}"#);
+
+    let response = http
+        .post(format!("{url}/chunks/file?assemble"))
+        .body(chunk_list(&chunks))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("etag"));
+
+    let response = http.get(format!("{url}/chunks/file")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "some pre-chunked content");
+}
+
+#[tokio::test]
+async fn test_zstd_chunk_stored_verbatim() {
+    let url = common::spawn_server(Default::default()).await.url;
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/chunks")).send().await.unwrap();
+
+    let chunk = "a chunk compressed by the client, a chunk compressed by the client";
+    let compressed = zstd::bulk::compress(chunk.as_bytes(), 19).unwrap();
+    let response = http
+        .post(format!("{url}/chunks?chunk={}", chunk_hex(chunk)))
+        .header("content-encoding", "zstd")
+        .body(compressed.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = http
+        .post(format!("{url}/chunks/file?assemble"))
+        .body(chunk_list(&[chunk]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // zstd responses reuse the frame as it was uploaded
+    let response = http
+        .get(format!("{url}/chunks/file"))
+        .header("accept-encoding", "zstd")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.bytes().await.unwrap(), compressed);
+    let response = http.get(format!("{url}/chunks/file")).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), chunk);
+}
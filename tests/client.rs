@@ -0,0 +1,389 @@
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use futures_util::TryStreamExt;
+use kycok::client::{Body, Error, StorageId, StorageScope, StorageServiceBuilder};
+use kycok::config::{ServerConfig, Usecase};
+use kycok::new_datamodel::quota::Quota;
+use kycok::new_datamodel::usecase::DedupScope;
+use kycok::new_datamodel::Chunking;
+use tokio::net::TcpListener;
+
+mod common;
+
+/// Serves kycok in-process with an `attachments` usecase.
+async fn spawn_server(mut config: ServerConfig) -> SocketAddr {
+    config.usecases.entry("attachments".into()).or_default();
+    common::spawn_server(config).await.addr
+}
+
+#[tokio::test]
+async fn test_put_get_delete() {
+    let addr = spawn_server(ServerConfig::default()).await;
+    let attachments =
+        StorageServiceBuilder::new(format!("http://{addr}/")).for_usecase("attachments");
+    let storage = attachments.with_scope(StorageScope::for_organization(1));
+
+    let id = storage
+        .put_blob("some raw attachment contents", None)
+        .await
+        .unwrap();
+    let blob = storage.get_blob(&id).await.unwrap();
+    assert_eq!(blob.size(), Some(28));
+    assert_eq!(blob.bytes().await.unwrap(), "some raw attachment contents");
+
+    // ids round-trip through their string representation
+    let id: StorageId = id.to_string().parse().unwrap();
+    storage.delete_blob(&id).await.unwrap();
+    assert!(matches!(storage.get_blob(&id).await, Err(Error::NotFound)));
+    storage.delete_blob(&id).await.unwrap();
+
+    // explicit ids are isolated per scope
+    let id: StorageId = "some/explicit-id.txt".parse().unwrap();
+    assert_eq!(
+        storage.put_blob("first", Some(id.clone())).await.unwrap(),
+        id
+    );
+    assert_eq!(
+        storage.put_blob("second", Some(id.clone())).await.unwrap(),
+        id
+    );
+    let blob = storage.get_blob(&id).await.unwrap();
+    assert_eq!(blob.bytes().await.unwrap(), "second");
+
+    let other_scope = attachments.with_scope(StorageScope::for_organization(2));
+    assert!(matches!(
+        other_scope.get_blob(&id).await,
+        Err(Error::NotFound)
+    ));
+    other_scope.delete_blob(&id).await.unwrap();
+    assert!(storage.get_blob(&id).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_streaming() {
+    let addr = spawn_server(ServerConfig::default()).await;
+    let storage = StorageServiceBuilder::new(format!("http://{addr}"))
+        .for_usecase("attachments")
+        .with_scope(StorageScope::for_organization(1));
+
+    let contents: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let chunks: Vec<Result<Vec<u8>, std::io::Error>> = contents
+        .chunks(64 * 1024)
+        .map(|chunk| Ok(chunk.to_vec()))
+        .collect();
+    let body = Body::from_stream(futures_util::stream::iter(chunks));
+    let id = storage.put_blob(body, None).await.unwrap();
+
+    let reader = Body::from_reader(std::io::Cursor::new(contents.clone()));
+    let other_id = storage.put_blob(reader, None).await.unwrap();
+
+    for id in [id, other_id] {
+        let blob = storage.get_blob(&id).await.unwrap();
+        let downloaded: Vec<u8> = blob
+            .into_stream()
+            .map_ok(|bytes| bytes.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(downloaded, contents);
+    }
+}
+
+#[tokio::test]
+async fn test_chunked() {
+    let config = ServerConfig {
+        usecases: [(
+            "attachments".into(),
+            Usecase {
+                chunking: Some(Chunking::ContentDefined),
+                chunk_size: Some(16 * 1024),
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+    let addr = spawn_server(config).await;
+    let storage = StorageServiceBuilder::new(format!("http://{addr}"))
+        .max_chunk_size(16 * 1024)
+        .for_usecase("attachments")
+        .with_scope(StorageScope::for_organization(1));
+
+    let mut state = 1u32;
+    let contents: Vec<u8> = (0..256 * 1024)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let id = storage
+        .put_blob_chunked(std::io::Cursor::new(contents.clone()), None)
+        .await
+        .unwrap();
+    let blob = storage.get_blob(&id).await.unwrap();
+    assert_eq!(blob.bytes().await.unwrap(), contents);
+
+    // most chunks are already stored, and empty blobs have no chunks at all
+    let mut modified = b"prefix".to_vec();
+    modified.extend_from_slice(&contents);
+    let id = storage
+        .put_blob_chunked(&modified[..], Some("modified".parse().unwrap()))
+        .await
+        .unwrap();
+    let blob = storage.get_blob(&id).await.unwrap();
+    assert_eq!(blob.bytes().await.unwrap(), modified);
+    let id = storage.put_blob_chunked(&b""[..], None).await.unwrap();
+    let blob = storage.get_blob(&id).await.unwrap();
+    assert_eq!(blob.bytes().await.unwrap(), "");
+}
+
+#[tokio::test]
+async fn test_unknown_usecase() {
+    let addr = spawn_server(ServerConfig::default()).await;
+    let storage = StorageServiceBuilder::new(format!("http://{addr}"))
+        .for_usecase("unknown")
+        .with_scope(StorageScope::for_organization(1));
+
+    let err = storage.put_blob("contents", None).await.unwrap_err();
+    let Error::Server { status, code, .. } = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(
+        (status, code.as_str()),
+        (StatusCode::BAD_REQUEST, "InvalidArgument")
+    );
+}
+
+#[tokio::test]
+async fn test_max_object_size() {
+    let config = ServerConfig {
+        usecases: [(
+            "attachments".into(),
+            Usecase {
+                max_object_size: Some(16),
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+    let addr = spawn_server(config).await;
+    let storage = StorageServiceBuilder::new(format!("http://{addr}"))
+        .for_usecase("attachments")
+        .with_scope(StorageScope::for_organization(1));
+
+    storage.put_blob("small contents", None).await.unwrap();
+    let err = storage
+        .put_blob("contents exceeding the limit", None)
+        .await
+        .unwrap_err();
+    let Error::Server { status, code, .. } = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(
+        (status, code.as_str()),
+        (StatusCode::BAD_REQUEST, "EntityTooLarge")
+    );
+}
+
+#[tokio::test]
+async fn test_quota() {
+    let config = ServerConfig {
+        usecases: [(
+            "attachments".into(),
+            Usecase {
+                hard_quota: Quota {
+                    objects: Some(1),
+                    ..Default::default()
+                },
+                dedup: DedupScope::Usecase,
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+    let addr = spawn_server(config).await;
+    let storage = StorageServiceBuilder::new(format!("http://{addr}")).for_usecase("attachments");
+    let client = storage.with_scope(StorageScope::for_organization(1));
+
+    let id = client.put_blob("contents", None).await.unwrap();
+    let err = client.put_blob("more contents", None).await.unwrap_err();
+    let Error::Server { status, code, .. } = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(
+        (status, code.as_str()),
+        (StatusCode::FORBIDDEN, "QuotaExceeded")
+    );
+
+    // other scopes have their own quota, and deleting frees up the quota again
+    let other_client = storage.with_scope(StorageScope::for_organization(2));
+    let other_id = other_client.put_blob("contents", None).await.unwrap();
+    client.delete_blob(&id).await.unwrap();
+    let blob = other_client.get_blob(&other_id).await.unwrap();
+    assert_eq!(blob.bytes().await.unwrap(), "contents");
+    client.put_blob("more contents", None).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_credentials() {
+    let auth_config = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(
+        auth_config.path(),
+        r#"
+        [[credentials]]
+        access_key = "access"
+        secret_key = "secret"
+        buckets = ["attachments.org-1"]
+        "#,
+    )
+    .unwrap();
+    let addr = spawn_server(ServerConfig {
+        auth_config: Some(auth_config.path().into()),
+        ..Default::default()
+    })
+    .await;
+    let builder = StorageServiceBuilder::new(format!("http://{addr}"));
+
+    let storage = builder
+        .clone()
+        .credentials("access", "secret")
+        .for_usecase("attachments");
+    let id = storage
+        .with_scope(StorageScope::for_organization(1))
+        .put_blob("contents", None)
+        .await
+        .unwrap();
+    let err = storage
+        .with_scope(StorageScope::for_organization(2))
+        .put_blob("contents", None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Server { code, .. } if code == "AccessDenied"));
+
+    let unsigned = builder
+        .for_usecase("attachments")
+        .with_scope(StorageScope::for_organization(1));
+    let err = unsigned.get_blob(&id).await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Server {
+            status: StatusCode::FORBIDDEN,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_allocate() {
+    let addr = spawn_server(ServerConfig::default()).await;
+    let http = reqwest::Client::new();
+    let bucket = format!("http://{addr}/allocated");
+    http.put(&bucket).send().await.unwrap();
+
+    let response = http
+        .post(format!("{bucket}?allocate=compact"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let allocated = response.text().await.unwrap();
+    let id = allocated
+        .strip_prefix(r#"{"id":""#)
+        .and_then(|id| id.strip_suffix(r#""}"#))
+        .unwrap();
+    assert_eq!(id.len(), 22);
+
+    let put = |contents: &'static str, idempotent: &'static str| {
+        http.put(format!("{bucket}/{id}"))
+            .header("if-none-match", "*")
+            .header("x-kycok-idempotent", idempotent)
+            .body(contents)
+            .send()
+    };
+    let response = put("contents", "true").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["etag"].clone();
+
+    // create-only uploads fail on an existing key, even with the same contents
+    let response = put("contents", "false").await.unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    // retrying an idempotent upload with the same contents succeeds
+    let response = put("contents", "true").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["etag"], etag);
+    let response = put("other contents", "true").await.unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let response = http
+        .post(format!("{bucket}?allocate=sequential"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Serves `503` for the first `failures` object requests, counting all of them.
+async fn spawn_flaky_server(failures: usize) -> (SocketAddr, Arc<AtomicUsize>) {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counter = attempts.clone();
+    let app = axum::Router::new()
+        .route("/{bucket}", axum::routing::put(|| async { StatusCode::OK }))
+        .route(
+            "/{bucket}/{key}",
+            axum::routing::any(move || async move {
+                if counter.fetch_add(1, Ordering::Relaxed) < failures {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, app).into_future());
+    (addr, attempts)
+}
+
+#[tokio::test]
+async fn test_retries() {
+    let builder = |addr| {
+        StorageServiceBuilder::new(format!("http://{addr}"))
+            .max_retries(2)
+            .retry_backoff(Duration::from_millis(1))
+            .for_usecase("attachments")
+            .with_scope(StorageScope::for_organization(1))
+    };
+    let id: StorageId = "id".parse().unwrap();
+
+    let (addr, attempts) = spawn_flaky_server(2).await;
+    builder(addr).delete_blob(&id).await.unwrap();
+    assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+    let (addr, attempts) = spawn_flaky_server(3).await;
+    let err = builder(addr)
+        .put_blob("contents", Some(id.clone()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Server {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            ..
+        }
+    ));
+    assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+    // streams are consumed by the first attempt
+    let (addr, attempts) = spawn_flaky_server(1).await;
+    let body = Body::from_reader(&b"contents"[..]);
+    assert!(builder(addr).put_blob(body, Some(id)).await.is_err());
+    assert_eq!(attempts.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,68 @@
+//! The in-process server shared by the integration tests.
+
+// each test only uses some of the listeners
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::net::SocketAddr;
+
+use kycok::config::{Backend, ServerConfig};
+use kycok::server::Listeners;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// A kycok server spawned by [`spawn_server`] or [`serve_until`].
+pub struct Server {
+    /// The address of the S3 API.
+    pub addr: SocketAddr,
+    /// The URL of the S3 API.
+    pub url: String,
+    /// The URL of the admin endpoints.
+    pub admin_url: String,
+    /// The URL of the gRPC service.
+    pub grpc_url: String,
+    /// The URL of the Sentry endpoints.
+    pub sentry_url: String,
+}
+
+/// Serves kycok in-process with the `mem` backend and all of its listeners, configured by
+/// `config` otherwise.
+pub async fn spawn_server(config: ServerConfig) -> Server {
+    let config = ServerConfig {
+        backend: Backend::Mem,
+        ..config
+    };
+    serve_until(config, std::future::pending()).await.0
+}
+
+/// Serves kycok in-process with all of its listeners until `shutdown` completes, returning
+/// the task which finishes once the server stopped.
+pub async fn serve_until(
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> (Server, JoinHandle<anyhow::Result<()>>) {
+    let bind = || TcpListener::bind("127.0.0.1:0");
+    let listeners = Listeners {
+        s3: bind().await.unwrap(),
+        admin: Some(bind().await.unwrap()),
+        grpc: Some(bind().await.unwrap()),
+        sentry: Some(bind().await.unwrap()),
+    };
+    let url = |listener: Option<&TcpListener>| {
+        format!("http://{}", listener.unwrap().local_addr().unwrap())
+    };
+    let server = Server {
+        addr: listeners.s3.local_addr().unwrap(),
+        url: url(Some(&listeners.s3)),
+        admin_url: url(listeners.admin.as_ref()),
+        grpc_url: url(listeners.grpc.as_ref()),
+        sentry_url: url(listeners.sentry.as_ref()),
+    };
+
+    let config = ServerConfig {
+        bind: server.addr,
+        ..config
+    };
+    let serving = tokio::spawn(kycok::server::serve(listeners, config, shutdown));
+    (server, serving)
+}
@@ -0,0 +1,179 @@
+use kycok::config::ServerConfig;
+use kycok::grpc::proto::storage_client::StorageClient;
+use kycok::grpc::proto::{
+    AllocateBlobRequest, AssembleFromPartsRequest, GetBlobLocationRequest, Scope, StorageId,
+};
+use tonic::transport::Channel;
+use tonic::Code;
+
+mod common;
+
+/// Serves kycok in-process with an `attachments` usecase, returning a client connected to
+/// the gRPC service.
+async fn spawn_server() -> StorageClient<Channel> {
+    let config = ServerConfig {
+        usecases: [("attachments".into(), Default::default())].into(),
+        ..Default::default()
+    };
+    let server = common::spawn_server(config).await;
+
+    StorageClient::connect(server.grpc_url).await.unwrap()
+}
+
+fn scope(org_id: u64) -> Option<Scope> {
+    Some(Scope {
+        usecase: "attachments".into(),
+        scope: format!("org-{org_id}"),
+    })
+}
+
+fn id(id: &str) -> Option<StorageId> {
+    Some(StorageId { id: id.into() })
+}
+
+#[tokio::test]
+async fn test_allocate_put_locate_get() {
+    let mut client = spawn_server().await;
+    let http = reqwest::Client::new();
+
+    let allocated = client
+        .allocate_blob(AllocateBlobRequest {
+            scope: scope(1),
+            id: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let blob_id = allocated.id.unwrap();
+    assert!(!blob_id.id.is_empty());
+
+    let response = http
+        .put(&allocated.signed_put_url)
+        .body("some blob contents")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let location = client
+        .get_blob_location(GetBlobLocationRequest {
+            scope: scope(1),
+            id: Some(blob_id.clone()),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let contents = http
+        .get(&location.signed_get_url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(contents, "some blob contents");
+
+    // the signed URLs only grant access to a single blob with a single method
+    let response = http.get(&allocated.signed_put_url).send().await.unwrap();
+    assert_eq!(response.status(), 403);
+
+    // blobs are isolated per scope
+    let status = client
+        .get_blob_location(GetBlobLocationRequest {
+            scope: scope(2),
+            id: Some(blob_id),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_assemble_from_parts() {
+    let mut client = spawn_server().await;
+    let http = reqwest::Client::new();
+
+    for (part, contents) in [("part-1", "first, "), ("part-2", "second")] {
+        let allocated = client
+            .allocate_blob(AllocateBlobRequest {
+                scope: scope(1),
+                id: id(part),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(allocated.id, id(part));
+        http.put(&allocated.signed_put_url)
+            .body(contents)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    let assembled = client
+        .assemble_from_parts(AssembleFromPartsRequest {
+            scope: scope(1),
+            id: id("assembled"),
+            parts: vec![id("part-1").unwrap(), id("part-2").unwrap()],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(assembled.id, id("assembled"));
+    let contents = http
+        .get(&assembled.signed_get_url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(contents, "first, second");
+
+    let status = client
+        .assemble_from_parts(AssembleFromPartsRequest {
+            scope: scope(1),
+            id: None,
+            parts: vec![id("part-1").unwrap(), id("missing").unwrap()],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_invalid_requests() {
+    let mut client = spawn_server().await;
+
+    let status = client
+        .allocate_blob(AllocateBlobRequest {
+            scope: None,
+            id: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let status = client
+        .allocate_blob(AllocateBlobRequest {
+            scope: Some(Scope {
+                usecase: "unknown".into(),
+                scope: "org-1".into(),
+            }),
+            id: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let status = client
+        .get_blob_location(GetBlobLocationRequest {
+            scope: scope(1),
+            id: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
@@ -0,0 +1,204 @@
+use reqwest::StatusCode;
+
+mod common;
+
+const LIFECYCLE: &str = "<LifecycleConfiguration>\
+    <Rule><ID>logs</ID><Filter><Prefix>logs/</Prefix></Filter><Status>Enabled</Status>\
+    <Expiration><Days>30</Days></Expiration>\
+    <NoncurrentVersionExpiration><NoncurrentDays>7</NoncurrentDays></NoncurrentVersionExpiration>\
+    </Rule>\
+    <Rule><ID>tmp</ID><Filter><And><Prefix>tmp/</Prefix></And></Filter>\
+    <Status>Disabled</Status><Expiration><Days>1</Days></Expiration></Rule>\
+    <Rule><ID>tagged</ID><Filter><Tag><Key>expire</Key><Value>true</Value></Tag></Filter>\
+    <Status>Enabled</Status><Expiration><Days>1</Days></Expiration></Rule>\
+    <Rule><ID>both</ID><Filter><And><Prefix>tmp/</Prefix>\
+    <Tag><Key>a</Key><Value>1</Value></Tag><Tag><Key>b</Key><Value>2</Value></Tag></And></Filter>\
+    <Status>Enabled</Status><Expiration><Days>1</Days></Expiration></Rule>\
+    <Rule><ID>uploads</ID><Filter /><Status>Enabled</Status>\
+    <AbortIncompleteMultipartUpload><DaysAfterInitiation>2</DaysAfterInitiation></AbortIncompleteMultipartUpload>\
+    </Rule>\
+    </LifecycleConfiguration>";
+
+#[tokio::test]
+async fn test_lifecycle_configuration() {
+    let server = common::spawn_server(Default::default()).await;
+    let (url, admin_url) = (server.url, server.admin_url);
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/bucket")).send().await.unwrap();
+
+    let lifecycle = format!("{url}/bucket?lifecycle");
+    let response = http.get(&lifecycle).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("NoSuchLifecycleConfiguration"));
+
+    let response = http.put(&lifecycle).body(LIFECYCLE).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http.get(&lifecycle).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let configuration = response.text().await.unwrap();
+    for expected in [
+        "<ID>logs</ID><Filter><Prefix>logs/</Prefix></Filter><Status>Enabled</Status>",
+        "<Expiration><Days>30</Days></Expiration>",
+        "<NoncurrentVersionExpiration><NoncurrentDays>7</NoncurrentDays></NoncurrentVersionExpiration>",
+        "<ID>tmp</ID><Filter><Prefix>tmp/</Prefix></Filter><Status>Disabled</Status>",
+        "<ID>tagged</ID><Filter><Tag><Key>expire</Key><Value>true</Value></Tag></Filter>",
+        "<ID>both</ID><Filter><And><Prefix>tmp/</Prefix><Tag><Key>a</Key><Value>1</Value></Tag><Tag><Key>b</Key><Value>2</Value></Tag></And></Filter>",
+        "<AbortIncompleteMultipartUpload><DaysAfterInitiation>2</DaysAfterInitiation></AbortIncompleteMultipartUpload>",
+    ] {
+        assert!(configuration.contains(expected), "{configuration}");
+    }
+
+    // nothing has expired yet
+    http.put(format!("{url}/bucket/logs/today"))
+        .body("log")
+        .send()
+        .await
+        .unwrap();
+    let response = http
+        .post(format!("{admin_url}/admin/lifecycle"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["objects_expired"], 0);
+    let response = http
+        .get(format!("{url}/bucket/logs/today"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let invalid = [
+        "<LifecycleConfiguration />",
+        "<LifecycleConfiguration><Rule><Status>Enabled</Status></Rule></LifecycleConfiguration>",
+        "<LifecycleConfiguration><Rule><Status>On</Status><Expiration><Days>1</Days></Expiration></Rule></LifecycleConfiguration>",
+        "<LifecycleConfiguration><Rule><Status>Enabled</Status><Expiration><Days>0</Days></Expiration></Rule></LifecycleConfiguration>",
+        "<LifecycleConfiguration><Rule><ID>a</ID><Status>Enabled</Status><Expiration><Days>1</Days></Expiration></Rule><Rule><ID>a</ID><Status>Enabled</Status><Expiration><Days>2</Days></Expiration></Rule></LifecycleConfiguration>",
+        "<LifecycleConfiguration><Rule><Filter><Prefix>a</Prefix><Tag><Key>a</Key><Value>1</Value></Tag></Filter><Status>Enabled</Status><Expiration><Days>1</Days></Expiration></Rule></LifecycleConfiguration>",
+        "<LifecycleConfiguration><Rule><Filter><Tag><Key>a</Key><Value>1</Value></Tag></Filter><Status>Enabled</Status><AbortIncompleteMultipartUpload><DaysAfterInitiation>1</DaysAfterInitiation></AbortIncompleteMultipartUpload></Rule></LifecycleConfiguration>",
+        "<LifecycleConfiguration><Rule><Filter><And><Tag><Key>a</Key><Value>1</Value></Tag><Tag><Key>a</Key><Value>2</Value></Tag></And></Filter><Status>Enabled</Status><Expiration><Days>1</Days></Expiration></Rule></LifecycleConfiguration>",
+    ];
+    for body in invalid {
+        let response = http.put(&lifecycle).body(body).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{body}");
+    }
+    let body = "<LifecycleConfiguration><Rule><Status>Enabled</Status><Expiration><Date>2100-01-01T00:00:00Z</Date></Expiration></Rule></LifecycleConfiguration>";
+    let response = http.put(&lifecycle).body(body).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    // the configuration is kept when a replacement is rejected
+    let response = http.get(&lifecycle).send().await.unwrap();
+    assert!(response.text().await.unwrap().contains("<ID>logs</ID>"));
+
+    // deleting the configuration keeps the bucket
+    let response = http.delete(&lifecycle).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = http.get(&lifecycle).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = http.head(format!("{url}/bucket")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = http
+        .put(format!("{url}/missing?lifecycle"))
+        .body(LIFECYCLE)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_object_tagging() {
+    let url = common::spawn_server(Default::default()).await.url;
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/tagged")).send().await.unwrap();
+    let object = format!("{url}/tagged/file");
+    let tagging = format!("{object}?tagging");
+
+    let response = http
+        .put(&object)
+        .header("x-amz-tagging", "expire=true&team=a%20b")
+        .body("contents")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http.head(&object).send().await.unwrap();
+    assert_eq!(response.headers()["x-amz-tagging-count"], "2");
+    let response = http.get(&tagging).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let tags = response.text().await.unwrap();
+    assert!(
+        tags.contains("<TagSet><Tag><Key>expire</Key><Value>true</Value></Tag><Tag><Key>team</Key><Value>a b</Value></Tag></TagSet>"),
+        "{tags}"
+    );
+
+    // copies keep the tags of their source, unless they are replaced
+    let copy = |key: &'static str, directive: &'static str| {
+        http.put(format!("{url}/tagged/{key}"))
+            .header("x-amz-copy-source", "/tagged/file")
+            .header("x-amz-tagging-directive", directive)
+            .header("x-amz-tagging", "copied=yes")
+            .send()
+    };
+    assert_eq!(copy("copy", "COPY").await.unwrap().status(), StatusCode::OK);
+    let response = http
+        .head(format!("{url}/tagged/copy"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-amz-tagging-count"], "2");
+    let response = copy("replaced", "REPLACE").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .get(format!("{url}/tagged/replaced?tagging"))
+        .send()
+        .await
+        .unwrap();
+    let tags = response.text().await.unwrap();
+    assert!(
+        tags.contains("<Key>copied</Key><Value>yes</Value>"),
+        "{tags}"
+    );
+
+    let body = "<Tagging><TagSet><Tag><Key>k</Key><Value>v</Value></Tag></TagSet></Tagging>";
+    let response = http.put(&tagging).body(body).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http.get(&tagging).send().await.unwrap();
+    let tags = response.text().await.unwrap();
+    assert!(
+        tags.contains("<TagSet><Tag><Key>k</Key><Value>v</Value></Tag></TagSet>"),
+        "{tags}"
+    );
+
+    let response = http.delete(&tagging).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = http.head(&object).send().await.unwrap();
+    assert!(!response.headers().contains_key("x-amz-tagging-count"));
+    // deleting the tags keeps the object
+    let response = http.get(&object).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "contents");
+
+    let duplicate = "<Tagging><TagSet><Tag><Key>k</Key><Value>1</Value></Tag><Tag><Key>k</Key><Value>2</Value></Tag></TagSet></Tagging>";
+    let response = http.put(&tagging).body(duplicate).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().contains("InvalidTag"));
+    let response = http
+        .put(&object)
+        .header("x-amz-tagging", "=empty-key")
+        .body("contents")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = http
+        .get(format!("{url}/tagged/missing?tagging"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,50 @@
+use reqwest::StatusCode;
+
+mod common;
+
+#[tokio::test]
+async fn test_metrics() {
+    let server = common::spawn_server(Default::default()).await;
+    let (url, admin_url) = (server.url, server.admin_url);
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/metered")).send().await.unwrap();
+    for key in ["first", "second"] {
+        http.put(format!("{url}/metered/{key}"))
+            .body("deduplicated contents")
+            .send()
+            .await
+            .unwrap();
+    }
+    let response = http
+        .get(format!("{url}/metered/first"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .get(format!("{url}/metered/missing"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = http
+        .get(format!("{admin_url}/metrics"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let metrics = response.text().await.unwrap();
+    for expected in [
+        "kycok_requests_total{operation=\"CreateBucket\"",
+        "kycok_responses_total{operation=\"PutObject\",status=\"200\"} 2",
+        "kycok_responses_total{operation=\"GetObject\",status=\"200\"} 1",
+        "kycok_responses_total{operation=\"GetObject\",status=\"404\"} 1",
+        "kycok_request_duration_seconds_bucket{operation=\"GetObject\"",
+        "kycok_uploaded_bytes_total 42",
+        "kycok_file_dedup_hits_total 1",
+        "kycok_files_stored_total 1",
+    ] {
+        assert!(metrics.contains(expected), "{expected}:\n{metrics}");
+    }
+}
@@ -0,0 +1,289 @@
+use reqwest::StatusCode;
+
+mod common;
+
+const DEFAULT_RETENTION: &str = "<ObjectLockConfiguration><ObjectLockEnabled>Enabled</ObjectLockEnabled><Rule><DefaultRetention><Mode>GOVERNANCE</Mode><Days>1</Days></DefaultRetention></Rule></ObjectLockConfiguration>";
+
+#[tokio::test]
+async fn test_object_lock() {
+    let url = common::spawn_server(Default::default()).await.url;
+    let http = reqwest::Client::new();
+    let response = http
+        .put(format!("{url}/locked"))
+        .header("x-amz-bucket-object-lock-enabled", "true")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .get(format!("{url}/locked?versioning"))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.text().await.unwrap().contains("Enabled"));
+
+    let response = http
+        .put(format!("{url}/locked?object-lock"))
+        .body(DEFAULT_RETENTION)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .get(format!("{url}/locked?object-lock"))
+        .send()
+        .await
+        .unwrap();
+    let configuration = response.text().await.unwrap();
+    assert!(
+        configuration.contains("<Mode>GOVERNANCE</Mode><Days>1</Days>"),
+        "{configuration}"
+    );
+
+    // new objects are retained by default
+    let object = format!("{url}/locked/object");
+    let response = http.put(&object).body("retained").send().await.unwrap();
+    let version_id = response.headers()["x-amz-version-id"].clone();
+    let version = format!("{object}?versionId={}", version_id.to_str().unwrap());
+    let response = http.head(&object).send().await.unwrap();
+    assert_eq!(response.headers()["x-amz-object-lock-mode"], "GOVERNANCE");
+    assert_eq!(response.headers()["x-amz-object-lock-legal-hold"], "OFF");
+
+    let response = http.delete(&version).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(response.text().await.unwrap().contains("AccessDenied"));
+
+    // a legal hold protects the object even when governance retention is bypassed
+    let response = http
+        .put(format!("{object}?legal-hold"))
+        .body("<LegalHold><Status>ON</Status></LegalHold>")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .get(format!("{object}?legal-hold"))
+        .send()
+        .await
+        .unwrap();
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("<Status>ON</Status>"));
+    let response = http
+        .delete(&version)
+        .header("x-amz-bypass-governance-retention", "true")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    http.put(format!("{object}?legal-hold"))
+        .body("<LegalHold><Status>OFF</Status></LegalHold>")
+        .send()
+        .await
+        .unwrap();
+    let response = http
+        .delete(&version)
+        .header("x-amz-bypass-governance-retention", "true")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // compliance retention can be extended, but not shortened
+    http.put(&object).body("compliance").send().await.unwrap();
+    let retention = |until: &str| {
+        format!("<Retention><Mode>COMPLIANCE</Mode><RetainUntilDate>{until}</RetainUntilDate></Retention>")
+    };
+    let response = http
+        .put(format!("{object}?retention"))
+        .body(retention("2100-01-01T00:00:00Z"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .get(format!("{object}?retention"))
+        .send()
+        .await
+        .unwrap();
+    let body = response.text().await.unwrap();
+    assert!(
+        body.contains("<Mode>COMPLIANCE</Mode><RetainUntilDate>2100-01-01T00:00:00.000Z"),
+        "{body}"
+    );
+    let response = http
+        .put(format!("{object}?retention"))
+        .header("x-amz-bypass-governance-retention", "true")
+        .body(retention("2099-01-01T00:00:00Z"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // objects can only be locked in buckets with object lock
+    http.put(format!("{url}/unlocked")).send().await.unwrap();
+    http.put(format!("{url}/unlocked/object"))
+        .body("unlocked")
+        .send()
+        .await
+        .unwrap();
+    let response = http
+        .put(format!("{url}/unlocked/object?retention"))
+        .body(retention("2100-01-01T00:00:00Z"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = http
+        .get(format!("{url}/unlocked?object-lock"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_object_lock_signed_url() {
+    let url = common::spawn_server(Default::default()).await.url;
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/locked"))
+        .header("x-amz-bucket-object-lock-enabled", "true")
+        .send()
+        .await
+        .unwrap();
+    let object = format!("{url}/locked/object");
+    http.put(&object).body("held").send().await.unwrap();
+    http.put(format!("{object}?legal-hold"))
+        .body("<LegalHold><Status>ON</Status></LegalHold>")
+        .send()
+        .await
+        .unwrap();
+
+    let response = http
+        .post(format!("{object}?presign=PUT"))
+        .send()
+        .await
+        .unwrap();
+    let presigned: serde_json::Value = response.json().await.unwrap();
+    let signed_url = presigned["url"].as_str().unwrap();
+
+    // a signed URL does not grant access to the object lock settings
+    let response = http
+        .put(format!("{signed_url}&legal-hold"))
+        .body("<LegalHold><Status>OFF</Status></LegalHold>")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = http
+        .put(format!("{signed_url}&retention"))
+        .header("x-amz-bypass-governance-retention", "true")
+        .body("<Retention><Mode>GOVERNANCE</Mode><RetainUntilDate>2000-01-01T00:00:00Z</RetainUntilDate></Retention>")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = http
+        .put(format!("{signed_url}&versionId=1"))
+        .body("overwritten")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = http
+        .get(format!("{object}?legal-hold"))
+        .send()
+        .await
+        .unwrap();
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("<Status>ON</Status>"));
+
+    // the plain signed URL keeps working
+    let response = http.put(signed_url).body("new").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_delete_object_versions() {
+    let url = common::spawn_server(Default::default()).await.url;
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/locked"))
+        .header("x-amz-bucket-object-lock-enabled", "true")
+        .send()
+        .await
+        .unwrap();
+    http.put(format!("{url}/locked?object-lock"))
+        .body(DEFAULT_RETENTION)
+        .send()
+        .await
+        .unwrap();
+    let response = http
+        .put(format!("{url}/locked/retained"))
+        .body("retained")
+        .send()
+        .await
+        .unwrap();
+    let retained = response.headers()["x-amz-version-id"].clone();
+    let retained = retained.to_str().unwrap();
+
+    let delete = |body: String| http.post(format!("{url}/locked?delete")).body(body).send();
+    let response = delete(format!(
+        "<Delete>\
+        <Object><Key>retained</Key><VersionId>{retained}</VersionId></Object>\
+        <Object><Key>retained</Key><VersionId>0123456789abcdef</VersionId></Object>\
+        <Object><Key>retained</Key><VersionId>latest</VersionId></Object>\
+        <Object><Key>retained</Key></Object>\
+        </Delete>"
+    ))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result = response.text().await.unwrap();
+    for expected in [
+        "<Deleted><Key>retained</Key><DeleteMarker>true</DeleteMarker><DeleteMarkerVersionId>",
+        &format!("<Error><Key>retained</Key><VersionId>{retained}</VersionId><Code>AccessDenied</Code>"),
+        "<Error><Key>retained</Key><VersionId>0123456789abcdef</VersionId><Code>NoSuchVersion</Code>",
+        "<Error><Key>retained</Key><VersionId>latest</VersionId><Code>NoSuchVersion</Code>",
+    ] {
+        assert!(result.contains(expected), "{result}");
+    }
+    let response = http
+        .get(format!("{url}/locked/retained?versionId={retained}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // bypassing governance retention deletes the version, and errors are listed in quiet mode
+    let response = http
+        .post(format!("{url}/locked?delete"))
+        .header("x-amz-bypass-governance-retention", "true")
+        .body(format!(
+            "<Delete><Quiet>true</Quiet>\
+            <Object><Key>retained</Key><VersionId>{retained}</VersionId></Object>\
+            <Object><Key>missing</Key><VersionId>0123456789abcdef</VersionId></Object>\
+            </Delete>"
+        ))
+        .send()
+        .await
+        .unwrap();
+    let result = response.text().await.unwrap();
+    assert!(!result.contains("<Deleted>"), "{result}");
+    assert!(
+        result.contains("<Error><Key>missing</Key><VersionId>0123456789abcdef</VersionId><Code>NoSuchVersion</Code>"),
+        "{result}"
+    );
+    let response = http
+        .get(format!("{url}/locked/retained?versionId={retained}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,56 @@
+use kycok::config::ServerConfig;
+use reqwest::StatusCode;
+
+mod common;
+
+async fn presign(http: &reqwest::Client, object: &str, proto: Option<&str>) -> String {
+    let mut request = http.post(format!("{object}?presign=GET"));
+    if let Some(proto) = proto {
+        request = request.header("x-forwarded-proto", proto);
+    }
+    let response = request.send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let presigned: serde_json::Value = response.json().await.unwrap();
+    presigned["url"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn test_presign_url() {
+    let server = common::spawn_server(Default::default()).await;
+    let url = server.url;
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/signed")).send().await.unwrap();
+    let object = format!("{url}/signed/object");
+    http.put(&object).body("contents").send().await.unwrap();
+
+    let signed_url = presign(&http, &object, None).await;
+    assert!(
+        signed_url.starts_with(&format!("{object}?")),
+        "{signed_url}"
+    );
+    let response = http.get(&signed_url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "contents");
+
+    // behind a proxy terminating TLS
+    let signed_url = presign(&http, &object, Some("https")).await;
+    let https = format!("https://{}/signed/object?", server.addr);
+    assert!(signed_url.starts_with(&https), "{signed_url}");
+}
+
+#[tokio::test]
+async fn test_presign_public_url() {
+    let config = ServerConfig {
+        public_url: Some("https://kycok.example.com/".into()),
+        ..Default::default()
+    };
+    let url = common::spawn_server(config).await.url;
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/signed")).send().await.unwrap();
+
+    let object = format!("{url}/signed/object");
+    for proto in [None, Some("http")] {
+        let signed_url = presign(&http, &object, proto).await;
+        let public = "https://kycok.example.com/signed/object?";
+        assert!(signed_url.starts_with(public), "{signed_url}");
+    }
+}
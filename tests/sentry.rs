@@ -0,0 +1,121 @@
+use std::io::Write;
+
+use kycok::config::{ServerConfig, Usecase};
+use reqwest::StatusCode;
+use sha1::{Digest, Sha1};
+
+mod common;
+
+/// Serves kycok in-process with a `sentry` usecase, returning the URL of the Sentry API.
+async fn spawn_server() -> String {
+    let usecase = Usecase {
+        chunk_size: Some(1024),
+        ..Default::default()
+    };
+    let config = ServerConfig {
+        usecases: [("sentry".into(), usecase)].into(),
+        ..Default::default()
+    };
+    let server = common::spawn_server(config).await;
+    format!("{}/api/0", server.sentry_url)
+}
+
+fn sha1_hex(contents: &[u8]) -> String {
+    base16ct::lower::encode_string(&Sha1::digest(contents))
+}
+
+/// A `multipart/form-data` body with a gzipped part per chunk, as sent by `sentry-cli`.
+fn multipart(boundary: &str, chunks: &[&[u8]]) -> Vec<u8> {
+    let mut body = vec![];
+    for chunk in chunks {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], Default::default());
+        encoder.write_all(chunk).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        write!(
+            body,
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file_gzip\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+            sha1_hex(chunk)
+        )
+        .unwrap();
+        body.extend_from_slice(&gzipped);
+        body.extend_from_slice(b"\r\n");
+    }
+    write!(body, "--{boundary}--\r\n").unwrap();
+    body
+}
+
+#[tokio::test]
+async fn test_chunk_upload() {
+    let api = spawn_server().await;
+    let http = reqwest::Client::new();
+
+    let response = http
+        .get(format!("{api}/organizations/acme/chunk-upload/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let options = response.text().await.unwrap();
+    assert!(options.contains(r#""chunkSize":1024"#), "{options}");
+    assert!(options.contains(r#""hashAlgorithm":"sha1""#), "{options}");
+    assert!(options.contains(r#""compression":["gzip"]"#), "{options}");
+
+    let chunks: [&[u8]; 2] = [&[b'a'; 1024], b"the rest of the debug file"];
+    let contents = [chunks[0], chunks[1]].concat();
+    let checksum = sha1_hex(&contents);
+    let assemble = format!(
+        r#"{{"{checksum}":{{"name":"app.dSYM","chunks":["{}","{}"]}}}}"#,
+        sha1_hex(chunks[0]),
+        sha1_hex(chunks[1])
+    );
+    let assemble_url = format!("{api}/projects/acme/app/files/difs/assemble/");
+
+    let response = http
+        .post(&assemble_url)
+        .body(assemble.clone())
+        .header("content-type", "application/json")
+        .send()
+        .await
+        .unwrap();
+    let state = response.text().await.unwrap();
+    assert!(state.contains(r#""state":"not_found""#), "{state}");
+    assert!(state.contains(&sha1_hex(chunks[1])), "{state}");
+
+    let response = http
+        .post(format!("{api}/organizations/acme/chunk-upload/"))
+        .header("content-type", "multipart/form-data; boundary=chunks")
+        .body(multipart("chunks", &chunks))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = http
+        .post(&assemble_url)
+        .body(assemble)
+        .header("content-type", "application/json")
+        .send()
+        .await
+        .unwrap();
+    let state = response.text().await.unwrap();
+    assert_eq!(
+        state,
+        format!(r#"{{"{checksum}":{{"state":"ok","missingChunks":[],"detail":null}}}}"#)
+    );
+
+    // the chunks have to match the checksum of the file
+    let response = http
+        .post(format!("{api}/organizations/acme/artifactbundle/assemble/"))
+        .header("content-type", "application/json")
+        .body(format!(
+            r#"{{"checksum":"{}","chunks":["{}"],"projects":["app"]}}"#,
+            sha1_hex(b"something else"),
+            sha1_hex(chunks[1])
+        ))
+        .send()
+        .await
+        .unwrap();
+    let state = response.text().await.unwrap();
+    assert!(state.contains(r#""state":"error""#), "{state}");
+}
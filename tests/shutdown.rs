@@ -0,0 +1,46 @@
+use kycok::config::{Backend, ServerConfig};
+use reqwest::StatusCode;
+use tokio::sync::oneshot;
+
+mod common;
+
+#[tokio::test]
+async fn test_graceful_shutdown() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = || ServerConfig {
+        backend: Backend::Fjall,
+        data_dir: Some(data_dir.path().into()),
+        ..Default::default()
+    };
+    let contents = "contents which are flushed on shutdown ".repeat(1024);
+
+    let (shutdown, stopped) = oneshot::channel();
+    let (server, serving) = common::serve_until(config(), async {
+        stopped.await.ok();
+    })
+    .await;
+    let url = server.url;
+    let http = reqwest::Client::new();
+    http.put(format!("{url}/durable")).send().await.unwrap();
+    let response = http
+        .put(format!("{url}/durable/object"))
+        .body(contents.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    shutdown.send(()).unwrap();
+    serving.await.unwrap().unwrap();
+
+    // everything written before the shutdown is there after a restart
+    let (server, _serving) = common::serve_until(config(), std::future::pending()).await;
+    let url = server.url;
+    let response = http
+        .get(format!("{url}/durable/object"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), contents);
+}
@@ -0,0 +1,127 @@
+use reqwest::StatusCode;
+
+mod common;
+
+const ENABLED: &str = "<VersioningConfiguration><Status>Enabled</Status></VersioningConfiguration>";
+
+#[tokio::test]
+async fn test_versioning() {
+    let url = common::spawn_server(Default::default()).await.url;
+    let http = reqwest::Client::new();
+    let response = http.put(format!("{url}/versions")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // objects written before enabling versioning keep the null version
+    let object = format!("{url}/versions/object");
+    http.put(&object).body("unversioned").send().await.unwrap();
+
+    let response = http
+        .put(format!("{url}/versions?versioning"))
+        .body(ENABLED)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = http
+        .get(format!("{url}/versions?versioning"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), ENABLED);
+
+    let response = http.put(&object).body("first").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let first = response.headers()["x-amz-version-id"].clone();
+    let response = http.put(&object).body("second").send().await.unwrap();
+    let second = response.headers()["x-amz-version-id"].clone();
+    assert_ne!(first, second);
+
+    let response = http.get(&object).send().await.unwrap();
+    assert_eq!(response.headers()["x-amz-version-id"], second);
+    assert_eq!(response.text().await.unwrap(), "second");
+    let response = http
+        .get(format!("{object}?versionId={}", first.to_str().unwrap()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "first");
+    let response = http
+        .get(format!("{object}?versionId=null"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "unversioned");
+
+    // deleting the object only adds a delete marker
+    let response = http.delete(&object).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()["x-amz-delete-marker"], "true");
+    let deleted_marker = response.headers()["x-amz-version-id"].clone();
+    let response = http.get(&object).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = http
+        .get(format!("{url}/versions?versions&prefix=obj"))
+        .send()
+        .await
+        .unwrap();
+    let listing = response.text().await.unwrap();
+    assert_eq!(listing.matches("<Version>").count(), 3, "{listing}");
+    assert_eq!(listing.matches("<DeleteMarker>").count(), 1, "{listing}");
+    let marker = listing
+        .split("<DeleteMarker><Key>object</Key><VersionId>")
+        .nth(1)
+        .and_then(|rest| rest.split_once('<'))
+        .map(|(marker, _)| marker.to_owned())
+        .unwrap();
+    assert_eq!(deleted_marker, marker.as_str());
+    assert!(
+        listing.contains("<IsLatest>true</IsLatest><LastModified>"),
+        "{listing}"
+    );
+
+    // removing the delete marker restores the previous version
+    let response = http
+        .delete(format!("{object}?versionId={marker}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()["x-amz-delete-marker"], "true");
+    let response = http.get(&object).send().await.unwrap();
+    assert_eq!(response.headers()["x-amz-version-id"], second);
+    assert_eq!(response.text().await.unwrap(), "second");
+
+    // old versions can be copied
+    let response = http
+        .put(format!("{url}/versions/copy"))
+        .header(
+            "x-amz-copy-source",
+            format!("versions/object?versionId={}", first.to_str().unwrap()),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-amz-copy-source-version-id"], first);
+    let response = http
+        .get(format!("{url}/versions/copy"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "first");
+
+    let response = http
+        .get(format!("{object}?versionId=0123456789abcdef"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response.text().await.unwrap().contains("NoSuchVersion"));
+    let response = http
+        .get(format!("{object}?versionId=latest"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}